@@ -0,0 +1,219 @@
+//! Call stacks of stopped threads
+//!
+//! Frames are walked with the frame pointer: `rbp` (`x29` on aarch64) points to the saved frame
+//! pointer of the caller, followed by the return address. On riscv64, `s0` points right above
+//! them. Code built without frame pointers (most distribution libraries) ends or skips frames.
+//! With the `dwarf` feature, on x86_64, the call frame information of `.eh_frame` is used first,
+//! and frame pointers only when a function has none.
+//!
+//! Stacks of 32-bit threads are made of 4-byte words, and only walked with `ebp`.
+
+use std::fmt::Write;
+
+use crate::elf::Class;
+use crate::memory::{self, PermissionBits, Region};
+use crate::procfs;
+use crate::regs::Registers;
+use crate::symbols::Symbolizer;
+use crate::target::{read_word, Read};
+
+/// Bound on the number of frames, in case the stack is corrupted
+const MAX_FRAMES: usize = 256;
+
+/// Offsets from the frame pointer of the saved frame pointer and return address, and of the stack
+/// pointer of the caller, in words
+#[cfg(not(target_arch = "riscv64"))]
+const FRAME_RECORD: [i64; 3] = [0, 1, 2];
+#[cfg(target_arch = "riscv64")]
+const FRAME_RECORD: [i64; 3] = [-2, -1, 0];
+
+/// Registers needed to unwind a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    /// Instruction pointer, the return address for callers
+    pub pc: u64,
+    pub sp: u64,
+    pub fp: u64,
+}
+
+fn is_code(regions: &[Region], address: u64) -> bool {
+    memory::region_containing(regions, address as usize)
+        .is_some_and(|r| r.perms.has_perm(PermissionBits::Exec))
+}
+
+/// Unwinds `frame` of a thread of `class` with the frame pointer
+fn step_frame_pointer(class: Class, frame: &Frame, read: &mut Read) -> Option<Frame> {
+    let word = class.word_size() as u64;
+    if frame.fp < frame.sp || !frame.fp.is_multiple_of(word) {
+        return None;
+    }
+    let [fp, pc, sp] = FRAME_RECORD.map(|offset| frame.fp.checked_add_signed(offset * word as i64));
+    Some(Frame {
+        pc: read_word(read, class, pc? as usize).ok()?,
+        sp: sp?,
+        fp: read_word(read, class, fp? as usize).ok()?,
+    })
+}
+
+/// Returns the return addresses of the stack of a thread, starting with its instruction pointer
+///
+/// The walk stops at the first return address outside of executable mappings, or when the stack
+/// pointer does not move up.
+pub fn walk(regs: &Registers, regions: &[Region], read: &mut Read) -> Vec<u64> {
+    let mut frame = Frame {
+        pc: regs.gp.pc(),
+        sp: regs.gp.sp(),
+        fp: regs.gp.fp(),
+    };
+    let mut frames = vec![frame.pc];
+    while frames.len() < MAX_FRAMES {
+        // Only the call frame information of 64-bit programs is parsed
+        #[cfg(all(feature = "dwarf", target_arch = "x86_64"))]
+        let next = (regs.class == Class::Elf64)
+            .then(|| crate::dwarf::step(&frame, frames.len() == 1, regions, read))
+            .flatten()
+            .or_else(|| step_frame_pointer(regs.class, &frame, read));
+        #[cfg(not(all(feature = "dwarf", target_arch = "x86_64")))]
+        let next = step_frame_pointer(regs.class, &frame, read);
+
+        match next {
+            Some(next) if next.sp > frame.sp && is_code(regions, next.pc) => {
+                frames.push(next.pc);
+                frame = next;
+            }
+            _ => break,
+        }
+    }
+    frames
+}
+
+/// Formats the stacks of `threads`, one block per thread
+///
+/// Thread names are read from `/proc` for a running process `pid`, and unknown for core files.
+pub fn format(
+    pid: Option<u32>,
+    threads: &[Registers],
+    regions: &[Region],
+    read: &mut Read,
+) -> String {
+    let mut symbols = Symbolizer::new(regions, pid);
+    let mut text = String::new();
+    for regs in threads {
+        let width = 2 * regs.class.word_size();
+        let name = pid
+            .and_then(|pid| procfs::task_stat(pid, regs.tid).ok())
+            .map_or_else(|| "?".into(), |s| s.comm);
+        // Writing to a String cannot fail
+        let _ = writeln!(text, "Thread {} ({}):", regs.tid, name);
+        for (idx, &pc) in walk(regs, regions, read).iter().enumerate() {
+            let location = match memory::region_containing(regions, pc as usize) {
+                Some(region) => symbols.describe(region, pc as usize),
+                None => "-".into(),
+            };
+            let _ = writeln!(text, "  #{:<3} 0x{:0width$x} {}", idx, pc, location);
+        }
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regs::UserRegs;
+    use crate::Error;
+
+    #[test]
+    #[cfg(not(target_arch = "riscv64"))]
+    fn test_walk() {
+        let regions = [
+            "555555554000-555555556000 r-xp 00000000 fe:01 42 /tmp/prog",
+            "7ffffffde000-7ffffffff000 rw-p 00000000 00:00 0 [stack]",
+        ]
+        .iter()
+        .map(|line| line.parse::<Region>().unwrap())
+        .collect::<Vec<_>>();
+
+        // Three frames linked by rbp, the last one returning to an unmapped address
+        let stack = 0x7fff_ffff_e000u64;
+        let words = [
+            // 0x00: locals of the innermost function
+            0,
+            0,
+            // 0x10: frame of the innermost function
+            stack + 0x30,
+            0x5555_5555_4100,
+            0,
+            0,
+            // 0x30: frame of its caller
+            stack + 0x40,
+            0x5555_5555_4200,
+            // 0x40: frame of the outermost function
+            0,
+            0xdead_0000,
+        ];
+        let mut read = |address: usize, buffer: &mut [u8]| {
+            let word = (address as u64)
+                .checked_sub(stack)
+                .and_then(|offset| words.get(offset as usize / 8))
+                .ok_or(Error::RegionNotFound {
+                    start: address,
+                    end: address + buffer.len(),
+                })?;
+            buffer.copy_from_slice(&word.to_ne_bytes());
+            Ok(())
+        };
+        let regs = Registers {
+            tid: 1,
+            class: Class::Elf64,
+            gp: UserRegs::with_frame(0x5555_5555_4010, stack, stack + 0x10),
+            fp: None,
+        };
+        assert_eq!(
+            walk(&regs, &regions, &mut read),
+            [0x5555_5555_4010, 0x5555_5555_4100, 0x5555_5555_4200]
+        );
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_walk32() {
+        let regions = [
+            "08048000-0804a000 r-xp 00000000 fe:01 42 /tmp/prog32",
+            "fffdd000-ffffe000 rw-p 00000000 00:00 0 [stack]",
+        ]
+        .iter()
+        .map(|line| line.parse::<Region>().unwrap())
+        .collect::<Vec<_>>();
+
+        let stack = 0xffff_d000u32;
+        let words = [
+            // 0x00: locals of the innermost function, then its frame
+            0,
+            stack + 0x10,
+            0x0804_8100,
+            0,
+            // 0x10: frame of its caller, returning to an unmapped address
+            0,
+            0xdead_0000,
+        ];
+        let mut read = |address: usize, buffer: &mut [u8]| {
+            let word = (address as u32)
+                .checked_sub(stack)
+                .and_then(|offset| words.get(offset as usize / 4))
+                .ok_or(Error::RegionNotFound {
+                    start: address,
+                    end: address + buffer.len(),
+                })?;
+            buffer.copy_from_slice(&word.to_ne_bytes());
+            Ok(())
+        };
+        let regs = Registers {
+            tid: 1,
+            class: Class::Elf32,
+            gp: UserRegs::with_frame(0x0804_8010, stack as u64, stack as u64 + 4),
+            fp: None,
+        };
+        assert_eq!(walk(&regs, &regions, &mut read), [0x0804_8010, 0x0804_8100]);
+    }
+}
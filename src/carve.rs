@@ -0,0 +1,234 @@
+//! Reconstruction of ELF files from their mappings
+//!
+//! The mappings of a file are grouped by device and inode. The ELF header is read at the start of
+//! the mapping of offset 0, and each loadable segment is read back from memory at its address and
+//! written at its offset in the file. What is not loaded, like section headers and symbol tables,
+//! is lost, and relocated data holds the values of the running process.
+
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::cli::CarveOptions;
+use crate::elf::{self, ElfHeader, ProgramHeader};
+use crate::error::Chain;
+use crate::memory::{Memory, PermissionBits, Region, RegionKind};
+use crate::pagemap;
+use crate::ptrace::Ptrace;
+use crate::signal;
+use crate::target::Read;
+use crate::{Error, Result};
+
+/// Bytes of a segment copied from memory to the file at once
+const CHUNK_SIZE: usize = 1 << 16;
+
+/// Reads `buffer.len()` bytes at `address`, leaving zeros in the pages which cannot be read
+///
+/// Returns the number of bytes which could not be read.
+fn read_lossy(read: &mut Read, address: usize, buffer: &mut [u8]) -> usize {
+    if read(address, buffer).is_ok() {
+        return 0;
+    }
+    let page_size = pagemap::page_size();
+    let mut lost = 0;
+    let mut offset = 0;
+    while offset < buffer.len() {
+        let len = (page_size - (address + offset) % page_size).min(buffer.len() - offset);
+        let page = &mut buffer[offset..offset + len];
+        if read(address + offset, page).is_err() {
+            page.fill(0);
+            lost += len;
+        }
+        offset += len;
+    }
+    lost
+}
+
+/// Writes the ELF file loaded at `start` (the address of its header) to `output`
+///
+/// Returns the size of the file, and the number of bytes which could not be read.
+fn rebuild<W: Write + Seek>(read: &mut Read, start: usize, output: &mut W) -> Result<(u64, usize)> {
+    let mut data = vec![0; elf::EHDR_SIZE];
+    read(start, &mut data)?;
+    let header = ElfHeader::parse(&data)?;
+    let mut data = vec![0; header.e_phnum as usize * elf::PHDR_SIZE];
+    read(start + header.e_phoff as usize, &mut data)?;
+    let mut segments = data
+        .chunks_exact(elf::PHDR_SIZE)
+        .map(ProgramHeader::parse)
+        .collect::<Result<Vec<_>>>()?;
+    segments.retain(|s| s.p_type == elf::PT_LOAD);
+
+    // The header is at the start of the first segment, whose address is rounded down to a page
+    let page_size = pagemap::page_size() as u64;
+    let first = segments
+        .iter()
+        .find(|s| s.p_offset == 0)
+        .ok_or_else(|| Error::Elf("no segment holds the ELF header".into()))?;
+    let bias = (start as u64).wrapping_sub(first.p_vaddr - first.p_vaddr % page_size);
+
+    let mut size = 0;
+    let mut lost = 0;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    for segment in &segments {
+        let address = bias.wrapping_add(segment.p_vaddr) as usize;
+        output.seek(SeekFrom::Start(segment.p_offset))?;
+        let mut done = 0;
+        while done < segment.p_filesz as usize {
+            let chunk = &mut buffer[..CHUNK_SIZE.min(segment.p_filesz as usize - done)];
+            lost += read_lossy(read, address + done, chunk);
+            if segment.p_offset == 0 && done == 0 {
+                ElfHeader::strip_sections(chunk);
+            }
+            output.write_all(chunk)?;
+            done += chunk.len();
+        }
+        size = size.max(segment.p_offset + segment.p_filesz);
+    }
+    Ok((size, lost))
+}
+
+/// Returns the mappings of offset 0 of files with an executable mapping, once per file
+fn executable_files<'a>(memory: &'a Memory, options: &CarveOptions) -> Vec<&'a Region> {
+    let mut files: Vec<&Region> = Vec::new();
+    for region in memory.iter() {
+        if region.kind != RegionKind::File
+            || region.offset != 0
+            || files.iter().any(|f| f.same_file(region))
+        {
+            continue;
+        }
+        let mut mappings = memory.iter().filter(|r| r.same_file(region));
+        if mappings.clone().any(|r| options.filter.matches(r))
+            && mappings.any(|r| r.perms.has_perm(PermissionBits::Exec))
+        {
+            files.push(region);
+        }
+    }
+    files
+}
+
+fn carve_file(process: &mut Ptrace, region: &Region, path: &Path) -> Result<()> {
+    let mut file = File::create(path)?;
+    let result = rebuild(
+        &mut |address, buffer| process.read(address, buffer),
+        region.start,
+        &mut file,
+    );
+    let (size, lost) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            // Best effort, the carving failure matters more
+            let _ = std::fs::remove_file(path);
+            return Err(e);
+        }
+    };
+    file.set_len(size)?;
+    if lost > 0 {
+        warn!(
+            "Could not read 0x{:x} bytes of {}, written as zeros",
+            lost,
+            region.path().unwrap_or("")
+        );
+    }
+    info!(
+        "Carved {}{} to {} ({} bytes)",
+        region.path().unwrap_or(""),
+        if region.deleted { " (deleted)" } else { "" },
+        path.display(),
+        size
+    );
+    Ok(())
+}
+
+pub fn run(options: &CarveOptions) -> Result<()> {
+    let output = match options.output {
+        Some(ref path) => path.clone(),
+        None => format!("carved-{}", options.pid).into(),
+    };
+    let mut process = Ptrace::new(options.pid)?;
+    let memory = Memory::from_pid(options.pid)?;
+    std::fs::create_dir_all(&output)?;
+
+    let mut carved = 0;
+    for region in executable_files(&memory, options) {
+        signal::check()?;
+        let name = format!(
+            "{:x}_{}",
+            region.start,
+            region.filename().unwrap_or("unknown")
+        );
+        match carve_file(&mut process, region, &output.join(name)) {
+            Ok(()) => carved += 1,
+            Err(e) => warn!(
+                "Could not carve {}: {}",
+                region.path().unwrap_or("no file"),
+                Chain(&e)
+            ),
+        }
+    }
+    if carved == 0 {
+        return Err(Error::NothingDumped { pid: options.pid });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_rebuild() {
+        let start = 0x7f00_0000_0000;
+        let page = pagemap::page_size();
+        let mut header = vec![0; elf::EHDR_SIZE];
+        header[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        header[32] = elf::EHDR_SIZE as u8;
+        header[40] = 0xff; // e_shoff, to be cleared
+        header[54] = elf::PHDR_SIZE as u8;
+        header[56] = 3;
+        // Headers and code at offset 0, data at offset 0x2010 and address 0x3010, and a note
+        for (p_type, offset, vaddr, filesz) in [
+            (elf::PT_LOAD, 0, 0, 0x1000),
+            (elf::PT_LOAD, 0x2010, 0x3010, 0x20),
+            (4, 0x200, 0x200, 0x10),
+        ] {
+            let mut phdr = vec![0; elf::PHDR_SIZE];
+            phdr[..4].copy_from_slice(&p_type.to_ne_bytes());
+            phdr[8..16].copy_from_slice(&(offset as u64).to_ne_bytes());
+            phdr[16..24].copy_from_slice(&(vaddr as u64).to_ne_bytes());
+            phdr[32..40].copy_from_slice(&(filesz as u64).to_ne_bytes());
+            header.extend_from_slice(&phdr);
+        }
+        let mut text = header.clone();
+        text.resize(0x1000, 0x90);
+        let mut data = vec![0; page];
+        data[0x10..0x30].fill(0xda);
+
+        let mut read = |address: usize, buffer: &mut [u8]| {
+            let (base, bytes) = match address {
+                a if a >= start + 0x3000 => (start + 0x3000, &data),
+                _ => (start, &text),
+            };
+            let offset = address - base;
+            let bytes = bytes
+                .get(offset..offset + buffer.len())
+                .ok_or(Error::RegionNotFound {
+                    start: address,
+                    end: address + buffer.len(),
+                })?;
+            buffer.copy_from_slice(bytes);
+            Ok(())
+        };
+        let mut output = Cursor::new(Vec::new());
+        assert_eq!(rebuild(&mut read, start, &mut output).unwrap(), (0x2030, 0));
+
+        let file = output.into_inner();
+        assert_eq!(file.len(), 0x2030);
+        assert_eq!(file[40], 0);
+        assert_eq!(file[64..0x1000], text[64..]);
+        assert!(file[0x1000..0x2010].iter().all(|&b| b == 0));
+        assert!(file[0x2010..].iter().all(|&b| b == 0xda));
+    }
+}
@@ -0,0 +1,301 @@
+//! Processes of a cgroup, or of a container
+//!
+//! Container runtimes put each container in its own cgroup, named after the container ID:
+//! `docker/ID` with the cgroupfs driver, `docker-ID.scope`, `libpod-ID.scope`,
+//! `cri-containerd-ID.scope` or `crio-ID.scope` with the systemd driver. Both cgroup v2 and the
+//! hierarchies of cgroup v1 are searched.
+//!
+//! The cgroup v2 of a process can also be frozen, with `--freeze cgroup`, to stop it without
+//! ptrace.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::{Error, Result};
+
+/// Where cgroup filesystems are mounted
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Shortest prefix of a container ID accepted, as printed by `docker ps`
+const MIN_ID_LEN: usize = 12;
+
+/// How long the processes of a cgroup may take to freeze
+const FREEZE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often `cgroup.events` is read while waiting for the processes to freeze
+const FREEZE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A cgroup given on the command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cgroup {
+    /// With `--cgroup`
+    Path(PathBuf),
+
+    /// With `--container`
+    Container(String),
+}
+
+impl Cgroup {
+    /// Returns the directory of the cgroup
+    pub fn resolve(&self) -> Result<PathBuf> {
+        match *self {
+            Self::Path(ref path) => directory(path),
+            Self::Container(ref id) => container(id),
+        }
+    }
+}
+
+/// Returns the directory of the cgroup `path`
+///
+/// `path` is either a directory, or relative to the cgroup mount point, as in `/proc/PID/cgroup`.
+fn directory(path: &Path) -> Result<PathBuf> {
+    let path = if path.starts_with(CGROUP_ROOT) {
+        path.to_path_buf()
+    } else {
+        Path::new(CGROUP_ROOT).join(path.strip_prefix("/").unwrap_or(path))
+    };
+    if !path.join("cgroup.procs").is_file() {
+        return Err(Error::Usage(format!(
+            "{} is not a cgroup directory",
+            path.display()
+        )));
+    }
+    Ok(path)
+}
+
+/// Returns the container ID of a cgroup directory named `name`, if it looks like one
+fn container_id(name: &str) -> Option<&str> {
+    let name = name.strip_suffix(".scope").unwrap_or(name);
+    let id = name.rsplit('-').next()?;
+    (id.len() >= MIN_ID_LEN && id.bytes().all(|b| b.is_ascii_hexdigit())).then_some(id)
+}
+
+/// Looks for the cgroups whose container ID starts with `prefix` below `dir`
+fn find_containers(dir: &Path, prefix: &str, found: &mut Vec<(String, PathBuf)>) {
+    // Cgroups may be removed while walking the tree
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut dirs: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+        .map(|e| e.path())
+        .collect();
+    dirs.sort();
+    for dir in dirs {
+        let id = dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(container_id);
+        match id {
+            Some(id) if id.starts_with(prefix) => found.push((id.into(), dir)),
+            _ => find_containers(&dir, prefix, found),
+        }
+    }
+}
+
+/// Returns the cgroup directory of the container `id`, which may be abbreviated
+fn container(id: &str) -> Result<PathBuf> {
+    let id = id.to_ascii_lowercase();
+    if container_id(&id) != Some(&id[..]) {
+        return Err(Error::Usage(format!(
+            "Invalid container ID {:?}, expected at least {} hexadecimal digits",
+            id, MIN_ID_LEN
+        )));
+    }
+    let mut found = Vec::new();
+    find_containers(Path::new(CGROUP_ROOT), &id, &mut found);
+    // With cgroup v1, the container has a cgroup in each hierarchy
+    let Some((first, path)) = found.first() else {
+        return Err(Error::Usage(format!(
+            "No cgroup found for container {}",
+            id
+        )));
+    };
+    if let Some((other, _)) = found.iter().find(|(other, _)| other != first) {
+        return Err(Error::Usage(format!(
+            "Container ID {} is ambiguous, it matches {} and {}",
+            id, first, other
+        )));
+    }
+    Ok(path.clone())
+}
+
+/// Parses a `cgroup.procs` file
+fn parse_procs(text: &str) -> Vec<u32> {
+    text.lines().filter_map(|line| line.parse().ok()).collect()
+}
+
+/// Returns the processes of the cgroup `dir` and of its descendants, except this process
+pub fn procs(dir: &Path) -> Result<Vec<u32>> {
+    let mut pids = parse_procs(&fs::read_to_string(dir.join("cgroup.procs"))?);
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            // A child cgroup may be removed in between
+            if let Ok(children) = procs(&entry.path()) {
+                pids.extend(children);
+            }
+        }
+    }
+    let own = std::process::id();
+    pids.retain(|&pid| pid != own);
+    pids.sort_unstable();
+    pids.dedup();
+    Ok(pids)
+}
+
+/// Returns the cgroup v2 path in a `/proc/PID/cgroup` file, relative to the mount point
+fn parse_unified(text: &str) -> Option<&str> {
+    text.lines().find_map(|line| line.strip_prefix("0::"))
+}
+
+/// Returns where the cgroup v2 filesystem is mounted, from a `/proc/mounts` file
+///
+/// It is `/sys/fs/cgroup` on most systems, and `/sys/fs/cgroup/unified` on hybrid ones.
+fn parse_unified_mount(text: &str) -> Option<&str> {
+    text.lines().find_map(|line| {
+        let mut fields = line.split(' ');
+        let mount_point = fields.nth(1)?;
+        (fields.next()? == "cgroup2").then_some(mount_point)
+    })
+}
+
+/// Whether all the processes of a cgroup are frozen, from its `cgroup.events` file
+fn is_frozen(events: &str) -> bool {
+    events.lines().any(|line| line == "frozen 1")
+}
+
+/// Returns the cgroup v2 path of process `pid`
+fn unified_path(pid: u32) -> Result<PathBuf> {
+    let text = fs::read_to_string(format!("/proc/{}/cgroup", pid))?;
+    parse_unified(&text).map(PathBuf::from).ok_or_else(|| {
+        Error::Usage(format!(
+            "Process {} is not in a cgroup v2, which cannot be frozen",
+            pid
+        ))
+    })
+}
+
+/// A cgroup v2 frozen with `cgroup.freeze`, thawed when dropped
+#[derive(Debug)]
+pub struct Freezer {
+    dir: PathBuf,
+
+    /// Whether the cgroup was frozen here, one which was frozen already stays frozen
+    thaw: bool,
+}
+
+impl Freezer {
+    /// Freezes the cgroup of process `pid`, with every other process in it and in its
+    /// descendants, and waits for all of them to be frozen
+    pub fn freeze(pid: u32) -> Result<Self> {
+        let path = unified_path(pid)?;
+        if path == Path::new("/") {
+            return Err(Error::Usage(format!(
+                "Process {} is in the root cgroup, which cannot be frozen",
+                pid
+            )));
+        }
+        if unified_path(std::process::id())?.starts_with(&path) {
+            return Err(Error::Usage(format!(
+                "dump-memory runs in the cgroup {} of process {}, and would freeze itself",
+                path.display(),
+                pid
+            )));
+        }
+        let mounts = fs::read_to_string("/proc/mounts")?;
+        let root = parse_unified_mount(&mounts)
+            .ok_or_else(|| io::Error::other("no cgroup v2 filesystem is mounted"))?;
+        let dir = Path::new(root).join(path.strip_prefix("/").unwrap_or(&path));
+
+        let thaw = fs::read_to_string(dir.join("cgroup.freeze"))?.trim() != "1";
+        let freezer = Self { dir, thaw };
+        if freezer.thaw {
+            freezer.write(true)?;
+        }
+        let started = Instant::now();
+        while !is_frozen(&fs::read_to_string(freezer.dir.join("cgroup.events"))?) {
+            if started.elapsed() >= FREEZE_TIMEOUT {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "the processes of {} were not frozen after {} s",
+                        freezer.dir.display(),
+                        FREEZE_TIMEOUT.as_secs()
+                    ),
+                )
+                .into());
+            }
+            std::thread::sleep(FREEZE_POLL_INTERVAL);
+        }
+        debug!(
+            "Froze {} in {:.3} s",
+            freezer.dir.display(),
+            started.elapsed().as_secs_f64()
+        );
+        Ok(freezer)
+    }
+
+    fn write(&self, frozen: bool) -> io::Result<()> {
+        let path = self.dir.join("cgroup.freeze");
+        fs::write(&path, if frozen { "1" } else { "0" }).map_err(|e| {
+            io::Error::new(e.kind(), format!("cannot write {}: {}", path.display(), e))
+        })
+    }
+}
+
+impl Drop for Freezer {
+    fn drop(&mut self) {
+        if !self.thaw {
+            return;
+        }
+        match self.write(false) {
+            Ok(()) => debug!("Thawed {}", self.dir.display()),
+            Err(e) => warn!("Could not thaw {}: {}", self.dir.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_container_id() {
+        let id = "4f2a9c1e7b3d5a6f8e9d0c1b2a3f4e5d6c7b8a9f0e1d2c3b4a5f6e7d8c9b0a1f";
+        assert_eq!(container_id(id), Some(id));
+        assert_eq!(container_id(&format!("docker-{}.scope", id)), Some(id));
+        assert_eq!(
+            container_id(&format!("cri-containerd-{}.scope", id)),
+            Some(id)
+        );
+        assert_eq!(container_id("user-1000.slice"), None);
+        assert_eq!(container_id("system.slice"), None);
+        assert_eq!(container_id("init.scope"), None);
+        assert_eq!(parse_procs("12\n345\n\n"), [12, 345]);
+    }
+
+    #[test]
+    fn test_freezer_files() {
+        assert_eq!(
+            parse_unified("12:pids:/user.slice\n0::/user.slice/app.scope\n"),
+            Some("/user.slice/app.scope")
+        );
+        assert_eq!(parse_unified("6:freezer:/\n"), None);
+        assert_eq!(
+            parse_unified_mount(
+                "proc /proc proc rw 0 0\ncgroup2 /sys/fs/cgroup/unified cgroup2 rw 0 0\n"
+            ),
+            Some("/sys/fs/cgroup/unified")
+        );
+        assert_eq!(
+            parse_unified_mount("cgroup /sys/fs/cgroup/pids cgroup rw 0 0\n"),
+            None
+        );
+        assert!(is_frozen("populated 1\nfrozen 1\n"));
+        assert!(!is_frozen("populated 1\nfrozen 0\n"));
+    }
+}
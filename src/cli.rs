@@ -0,0 +1,2100 @@
+use std::fmt;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::cgroup::Cgroup;
+use crate::compress::Compression;
+use crate::config;
+use crate::dump::Targets;
+use crate::logger::LogFormat;
+use crate::maps::ListFormat;
+use crate::memory::RegionFilter;
+use crate::output::Format;
+use crate::pattern::Pattern;
+use crate::ptrace::{Attach, Backend};
+#[cfg(feature = "secrets")]
+use crate::regex::Regex;
+use crate::scan::{Endian, Refine, ValueType};
+use crate::search::BytePattern;
+use crate::target::Target;
+use crate::{Error, Result};
+
+pub const USAGE: &str = "\
+Usage: dump-memory [dump] [OPTIONS] PID [OUTPUT]
+       dump-memory [dump] [OPTIONS] --from-core CORE [OUTPUT]
+       dump-memory [dump] [OPTIONS] --kernel [OUTPUT]
+       dump-memory [dump] [OPTIONS] (--cgroup CGROUP | --container ID) [OUTPUT]
+       dump-memory [dump] [OPTIONS] --remote HOST:PORT PID [OUTPUT]
+       dump-memory search [OPTIONS] PID (PATTERN | --file FILE)
+       dump-memory scan [OPTIONS] --type TYPE --value VALUE PID
+       dump-memory scan [OPTIONS] --from STATE CONDITION PID
+       dump-memory write [OPTIONS] PID ADDRESS DATA
+       dump-memory diff [OPTIONS] OLD NEW
+       dump-memory info DUMP
+       dump-memory watch [OPTIONS] PID START..END
+       dump-memory hexdump PID START..END
+       dump-memory read [OPTIONS] PID ADDRESS LENGTH
+       dump-memory restore [OPTIONS] PID DUMP
+       dump-memory maps [OPTIONS] PID
+       dump-memory where PID ADDRESS
+       dump-memory summary [OPTIONS] PID
+       dump-memory modules [OPTIONS] PID
+       dump-memory carve [OPTIONS] PID [OUTPUT]
+       dump-memory verify [OPTIONS] PID
+       dump-memory serve [OPTIONS] ADDRESS
+       dump-memory repl [OPTIONS] PID
+       dump-memory selftest [OPTIONS] [OUTPUT]
+       dump-memory mount [OPTIONS] PID MOUNTPOINT (with the `fuse` feature)
+       dump-memory scan-yara [OPTIONS] PID RULES (with the `yara` feature)
+       dump-memory scan-secrets [OPTIONS] PID (with the `secrets` feature)
+
+Dump options:
+  --format FORMAT     `dir` (default) writes one file per region in the OUTPUT
+                      directory, `core` writes an ELF core file to OUTPUT,
+                      `tar` writes the files of `dir` to the OUTPUT archive,
+                      `dedup` stores each distinct page once in OUTPUT/pages,
+                      `minidump` writes a Breakpad minidump to OUTPUT
+                      (alias: --output-format)
+  --compress ALGO     compress region files with `gzip` or `zstd` (`dir` and
+                      `tar` formats only)
+  --sha256            record the SHA-256 of each region in the manifest, and in
+                      a SHA256SUMS file for uncompressed `dir` dumps
+  --entropy           record the entropy of each region in the manifest, print
+                      them as a table, and warn about high-entropy anonymous
+                      executable regions (packed or injected code)
+  --jobs N            dump regions with N threads while the process is stopped
+                      (`dir` format only)
+  --stacks            only dump the stack of each thread, in `stack-TID` files
+  --children          also dump the direct children of PID, in per-PID entries
+                      of OUTPUT
+  --tree              same as --children, for all the descendants of PID
+  --cgroup CGROUP     dump all the processes of CGROUP and of its sub-cgroups
+                      instead of PID, in per-PID entries of OUTPUT; CGROUP is a
+                      directory, or relative to /sys/fs/cgroup
+  --container ID      same as --cgroup, with the cgroup of the container ID
+                      (docker, podman, containerd or CRI-O), which may be
+                      abbreviated to 12 digits
+  --remote HOST:PORT  dump PID on another host, read through the agent started
+                      there by `serve`; the dump is only written here
+  --remote-compress ALGO
+                      compress memory sent by the agent with `gzip` or `zstd`
+  --seize             attach with PTRACE_SEIZE and PTRACE_INTERRUPT instead of
+                      PTRACE_ATTACH, so that no SIGSTOP is sent to PID
+  --minimize-pause    resume the process between regions, instead of keeping
+                      it stopped for the whole dump
+  --max-pause SECS    detach once the process was stopped for SECS seconds,
+                      the regions not dumped yet are recorded as skipped
+  --no-stop           do not attach, read memory while the process runs: the
+                      dump may be inconsistent and has no registers
+  --freeze cgroup     do not attach, freeze the cgroup v2 of PID (and every
+                      process in it) while memory is read; the dump has no
+                      registers
+  --follow-forks      keep the processes traced while they run until the end of
+                      the dump, also dumping the children they fork, in per-PID
+                      entries of OUTPUT
+  --interval SECS     dump every SECS seconds, in timestamped entries of OUTPUT
+  --count N           stop after N dumps (with --interval)
+  --with-proc         save `cmdline`, `environ`, `status`, `stat`, `auxv`, `limits`
+                      and the targets of `fd/` from /proc/PID to `proc/` (not
+                      with the `core` and `minidump` formats)
+  --with-files        copy each distinct file mapped by the process to `files/`,
+                      under its absolute path, from /proc/PID/map_files so that
+                      deleted files are copied too (not with the `core` and
+                      `minidump` formats)
+  --with-fds          save the open file descriptors to `fds.json`: their target,
+                      offset and flags from /proc/PID/fdinfo, and the addresses
+                      and state of TCP, UDP and unix sockets from /proc/PID/net
+                      (not with the `core` and `minidump` formats)
+  --backtraces        save the call stack of each thread to `backtraces.txt`,
+                      walked with frame pointers (and `.eh_frame` with the
+                      `dwarf` feature); not with the `core` and `minidump`
+                      formats or --no-stop
+  --dry-run           print the selected regions and the estimated size to read,
+                      without dumping anything
+  --exclude-path PATTERN
+                      do not dump the regions whose backing path matches
+                      PATTERN (as for --path), they are recorded as skipped in
+                      the manifest; can be repeated
+  --max-region-size SIZE
+                      do not dump the regions larger than SIZE bytes, which may
+                      have a K, M or G suffix, they are recorded as skipped
+  --no-default-excludes
+                      also dump the device mappings of /dev/nvidia* and
+                      /dev/dri/*, whose reads may hang or fail, skipped by
+                      default
+  --strict            stop at the first region which cannot be entirely dumped,
+                      and fail
+  --read-timeout SECS give up the regions which are not read within SECS
+                      seconds, instead of blocking forever on some FUSE or
+                      device mappings; they are recorded as failed
+  --backend BACKEND   read /proc/PID/mem with `read` (default), one chunk at a
+                      time, or with `uring`, submitting 16 chunks at once to
+                      io_uring which reads them in parallel (experimental,
+                      Linux 5.6 or later, needs the `uring` feature)
+  --no-peek           do not read the pages which cannot be read through
+                      /proc/PID/mem word by word with PTRACE_PEEKDATA, which
+                      some device mappings allow (not done by --jobs workers)
+  --redump-changed    dump again the regions whose mapping changed during the
+                      dump, as they are mapped after it (`dir` and `dedup`
+                      formats only)
+  --events ndjson     write the events of the dump (attach, region_start,
+                      region_dumped, region_failed, detach, summary...) as
+                      JSON lines on the standard output, whatever the log level
+  --events-output FILE
+                      write the events to FILE instead of the standard output
+  --name TEMPLATE     name of the output when OUTPUT is not given, in which
+                      `{name}` (the default, `PROGRAM-PID`), `{comm}`, `{pid}`,
+                      `{host}` and `{time}` (UTC) are replaced
+  --profile NAME      use the options of the profile NAME of the configuration,
+                      `~/.config/dump-memory/config.toml` or $DUMP_MEMORY_CONFIG;
+                      options given after it replace all its values for them,
+                      and --no-FLAG after it turns off a flag it sets
+
+Search options:
+  -i, --ignore-case   ignore ASCII case when matching PATTERN
+  -x, --hex           PATTERN is hex-encoded bytes (`deadbeef` or `de ad be ef`),
+                      in which `??` matches any byte
+  -f, --file FILE     search for each non-empty line of FILE instead of PATTERN,
+                      hits are prefixed with their pattern
+  -C, --context N     print N bytes before and after each hit, as a hexdump
+
+Scan options:
+  --type TYPE         i8, i16, i32, i64, u8, u16, u32, u64, f32 or f64
+  --value VALUE       value to look for
+  --big-endian        values are stored in big endian
+  --align N           only consider addresses aligned on N bytes (defaults to
+                      the size of TYPE)
+  --save STATE        save hits to STATE for later refinement
+  --from STATE        only re-check hits saved in STATE, keeping those matching
+                      CONDITION: `--value VALUE`, `--changed`, `--unchanged`,
+                      `--increased` or `--decreased`
+
+Write options:
+  -x, --hex           DATA is hex-encoded bytes
+  --type TYPE         DATA is a value of TYPE (see scan options)
+  --big-endian        write the value in big endian
+  --force             write even if ADDRESS is not in a writable region
+
+Diff options:
+  --pages             report changed pages instead of changed byte ranges
+  --hex               print changed lines as a unified hex diff
+
+Watch options:
+  --interval SECS     time between reads, defaults to 1 second
+  --count N           stop after N reads
+
+Read options:
+  -o, --output FILE   write to FILE instead of the standard output
+  --force             write unmapped or unreadable parts of the range as zeros,
+                      instead of failing
+  LENGTH is decimal, or hexadecimal with a `0x` prefix
+
+Restore options:
+  --force             also restore regions which are not writable
+
+Maps options:
+  --format FORMAT     `table` (default), `json` or `csv`
+
+Summary options:
+  --format FORMAT     `table` (default) or `json`
+  --top N             number of largest regions listed, defaults to 10
+
+Modules options:
+  --format FORMAT     `table` (default), `json` or `csv`
+
+Carve options:
+  Region selection options select files with a matching mapping, OUTPUT
+  defaults to `carved-PID`
+
+Verify options:
+  --format FORMAT     `table` (default) or `json`
+  Region selection options restrict the executable file mappings compared with
+  their files; the exit status is 1 if a page was modified
+
+Serve options:
+  --once              exit after the first client disconnects
+  --allow-non-loopback
+                      listen on ADDRESS even if it is not a loopback address
+  ADDRESS is the address and port to listen on, such as 127.0.0.1:7070; clients
+  are not authenticated and can read the memory of any process the agent may
+  trace, so only loopback addresses are accepted by default
+
+Repl options:
+  --history FILE      load previous commands from FILE, and append the new ones
+                      to it
+  `help` lists the commands of the prompt
+
+Selftest options:
+  --size SIZE         size of each of the dense and sparse mappings of the child,
+                      with an optional K, M or G suffix, defaults to 64M
+  --bench             only measure the throughput of the backends, dumping the
+                      child 5 times with each of them in turn
+  --backend BACKEND   backend measured by --bench, `read` or `uring` (with the
+                      `uring` feature), can be repeated, defaults to those
+                      built
+  Spawns a child process whose memory is known, dumps it with each backend and
+  way of stopping it, and checks the dumps word by word; OUTPUT keeps the dumps,
+  which are written to a temporary directory otherwise. The exit status is 1 if
+  a dump is wrong
+
+Mount options:
+  Region selection options select the regions listed in MOUNTPOINT, one
+  read-only file per region named as in `dir` dumps; PID stays stopped until
+  MOUNTPOINT is unmounted (needs the CAP_SYS_ADMIN capability)
+
+Scan-secrets options:
+  --pattern REGEX     also look for matches of the regular expression REGEX,
+                      can be repeated
+  --no-builtin        only look for the --pattern expressions, not for private
+                      keys, AWS access keys, JWTs and bearer tokens
+  --no-redact         print the matches entirely, instead of their first bytes
+
+Core files, dumps and the kernel:
+  --from-core CORE    read memory from the ELF core file CORE instead of a
+                      running process, replacing PID; supported by dump,
+                      search, scan, hexdump, read, maps, where, repl,
+                      mount, scan-yara and scan-secrets. Pages which the core
+                      does not hold cannot be read. diff, info and restore also
+                      accept core files in place of dumps
+  --from-dump DUMP    read memory from a `dir` or `dedup` dump (its directory
+                      or manifest), replacing PID; supported by search, scan,
+                      hexdump, read, maps, where, repl, mount, scan-yara and
+                      scan-secrets
+  --kernel            read kernel memory from /proc/kcore instead of a process,
+                      replacing PID (root only); supported like --from-core.
+                      Only physical memory and the areas of /proc/vmallocinfo
+                      can be read
+
+Region selection:
+  --path PATTERN      only use regions whose backing path matches PATTERN
+                      (shell glob, or regular expression if prefixed with `re:`),
+                      can be repeated
+  --kind KINDS        only use regions of the comma-separated KINDS: `file`,
+                      `heap`, `stack`, `vdso`, `vvar`, `vsyscall`, `anonymous`
+                      or `anon:NAME` (named with PR_SET_VMA_ANON_NAME), can be
+                      repeated
+  --anonymous-only    only use regions without a backing file
+  --shared-only       only use regions of shared memory: System V segments,
+                      POSIX objects of /dev/shm and memfd files
+
+Logging options, accepted by every command:
+  -v, --verbose       print more details, can be repeated
+  -q, --quiet         only print warnings and errors, or only errors if repeated
+  --log-format FMT    `text` (default) or `json`, one object per line with the
+                      `level`, `event` and `message`, and the fields of the event
+
+Address ranges are written START..END or START..+LENGTH, in hexadecimal.
+
+  -h, --help          print this help";
+
+/// Command line arguments, handling `--flag=value` forms
+pub struct Args {
+    args: std::vec::IntoIter<String>,
+    pending: Option<String>,
+}
+
+impl Args {
+    pub fn new(args: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            args: args.into_iter().collect::<Vec<_>>().into_iter(),
+            pending: None,
+        }
+    }
+
+    /// Returns the next argument
+    pub fn next_arg(&mut self) -> Result<Option<String>> {
+        if let Some(value) = self.pending.take() {
+            return Err(Error::Usage(format!("Unexpected value {:?}", value)));
+        }
+        let arg = match self.args.next() {
+            Some(arg) => arg,
+            None => return Ok(None),
+        };
+        if arg.starts_with("--") {
+            if let Some((flag, value)) = arg.split_once('=') {
+                self.pending = Some(value.into());
+                return Ok(Some(flag.into()));
+            }
+        }
+        Ok(Some(arg))
+    }
+
+    /// Returns the arguments not consumed yet, which are then consumed
+    pub fn rest(&mut self) -> Vec<String> {
+        self.args.by_ref().collect()
+    }
+
+    /// Makes `args` the next arguments, before those not consumed yet
+    pub fn insert(&mut self, args: Vec<String>) {
+        let rest: Vec<_> = args.into_iter().chain(self.args.by_ref()).collect();
+        self.args = rest.into_iter();
+    }
+
+    /// Returns the value of `flag`
+    pub fn value(&mut self, flag: &str) -> Result<String> {
+        self.pending
+            .take()
+            .or_else(|| self.args.next())
+            .ok_or_else(|| Error::Usage(format!("Missing value for {}", flag)))
+    }
+
+    /// Returns the parsed value of `flag`
+    pub fn parse_value<T>(&mut self, flag: &str) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let value = self.value(flag)?;
+        value
+            .parse()
+            .map_err(|e| Error::Usage(format!("Invalid value {:?} for {}: {}", value, flag, e)))
+    }
+}
+
+fn unknown_option(arg: &str) -> Error {
+    Error::Usage(format!("Unknown option {}", arg))
+}
+
+pub fn is_option(arg: &str) -> bool {
+    arg.len() > 1 && arg.starts_with('-')
+}
+
+fn print_help() -> ! {
+    println!("{}", USAGE);
+    std::process::exit(0);
+}
+
+/// Handles region selection options, returns false if `arg` is not one of them
+pub fn parse_filter_option(arg: &str, args: &mut Args, filter: &mut RegionFilter) -> Result<bool> {
+    match arg {
+        "--path" => {
+            filter.add_path(args.parse_value("--path")?);
+        }
+        "--kind" => {
+            for kind in args.value("--kind")?.split(',') {
+                filter.add_kind(kind.parse()?);
+            }
+        }
+        "--anonymous-only" => {
+            filter.anonymous_only(true);
+        }
+        "--shared-only" => {
+            filter.shared_only(true);
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Positional arguments, consumed in order
+struct Positional(std::vec::IntoIter<String>);
+
+impl Positional {
+    fn required(&mut self, name: &str) -> Result<String> {
+        self.0
+            .next()
+            .ok_or_else(|| Error::Usage(format!("Missing {}", name)))
+    }
+
+    fn optional(&mut self) -> Option<String> {
+        self.0.next()
+    }
+
+    fn pid(&mut self) -> Result<u32> {
+        let pid = self.required("PID")?;
+        pid.parse()
+            .map_err(|_| Error::Usage(format!("Invalid PID {:?}", pid)))
+    }
+
+    /// Returns the source given with `--from-core`, `--from-dump` or `--kernel`, or the PID argument
+    fn target(&mut self, input: Option<Target>) -> Result<Target> {
+        match input {
+            Some(target) => Ok(target),
+            None => Ok(Target::Process(self.pid()?)),
+        }
+    }
+
+    fn finish(mut self) -> Result<()> {
+        match self.0.next() {
+            Some(extra) => Err(Error::Usage(format!("Unexpected argument {:?}", extra))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parses `deadbeef` or `de ad be ef`
+pub fn parse_hex_bytes(s: &str) -> Result<Vec<u8>> {
+    parse_hex_pattern(s)?
+        .into_iter()
+        .collect::<Option<_>>()
+        .ok_or_else(|| Error::Usage(format!("Invalid hex string {:?}", s)))
+}
+
+/// Parses hex bytes as [`parse_hex_bytes`] does, `??` standing for any byte
+pub fn parse_hex_pattern(s: &str) -> Result<Vec<Option<u8>>> {
+    let digits: Vec<u8> = s.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return Err(Error::Usage(format!("Invalid hex string {:?}", s)));
+    }
+    digits
+        .chunks(2)
+        .map(|pair| match pair {
+            b"??" => Ok(None),
+            _ => std::str::from_utf8(pair)
+                .ok()
+                .and_then(|p| u8::from_str_radix(p, 16).ok())
+                .map(Some)
+                .ok_or_else(|| Error::Usage(format!("Invalid hex string {:?}", s))),
+        })
+        .collect()
+}
+
+/// Parses a hexadecimal address, with or without a `0x` prefix
+pub fn parse_address(s: &str) -> Result<usize> {
+    let digits = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    usize::from_str_radix(digits, 16).map_err(|_| Error::Usage(format!("Invalid address {:?}", s)))
+}
+
+/// Parses a size in bytes, with an optional binary `K`, `M` or `G` suffix
+pub fn parse_size(s: &str) -> Result<u64> {
+    let (digits, shift) = match s.as_bytes().last().map(u8::to_ascii_uppercase) {
+        Some(b'K') => (&s[..s.len() - 1], 10),
+        Some(b'M') => (&s[..s.len() - 1], 20),
+        Some(b'G') => (&s[..s.len() - 1], 30),
+        _ => (s, 0),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .filter(|&n| n > 0)
+        .ok_or_else(|| Error::Usage(format!("Invalid size {:?}", s)))
+}
+
+/// Parses `START..END` or `START..+LENGTH`, with hexadecimal numbers
+pub fn parse_range(s: &str) -> Result<Range<usize>> {
+    let (start, end) = s
+        .split_once("..")
+        .ok_or_else(|| Error::Usage(format!("Invalid range {:?}", s)))?;
+    let start = parse_address(start)?;
+    let end = match end.strip_prefix('+') {
+        Some(length) => start
+            .checked_add(parse_address(length)?)
+            .ok_or_else(|| Error::Usage(format!("Invalid range {:?}", s)))?,
+        None => parse_address(end)?,
+    };
+    let range = start..end;
+    if range.is_empty() {
+        return Err(Error::Usage(format!("Empty range {:?}", s)));
+    }
+    Ok(range)
+}
+
+/// Parses the value of `--interval`, in seconds
+fn parse_interval(args: &mut Args) -> Result<Duration> {
+    let secs: f64 = args.parse_value("--interval")?;
+    Duration::try_from_secs_f64(secs)
+        .map_err(|_| Error::Usage(format!("Invalid interval {}", secs)))
+}
+
+/// Whether `arg` is `-F`, `-FF`, ...
+fn is_repeated_flag(arg: &str, flag: u8) -> bool {
+    arg.strip_prefix('-')
+        .is_some_and(|f| !f.is_empty() && f.bytes().all(|b| b == flag))
+}
+
+/// Logging options, accepted anywhere on the command line
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct LogOptions {
+    /// Number of `-v` minus the number of `-q`
+    pub verbosity: i8,
+    pub format: LogFormat,
+}
+
+impl LogOptions {
+    /// Removes the logging options from `args`, returning them with the other arguments
+    pub fn extract(args: impl IntoIterator<Item = String>) -> Result<(Self, Vec<String>)> {
+        let mut options = Self::default();
+        let mut rest = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--verbose" => options.verbosity += 1,
+                "--quiet" => options.verbosity -= 1,
+                "--log-format" => {
+                    let format = args
+                        .next()
+                        .ok_or_else(|| Error::Usage("Missing value for --log-format".into()))?;
+                    options.format = format.parse()?;
+                }
+                _ if arg.starts_with("--log-format=") => {
+                    options.format = arg["--log-format=".len()..].parse()?;
+                }
+                _ if is_repeated_flag(&arg, b'v') => options.verbosity += (arg.len() - 1) as i8,
+                _ if is_repeated_flag(&arg, b'q') => options.verbosity -= (arg.len() - 1) as i8,
+                _ => rest.push(arg),
+            }
+        }
+        Ok((options, rest))
+    }
+}
+
+/// A parsed command line
+#[derive(Debug)]
+pub enum Command {
+    Dump(DumpOptions),
+    Search(SearchOptions),
+    Scan(ScanOptions),
+    Write(WriteOptions),
+    Diff(DiffOptions),
+    Info(InfoOptions),
+    Watch(WatchOptions),
+    Hexdump(HexdumpOptions),
+    Read(ReadOptions),
+    Restore(RestoreOptions),
+    Maps(MapsOptions),
+    Where(WhereOptions),
+    Summary(SummaryOptions),
+    Modules(ModulesOptions),
+    Carve(CarveOptions),
+    Verify(VerifyOptions),
+    Serve(ServeOptions),
+    Repl(ReplOptions),
+    Selftest(SelftestOptions),
+    #[cfg(feature = "fuse")]
+    Mount(MountOptions),
+    #[cfg(feature = "yara")]
+    ScanYara(ScanYaraOptions),
+    #[cfg(feature = "secrets")]
+    ScanSecrets(ScanSecretsOptions),
+}
+
+impl Command {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = args.into_iter().peekable();
+        match args.peek().map(String::as_str) {
+            Some("dump") => {
+                args.next();
+                Ok(Self::Dump(DumpOptions::parse(args)?))
+            }
+            Some("search") => {
+                args.next();
+                Ok(Self::Search(SearchOptions::parse(args)?))
+            }
+            Some("scan") => {
+                args.next();
+                Ok(Self::Scan(ScanOptions::parse(args)?))
+            }
+            Some("write") => {
+                args.next();
+                Ok(Self::Write(WriteOptions::parse(args)?))
+            }
+            Some("diff") => {
+                args.next();
+                Ok(Self::Diff(DiffOptions::parse(args)?))
+            }
+            Some("info") => {
+                args.next();
+                Ok(Self::Info(InfoOptions::parse(args)?))
+            }
+            Some("watch") => {
+                args.next();
+                Ok(Self::Watch(WatchOptions::parse(args)?))
+            }
+            Some("hexdump") => {
+                args.next();
+                Ok(Self::Hexdump(HexdumpOptions::parse(args)?))
+            }
+            Some("read") => {
+                args.next();
+                Ok(Self::Read(ReadOptions::parse(args)?))
+            }
+            Some("restore") => {
+                args.next();
+                Ok(Self::Restore(RestoreOptions::parse(args)?))
+            }
+            Some("maps") => {
+                args.next();
+                Ok(Self::Maps(MapsOptions::parse(args)?))
+            }
+            Some("where") => {
+                args.next();
+                Ok(Self::Where(WhereOptions::parse(args)?))
+            }
+            Some("summary") => {
+                args.next();
+                Ok(Self::Summary(SummaryOptions::parse(args)?))
+            }
+            Some("modules") => {
+                args.next();
+                Ok(Self::Modules(ModulesOptions::parse(args)?))
+            }
+            Some("carve") => {
+                args.next();
+                Ok(Self::Carve(CarveOptions::parse(args)?))
+            }
+            Some("verify") => {
+                args.next();
+                Ok(Self::Verify(VerifyOptions::parse(args)?))
+            }
+            Some("serve") => {
+                args.next();
+                Ok(Self::Serve(ServeOptions::parse(args)?))
+            }
+            Some("repl") => {
+                args.next();
+                Ok(Self::Repl(ReplOptions::parse(args)?))
+            }
+            Some("selftest") => {
+                args.next();
+                Ok(Self::Selftest(SelftestOptions::parse(args)?))
+            }
+            #[cfg(feature = "fuse")]
+            Some("mount") => {
+                args.next();
+                Ok(Self::Mount(MountOptions::parse(args)?))
+            }
+            #[cfg(not(feature = "fuse"))]
+            Some("mount") => Err(Error::Usage(
+                "mount needs dump-memory to be built with the `fuse` feature".into(),
+            )),
+            #[cfg(feature = "yara")]
+            Some("scan-yara") => {
+                args.next();
+                Ok(Self::ScanYara(ScanYaraOptions::parse(args)?))
+            }
+            #[cfg(not(feature = "yara"))]
+            Some("scan-yara") => Err(Error::Usage(
+                "scan-yara needs dump-memory to be built with the `yara` feature".into(),
+            )),
+            #[cfg(feature = "secrets")]
+            Some("scan-secrets") => {
+                args.next();
+                Ok(Self::ScanSecrets(ScanSecretsOptions::parse(args)?))
+            }
+            #[cfg(not(feature = "secrets"))]
+            Some("scan-secrets") => Err(Error::Usage(
+                "scan-secrets needs dump-memory to be built with the `secrets` feature".into(),
+            )),
+            _ => Ok(Self::Dump(DumpOptions::parse(args)?)),
+        }
+    }
+}
+
+/// Options of the dump command
+#[derive(Debug)]
+pub struct DumpOptions {
+    pub target: Target,
+    pub output: Option<PathBuf>,
+    pub format: Format,
+    pub compression: Compression,
+    pub jobs: usize,
+    pub sha256: bool,
+    pub entropy: bool,
+    pub stacks: bool,
+    pub targets: Targets,
+    pub attach: Attach,
+    pub minimize_pause: bool,
+    /// Whether the processes dumped stay traced while they run, to dump their new children too
+    pub follow_forks: bool,
+    /// Longest time the process may be stopped for, over all the attachments of a dump
+    pub max_pause: Option<Duration>,
+    pub strict: bool,
+    pub redump_changed: bool,
+    pub peek_fallback: bool,
+    pub read_timeout: Option<Duration>,
+    pub backend: Backend,
+    /// Compression of the memory sent by the agent, with `--remote`
+    pub remote_compression: Compression,
+    /// Whether the named events are written as JSON lines, to `events_output` or the standard
+    /// output
+    pub events: bool,
+    pub events_output: Option<PathBuf>,
+    /// Regions excluded from the dump, recorded as skipped
+    pub exclude_paths: Vec<Pattern>,
+    pub default_excludes: bool,
+    pub max_region_size: Option<u64>,
+    pub dry_run: bool,
+    pub with_proc: bool,
+    pub with_files: bool,
+    pub with_fds: bool,
+    pub backtraces: bool,
+    pub interval: Option<Duration>,
+    pub count: Option<u64>,
+    /// Template of the name of the output, when none is given
+    pub name: Option<String>,
+    pub filter: RegionFilter,
+}
+
+impl DumpOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = Args::new(args);
+        let mut positional = Vec::new();
+        let mut input = None;
+        let mut filter = RegionFilter::new();
+        let mut format = Format::default();
+        let mut compression = Compression::default();
+        let mut jobs = 1;
+        let mut sha256 = false;
+        let mut entropy = false;
+        let mut stacks = false;
+        let mut targets = Targets::default();
+        let mut attach = Attach::default();
+        let mut minimize_pause = false;
+        let mut follow_forks = false;
+        let mut max_pause = None;
+        let mut strict = false;
+        let mut redump_changed = false;
+        let mut peek_fallback = true;
+        let mut read_timeout = None;
+        let mut backend = Backend::default();
+        let mut remote = None;
+        let mut remote_compression = Compression::None;
+        let mut events = false;
+        let mut events_output = None;
+        let mut exclude_paths = Vec::new();
+        let mut default_excludes = true;
+        let mut max_region_size = None;
+        let mut dry_run = false;
+        let mut with_proc = false;
+        let mut with_files = false;
+        let mut with_fds = false;
+        let mut backtraces = false;
+        let mut interval = None;
+        let mut count = None;
+        let mut name = None;
+
+        while let Some(arg) = args.next_arg()? {
+            match arg.as_str() {
+                "--profile" => {
+                    let name = args.value("--profile")?;
+                    let mut after = args.rest();
+                    let profile = config::profile(&name, &mut after)?;
+                    args.insert(profile.into_iter().chain(after).collect());
+                }
+                "--name" => name = Some(args.value("--name")?),
+                "--format" | "--output-format" => format = args.parse_value(&arg)?,
+                "--compress" => compression = args.parse_value("--compress")?,
+                "--sha256" => sha256 = true,
+                "--entropy" => entropy = true,
+                "--jobs" => jobs = args.parse_value("--jobs")?,
+                "--stacks" => stacks = true,
+                "--children" => targets = Targets::Children,
+                "--tree" => targets = Targets::Tree,
+                "--seize" => attach = Attach::Seize,
+                "--no-stop" => attach = Attach::NoStop,
+                "--freeze" => match args.value("--freeze")?.as_str() {
+                    "cgroup" => attach = Attach::Freeze,
+                    method => {
+                        return Err(Error::Usage(format!(
+                            "Unknown freeze method {:?}, only `cgroup` is supported",
+                            method
+                        )))
+                    }
+                },
+                "--minimize-pause" => minimize_pause = true,
+                "--follow-forks" => follow_forks = true,
+                "--max-pause" => {
+                    let secs: f64 = args.parse_value("--max-pause")?;
+                    max_pause = Some(
+                        Duration::try_from_secs_f64(secs)
+                            .ok()
+                            .filter(|t| !t.is_zero())
+                            .ok_or_else(|| Error::Usage(format!("Invalid pause time {}", secs)))?,
+                    );
+                }
+                "--strict" => strict = true,
+                "--redump-changed" => redump_changed = true,
+                "--no-peek" => peek_fallback = false,
+                "--read-timeout" => {
+                    let secs: f64 = args.parse_value("--read-timeout")?;
+                    read_timeout = Some(
+                        Duration::try_from_secs_f64(secs)
+                            .ok()
+                            .filter(|t| !t.is_zero())
+                            .ok_or_else(|| {
+                                Error::Usage(format!("Invalid read timeout {}", secs))
+                            })?,
+                    );
+                }
+                "--backend" => backend = args.parse_value("--backend")?,
+                "--events" => match args.value("--events")?.as_str() {
+                    "ndjson" => events = true,
+                    format => {
+                        return Err(Error::Usage(format!(
+                            "Unknown event format {:?}, only `ndjson` is supported",
+                            format
+                        )))
+                    }
+                },
+                "--events-output" => events_output = Some(args.value("--events-output")?.into()),
+                "--exclude-path" => exclude_paths.push(args.parse_value("--exclude-path")?),
+                "--max-region-size" => {
+                    max_region_size = Some(parse_size(&args.value("--max-region-size")?)?)
+                }
+                "--no-default-excludes" => default_excludes = false,
+                "--dry-run" => dry_run = true,
+                "--with-proc" => with_proc = true,
+                "--with-files" => with_files = true,
+                "--with-fds" => with_fds = true,
+                "--backtraces" => backtraces = true,
+                "--interval" => interval = Some(parse_interval(&mut args)?),
+                "--count" => count = Some(args.parse_value("--count")?),
+                "--from-core" => input = Some(Target::Core(args.value("--from-core")?.into())),
+                "--kernel" => input = Some(Target::Kernel),
+                "--remote" => remote = Some(args.value("--remote")?),
+                "--remote-compress" => {
+                    remote_compression = args.parse_value("--remote-compress")?
+                }
+                "--cgroup" => {
+                    let path = args.value("--cgroup")?.into();
+                    input = Some(Target::Cgroup(Cgroup::Path(path)));
+                }
+                "--container" => {
+                    let id = args.value("--container")?;
+                    input = Some(Target::Cgroup(Cgroup::Container(id)));
+                }
+                "-h" | "--help" => print_help(),
+                _ if parse_filter_option(&arg, &mut args, &mut filter)? => {}
+                _ if is_option(&arg) => return Err(unknown_option(&arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = Positional(positional.into_iter());
+        let target = match (positional.target(input)?, remote) {
+            (Target::Process(pid), Some(address)) => Target::Remote(address, pid),
+            (_, Some(_)) => {
+                return Err(Error::Usage(
+                    "--remote needs a PID, and conflicts with --from-core, --kernel, --cgroup \
+                     and --container"
+                        .into(),
+                ))
+            }
+            (target, None) => target,
+        };
+        let output = positional.optional().map(PathBuf::from);
+        positional.finish()?;
+        if compression != Compression::None
+            && matches!(format, Format::Core | Format::Dedup | Format::Minidump)
+        {
+            return Err(Error::Usage(format!(
+                "Compression is not supported with {} format",
+                format
+            )));
+        }
+        // Core files and minidumps only hold the regions and the registers
+        let single_file = matches!(format, Format::Core | Format::Minidump);
+        if with_proc && single_file {
+            return Err(Error::Usage(format!(
+                "--with-proc is not supported with {} format, which has no room for files",
+                format
+            )));
+        }
+        if with_files && single_file {
+            return Err(Error::Usage(format!(
+                "--with-files is not supported with {} format, which has no room for files",
+                format
+            )));
+        }
+        if with_fds && single_file {
+            return Err(Error::Usage(format!(
+                "--with-fds is not supported with {} format, which has no room for files",
+                format
+            )));
+        }
+        if backtraces && single_file {
+            return Err(Error::Usage(format!(
+                "--backtraces is not supported with {} format, which has the registers already",
+                format
+            )));
+        }
+        if jobs == 0 {
+            return Err(Error::Usage("--jobs must not be 0".into()));
+        }
+        if jobs > 1 && format != Format::Directory {
+            return Err(Error::Usage(format!(
+                "--jobs is not supported with {} format",
+                format
+            )));
+        }
+        #[cfg(feature = "uring")]
+        if backend == Backend::Uring {
+            // Reads submitted to io_uring cannot be given up, and are already done in parallel
+            if read_timeout.is_some() {
+                return Err(Error::Usage(
+                    "--backend uring conflicts with --read-timeout".into(),
+                ));
+            }
+            if jobs > 1 {
+                return Err(Error::Usage("--backend uring conflicts with --jobs".into()));
+            }
+        }
+        if redump_changed && matches!(format, Format::Core | Format::Tar | Format::Minidump) {
+            return Err(Error::Usage(format!(
+                "--redump-changed is not supported with {} format, whose regions cannot be \
+                 replaced",
+                format
+            )));
+        }
+        // Registers are only read from attached threads
+        let unattached = match attach {
+            Attach::NoStop => Some("--no-stop"),
+            Attach::Freeze => Some("--freeze"),
+            Attach::Stop | Attach::Seize => None,
+        };
+        if let (true, Some(option)) = (stacks, unattached) {
+            return Err(Error::Usage(format!(
+                "--stacks needs the registers and conflicts with {}",
+                option
+            )));
+        }
+        if let (true, Some(option)) = (backtraces, unattached) {
+            return Err(Error::Usage(format!(
+                "--backtraces needs the registers and conflicts with {}",
+                option
+            )));
+        }
+        if let (true, Some(option)) = (follow_forks, unattached) {
+            return Err(Error::Usage(format!(
+                "--follow-forks needs to trace the process and conflicts with {}",
+                option
+            )));
+        }
+        if minimize_pause && attach == Attach::NoStop {
+            return Err(Error::Usage(
+                "--minimize-pause conflicts with --no-stop, which never stops the process".into(),
+            ));
+        }
+        if max_pause.is_some() && attach == Attach::NoStop {
+            return Err(Error::Usage(
+                "--max-pause conflicts with --no-stop, which never stops the process".into(),
+            ));
+        }
+        if minimize_pause && jobs > 1 {
+            return Err(Error::Usage(
+                "--jobs needs the process to stay stopped and conflicts with --minimize-pause"
+                    .into(),
+            ));
+        }
+        if matches!(target, Target::Cgroup(_)) && targets != Targets::Process {
+            return Err(Error::Usage(
+                "--children and --tree conflict with --cgroup and --container, which dump all \
+                 the processes of the cgroup"
+                    .into(),
+            ));
+        }
+        let source = match target {
+            Target::Core(_) => Some("--from-core"),
+            Target::Kernel => Some("--kernel"),
+            Target::Remote(..) => Some("--remote"),
+            Target::Process(_) | Target::Dump(_) | Target::Cgroup(_) => None,
+        };
+        if remote_compression != Compression::None && source != Some("--remote") {
+            return Err(Error::Usage("--remote-compress needs --remote".into()));
+        }
+        if let Some(source) = source {
+            // Only what is saved in the core file, or sent by the agent, is read, without a
+            // process to attach to here
+            let threads = matches!(target, Target::Core(_));
+            let conflicts = [
+                (format == Format::Core, "--format core"),
+                (format == Format::Minidump, "--format minidump"),
+                (targets == Targets::Children, "--children"),
+                (targets == Targets::Tree, "--tree"),
+                (attach == Attach::Seize, "--seize"),
+                (attach == Attach::NoStop, "--no-stop"),
+                (attach == Attach::Freeze, "--freeze"),
+                (minimize_pause, "--minimize-pause"),
+                (follow_forks, "--follow-forks"),
+                (max_pause.is_some(), "--max-pause"),
+                (jobs > 1, "--jobs"),
+                (interval.is_some(), "--interval"),
+                (with_proc, "--with-proc"),
+                (with_files, "--with-files"),
+                (with_fds, "--with-fds"),
+                (redump_changed, "--redump-changed"),
+                (!peek_fallback, "--no-peek"),
+                (read_timeout.is_some(), "--read-timeout"),
+                (backend != Backend::Read, "--backend"),
+                (dry_run, "--dry-run"),
+                // The kernel has no threads, and agents do not send their registers
+                (!threads && stacks, "--stacks"),
+                (!threads && backtraces, "--backtraces"),
+            ];
+            if let Some((_, option)) = conflicts.iter().find(|(conflict, _)| *conflict) {
+                return Err(Error::Usage(format!(
+                    "{} is not supported with {}",
+                    option, source
+                )));
+            }
+        }
+        if events_output.is_some() && !events {
+            return Err(Error::Usage("--events-output needs --events".into()));
+        }
+        if count.is_some() && interval.is_none() {
+            return Err(Error::Usage("--count needs --interval".into()));
+        }
+        if count == Some(0) {
+            return Err(Error::Usage("--count must not be 0".into()));
+        }
+
+        Ok(Self {
+            target,
+            output,
+            format,
+            compression,
+            jobs,
+            sha256,
+            entropy,
+            stacks,
+            targets,
+            attach,
+            minimize_pause,
+            follow_forks,
+            max_pause,
+            strict,
+            redump_changed,
+            peek_fallback,
+            read_timeout,
+            backend,
+            remote_compression,
+            events,
+            events_output,
+            exclude_paths,
+            default_excludes,
+            max_region_size,
+            dry_run,
+            with_proc,
+            with_files,
+            with_fds,
+            backtraces,
+            interval,
+            count,
+            name,
+            filter,
+        })
+    }
+}
+
+/// Options of the search command
+#[derive(Debug)]
+pub struct SearchOptions {
+    pub target: Target,
+    pub patterns: Vec<BytePattern>,
+    pub ignore_case: bool,
+
+    /// Bytes printed before and after each hit
+    pub context: usize,
+    pub filter: RegionFilter,
+}
+
+impl SearchOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = Args::new(args);
+        let mut positional = Vec::new();
+        let mut input = None;
+        let mut filter = RegionFilter::new();
+        let mut ignore_case = false;
+        let mut hex = false;
+        let mut file = None;
+        let mut context = 0;
+
+        while let Some(arg) = args.next_arg()? {
+            match arg.as_str() {
+                "-i" | "--ignore-case" => ignore_case = true,
+                "-x" | "--hex" => hex = true,
+                "-f" | "--file" => file = Some(args.value(&arg)?),
+                "-C" | "--context" => context = args.parse_value(&arg)?,
+                "--from-core" => input = Some(Target::Core(args.value("--from-core")?.into())),
+                "--kernel" => input = Some(Target::Kernel),
+                "--from-dump" => input = Some(Target::Dump(args.value("--from-dump")?.into())),
+                "-h" | "--help" => print_help(),
+                _ if parse_filter_option(&arg, &mut args, &mut filter)? => {}
+                _ if is_option(&arg) => return Err(unknown_option(&arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = Positional(positional.into_iter());
+        let target = positional.target(input)?;
+        let texts = match file {
+            Some(path) => std::fs::read_to_string(&path)
+                .map_err(|e| Error::Usage(format!("Cannot read {}: {}", path, e)))?
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(String::from)
+                .collect(),
+            None => vec![positional.required("PATTERN")?],
+        };
+        positional.finish()?;
+        let patterns = texts
+            .iter()
+            .map(|text| BytePattern::parse(text, hex))
+            .collect::<Result<Vec<_>>>()?;
+        if patterns.is_empty() {
+            return Err(Error::Usage("No pattern to search for".into()));
+        }
+
+        Ok(Self {
+            target,
+            patterns,
+            ignore_case,
+            context,
+            filter,
+        })
+    }
+}
+
+/// Options of the scan command
+#[derive(Debug)]
+pub struct ScanOptions {
+    pub target: Target,
+    pub value_type: Option<ValueType>,
+    pub endian: Endian,
+    pub align: Option<usize>,
+    pub refine: Option<Refine>,
+    pub from: Option<PathBuf>,
+    pub save: Option<PathBuf>,
+    pub filter: RegionFilter,
+}
+
+impl ScanOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = Args::new(args);
+        let mut positional = Vec::new();
+        let mut input = None;
+        let mut filter = RegionFilter::new();
+        let mut value_type = None;
+        let mut refine = None;
+        let mut endian = Endian::default();
+        let mut align = None;
+        let mut from = None;
+        let mut save = None;
+
+        while let Some(arg) = args.next_arg()? {
+            let condition = match arg.as_str() {
+                "--type" => {
+                    value_type = Some(args.parse_value("--type")?);
+                    None
+                }
+                "--value" => Some(Refine::Value(args.value("--value")?)),
+                "--changed" => Some(Refine::Changed),
+                "--unchanged" => Some(Refine::Unchanged),
+                "--increased" => Some(Refine::Increased),
+                "--decreased" => Some(Refine::Decreased),
+                "--big-endian" => {
+                    endian = Endian::Big;
+                    None
+                }
+                "--align" => {
+                    align = Some(args.parse_value("--align")?);
+                    None
+                }
+                "--from" => {
+                    from = Some(PathBuf::from(args.value("--from")?));
+                    None
+                }
+                "--save" => {
+                    save = Some(PathBuf::from(args.value("--save")?));
+                    None
+                }
+                "--from-core" => {
+                    input = Some(Target::Core(args.value("--from-core")?.into()));
+                    None
+                }
+                "--kernel" => {
+                    input = Some(Target::Kernel);
+                    None
+                }
+                "--from-dump" => {
+                    input = Some(Target::Dump(args.value("--from-dump")?.into()));
+                    None
+                }
+                "-h" | "--help" => print_help(),
+                _ if parse_filter_option(&arg, &mut args, &mut filter)? => None,
+                _ if is_option(&arg) => return Err(unknown_option(&arg)),
+                _ => {
+                    positional.push(arg);
+                    None
+                }
+            };
+            if let Some(condition) = condition {
+                if refine.replace(condition).is_some() {
+                    return Err(Error::Usage("Only one scan condition can be given".into()));
+                }
+            }
+        }
+
+        let mut positional = Positional(positional.into_iter());
+        let target = positional.target(input)?;
+        positional.finish()?;
+        if align == Some(0) {
+            return Err(Error::Usage("Alignment must not be 0".into()));
+        }
+        match (&from, &refine) {
+            (None, Some(Refine::Value(_))) if value_type.is_some() => {}
+            (None, _) => return Err(Error::Usage("A first scan needs --type and --value".into())),
+            (Some(_), None) => return Err(Error::Usage("Missing scan condition".into())),
+            (Some(_), Some(_)) if value_type.is_some() => {
+                return Err(Error::Usage(
+                    "The value type is taken from the --from state".into(),
+                ))
+            }
+            (Some(_), Some(_)) => {}
+        }
+
+        Ok(Self {
+            target,
+            value_type,
+            endian,
+            align,
+            refine,
+            from,
+            save,
+            filter,
+        })
+    }
+}
+
+/// Options of the write command
+#[derive(Debug)]
+pub struct WriteOptions {
+    pub pid: u32,
+    pub address: usize,
+    pub data: Vec<u8>,
+    pub force: bool,
+}
+
+impl WriteOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = Args::new(args);
+        let mut positional = Vec::new();
+        let mut hex = false;
+        let mut value_type: Option<ValueType> = None;
+        let mut endian = Endian::default();
+        let mut force = false;
+
+        while let Some(arg) = args.next_arg()? {
+            match arg.as_str() {
+                "-x" | "--hex" => hex = true,
+                "--type" => value_type = Some(args.parse_value("--type")?),
+                "--big-endian" => endian = Endian::Big,
+                "--force" => force = true,
+                "-h" | "--help" => print_help(),
+                _ if is_option(&arg) => return Err(unknown_option(&arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = Positional(positional.into_iter());
+        let pid = positional.pid()?;
+        let address = parse_address(&positional.required("ADDRESS")?)?;
+        let data = positional.required("DATA")?;
+        positional.finish()?;
+        let data = match value_type {
+            Some(_) if hex => return Err(Error::Usage("--hex conflicts with --type".into())),
+            Some(value_type) => value_type.encode(&data, endian)?,
+            None if hex => parse_hex_bytes(&data)?,
+            None => data.into_bytes(),
+        };
+        if data.is_empty() {
+            return Err(Error::Usage("Empty DATA".into()));
+        }
+
+        Ok(Self {
+            pid,
+            address,
+            data,
+            force,
+        })
+    }
+}
+
+/// Options of the diff command
+#[derive(Debug)]
+pub struct DiffOptions {
+    pub old: PathBuf,
+    pub new: PathBuf,
+    pub pages: bool,
+    pub hex: bool,
+}
+
+impl DiffOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = Args::new(args);
+        let mut positional = Vec::new();
+        let mut pages = false;
+        let mut hex = false;
+
+        while let Some(arg) = args.next_arg()? {
+            match arg.as_str() {
+                "--pages" => pages = true,
+                "--hex" => hex = true,
+                "-h" | "--help" => print_help(),
+                _ if is_option(&arg) => return Err(unknown_option(&arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = Positional(positional.into_iter());
+        let old = PathBuf::from(positional.required("OLD")?);
+        let new = PathBuf::from(positional.required("NEW")?);
+        positional.finish()?;
+        if pages && hex {
+            return Err(Error::Usage("--pages conflicts with --hex".into()));
+        }
+
+        Ok(Self {
+            old,
+            new,
+            pages,
+            hex,
+        })
+    }
+}
+
+/// Options of the info command
+#[derive(Debug)]
+pub struct InfoOptions {
+    pub dump: PathBuf,
+}
+
+impl InfoOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = Args::new(args);
+        let mut positional = Vec::new();
+
+        while let Some(arg) = args.next_arg()? {
+            match arg.as_str() {
+                "-h" | "--help" => print_help(),
+                _ if is_option(&arg) => return Err(unknown_option(&arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = Positional(positional.into_iter());
+        let dump = PathBuf::from(positional.required("DUMP")?);
+        positional.finish()?;
+
+        Ok(Self { dump })
+    }
+}
+
+/// Options of the watch command
+#[derive(Debug)]
+pub struct WatchOptions {
+    pub pid: u32,
+    pub range: Range<usize>,
+    pub interval: Duration,
+    pub count: Option<u64>,
+}
+
+impl WatchOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = Args::new(args);
+        let mut positional = Vec::new();
+        let mut interval = Duration::from_secs(1);
+        let mut count = None;
+
+        while let Some(arg) = args.next_arg()? {
+            match arg.as_str() {
+                "--interval" => interval = parse_interval(&mut args)?,
+                "--count" => count = Some(args.parse_value("--count")?),
+                "-h" | "--help" => print_help(),
+                _ if is_option(&arg) => return Err(unknown_option(&arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = Positional(positional.into_iter());
+        let pid = positional.pid()?;
+        let range = parse_range(&positional.required("START..END")?)?;
+        positional.finish()?;
+
+        Ok(Self {
+            pid,
+            range,
+            interval,
+            count,
+        })
+    }
+}
+
+/// Options of the hexdump command
+#[derive(Debug)]
+pub struct HexdumpOptions {
+    pub target: Target,
+    pub range: Range<usize>,
+}
+
+impl HexdumpOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = Args::new(args);
+        let mut positional = Vec::new();
+        let mut input = None;
+
+        while let Some(arg) = args.next_arg()? {
+            match arg.as_str() {
+                "--from-core" => input = Some(Target::Core(args.value("--from-core")?.into())),
+                "--kernel" => input = Some(Target::Kernel),
+                "--from-dump" => input = Some(Target::Dump(args.value("--from-dump")?.into())),
+                "-h" | "--help" => print_help(),
+                _ if is_option(&arg) => return Err(unknown_option(&arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = Positional(positional.into_iter());
+        let target = positional.target(input)?;
+        let range = parse_range(&positional.required("START..END")?)?;
+        positional.finish()?;
+
+        Ok(Self { target, range })
+    }
+}
+
+/// Options of the read command
+#[derive(Debug)]
+pub struct ReadOptions {
+    pub target: Target,
+    pub range: Range<usize>,
+    pub output: Option<PathBuf>,
+    pub force: bool,
+}
+
+impl ReadOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = Args::new(args);
+        let mut positional = Vec::new();
+        let mut input = None;
+        let mut output = None;
+        let mut force = false;
+
+        while let Some(arg) = args.next_arg()? {
+            match arg.as_str() {
+                "-o" | "--output" => output = Some(args.value(&arg)?.into()),
+                "--force" => force = true,
+                "--from-core" => input = Some(Target::Core(args.value("--from-core")?.into())),
+                "--kernel" => input = Some(Target::Kernel),
+                "--from-dump" => input = Some(Target::Dump(args.value("--from-dump")?.into())),
+                "-h" | "--help" => print_help(),
+                _ if is_option(&arg) => return Err(unknown_option(&arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = Positional(positional.into_iter());
+        let target = positional.target(input)?;
+        let address = parse_address(&positional.required("ADDRESS")?)?;
+        let length = positional.required("LENGTH")?;
+        positional.finish()?;
+        let length = match length.strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16).ok(),
+            None => length.parse().ok(),
+        }
+        .filter(|&length| length > 0)
+        .ok_or_else(|| Error::Usage(format!("Invalid length {:?}", length)))?;
+        let end = address
+            .checked_add(length)
+            .ok_or_else(|| Error::Usage("Range overflows the address space".into()))?;
+
+        Ok(Self {
+            target,
+            range: address..end,
+            output,
+            force,
+        })
+    }
+}
+
+/// Options of the restore command
+#[derive(Debug)]
+pub struct RestoreOptions {
+    pub pid: u32,
+    pub dump: PathBuf,
+    pub force: bool,
+    pub filter: RegionFilter,
+}
+
+impl RestoreOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = Args::new(args);
+        let mut positional = Vec::new();
+        let mut filter = RegionFilter::new();
+        let mut force = false;
+
+        while let Some(arg) = args.next_arg()? {
+            match arg.as_str() {
+                "--force" => force = true,
+                "-h" | "--help" => print_help(),
+                _ if parse_filter_option(&arg, &mut args, &mut filter)? => {}
+                _ if is_option(&arg) => return Err(unknown_option(&arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = Positional(positional.into_iter());
+        let pid = positional.pid()?;
+        let dump = PathBuf::from(positional.required("DUMP")?);
+        positional.finish()?;
+
+        Ok(Self {
+            pid,
+            dump,
+            force,
+            filter,
+        })
+    }
+}
+
+/// Options of the maps command
+#[derive(Debug)]
+pub struct MapsOptions {
+    pub target: Target,
+    pub format: ListFormat,
+    pub filter: RegionFilter,
+}
+
+impl MapsOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = Args::new(args);
+        let mut positional = Vec::new();
+        let mut input = None;
+        let mut filter = RegionFilter::new();
+        let mut format = ListFormat::default();
+
+        while let Some(arg) = args.next_arg()? {
+            match arg.as_str() {
+                "--format" => format = args.parse_value("--format")?,
+                "--from-core" => input = Some(Target::Core(args.value("--from-core")?.into())),
+                "--kernel" => input = Some(Target::Kernel),
+                "--from-dump" => input = Some(Target::Dump(args.value("--from-dump")?.into())),
+                "-h" | "--help" => print_help(),
+                _ if parse_filter_option(&arg, &mut args, &mut filter)? => {}
+                _ if is_option(&arg) => return Err(unknown_option(&arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = Positional(positional.into_iter());
+        let target = positional.target(input)?;
+        positional.finish()?;
+
+        Ok(Self {
+            target,
+            format,
+            filter,
+        })
+    }
+}
+
+/// Options of the where command
+#[derive(Debug)]
+pub struct WhereOptions {
+    pub target: Target,
+    pub address: usize,
+}
+
+impl WhereOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = Args::new(args);
+        let mut positional = Vec::new();
+        let mut input = None;
+
+        while let Some(arg) = args.next_arg()? {
+            match arg.as_str() {
+                "--from-core" => input = Some(Target::Core(args.value("--from-core")?.into())),
+                "--kernel" => input = Some(Target::Kernel),
+                "--from-dump" => input = Some(Target::Dump(args.value("--from-dump")?.into())),
+                "-h" | "--help" => print_help(),
+                _ if is_option(&arg) => return Err(unknown_option(&arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = Positional(positional.into_iter());
+        let target = positional.target(input)?;
+        let address = parse_address(&positional.required("ADDRESS")?)?;
+        positional.finish()?;
+
+        Ok(Self { target, address })
+    }
+}
+
+/// Options of the summary command
+#[derive(Debug)]
+pub struct SummaryOptions {
+    pub pid: u32,
+    pub format: ListFormat,
+    pub top: usize,
+    pub filter: RegionFilter,
+}
+
+impl SummaryOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = Args::new(args);
+        let mut positional = Vec::new();
+        let mut format = ListFormat::default();
+        let mut top = 10;
+        let mut filter = RegionFilter::new();
+
+        while let Some(arg) = args.next_arg()? {
+            match arg.as_str() {
+                "--format" => format = args.parse_value("--format")?,
+                "--top" => top = args.parse_value("--top")?,
+                "-h" | "--help" => print_help(),
+                _ if parse_filter_option(&arg, &mut args, &mut filter)? => {}
+                _ if is_option(&arg) => return Err(unknown_option(&arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = Positional(positional.into_iter());
+        let pid = positional.pid()?;
+        positional.finish()?;
+
+        if format == ListFormat::Csv {
+            return Err(Error::Usage(
+                "The summary is printed as a table or as JSON, not as CSV".into(),
+            ));
+        }
+
+        Ok(Self {
+            pid,
+            format,
+            top,
+            filter,
+        })
+    }
+}
+
+/// Options of the modules command
+#[derive(Debug)]
+pub struct ModulesOptions {
+    pub pid: u32,
+    pub format: ListFormat,
+}
+
+impl ModulesOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = Args::new(args);
+        let mut positional = Vec::new();
+        let mut format = ListFormat::default();
+
+        while let Some(arg) = args.next_arg()? {
+            match arg.as_str() {
+                "--format" => format = args.parse_value("--format")?,
+                "-h" | "--help" => print_help(),
+                _ if is_option(&arg) => return Err(unknown_option(&arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = Positional(positional.into_iter());
+        let pid = positional.pid()?;
+        positional.finish()?;
+
+        Ok(Self { pid, format })
+    }
+}
+
+/// Options of the carve command
+#[derive(Debug)]
+pub struct CarveOptions {
+    pub pid: u32,
+    pub output: Option<PathBuf>,
+    pub filter: RegionFilter,
+}
+
+impl CarveOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = Args::new(args);
+        let mut positional = Vec::new();
+        let mut filter = RegionFilter::new();
+
+        while let Some(arg) = args.next_arg()? {
+            match arg.as_str() {
+                "-h" | "--help" => print_help(),
+                _ if parse_filter_option(&arg, &mut args, &mut filter)? => {}
+                _ if is_option(&arg) => return Err(unknown_option(&arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = Positional(positional.into_iter());
+        let pid = positional.pid()?;
+        let output = positional.optional().map(PathBuf::from);
+        positional.finish()?;
+
+        Ok(Self {
+            pid,
+            output,
+            filter,
+        })
+    }
+}
+
+/// Options of the verify command
+#[derive(Debug)]
+pub struct VerifyOptions {
+    pub pid: u32,
+    pub format: ListFormat,
+    pub filter: RegionFilter,
+}
+
+impl VerifyOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = Args::new(args);
+        let mut positional = Vec::new();
+        let mut format = ListFormat::default();
+        let mut filter = RegionFilter::new();
+
+        while let Some(arg) = args.next_arg()? {
+            match arg.as_str() {
+                "--format" => format = args.parse_value("--format")?,
+                "-h" | "--help" => print_help(),
+                _ if parse_filter_option(&arg, &mut args, &mut filter)? => {}
+                _ if is_option(&arg) => return Err(unknown_option(&arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = Positional(positional.into_iter());
+        let pid = positional.pid()?;
+        positional.finish()?;
+
+        if format == ListFormat::Csv {
+            return Err(Error::Usage(
+                "The verification is printed as a table or as JSON, not as CSV".into(),
+            ));
+        }
+
+        Ok(Self {
+            pid,
+            format,
+            filter,
+        })
+    }
+}
+
+/// Options of the serve command
+#[derive(Debug)]
+pub struct ServeOptions {
+    pub address: String,
+    pub once: bool,
+
+    /// Whether addresses other than loopback ones may be listened on
+    pub allow_non_loopback: bool,
+}
+
+impl ServeOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = Args::new(args);
+        let mut positional = Vec::new();
+        let mut once = false;
+        let mut allow_non_loopback = false;
+
+        while let Some(arg) = args.next_arg()? {
+            match arg.as_str() {
+                "--once" => once = true,
+                "--allow-non-loopback" => allow_non_loopback = true,
+                "-h" | "--help" => print_help(),
+                _ if is_option(&arg) => return Err(unknown_option(&arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = Positional(positional.into_iter());
+        let address = positional.required("ADDRESS")?;
+        positional.finish()?;
+
+        Ok(Self {
+            address,
+            once,
+            allow_non_loopback,
+        })
+    }
+}
+
+/// Options of the repl command
+#[derive(Debug)]
+pub struct ReplOptions {
+    pub target: Target,
+    pub history: Option<PathBuf>,
+}
+
+impl ReplOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = Args::new(args);
+        let mut positional = Vec::new();
+        let mut input = None;
+        let mut history = None;
+
+        while let Some(arg) = args.next_arg()? {
+            match arg.as_str() {
+                "--history" => history = Some(args.value("--history")?.into()),
+                "--from-core" => input = Some(Target::Core(args.value("--from-core")?.into())),
+                "--kernel" => input = Some(Target::Kernel),
+                "--from-dump" => input = Some(Target::Dump(args.value("--from-dump")?.into())),
+                "-h" | "--help" => print_help(),
+                _ if is_option(&arg) => return Err(unknown_option(&arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = Positional(positional.into_iter());
+        let target = positional.target(input)?;
+        positional.finish()?;
+
+        Ok(Self { target, history })
+    }
+}
+
+/// Options of the selftest command
+#[derive(Debug)]
+pub struct SelftestOptions {
+    pub size: usize,
+    pub output: Option<PathBuf>,
+    /// Run as the child under test, which `selftest` spawns
+    pub child: bool,
+    /// Backends whose throughput is measured instead of testing the dumps, if not empty
+    pub bench: Vec<Backend>,
+}
+
+impl SelftestOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = Args::new(args);
+        let mut positional = Vec::new();
+        let mut size = 64 << 20;
+        let mut child = false;
+        let mut bench = false;
+        let mut backends = Vec::new();
+
+        while let Some(arg) = args.next_arg()? {
+            match arg.as_str() {
+                "--size" => size = parse_size(&args.value("--size")?)? as usize,
+                "--child" => child = true,
+                "--bench" => bench = true,
+                "--backend" => backends.push(args.parse_value("--backend")?),
+                "-h" | "--help" => print_help(),
+                _ if is_option(&arg) => return Err(unknown_option(&arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = Positional(positional.into_iter());
+        let output = positional.optional().map(PathBuf::from);
+        positional.finish()?;
+
+        if !bench && !backends.is_empty() {
+            return Err(Error::Usage("--backend needs --bench".into()));
+        }
+        if bench && backends.is_empty() {
+            backends = vec![
+                Backend::Read,
+                #[cfg(feature = "uring")]
+                Backend::Uring,
+            ];
+        }
+        backends.dedup();
+
+        Ok(Self {
+            size,
+            output,
+            child,
+            bench: backends,
+        })
+    }
+}
+
+/// Options of the mount command
+#[cfg(feature = "fuse")]
+#[derive(Debug)]
+pub struct MountOptions {
+    pub target: Target,
+    pub mountpoint: PathBuf,
+    pub filter: RegionFilter,
+}
+
+#[cfg(feature = "fuse")]
+impl MountOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = Args::new(args);
+        let mut positional = Vec::new();
+        let mut input = None;
+        let mut filter = RegionFilter::new();
+
+        while let Some(arg) = args.next_arg()? {
+            match arg.as_str() {
+                "--from-core" => input = Some(Target::Core(args.value("--from-core")?.into())),
+                "--kernel" => input = Some(Target::Kernel),
+                "--from-dump" => input = Some(Target::Dump(args.value("--from-dump")?.into())),
+                "-h" | "--help" => print_help(),
+                _ if parse_filter_option(&arg, &mut args, &mut filter)? => {}
+                _ if is_option(&arg) => return Err(unknown_option(&arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = Positional(positional.into_iter());
+        let target = positional.target(input)?;
+        let mountpoint = positional.required("MOUNTPOINT")?.into();
+        positional.finish()?;
+
+        Ok(Self {
+            target,
+            mountpoint,
+            filter,
+        })
+    }
+}
+
+/// Options of the scan-yara command
+#[cfg(feature = "yara")]
+#[derive(Debug)]
+pub struct ScanYaraOptions {
+    pub target: Target,
+    pub rules: PathBuf,
+    pub filter: RegionFilter,
+}
+
+#[cfg(feature = "yara")]
+impl ScanYaraOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = Args::new(args);
+        let mut positional = Vec::new();
+        let mut input = None;
+        let mut filter = RegionFilter::new();
+
+        while let Some(arg) = args.next_arg()? {
+            match arg.as_str() {
+                "--from-core" => input = Some(Target::Core(args.value("--from-core")?.into())),
+                "--kernel" => input = Some(Target::Kernel),
+                "--from-dump" => input = Some(Target::Dump(args.value("--from-dump")?.into())),
+                "-h" | "--help" => print_help(),
+                _ if parse_filter_option(&arg, &mut args, &mut filter)? => {}
+                _ if is_option(&arg) => return Err(unknown_option(&arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = Positional(positional.into_iter());
+        let target = positional.target(input)?;
+        let rules = positional.required("RULES")?.into();
+        positional.finish()?;
+
+        Ok(Self {
+            target,
+            rules,
+            filter,
+        })
+    }
+}
+
+/// Options of the scan-secrets command
+#[cfg(feature = "secrets")]
+#[derive(Debug)]
+pub struct ScanSecretsOptions {
+    pub target: Target,
+    pub patterns: Vec<Regex>,
+    pub builtin: bool,
+    pub redact: bool,
+    pub filter: RegionFilter,
+}
+
+#[cfg(feature = "secrets")]
+impl ScanSecretsOptions {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = Args::new(args);
+        let mut positional = Vec::new();
+        let mut input = None;
+        let mut filter = RegionFilter::new();
+        let mut patterns = Vec::new();
+        let mut builtin = true;
+        let mut redact = true;
+
+        while let Some(arg) = args.next_arg()? {
+            match arg.as_str() {
+                "--pattern" => patterns.push(args.parse_value("--pattern")?),
+                "--no-builtin" => builtin = false,
+                "--no-redact" => redact = false,
+                "--from-core" => input = Some(Target::Core(args.value("--from-core")?.into())),
+                "--kernel" => input = Some(Target::Kernel),
+                "--from-dump" => input = Some(Target::Dump(args.value("--from-dump")?.into())),
+                "-h" | "--help" => print_help(),
+                _ if parse_filter_option(&arg, &mut args, &mut filter)? => {}
+                _ if is_option(&arg) => return Err(unknown_option(&arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = Positional(positional.into_iter());
+        let target = positional.target(input)?;
+        positional.finish()?;
+
+        if !builtin && patterns.is_empty() {
+            return Err(Error::Usage(
+                "--no-builtin needs at least one --pattern".into(),
+            ));
+        }
+
+        Ok(Self {
+            target,
+            patterns,
+            builtin,
+            redact,
+            filter,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_address("0x7fff1000").unwrap(), 0x7fff1000);
+        assert_eq!(parse_address("7fff1000").unwrap(), 0x7fff1000);
+        assert!(parse_address("0xzz").is_err());
+        assert_eq!(parse_range("0x1000..0x1010").unwrap(), 0x1000..0x1010);
+        assert!(parse_range("0x1010..0x1000").is_err());
+        assert!(parse_range("0x1000").is_err());
+        assert_eq!(parse_range("0x1000..+0x200").unwrap(), 0x1000..0x1200);
+        assert!(parse_range("0x1000..+0").is_err());
+        assert!(parse_range("0x1000..+0xffffffffffffffff").is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096").unwrap(), 4096);
+        assert_eq!(parse_size("64k").unwrap(), 64 << 10);
+        assert_eq!(parse_size("512M").unwrap(), 512 << 20);
+        assert_eq!(parse_size("2G").unwrap(), 2 << 30);
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("1T").is_err());
+        assert!(parse_size("99999999999G").is_err());
+    }
+
+    #[test]
+    fn test_log_options() {
+        let args = ["-vv", "dump", "--log-format=json", "-q", "1234", "vv"].map(String::from);
+        let (options, rest) = LogOptions::extract(args).unwrap();
+        assert_eq!(
+            options,
+            LogOptions {
+                verbosity: 1,
+                format: LogFormat::Json
+            }
+        );
+        assert_eq!(rest, ["dump", "1234", "vv"]);
+        assert!(LogOptions::extract(["--log-format".to_string()]).is_err());
+    }
+}
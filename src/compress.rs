@@ -0,0 +1,665 @@
+//! Compressing writers and decoders for region files
+//!
+//! gzip is built in, tuned for memory dumps which are mostly made of zero-filled pages: DEFLATE
+//! blocks with fixed Huffman codes and a hash-chain LZ77 matcher. Its decoder handles any DEFLATE
+//! stream.
+//!
+//! zstd is the reference library, with the `zstd` feature (enabled by default), which compresses
+//! better and faster.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::checksum::Crc32;
+use crate::{Error, Result};
+
+/// Compression algorithm applied to region files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Compression {
+    /// Suffix appended to compressed file names
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::None => "",
+            Self::Gzip => ".gz",
+            #[cfg(feature = "zstd")]
+            Self::Zstd => ".zst",
+        }
+    }
+
+    /// Wraps `w` into a compressing writer
+    pub fn encoder<'a, W: Write + 'a>(&self, w: W) -> io::Result<Box<dyn Encoder + 'a>> {
+        Ok(match self {
+            Self::None => Box::new(Plain(w)),
+            Self::Gzip => Box::new(GzipEncoder::new(w)),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Box::new(zstd::stream::write::Encoder::new(w, ZSTD_LEVEL)?),
+        })
+    }
+
+    /// Decompresses the content of a whole file
+    pub fn decode(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            Self::Gzip => gunzip(data),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => zstd::stream::decode_all(data).map_err(|_| Error::Decompress {
+                algorithm: "zstd",
+                reason: "invalid or truncated frame",
+            }),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Self::None),
+            "gzip" | "gz" => Ok(Self::Gzip),
+            #[cfg(feature = "zstd")]
+            "zstd" | "zst" => Ok(Self::Zstd),
+            #[cfg(not(feature = "zstd"))]
+            "zstd" | "zst" => Err(Error::Usage(
+                "zstd compression needs dump-memory to be built with the `zstd` feature".into(),
+            )),
+            _ => Err(Error::Usage(format!("Unknown compression {:?}", s))),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => f.write_str("none"),
+            Self::Gzip => f.write_str("gzip"),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => f.write_str("zstd"),
+        }
+    }
+}
+
+/// A writer which must be explicitly finished to write trailing data
+pub trait Encoder: Write {
+    /// Writes pending data and trailers, then flushes the underlying writer
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+struct Plain<W>(W);
+
+impl<W: Write> Write for Plain<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<W: Write> Encoder for Plain<W> {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Level of the zstd encoder, the default of the `zstd` tool which dumps are fast enough with
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 3;
+
+#[cfg(feature = "zstd")]
+impl<W: Write> Encoder for zstd::stream::write::Encoder<'_, W> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        (*self).finish()?.flush()
+    }
+}
+
+/// Size of the input chunks compressed as independent blocks
+const BLOCK_SIZE: usize = 128 * 1024;
+
+/// LSB-first bit writer, as used by DEFLATE
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    nbits: u32,
+}
+
+impl BitWriter {
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.bits |= (value as u64) << self.nbits;
+        self.nbits += count;
+        while self.nbits >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.nbits -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, which is stored most significant bit first
+    fn write_code(&mut self, code: u32, len: u32) {
+        let reversed = code.reverse_bits() >> (32 - len);
+        self.write_bits(reversed, len);
+    }
+
+    fn align(&mut self) {
+        if self.nbits > 0 {
+            self.write_bits(0, 8 - self.nbits);
+        }
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_DISTANCE: usize = 32768;
+const MAX_CHAIN: usize = 32;
+const HASH_BITS: u32 = 15;
+
+/// Writes a literal/length symbol with the fixed Huffman code
+fn write_litlen(bw: &mut BitWriter, symbol: u16) {
+    let symbol = symbol as u32;
+    match symbol {
+        0..=143 => bw.write_code(0x30 + symbol, 8),
+        144..=255 => bw.write_code(0x190 + symbol - 144, 9),
+        256..=279 => bw.write_code(symbol - 256, 7),
+        _ => bw.write_code(0xc0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(bw: &mut BitWriter, len: usize, dist: usize) {
+    let li = LENGTH_BASE.partition_point(|b| *b as usize <= len) - 1;
+    write_litlen(bw, 257 + li as u16);
+    bw.write_bits(
+        (len - LENGTH_BASE[li] as usize) as u32,
+        LENGTH_EXTRA[li] as u32,
+    );
+    let di = DIST_BASE.partition_point(|b| *b as usize <= dist) - 1;
+    bw.write_code(di as u32, 5);
+    bw.write_bits(
+        (dist - DIST_BASE[di] as usize) as u32,
+        DIST_EXTRA[di] as u32,
+    );
+}
+
+fn hash3(data: &[u8]) -> usize {
+    let v = (data[0] as u32) << 16 | (data[1] as u32) << 8 | data[2] as u32;
+    (v.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+/// Records `pos` in the hash chains
+fn insert_hash(head: &mut [usize], prev: &mut [usize], data: &[u8], pos: usize) {
+    if pos + MIN_MATCH <= data.len() {
+        let h = hash3(&data[pos..]);
+        prev[pos] = head[h];
+        head[h] = pos;
+    }
+}
+
+/// Compresses `data` as a single fixed Huffman DEFLATE block
+fn deflate_block(bw: &mut BitWriter, data: &[u8], last: bool) {
+    bw.write_bits(last as u32, 1);
+    bw.write_bits(1, 2);
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; data.len()];
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let mut best = (0, 0);
+        if pos + MIN_MATCH <= data.len() {
+            let max_len = MAX_MATCH.min(data.len() - pos);
+            let mut candidate = head[hash3(&data[pos..])];
+            let mut chain = 0;
+            while candidate != usize::MAX && pos - candidate <= MAX_DISTANCE && chain < MAX_CHAIN {
+                let len = data[candidate..]
+                    .iter()
+                    .zip(&data[pos..pos + max_len])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best.0 {
+                    best = (len, pos - candidate);
+                    if len == max_len {
+                        break;
+                    }
+                }
+                candidate = prev[candidate];
+                chain += 1;
+            }
+        }
+
+        if best.0 >= MIN_MATCH {
+            write_match(bw, best.0, best.1);
+            for p in pos..pos + best.0 {
+                insert_hash(&mut head, &mut prev, data, p);
+            }
+            pos += best.0;
+        } else {
+            write_litlen(bw, data[pos] as u16);
+            insert_hash(&mut head, &mut prev, data, pos);
+            pos += 1;
+        }
+    }
+
+    write_litlen(bw, 256);
+}
+
+/// gzip (RFC 1952) encoder
+pub struct GzipEncoder<W: Write> {
+    inner: W,
+    pending: Vec<u8>,
+    bits: BitWriter,
+    crc: Crc32,
+    size: u32,
+}
+
+impl<W: Write> GzipEncoder<W> {
+    pub fn new(inner: W) -> Self {
+        let mut bits = BitWriter::default();
+        // Magic, CM=deflate, no flags, no mtime, XFL=0, OS=unix
+        bits.out
+            .extend_from_slice(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 3]);
+        Self {
+            inner,
+            pending: Vec::with_capacity(BLOCK_SIZE),
+            bits,
+            crc: Crc32::new(),
+            size: 0,
+        }
+    }
+
+    fn flush_output(&mut self) -> io::Result<()> {
+        self.inner.write_all(&self.bits.out[..])?;
+        self.bits.out.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for GzipEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(BLOCK_SIZE - self.pending.len());
+        self.pending.extend_from_slice(&buf[..len]);
+        self.crc.update(&buf[..len]);
+        self.size = self.size.wrapping_add(len as u32);
+        if self.pending.len() == BLOCK_SIZE {
+            deflate_block(&mut self.bits, &self.pending[..], false);
+            self.pending.clear();
+            self.flush_output()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_output()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Encoder for GzipEncoder<W> {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        let pending = std::mem::take(&mut self.pending);
+        deflate_block(&mut self.bits, &pending[..], true);
+        self.bits.align();
+        let crc = self.crc.finish();
+        self.bits.out.extend_from_slice(&crc.to_le_bytes());
+        self.bits.out.extend_from_slice(&self.size.to_le_bytes());
+        self.flush()
+    }
+}
+
+fn gzip_error(reason: &'static str) -> Error {
+    Error::Decompress {
+        algorithm: "gzip",
+        reason,
+    }
+}
+
+/// LSB-first bit reader, as used by DEFLATE
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u32,
+    nbits: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            bits: 0,
+            nbits: 0,
+        }
+    }
+
+    fn read_bits(&mut self, count: u32) -> Result<u32> {
+        while self.nbits < count {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or_else(|| gzip_error("truncated stream"))?;
+            self.bits |= (byte as u32) << self.nbits;
+            self.pos += 1;
+            self.nbits += 8;
+        }
+        let value = self.bits & ((1u64 << count) - 1) as u32;
+        self.bits >>= count;
+        self.nbits -= count;
+        Ok(value)
+    }
+
+    /// Drops bits up to the next byte boundary
+    fn align(&mut self) {
+        self.bits = 0;
+        self.nbits = 0;
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| gzip_error("truncated stream"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+}
+
+const MAX_CODE_BITS: usize = 15;
+
+/// Canonical Huffman decoding table
+struct Huffman {
+    counts: [u16; MAX_CODE_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; MAX_CODE_BITS + 1];
+        for len in lengths {
+            counts[*len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; MAX_CODE_BITS + 2];
+        for len in 1..=MAX_CODE_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; offsets[MAX_CODE_BITS + 1] as usize];
+        for (symbol, len) in lengths.iter().enumerate() {
+            if *len != 0 {
+                symbols[offsets[*len as usize] as usize] = symbol as u16;
+                offsets[*len as usize] += 1;
+            }
+        }
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, br: &mut BitReader) -> Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..=MAX_CODE_BITS {
+            code |= br.read_bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(gzip_error("invalid Huffman code"))
+    }
+}
+
+fn fixed_tables() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_tables(br: &mut BitReader) -> Result<(Huffman, Huffman)> {
+    const ORDER: [usize; 19] = [
+        16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+    ];
+    let nlen = br.read_bits(5)? as usize + 257;
+    let ndist = br.read_bits(5)? as usize + 1;
+    let ncode = br.read_bits(4)? as usize + 4;
+    let mut code_lengths = [0u8; 19];
+    for i in ORDER.iter().take(ncode) {
+        code_lengths[*i] = br.read_bits(3)? as u8;
+    }
+    let codes = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(nlen + ndist);
+    while lengths.len() < nlen + ndist {
+        let (value, repeat) = match codes.decode(br)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let last = *lengths
+                    .last()
+                    .ok_or_else(|| gzip_error("repeat without length"))?;
+                (last, 3 + br.read_bits(2)?)
+            }
+            17 => (0, 3 + br.read_bits(3)?),
+            _ => (0, 11 + br.read_bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() != nlen + ndist {
+        return Err(gzip_error("too many code lengths"));
+    }
+    Ok((
+        Huffman::new(&lengths[..nlen]),
+        Huffman::new(&lengths[nlen..]),
+    ))
+}
+
+/// Decompresses a raw DEFLATE stream, returns the number of bytes consumed
+fn inflate(data: &[u8], out: &mut Vec<u8>) -> Result<usize> {
+    let mut br = BitReader::new(data);
+    loop {
+        let last = br.read_bits(1)? == 1;
+        match br.read_bits(2)? {
+            0 => {
+                br.align();
+                let header = br.read_bytes(4)?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                if len != !u16::from_le_bytes([header[2], header[3]]) {
+                    return Err(gzip_error("invalid stored block length"));
+                }
+                out.extend_from_slice(br.read_bytes(len as usize)?);
+            }
+            block_type @ (1 | 2) => {
+                let (litlen, dist) = if block_type == 1 {
+                    fixed_tables()
+                } else {
+                    dynamic_tables(&mut br)?
+                };
+                loop {
+                    let symbol = litlen.decode(&mut br)? as usize;
+                    if symbol < 256 {
+                        out.push(symbol as u8);
+                        continue;
+                    } else if symbol == 256 {
+                        break;
+                    }
+                    let li = symbol - 257;
+                    if li >= LENGTH_BASE.len() {
+                        return Err(gzip_error("invalid length symbol"));
+                    }
+                    let len =
+                        LENGTH_BASE[li] as usize + br.read_bits(LENGTH_EXTRA[li] as u32)? as usize;
+                    let di = dist.decode(&mut br)? as usize;
+                    if di >= DIST_BASE.len() {
+                        return Err(gzip_error("invalid distance symbol"));
+                    }
+                    let distance =
+                        DIST_BASE[di] as usize + br.read_bits(DIST_EXTRA[di] as u32)? as usize;
+                    if distance > out.len() {
+                        return Err(gzip_error("distance too far back"));
+                    }
+                    let start = out.len() - distance;
+                    for i in 0..len {
+                        out.push(out[start + i]);
+                    }
+                }
+            }
+            _ => return Err(gzip_error("invalid block type")),
+        }
+        if last {
+            // Unused bits of the last byte are dropped
+            return Ok(br.pos);
+        }
+    }
+}
+
+const GZIP_FHCRC: u8 = 2;
+const GZIP_FEXTRA: u8 = 4;
+const GZIP_FNAME: u8 = 8;
+const GZIP_FCOMMENT: u8 = 16;
+
+/// Decompresses a gzip file, which may hold several members
+fn gunzip(mut data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    while !data.is_empty() {
+        if data.len() < 18 || data[..3] != [0x1f, 0x8b, 8] {
+            return Err(gzip_error("invalid header"));
+        }
+        let flags = data[3];
+        let mut pos = 10;
+        if flags & GZIP_FEXTRA != 0 {
+            let len = data
+                .get(pos..pos + 2)
+                .map(|l| u16::from_le_bytes([l[0], l[1]]) as usize)
+                .ok_or_else(|| gzip_error("truncated header"))?;
+            pos += 2 + len;
+        }
+        for flag in [GZIP_FNAME, GZIP_FCOMMENT] {
+            if flags & flag != 0 {
+                pos += data
+                    .get(pos..)
+                    .and_then(|d| d.iter().position(|b| *b == 0))
+                    .ok_or_else(|| gzip_error("truncated header"))?
+                    + 1;
+            }
+        }
+        if flags & GZIP_FHCRC != 0 {
+            pos += 2;
+        }
+
+        let start = out.len();
+        pos += inflate(data.get(pos..).unwrap_or_default(), &mut out)?;
+        let trailer = data
+            .get(pos..pos + 8)
+            .ok_or_else(|| gzip_error("truncated trailer"))?;
+        let crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+        let size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+        if crc != crate::checksum::crc32(&out[start..]) || size != (out.len() - start) as u32 {
+            return Err(gzip_error("checksum mismatch"));
+        }
+        data = &data[pos + 8..];
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compress(compression: Compression, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut encoder = compression.encoder(&mut out).unwrap();
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap();
+        out
+    }
+
+    #[test]
+    fn test_gzip_framing() {
+        let out = compress(Compression::Gzip, b"");
+        // Header, empty fixed block, CRC-32 and size
+        assert_eq!(
+            out,
+            [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 3, 0x03, 0x00, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        let zeros = compress(Compression::Gzip, &[0u8; 4096][..]);
+        assert!(zeros.len() < 64);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut data = vec![0u8; 200_000];
+        for (i, b) in data.iter_mut().enumerate().skip(1000).take(70_000) {
+            *b = (i * 7 % 251) as u8;
+        }
+        data.extend_from_slice(b"the end, the end, the end");
+        for compression in [
+            Compression::None,
+            Compression::Gzip,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd,
+        ] {
+            let compressed = compress(compression, &data);
+            assert_eq!(compression.decode(&compressed).unwrap(), data);
+        }
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd_ratio() {
+        // Repeated records, as in heaps, which runs of identical bytes do not capture
+        let data: Vec<u8> = (0..100_000u32)
+            .flat_map(|i| [b"object ".as_slice(), &(i % 97).to_le_bytes()].concat())
+            .collect();
+        let zstd = compress(Compression::Zstd, &data);
+        let gzip = compress(Compression::Gzip, &data);
+        assert!(zstd.len() < gzip.len(), "{} >= {}", zstd.len(), gzip.len());
+        assert_eq!(Compression::Zstd.decode(&zstd).unwrap(), data);
+        assert!(Compression::Zstd.decode(&zstd[..zstd.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn test_gunzip_dynamic() {
+        // Two pangrams compressed by `gzip -9n`, using a dynamic Huffman block
+        let gz = [
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xad, 0x8c, 0x57, 0x15,
+            0x80, 0x30, 0x0c, 0x45, 0xad, 0x3c, 0x05, 0x18, 0x40, 0x4d, 0x0b, 0xe9, 0x60, 0x34,
+            0xdd, 0x05, 0xd4, 0x93, 0x83, 0x06, 0xbe, 0xef, 0xa8, 0x8e, 0x90, 0x9a, 0x5f, 0x76,
+            0xe8, 0xcc, 0x23, 0xc0, 0xf0, 0x85, 0xad, 0x9d, 0xb1, 0x80, 0x3b, 0x65, 0x54, 0xc1,
+            0x87, 0x7a, 0x6e, 0xac, 0x6c, 0x67, 0x44, 0x25, 0xde, 0x79, 0x43, 0x8b, 0x34, 0x7c,
+            0x75, 0x30, 0xbe, 0x93, 0xa0, 0x87, 0x02, 0x0e, 0x9f, 0x1a, 0x67, 0x69, 0x6d, 0x99,
+            0xbe, 0xec, 0xff, 0xeb, 0x0b, 0xbc, 0xd9, 0x63, 0x8e, 0xac, 0x00, 0x00, 0x00,
+        ];
+        let text = b"the quick brown fox jumps over the lazy dog; pack my box with five dozen liquor jugs. ";
+        assert_eq!(
+            Compression::Gzip.decode(&gz).unwrap(),
+            [&text[..], &text[..]].concat()
+        );
+        assert!(Compression::Gzip.decode(&gz[..40]).is_err());
+    }
+}
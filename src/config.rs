@@ -0,0 +1,478 @@
+//! Configuration file, holding named profiles of dump options
+//!
+//! Each `[profile.NAME]` table of the file gives options of `dump`, named as on the command line
+//! without their leading dashes. Only the subset of TOML they need is read: tables, strings,
+//! numbers, booleans and arrays of them.
+
+use std::path::PathBuf;
+
+use crate::json::Value;
+use crate::{Error, Result};
+
+/// Environment variable naming another configuration file
+const CONFIG_ENV: &str = "DUMP_MEMORY_CONFIG";
+
+/// Path of the configuration file: `$DUMP_MEMORY_CONFIG`, or `dump-memory/config.toml` in
+/// `$XDG_CONFIG_HOME` or `~/.config`
+pub fn path() -> Option<PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|v| !v.is_empty());
+    if let Some(path) = var(CONFIG_ENV) {
+        return Some(path.into());
+    }
+    let dir = match var("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(var("HOME")?).join(".config"),
+    };
+    Some(dir.join("dump-memory").join("config.toml"))
+}
+
+/// Returns the command line options of the profile `name` which `after`, the arguments following
+/// `--profile`, do not override
+///
+/// An option given after the profile replaces all its values in the profile, even if it can be
+/// repeated, and `--no-FLAG` turns off a flag the profile sets: it is removed from `after`.
+pub fn profile(name: &str, after: &mut Vec<String>) -> Result<Vec<String>> {
+    let path = path().ok_or_else(|| {
+        Error::Usage("--profile needs HOME or XDG_CONFIG_HOME to find the configuration".into())
+    })?;
+    let text = std::fs::read_to_string(&path).map_err(|e| {
+        Error::Usage(format!(
+            "Cannot read the configuration {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    let invalid = |message| Error::Config {
+        path: path.clone(),
+        message,
+    };
+    let config =
+        parse(&text).map_err(|(line, message)| invalid(format!("line {}: {}", line, message)))?;
+    let profile = config
+        .get("profile")
+        .and_then(|profiles| profiles.get(name))
+        .ok_or_else(|| Error::Usage(format!("No profile {:?} in {}", name, path.display())))?;
+    to_args(&without_overridden(profile, after))
+        .map_err(|message| invalid(format!("profile {}: {}", name, message)))
+}
+
+/// Removes the options of `profile` which `after` gives or turns off, see [`profile`]
+fn without_overridden(profile: &Value, after: &mut Vec<String>) -> Value {
+    let Value::Object(ref fields) = *profile else {
+        return profile.clone();
+    };
+    let given = |after: &[String], option: &str| {
+        after
+            .iter()
+            .any(|arg| arg.split_once('=').map_or(arg.as_str(), |(flag, _)| flag) == option)
+    };
+    let mut kept = Vec::new();
+    for (key, value) in fields {
+        if given(after, &format!("--{}", key)) {
+            continue;
+        }
+        let negation = format!("--no-{}", key);
+        if *value == Value::Bool(true) && given(after, &negation) {
+            after.retain(|arg| *arg != negation);
+            continue;
+        }
+        kept.push((key.clone(), value.clone()));
+    }
+    Value::Object(kept)
+}
+
+/// Converts a profile to command line options: `key = value` is `--key value`, `key = true` is
+/// `--key`, and an array repeats the option for each of its values
+fn to_args(profile: &Value) -> std::result::Result<Vec<String>, String> {
+    let Value::Object(ref fields) = *profile else {
+        return Err("not a table".into());
+    };
+    let scalar = |key: &str, value: &Value| match *value {
+        Value::String(ref s) => Ok(s.clone()),
+        Value::UInt(n) => Ok(n.to_string()),
+        Value::Int(n) => Ok(n.to_string()),
+        Value::Float(n) => Ok(n.to_string()),
+        _ => Err(format!(
+            "{} must be a string, a number or an array of them",
+            key
+        )),
+    };
+    let mut args = Vec::new();
+    for (key, value) in fields {
+        let option = format!("--{}", key);
+        match *value {
+            // Profiles are expanded in place, they would include each other forever
+            _ if key == "profile" => return Err("profiles cannot include other profiles".into()),
+            Value::Bool(true) => args.push(option),
+            Value::Bool(false) => {}
+            Value::Array(ref values) => {
+                for value in values {
+                    args.extend([option.clone(), scalar(key, value)?]);
+                }
+            }
+            ref value => args.extend([option, scalar(key, value)?]),
+        }
+    }
+    Ok(args)
+}
+
+/// Parses a TOML document into nested objects, failing with the line of the error
+fn parse(text: &str) -> std::result::Result<Value, (usize, String)> {
+    let mut parser = Parser { text, pos: 0 };
+    parser
+        .document()
+        .map_err(|message| (parser.line(), message))
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn line(&self) -> usize {
+        self.text[..self.pos].matches('\n').count() + 1
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> std::result::Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(format!("expected `{}`", c))
+        }
+    }
+
+    /// Skips spaces and tabs
+    fn blanks(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.pos += 1;
+        }
+    }
+
+    /// Skips blanks, comments and newlines
+    fn whitespace(&mut self) {
+        loop {
+            self.blanks();
+            match self.peek() {
+                Some('#') => self.comment(),
+                Some('\n' | '\r') => self.pos += 1,
+                _ => break,
+            }
+        }
+    }
+
+    fn comment(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.find('\n').unwrap_or(rest.len());
+    }
+
+    /// Expects the end of a line, after a table header or a key/value pair
+    fn end_of_line(&mut self) -> std::result::Result<(), String> {
+        self.blanks();
+        if self.peek() == Some('#') {
+            self.comment();
+        }
+        self.eat('\r');
+        match self.peek() {
+            None => Ok(()),
+            Some('\n') => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(c) => Err(format!("unexpected `{}`", c)),
+        }
+    }
+
+    fn document(&mut self) -> std::result::Result<Value, String> {
+        let mut root = Value::Object(Vec::new());
+        let mut table = Vec::new();
+        loop {
+            self.whitespace();
+            if self.peek().is_none() {
+                return Ok(root);
+            }
+            if self.eat('[') {
+                if self.peek() == Some('[') {
+                    return Err("arrays of tables are not supported".into());
+                }
+                table = self.key()?;
+                self.blanks();
+                self.expect(']')?;
+                object(&mut root, &table)?;
+            } else {
+                let mut key = self.key()?;
+                self.blanks();
+                self.expect('=')?;
+                self.blanks();
+                let value = self.value()?;
+                let name = key.pop().unwrap_or_default();
+                let mut path = table.clone();
+                path.extend(key);
+                let fields = object(&mut root, &path)?;
+                if fields.iter().any(|(k, _)| *k == name) {
+                    return Err(format!("duplicate key {}", name));
+                }
+                fields.push((name, value));
+            }
+            self.end_of_line()?;
+        }
+    }
+
+    /// Parses a dotted key, `a."b c".d`
+    fn key(&mut self) -> std::result::Result<Vec<String>, String> {
+        let mut parts = Vec::new();
+        loop {
+            self.blanks();
+            let part = match self.peek() {
+                Some('"') => self.basic_string()?,
+                Some('\'') => self.literal_string()?,
+                _ => {
+                    let rest = &self.text[self.pos..];
+                    let len = rest
+                        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+                        .unwrap_or(rest.len());
+                    if len == 0 {
+                        return Err("expected a key".into());
+                    }
+                    self.pos += len;
+                    rest[..len].to_owned()
+                }
+            };
+            parts.push(part);
+            self.blanks();
+            if !self.eat('.') {
+                return Ok(parts);
+            }
+        }
+    }
+
+    fn value(&mut self) -> std::result::Result<Value, String> {
+        match self.peek() {
+            Some('"') => Ok(Value::String(self.basic_string()?)),
+            Some('\'') => Ok(Value::String(self.literal_string()?)),
+            Some('[') => self.array(),
+            Some('{') => Err("inline tables are not supported".into()),
+            _ => {
+                let rest = &self.text[self.pos..];
+                let len = rest
+                    .find(|c: char| c.is_whitespace() || matches!(c, ',' | ']' | '#'))
+                    .unwrap_or(rest.len());
+                let token = &rest[..len];
+                let value = match token {
+                    "true" => Value::Bool(true),
+                    "false" => Value::Bool(false),
+                    _ => number(token).ok_or_else(|| format!("invalid value {:?}", token))?,
+                };
+                self.pos += len;
+                Ok(value)
+            }
+        }
+    }
+
+    fn array(&mut self) -> std::result::Result<Value, String> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+        loop {
+            self.whitespace();
+            if self.eat(']') {
+                return Ok(Value::Array(values));
+            }
+            values.push(self.value()?);
+            self.whitespace();
+            if !self.eat(',') {
+                self.expect(']')?;
+                return Ok(Value::Array(values));
+            }
+        }
+    }
+
+    fn basic_string(&mut self) -> std::result::Result<String, String> {
+        self.expect('"')?;
+        if self.text[self.pos..].starts_with("\"\"") {
+            return Err("multi-line strings are not supported".into());
+        }
+        let mut s = String::new();
+        loop {
+            let c = self.peek().ok_or("unterminated string")?;
+            self.pos += c.len_utf8();
+            match c {
+                '"' => return Ok(s),
+                '\n' => return Err("unterminated string".into()),
+                '\\' => {
+                    let escaped = self.peek().ok_or("unterminated string")?;
+                    self.pos += escaped.len_utf8();
+                    s.push(match escaped {
+                        '"' => '"',
+                        '\\' => '\\',
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        _ => return Err(format!("unsupported escape `\\{}`", escaped)),
+                    });
+                }
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> std::result::Result<String, String> {
+        self.expect('\'')?;
+        let rest = &self.text[self.pos..];
+        let len = rest
+            .find(['\'', '\n'])
+            .filter(|&len| rest[len..].starts_with('\''))
+            .ok_or("unterminated string")?;
+        self.pos += len + 1;
+        Ok(rest[..len].to_owned())
+    }
+}
+
+/// Parses an integer or a float, with `_` separators
+fn number(token: &str) -> Option<Value> {
+    let digits = token.replace('_', "");
+    if let Ok(n) = digits.parse::<u64>() {
+        return Some(Value::UInt(n));
+    }
+    if let Ok(n) = digits.parse::<i64>() {
+        return Some(Value::Int(n));
+    }
+    // Rust also parses `inf` and `nan`, which TOML spells differently
+    digits
+        .starts_with(|c: char| c.is_ascii_digit() || c == '+' || c == '-')
+        .then(|| digits.parse().ok().map(Value::Float))
+        .flatten()
+}
+
+/// Returns the fields of the table at `path`, creating it and its parents as needed
+fn object<'a>(
+    root: &'a mut Value,
+    path: &[String],
+) -> std::result::Result<&'a mut Vec<(String, Value)>, String> {
+    let mut fields = match root {
+        Value::Object(fields) => fields,
+        _ => unreachable!("the root is a table"),
+    };
+    for name in path {
+        let index = match fields.iter().position(|(k, _)| k == name) {
+            Some(index) => index,
+            None => {
+                fields.push((name.clone(), Value::Object(Vec::new())));
+                fields.len() - 1
+            }
+        };
+        fields = match fields[index].1 {
+            Value::Object(ref mut fields) => fields,
+            _ => return Err(format!("{} is not a table", name)),
+        };
+    }
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = parse(
+            "# Team profiles\n\
+             [profile.forensics]\n\
+             format = \"tar\"   # one file\n\
+             compress = 'zstd'\n\
+             sha256 = true\n\
+             no-stop = false\n\
+             kind = [\"heap\", \"stack\",\n    \"anonymous\",  # not files\n]\n\
+             max-region-size = 1_073_741_824\n\
+             max-pause = 0.5\n\
+             \n\
+             [profile.\"quick look\"]\n\
+             name = \"{comm}-{time}\"\n\
+             profile.other.backend = \"uring\"\n",
+        )
+        .unwrap();
+        let forensics = config.get("profile").unwrap().get("forensics").unwrap();
+        assert_eq!(
+            to_args(forensics).unwrap(),
+            [
+                "--format",
+                "tar",
+                "--compress",
+                "zstd",
+                "--sha256",
+                "--kind",
+                "heap",
+                "--kind",
+                "stack",
+                "--kind",
+                "anonymous",
+                "--max-region-size",
+                "1073741824",
+                "--max-pause",
+                "0.5",
+            ]
+        );
+        let quick = config.get("profile").unwrap().get("quick look").unwrap();
+        assert_eq!(
+            quick.get("name"),
+            Some(&Value::String("{comm}-{time}".into()))
+        );
+        assert!(to_args(quick).is_err());
+
+        assert_eq!(
+            parse("a = 1\na = 2").unwrap_err(),
+            (2, "duplicate key a".into())
+        );
+        assert_eq!(
+            parse("[profile]\nx = \"open").unwrap_err(),
+            (2, "unterminated string".into())
+        );
+        assert_eq!(
+            parse("x = {a = 1}").unwrap_err(),
+            (1, "inline tables are not supported".into())
+        );
+        assert_eq!(parse("x = 1 y").unwrap_err(), (1, "unexpected `y`".into()));
+        assert_eq!(parse("x = nan").unwrap_err().0, 1);
+        assert_eq!(
+            to_args(&parse("x = [[1]]").unwrap()).unwrap_err(),
+            "x must be a string, a number or an array of them"
+        );
+    }
+
+    #[test]
+    fn test_overrides() {
+        let profile = parse(
+            "format = \"tar\"\n\
+             sha256 = true\n\
+             with-proc = true\n\
+             kind = [\"heap\", \"anonymous\"]\n\
+             exclude-path = [\"/dev/shm/*\"]\n",
+        )
+        .unwrap();
+        let mut after: Vec<String> = ["--kind", "stack", "--format=dir", "--no-sha256", "42"]
+            .map(String::from)
+            .into();
+        assert_eq!(
+            to_args(&without_overridden(&profile, &mut after)).unwrap(),
+            ["--with-proc", "--exclude-path", "/dev/shm/*"]
+        );
+        assert_eq!(after, ["--kind", "stack", "--format=dir", "42"]);
+
+        // Flags which the profile does not set cannot be turned off
+        let mut after = vec!["--no-entropy".to_owned()];
+        without_overridden(&profile, &mut after);
+        assert_eq!(after, ["--no-entropy"]);
+    }
+}
@@ -0,0 +1,336 @@
+//! ELF core file writer
+//!
+//! The layout mimics the one produced by the kernel: ELF header, a `PT_NOTE` program header
+//! followed by one `PT_LOAD` per dumped region, the notes (`NT_PRSTATUS` and `NT_PRFPREG` for
+//! each thread, `NT_PRPSINFO`, `NT_AUXV` and `NT_FILE`) and finally page-aligned region contents.
+//!
+//! The machine and register layouts are the ones of the architecture this is built for. On x86_64,
+//! 32-bit processes get an i386 core file, with 32-bit structures and the `fxsave` area of each
+//! thread in `NT_PRXFPREG` instead of `NT_PRFPREG`.
+
+use std::path::{Path, PathBuf};
+
+use crate::elf::{
+    Class, EM_386, EM_HOST, ET_CORE, NT_AUXV, NT_FILE, NT_PRFPREG, NT_PRPSINFO, NT_PRSTATUS, PF_R,
+    PF_W, PF_X, PT_LOAD, PT_NOTE,
+};
+use crate::manifest::Manifest;
+use crate::memory::{Memory, PermissionBits, Region};
+use crate::output::{Output, SingleFile, Source, Stored};
+use crate::procfs::{self, Stat};
+#[cfg(target_arch = "x86_64")]
+use crate::regs::UserRegs32;
+use crate::regs::{self, Registers};
+use crate::Result;
+
+const PAGE_SIZE: u64 = 4096;
+
+const NT_PRXFPREG: u32 = 0x46e62b7f;
+
+const SIGSTOP: i32 = 19;
+
+fn align_up(n: u64, align: u64) -> u64 {
+    n.div_ceil(align) * align
+}
+
+/// Appends `value` as a little-endian word of `class`
+///
+/// The values of 32-bit processes fit, except file offsets checked by [`CoreDump::finish`].
+fn push_word(data: &mut Vec<u8>, class: Class, value: u64) {
+    match class {
+        Class::Elf32 => data.extend_from_slice(&(value as u32).to_le_bytes()),
+        Class::Elf64 => data.extend_from_slice(&value.to_le_bytes()),
+    }
+}
+
+/// Serialized ELF notes
+#[derive(Debug, Default)]
+pub struct Notes {
+    data: Vec<u8>,
+}
+
+impl Notes {
+    pub fn push(&mut self, name: &str, n_type: u32, desc: &[u8]) {
+        let namesz = name.len() + 1;
+        self.data.extend_from_slice(&(namesz as u32).to_le_bytes());
+        self.data
+            .extend_from_slice(&(desc.len() as u32).to_le_bytes());
+        self.data.extend_from_slice(&n_type.to_le_bytes());
+        self.data.extend_from_slice(name.as_bytes());
+        self.data.push(0);
+        self.pad();
+        self.data.extend_from_slice(desc);
+        self.pad();
+    }
+
+    fn pad(&mut self) {
+        let len = align_up(self.data.len() as u64, 4) as usize;
+        self.data.resize(len, 0);
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[..]
+    }
+}
+
+/// Builds a `struct elf_prstatus` for a thread
+fn prstatus(stat: &Stat, regs: &Registers) -> Vec<u8> {
+    let word = regs.class.word_size();
+    let mut desc = Vec::with_capacity(336);
+    // pr_info (si_signo, si_code, si_errno), pr_cursig and padding
+    desc.extend_from_slice(&SIGSTOP.to_le_bytes());
+    desc.extend_from_slice(&[0; 8]);
+    desc.extend_from_slice(&(SIGSTOP as i16).to_le_bytes());
+    desc.extend_from_slice(&[0; 2]);
+    // pr_sigpend and pr_sighold
+    desc.resize(desc.len() + 2 * word, 0);
+    for id in [regs.tid, stat.ppid, stat.pgrp, stat.session] {
+        desc.extend_from_slice(&id.to_le_bytes());
+    }
+    // pr_utime, pr_stime, pr_cutime and pr_cstime
+    desc.resize(desc.len() + 8 * word, 0);
+    match regs.class {
+        #[cfg(target_arch = "x86_64")]
+        Class::Elf32 => desc.extend_from_slice(regs::as_bytes(&UserRegs32::from(regs.gp))),
+        _ => desc.extend_from_slice(regs::as_bytes(&regs.gp)),
+    }
+    // pr_fpvalid and padding
+    desc.extend_from_slice(&(regs.fp.is_some() as u32).to_le_bytes());
+    desc.resize(desc.len().next_multiple_of(word), 0);
+    desc
+}
+
+/// Builds a `struct elf_prpsinfo`
+fn prpsinfo(class: Class, stat: &Stat, cmdline: &str) -> Vec<u8> {
+    let mut desc = Vec::with_capacity(136);
+    let state = "RSDTZW".find(stat.state).unwrap_or(0) as u8;
+    desc.extend_from_slice(&[state, stat.state as u8, (stat.state == 'Z') as u8, 0]);
+    // padding, pr_flag, pr_uid and pr_gid, which are 16-bit for 32-bit processes
+    desc.resize(
+        match class {
+            Class::Elf32 => 12,
+            Class::Elf64 => 24,
+        },
+        0,
+    );
+    for id in [stat.pid, stat.ppid, stat.pgrp, stat.session] {
+        desc.extend_from_slice(&id.to_le_bytes());
+    }
+    let mut fname = [0u8; 16];
+    let comm = stat.comm.as_bytes();
+    let len = comm.len().min(fname.len() - 1);
+    fname[..len].copy_from_slice(&comm[..len]);
+    desc.extend_from_slice(&fname);
+    let mut psargs = [0u8; 80];
+    let args = cmdline.as_bytes();
+    let len = args.len().min(psargs.len() - 1);
+    psargs[..len].copy_from_slice(&args[..len]);
+    desc.extend_from_slice(&psargs);
+    desc
+}
+
+/// Builds the `NT_FILE` note describing file-backed mappings
+fn file_mappings(class: Class, memory: &Memory) -> Vec<u8> {
+    let files: Vec<_> = memory
+        .iter()
+        .filter_map(|r| r.path().filter(|_| !r.is_anonymous()).map(|p| (r, p)))
+        .collect();
+    let mut desc = Vec::new();
+    push_word(&mut desc, class, files.len() as u64);
+    push_word(&mut desc, class, PAGE_SIZE);
+    for (region, _) in &files {
+        push_word(&mut desc, class, region.start as u64);
+        push_word(&mut desc, class, region.end as u64);
+        push_word(&mut desc, class, region.offset as u64 / PAGE_SIZE);
+    }
+    for (_, path) in &files {
+        desc.extend_from_slice(path.as_bytes());
+        desc.push(0);
+    }
+    desc
+}
+
+#[derive(Debug)]
+struct Segment {
+    vaddr: u64,
+    memsz: u64,
+    filesz: u64,
+    offset: u64,
+    flags: u32,
+}
+
+/// Writes regions into a single ELF core file
+#[derive(Debug)]
+pub struct CoreDump {
+    path: PathBuf,
+    file: SingleFile,
+    class: Class,
+    notes: Notes,
+    segments: Vec<Segment>,
+}
+
+impl CoreDump {
+    /// Creates a core file able to hold up to `max_segments` regions of `memory`
+    ///
+    /// `threads` must start with the main thread, which debuggers consider as the crashing one. The
+    /// class of the core file is the one of the executable of `pid`.
+    pub fn create(
+        path: &Path,
+        pid: u32,
+        memory: &Memory,
+        threads: &[Registers],
+        max_segments: usize,
+    ) -> Result<Self> {
+        let stat = procfs::stat(pid)?;
+        let cmdline = procfs::cmdline(pid)?;
+        let class = procfs::elf_class(pid).unwrap_or(Class::Elf64);
+        if cfg!(not(target_arch = "x86_64")) && class == Class::Elf32 {
+            return Err(std::io::Error::other(
+                "core files of 32-bit processes are only written on x86_64",
+            )
+            .into());
+        }
+
+        let mut notes = Notes::default();
+        for regs in threads {
+            notes.push("CORE", NT_PRSTATUS, &prstatus(&stat, regs));
+            match (regs.fp, class) {
+                (Some(ref fp), Class::Elf64) => notes.push("CORE", NT_PRFPREG, regs::as_bytes(fp)),
+                (Some(ref fp), Class::Elf32) => {
+                    notes.push("LINUX", NT_PRXFPREG, regs::as_bytes(fp))
+                }
+                (None, _) => (),
+            }
+        }
+        notes.push("CORE", NT_PRPSINFO, &prpsinfo(class, &stat, &cmdline));
+        notes.push(
+            "CORE",
+            NT_AUXV,
+            &std::fs::read(format!("/proc/{}/auxv", pid))?,
+        );
+        notes.push("CORE", NT_FILE, &file_mappings(class, memory));
+
+        let (ehdr_size, phdr_size) = (class.ehdr_size() as u64, class.phdr_size() as u64);
+        let headers = ehdr_size + phdr_size * (max_segments as u64 + 1) + notes.len() as u64;
+
+        Ok(Self {
+            path: path.into(),
+            file: SingleFile::create(path, headers, max_segments)?,
+            class,
+            notes,
+            segments: Vec::with_capacity(max_segments),
+        })
+    }
+
+    /// Returns the ELF header, the program headers and the notes
+    fn headers(&self) -> Result<Vec<u8>> {
+        let class = self.class;
+        if class == Class::Elf32 && self.file.end() > u32::MAX as u64 {
+            return Err(std::io::Error::other("32-bit core files are limited to 4 GiB").into());
+        }
+        let (ehdr_size, phdr_size) = (class.ehdr_size() as u64, class.phdr_size() as u64);
+        let phnum = self.segments.len() as u64 + 1;
+        let notes_offset = ehdr_size + phdr_size * phnum;
+        let mut headers = Vec::with_capacity(notes_offset as usize);
+
+        headers.extend_from_slice(b"\x7fELF");
+        // ELFCLASS32 or ELFCLASS64, ELFDATA2LSB, EV_CURRENT, ELFOSABI_NONE and padding
+        let (ei_class, machine, shdr_size) = match class {
+            Class::Elf32 => (1, EM_386, 40u16),
+            Class::Elf64 => (2, EM_HOST, 64),
+        };
+        headers.extend_from_slice(&[ei_class, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        headers.extend_from_slice(&ET_CORE.to_le_bytes());
+        headers.extend_from_slice(&machine.to_le_bytes());
+        headers.extend_from_slice(&1u32.to_le_bytes());
+        // e_entry, e_phoff and e_shoff
+        push_word(&mut headers, class, 0);
+        push_word(&mut headers, class, ehdr_size);
+        push_word(&mut headers, class, 0);
+        // e_flags, e_ehsize, e_phentsize, e_phnum, e_shentsize, e_shnum and e_shstrndx
+        headers.extend_from_slice(&0u32.to_le_bytes());
+        headers.extend_from_slice(&(ehdr_size as u16).to_le_bytes());
+        headers.extend_from_slice(&(phdr_size as u16).to_le_bytes());
+        headers.extend_from_slice(&(phnum as u16).to_le_bytes());
+        headers.extend_from_slice(&shdr_size.to_le_bytes());
+        headers.extend_from_slice(&0u16.to_le_bytes());
+        headers.extend_from_slice(&0u16.to_le_bytes());
+
+        let note = Segment {
+            vaddr: 0,
+            memsz: 0,
+            filesz: self.notes.len() as u64,
+            offset: notes_offset,
+            flags: 0,
+        };
+        for (p_type, segment) in
+            std::iter::once((PT_NOTE, &note)).chain(self.segments.iter().map(|s| (PT_LOAD, s)))
+        {
+            let align = if p_type == PT_NOTE { 4 } else { PAGE_SIZE };
+            headers.extend_from_slice(&p_type.to_le_bytes());
+            // p_flags follows p_type in 64-bit program headers, and comes before p_align otherwise
+            if class == Class::Elf64 {
+                headers.extend_from_slice(&segment.flags.to_le_bytes());
+            }
+            for value in [
+                segment.offset,
+                segment.vaddr,
+                0,
+                segment.filesz,
+                segment.memsz,
+            ] {
+                push_word(&mut headers, class, value);
+            }
+            if class == Class::Elf32 {
+                headers.extend_from_slice(&segment.flags.to_le_bytes());
+            }
+            push_word(&mut headers, class, align);
+        }
+
+        headers.extend_from_slice(self.notes.as_bytes());
+        Ok(headers)
+    }
+}
+
+impl Output for CoreDump {
+    fn write_region(
+        &mut self,
+        region: &Region,
+        _name: &str,
+        source: &mut Source,
+    ) -> Result<Stored> {
+        let mut flags = 0;
+        for (pbit, flag) in [
+            (PermissionBits::Read, PF_R),
+            (PermissionBits::Write, PF_W),
+            (PermissionBits::Exec, PF_X),
+        ] {
+            if region.perms.has_perm(pbit) {
+                flags |= flag;
+            }
+        }
+
+        let (offset, size) = self.file.append(source)?;
+        self.segments.push(Segment {
+            vaddr: region.start as u64,
+            memsz: region.size() as u64,
+            filesz: size,
+            offset,
+            flags,
+        });
+        Ok(Stored { file: None, size })
+    }
+
+    /// The manifest is written next to the core file, with a `.manifest.json` suffix
+    fn finish(self: Box<Self>, manifest: &Manifest) -> Result<()> {
+        let headers = self.headers()?;
+        self.file.finish(&headers)?;
+        let mut manifest_path = self.path.into_os_string();
+        manifest_path.push(".manifest.json");
+        manifest.write(Path::new(&manifest_path))
+    }
+}
@@ -0,0 +1,556 @@
+//! Reading of ELF core files, as written by the kernel, gdb or the `core` format
+//!
+//! Each `PT_LOAD` segment is a region, mapping the file given by the `NT_FILE` note if any. The
+//! registers of each thread come from the `NT_PRSTATUS` and `NT_PRFPREG` notes. Segments may be
+//! shorter in the file than in memory: pages which can be read back from the mapped files are
+//! usually not saved, and cannot be read.
+//!
+//! Kernel memory is read the same way from `/proc/kcore`.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+use crate::compress::Compression;
+use crate::elf::{self, Class, ElfHeader, ProgramHeader};
+use crate::manifest::{Entry, Manifest, Status};
+use crate::memory::{Memory, PermissionBits, Permissions, Region};
+use crate::output::Format;
+use crate::regs::{self, Registers};
+use crate::{Error, Result};
+
+/// Offsets of `pr_pid` and `pr_reg` in `struct elf_prstatus`
+const PR_STATUS_PID: usize = 32;
+const PR_REG: usize = 112;
+
+/// Offsets of `pr_pid` and `pr_fname` in `struct elf_prpsinfo`
+const PR_PSINFO_PID: usize = 24;
+const PR_FNAME: usize = 40;
+
+/// Kernel virtual memory, as an ELF core file
+pub const KCORE: &str = "/proc/kcore";
+
+/// Allocated areas of the vmalloc and modules address space
+const VMALLOCINFO: &str = "/proc/vmallocinfo";
+
+/// `p_paddr` of the `/proc/kcore` segments outside of the mapping of physical memory
+const NO_PADDR: u64 = u64::MAX;
+
+/// A `PT_LOAD` segment
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Segment {
+    start: u64,
+    end: u64,
+    flags: u32,
+    /// Where its content is in the file, the first `filesz` bytes only are saved
+    offset: u64,
+    filesz: u64,
+    paddr: u64,
+}
+
+/// A file mapping of `NT_FILE`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FileMapping {
+    start: u64,
+    end: u64,
+    /// Offset in the file, in bytes
+    offset: u64,
+    path: String,
+}
+
+/// What the notes tell about the process
+#[derive(Debug, Default)]
+struct Process {
+    pid: u32,
+    name: String,
+    threads: Vec<Registers>,
+    files: Vec<FileMapping>,
+}
+
+fn malformed(what: &str) -> Error {
+    Error::Elf(format!("malformed {} note", what))
+}
+
+/// Parses the `NT_FILE` note
+fn parse_files(desc: &[u8]) -> Result<Vec<FileMapping>> {
+    let word = |idx: usize| {
+        desc.get(idx * 8..idx * 8 + 8)
+            .map(|w| elf::u64_at(w, 0))
+            .ok_or_else(|| malformed("NT_FILE"))
+    };
+    let count = word(0)? as usize;
+    let page_size = word(1)?;
+    let names = desc
+        .get(8 * (2 + 3 * count.min(desc.len()))..)
+        .ok_or_else(|| malformed("NT_FILE"))?;
+    let mut names = names.split(|&b| b == 0);
+    (0..count)
+        .map(|i| {
+            let name = names.next().ok_or_else(|| malformed("NT_FILE"))?;
+            Ok(FileMapping {
+                start: word(2 + 3 * i)?,
+                end: word(3 + 3 * i)?,
+                offset: word(4 + 3 * i)?.wrapping_mul(page_size),
+                path: String::from_utf8_lossy(name).into_owned(),
+            })
+        })
+        .collect()
+}
+
+/// Parses the content of a `PT_NOTE` segment
+fn parse_notes(data: &[u8]) -> Result<Process> {
+    let mut process = Process::default();
+    let mut pos = 0;
+    while pos + 12 <= data.len() {
+        let namesz = elf::u32_at(data, pos) as usize;
+        let descsz = elf::u32_at(data, pos + 4) as usize;
+        let n_type = elf::u32_at(data, pos + 8);
+        let start = (pos + 12).saturating_add(namesz).next_multiple_of(4);
+        let desc = data
+            .get(start..start.saturating_add(descsz))
+            .ok_or_else(|| malformed("truncated"))?;
+        pos = (start + descsz).next_multiple_of(4);
+
+        match n_type {
+            elf::NT_PRSTATUS => {
+                let gp = desc
+                    .get(PR_REG..)
+                    .and_then(regs::from_bytes)
+                    .ok_or_else(|| malformed("NT_PRSTATUS"))?;
+                process.threads.push(Registers {
+                    tid: elf::u32_at(desc, PR_STATUS_PID),
+                    class: Class::Elf64,
+                    gp,
+                    fp: None,
+                });
+            }
+            // Follows the NT_PRSTATUS of its thread
+            elf::NT_PRFPREG => {
+                if let Some(thread) = process.threads.last_mut() {
+                    thread.fp = regs::from_bytes(desc);
+                }
+            }
+            elf::NT_PRPSINFO if desc.len() >= PR_FNAME + 16 => {
+                process.pid = elf::u32_at(desc, PR_PSINFO_PID);
+                let name = &desc[PR_FNAME..PR_FNAME + 16];
+                let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+                process.name = String::from_utf8_lossy(&name[..len]).into_owned();
+            }
+            elf::NT_FILE => process.files = parse_files(desc)?,
+            _ => {}
+        }
+    }
+    if process.pid == 0 {
+        process.pid = process.threads.first().map_or(0, |t| t.tid);
+    }
+    Ok(process)
+}
+
+/// Builds the region of each segment, with its file from the `NT_FILE` mappings
+fn regions(segments: &[Segment], files: &[FileMapping]) -> Vec<Region> {
+    segments
+        .iter()
+        .map(|segment| {
+            let mut perms = Permissions::new();
+            for (flag, bit) in [
+                (elf::PF_R, PermissionBits::Read),
+                (elf::PF_W, PermissionBits::Write),
+                (elf::PF_X, PermissionBits::Exec),
+            ] {
+                if segment.flags & flag != 0 {
+                    perms.add(bit);
+                }
+            }
+            // Cores do not tell shared mappings apart
+            perms.add(PermissionBits::Private);
+            let file = files
+                .iter()
+                .find(|f| f.start <= segment.start && segment.start < f.end);
+            let (offset, path) = match file {
+                Some(f) => (f.offset + (segment.start - f.start), f.path.as_str()),
+                None => (0, ""),
+            };
+            Region::new(
+                segment.start as usize,
+                segment.end as usize,
+                perms,
+                offset as usize,
+                path,
+            )
+        })
+        .collect()
+}
+
+/// Parses `/proc/vmallocinfo`, returning the sorted and merged ranges of the areas
+fn parse_vmallocinfo(text: &str) -> Vec<Range<u64>> {
+    let parse = |s: &str| u64::from_str_radix(s.trim_start_matches("0x"), 16).ok();
+    let mut areas: Vec<Range<u64>> = text
+        .lines()
+        .filter_map(|line| {
+            let (start, end) = line.split_whitespace().next()?.split_once('-')?;
+            Some(parse(start)?..parse(end)?)
+        })
+        .collect();
+    areas.sort_by_key(|a| a.start);
+    let mut merged: Vec<Range<u64>> = Vec::with_capacity(areas.len());
+    for area in areas {
+        match merged.last_mut() {
+            Some(last) if area.start <= last.end => last.end = last.end.max(area.end),
+            _ => merged.push(area),
+        }
+    }
+    merged
+}
+
+/// Replaces the segments of `/proc/kcore` outside of physical memory by their allocated `areas`
+///
+/// The vmalloc segment spans terabytes of mostly unmapped addresses, which cannot be searched or
+/// dumped as a whole.
+fn kernel_segments(segments: Vec<Segment>, areas: &[Range<u64>]) -> Vec<Segment> {
+    let mut kept = Vec::with_capacity(segments.len());
+    for segment in segments {
+        if segment.paddr != NO_PADDR {
+            kept.push(segment);
+            continue;
+        }
+        let saved = segment.start + segment.filesz;
+        for area in areas
+            .iter()
+            .filter(|a| a.start < segment.end && segment.start < a.end)
+        {
+            let start = area.start.max(segment.start);
+            let end = area.end.min(segment.end);
+            kept.push(Segment {
+                start,
+                end,
+                flags: segment.flags,
+                offset: segment.offset + (start - segment.start),
+                filesz: saved.min(end).saturating_sub(start),
+                paddr: NO_PADDR,
+            });
+        }
+    }
+    kept.sort_by_key(|s| s.start);
+    kept
+}
+
+/// An opened core file
+#[derive(Debug)]
+pub struct CoreFile {
+    file: File,
+    /// Sorted by address
+    segments: Vec<Segment>,
+    process: Process,
+}
+
+impl CoreFile {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file = File::open(path)?;
+        let mut header = vec![0; elf::EHDR_SIZE];
+        file.read_exact(&mut header)?;
+        let ehdr = ElfHeader::parse(&header)?;
+        if u16::from_le_bytes([header[16], header[17]]) != elf::ET_CORE {
+            return Err(Error::Elf(format!("{} is not a core file", path.display())));
+        }
+        // Registers are only parsed with the layout of this architecture
+        if u16::from_le_bytes([header[18], header[19]]) != elf::EM_HOST {
+            return Err(Error::Elf(format!(
+                "{} was not dumped on this architecture",
+                path.display()
+            )));
+        }
+
+        let mut data = vec![0; ehdr.e_phnum as usize * elf::PHDR_SIZE];
+        file.read_exact_at(&mut data, ehdr.e_phoff)?;
+        let mut segments = Vec::new();
+        let mut process = Process::default();
+        for data in data.chunks_exact(elf::PHDR_SIZE) {
+            let header = ProgramHeader::parse(data)?;
+            match header.p_type {
+                elf::PT_LOAD => segments.push(Segment {
+                    start: header.p_vaddr,
+                    end: header.p_vaddr + header.p_memsz,
+                    flags: header.p_flags,
+                    offset: header.p_offset,
+                    filesz: header.p_filesz,
+                    paddr: header.p_paddr,
+                }),
+                elf::PT_NOTE => {
+                    let mut notes = vec![0; header.p_filesz as usize];
+                    file.read_exact_at(&mut notes, header.p_offset)?;
+                    process = parse_notes(&notes)?;
+                }
+                _ => {}
+            }
+        }
+        segments.sort_by_key(|s| s.start);
+        Ok(Self {
+            file,
+            segments,
+            process,
+        })
+    }
+
+    /// Opens `/proc/kcore`, which only root can read
+    ///
+    /// The kernel has no threads to save the registers of. Segments outside of physical memory
+    /// are reduced to the areas listed in `/proc/vmallocinfo`, the rest cannot be read.
+    pub fn kernel() -> Result<Self> {
+        let mut core = Self::open(Path::new(KCORE)).map_err(|e| match e {
+            Error::IO(source) => {
+                let reason = match source.kind() {
+                    io::ErrorKind::PermissionDenied => "only root can read it".into(),
+                    _ => source.to_string(),
+                };
+                Error::IO(io::Error::new(
+                    source.kind(),
+                    format!("Cannot open {}: {}", KCORE, reason),
+                ))
+            }
+            e => e,
+        })?;
+        let areas = parse_vmallocinfo(&fs::read_to_string(VMALLOCINFO)?);
+        core.segments = kernel_segments(std::mem::take(&mut core.segments), &areas[..]);
+        core.process.threads.clear();
+        Ok(core)
+    }
+
+    /// Returns true if `path` starts like an ELF file
+    pub fn is_elf(path: &Path) -> bool {
+        let mut magic = [0; 4];
+        File::open(path).is_ok_and(|mut f| f.read_exact(&mut magic).is_ok()) && &magic == b"\x7fELF"
+    }
+
+    /// PID of the process, or of its first thread if the core has no `NT_PRPSINFO`
+    pub fn pid(&self) -> u32 {
+        self.process.pid
+    }
+
+    /// Name of the program, empty if unknown
+    pub fn name(&self) -> &str {
+        &self.process.name
+    }
+
+    /// Registers of each thread
+    pub fn threads(&self) -> &[Registers] {
+        &self.process.threads[..]
+    }
+
+    pub fn memory(&self) -> Memory {
+        Memory::from_regions(self.pid(), regions(&self.segments, &self.process.files))
+    }
+
+    /// Describes the core like a dump, unsaved ends of segments being unreadable
+    pub fn manifest(&self) -> Manifest {
+        let mut manifest = Manifest::new(self.pid(), Format::Core, Compression::None);
+        let memory = self.memory();
+        for (segment, region) in self.segments.iter().zip(memory.iter()) {
+            let saved = (segment.start + segment.filesz).min(segment.end) as usize;
+            manifest.entries.push(Entry {
+                region: region.clone(),
+                status: Status::Dumped,
+                thread: None,
+                file: None,
+                bytes: region.size() as u64,
+                stored_bytes: (saved - region.start) as u64,
+                crc32: None,
+                sha256: None,
+                unreadable: Some(saved..region.end)
+                    .filter(|r| !r.is_empty())
+                    .into_iter()
+                    .collect(),
+                entropy: None,
+                may_be_inconsistent: false,
+                changed: false,
+                read_time: None,
+            });
+        }
+        manifest
+    }
+
+    /// Reads the segment starting at `start`, with zeros for the bytes which were not saved
+    pub fn read_segment(&self, start: usize) -> Result<Vec<u8>> {
+        let segment = self
+            .segments
+            .iter()
+            .find(|s| s.start == start as u64)
+            .ok_or(Error::RegionNotFound { start, end: start })?;
+        let mut data = vec![0; (segment.end - segment.start) as usize];
+        let saved = (segment.filesz as usize).min(data.len());
+        self.file
+            .read_exact_at(&mut data[..saved], segment.offset)?;
+        Ok(data)
+    }
+
+    /// Reads `buffer.len()` bytes at `addr`, failing if any of them was not saved
+    pub fn read(&self, addr: usize, buffer: &mut [u8]) -> Result<()> {
+        let mut done = 0;
+        while done < buffer.len() {
+            let address = (addr + done) as u64;
+            let idx = self.segments.partition_point(|s| s.end <= address);
+            let reason = match self.segments.get(idx).filter(|s| s.start <= address) {
+                Some(segment) if address < segment.start + segment.filesz => {
+                    let len = (segment.start + segment.filesz - address)
+                        .min((buffer.len() - done) as u64) as usize;
+                    let offset = segment.offset + (address - segment.start);
+                    self.file
+                        .read_exact_at(&mut buffer[done..done + len], offset)
+                        .map_err(|source| Error::Read {
+                            pid: self.pid(),
+                            address: address as usize,
+                            source,
+                        })?;
+                    done += len;
+                    continue;
+                }
+                Some(_) => "not saved in the core file",
+                None => "not mapped",
+            };
+            return Err(Error::Read {
+                pid: self.pid(),
+                address: address as usize,
+                source: io::Error::other(reason),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coredump::Notes;
+    use crate::memory::RegionKind;
+
+    #[test]
+    fn test_parse_notes() {
+        let mut notes = Notes::default();
+        for tid in [42u32, 43] {
+            let mut prstatus = vec![0; PR_REG];
+            prstatus[PR_STATUS_PID..PR_STATUS_PID + 4].copy_from_slice(&tid.to_le_bytes());
+            let gp = regs::UserRegs::with_frame(tid as u64 * 0x1000, 0, 0);
+            prstatus.extend_from_slice(regs::as_bytes(&gp));
+            notes.push("CORE", elf::NT_PRSTATUS, &prstatus);
+        }
+        let fp = regs::UserFpRegs::default();
+        notes.push("CORE", elf::NT_PRFPREG, regs::as_bytes(&fp));
+        let mut prpsinfo = vec![0; PR_FNAME + 16 + 80];
+        prpsinfo[PR_PSINFO_PID..PR_PSINFO_PID + 4].copy_from_slice(&42u32.to_le_bytes());
+        prpsinfo[PR_FNAME..PR_FNAME + 4].copy_from_slice(b"prog");
+        notes.push("CORE", elf::NT_PRPSINFO, &prpsinfo);
+        let mut files = Vec::new();
+        for word in [1, 0x1000, 0x40_0000, 0x40_2000, 2] {
+            files.extend_from_slice(&(word as u64).to_le_bytes());
+        }
+        files.extend_from_slice(b"/usr/bin/prog (deleted)\0");
+        notes.push("CORE", elf::NT_FILE, &files);
+
+        let process = parse_notes(notes.as_bytes()).unwrap();
+        assert_eq!(process.pid, 42);
+        assert_eq!(process.name, "prog");
+        assert_eq!(process.threads.len(), 2);
+        assert_eq!(process.threads[1].tid, 43);
+        assert_eq!(process.threads[1].gp.pc(), 0x2b000);
+        assert!(process.threads[0].fp.is_none());
+        assert!(process.threads[1].fp.is_some());
+        assert_eq!(
+            process.files,
+            [FileMapping {
+                start: 0x40_0000,
+                end: 0x40_2000,
+                offset: 0x2000,
+                path: "/usr/bin/prog (deleted)".into(),
+            }]
+        );
+
+        let segments = [
+            Segment {
+                start: 0x40_1000,
+                end: 0x40_2000,
+                flags: elf::PF_R | elf::PF_X,
+                offset: 0x1000,
+                filesz: 0,
+                paddr: 0,
+            },
+            Segment {
+                start: 0x7ff0_0000,
+                end: 0x7ff1_0000,
+                flags: elf::PF_R | elf::PF_W,
+                offset: 0x2000,
+                filesz: 0x10000,
+                paddr: 0,
+            },
+        ];
+        let regions = regions(&segments, &process.files);
+        assert_eq!(regions[0].offset, 0x3000);
+        assert_eq!(regions[0].path(), Some("/usr/bin/prog"));
+        assert!(regions[0].deleted);
+        assert_eq!(regions[0].perms.to_maps_string(), "r-xp");
+        assert_eq!(regions[1].kind, RegionKind::Anonymous);
+        assert_eq!(regions[1].perms.to_maps_string(), "rw-p");
+    }
+
+    #[test]
+    fn test_kernel_segments() {
+        let areas = parse_vmallocinfo(
+            "0xffffc90000000000-0xffffc90000005000   20480 irq_init_percpu_irqstack+0x114/0x1b0 vmap\n\
+             0xffffc90000004000-0xffffc90000009000   20480 dup_task_struct+0x4c/0x190 pages=4 vmalloc\n\
+             0xffffc90000010000-0xffffc90000012000    8192 acpi_os_map_iomem+0x1b2/0x1e0 phys=0x00000000fed00000 ioremap\n\
+             0xffffffffc0000000-0xffffffffc0008000   32768 load_module+0x1a3/0x2a0 pages=7 vmalloc N0=7\n\
+             malformed\n",
+        );
+        assert_eq!(
+            areas,
+            [
+                0xffff_c900_0000_0000..0xffff_c900_0000_9000,
+                0xffff_c900_0001_0000..0xffff_c900_0001_2000,
+                0xffff_ffff_c000_0000..0xffff_ffff_c000_8000,
+            ]
+        );
+
+        let segment = |start: u64, end: u64, offset: u64, paddr: u64| Segment {
+            start,
+            end,
+            flags: elf::PF_R | elf::PF_W | elf::PF_X,
+            offset,
+            filesz: end - start,
+            paddr,
+        };
+        let segments = vec![
+            segment(
+                0xffff_c900_0000_0000,
+                0xffff_e8ff_ffff_ffff,
+                0x1000,
+                NO_PADDR,
+            ),
+            segment(0xffff_8880_0000_0000, 0xffff_8880_8000_0000, 0x2000, 0),
+            segment(
+                0xffff_ea00_0000_0000,
+                0xffff_ea00_0200_0000,
+                0x3000,
+                NO_PADDR,
+            ),
+        ];
+        let segments = kernel_segments(segments, &areas[..]);
+        assert_eq!(
+            segments,
+            [
+                segment(0xffff_8880_0000_0000, 0xffff_8880_8000_0000, 0x2000, 0),
+                segment(
+                    0xffff_c900_0000_0000,
+                    0xffff_c900_0000_9000,
+                    0x1000,
+                    NO_PADDR
+                ),
+                segment(
+                    0xffff_c900_0001_0000,
+                    0xffff_c900_0001_2000,
+                    0x11000,
+                    NO_PADDR
+                ),
+            ]
+        );
+    }
+}
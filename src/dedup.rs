@@ -0,0 +1,236 @@
+//! Content-addressed page store
+//!
+//! Each distinct page is written once, to `<store>/<xx>/<rest of its SHA-256>`. A region is
+//! stored as a `<name>.pages` index next to the manifest, holding the SHA-256 of each of its pages.
+//! An all-zero hash stands for a zero page, which is not stored. The store can be shared by
+//! several dumps, such as the processes of a tree or periodic snapshots.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use crate::checksum;
+use crate::compress::Compression;
+use crate::json::Value;
+use crate::manifest::{Manifest, PageStore};
+use crate::memory::Region;
+use crate::output::{Directory, Output, Source, Stored};
+use crate::pagemap;
+use crate::regs::Registers;
+use crate::{Error, Result};
+
+const HASH_SIZE: usize = 32;
+const ZERO_HASH: [u8; HASH_SIZE] = [0; HASH_SIZE];
+
+/// Path of the page with hash `hash` in `store`
+fn page_path(store: &Path, hash: &[u8]) -> PathBuf {
+    let hex = checksum::to_hex(hash);
+    store.join(&hex[..2]).join(&hex[2..])
+}
+
+/// Returns the path leading from directory `from` to `to`, both relative to the same directory
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let mut path = PathBuf::new();
+    for _ in common..from.len() {
+        path.push("..");
+    }
+    path.extend(&to[common..]);
+    path
+}
+
+/// Sink splitting data in pages, storing new ones and writing their hashes to an index
+struct PageWriter<'a> {
+    index: BufWriter<File>,
+    store: &'a Path,
+    page: Vec<u8>,
+    page_size: usize,
+
+    /// Bytes written to the index and the store
+    stored: u64,
+}
+
+impl PageWriter<'_> {
+    fn store_page(&mut self) -> io::Result<()> {
+        if self.page.iter().all(|b| *b == 0) {
+            self.index.write_all(&ZERO_HASH)?;
+        } else {
+            let hash = checksum::sha256(&self.page[..]);
+            let path = page_path(self.store, &hash);
+            if !path.exists() {
+                std::fs::create_dir_all(path.parent().unwrap())?;
+                // Another process of the tree may store the same page concurrently
+                match OpenOptions::new().write(true).create_new(true).open(&path) {
+                    Ok(mut file) => {
+                        file.write_all(&self.page[..])?;
+                        self.stored += self.page.len() as u64;
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            self.index.write_all(&hash)?;
+        }
+        self.stored += HASH_SIZE as u64;
+        self.page.clear();
+        Ok(())
+    }
+
+    /// Stores the last partial page, if any
+    fn finish(mut self) -> io::Result<u64> {
+        if !self.page.is_empty() {
+            self.store_page()?;
+        }
+        self.index.flush()?;
+        Ok(self.stored)
+    }
+}
+
+impl Write for PageWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = (self.page_size - self.page.len()).min(buf.len());
+        self.page.extend_from_slice(&buf[..n]);
+        if self.page.len() == self.page_size {
+            self.store_page()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.index.flush()
+    }
+}
+
+/// Writes regions as page indexes, and their pages in a shared store
+#[derive(Debug)]
+pub struct Dedup {
+    directory: Directory,
+    path: PathBuf,
+    store: PathBuf,
+    page_size: usize,
+}
+
+impl Dedup {
+    /// Creates a dump in `path`, storing pages in `store`
+    pub fn create(path: &Path, store: &Path) -> Result<Self> {
+        std::fs::create_dir_all(store)?;
+        Ok(Self {
+            directory: Directory::create(path, Compression::None)?,
+            path: path.into(),
+            store: store.into(),
+            page_size: pagemap::page_size(),
+        })
+    }
+
+    /// Writes the registers of each thread to `registers/<tid>.json`
+    pub fn write_registers(&self, threads: &[Registers]) -> Result<()> {
+        self.directory.write_registers(threads)
+    }
+
+    /// Writes files of `/proc/PID` to `proc/<name>`
+    pub fn write_proc(&self, files: &[(&str, Vec<u8>)]) -> Result<()> {
+        self.directory.write_proc(files)
+    }
+
+    /// Writes the call stacks of the threads to `backtraces.txt`
+    pub fn write_backtraces(&self, text: &str) -> Result<()> {
+        self.directory.write_backtraces(text)
+    }
+
+    /// Writes the file descriptors of the process to `fds.json`
+    pub fn write_fds(&self, fds: &Value) -> Result<()> {
+        self.directory.write_fds(fds)
+    }
+
+    /// Copies `file` to `name`, as returned by [`crate::output::mapped_file_name`], returning its size
+    pub fn write_mapped_file(&self, name: &str, file: &mut File) -> Result<u64> {
+        self.directory.write_mapped_file(name, file)
+    }
+}
+
+impl Output for Dedup {
+    fn write_region(
+        &mut self,
+        _region: &Region,
+        name: &str,
+        source: &mut Source,
+    ) -> Result<Stored> {
+        let filename = format!("{}.pages", name);
+        let path = self.path.join(&filename);
+        let mut writer = PageWriter {
+            index: BufWriter::new(File::create(&path)?),
+            store: &self.store,
+            page: Vec::with_capacity(self.page_size),
+            page_size: self.page_size,
+            stored: 0,
+        };
+        match source(&mut writer).and_then(|_| Ok(writer.finish()?)) {
+            Ok(size) => Ok(Stored {
+                file: Some(filename),
+                size,
+            }),
+            Err(e) => {
+                // Best effort, the region failure matters more. Stored pages are still valid.
+                let _ = std::fs::remove_file(&path);
+                Err(e)
+            }
+        }
+    }
+
+    /// Only the page index is removed, its pages may be shared
+    fn discard(&mut self, name: &str) -> Result<()> {
+        Ok(std::fs::remove_file(self.path.join(name))?)
+    }
+
+    /// Records the location of the store in the manifest, relatively to the dump
+    fn finish(self: Box<Self>, manifest: &Manifest) -> Result<()> {
+        let mut manifest = manifest.clone();
+        manifest.store = Some(PageStore {
+            path: relative_path(&self.path, &self.store),
+            page_size: self.page_size,
+        });
+        Box::new(self.directory).finish(&manifest)
+    }
+}
+
+/// Rebuilds the content of a region from its page index
+pub fn read(index: &[u8], store: &Path, page_size: usize) -> Result<Vec<u8>> {
+    if !index.len().is_multiple_of(HASH_SIZE) {
+        return Err(Error::MalformedState {
+            field: "pages",
+            value: format!("{} bytes index", index.len()),
+        });
+    }
+    let mut data = Vec::with_capacity(index.len() / HASH_SIZE * page_size);
+    for hash in index.chunks_exact(HASH_SIZE) {
+        if hash == ZERO_HASH {
+            data.resize(data.len() + page_size, 0);
+        } else {
+            File::open(page_path(store, hash))?.read_to_end(&mut data)?;
+        }
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(
+            relative_path(Path::new("out"), Path::new("out/pages")),
+            Path::new("pages")
+        );
+        assert_eq!(
+            relative_path(Path::new("out/1234"), Path::new("out/pages")),
+            Path::new("../pages")
+        );
+        assert_eq!(
+            relative_path(Path::new("/a/b/c"), Path::new("/a/pages")),
+            Path::new("../../pages")
+        );
+    }
+}
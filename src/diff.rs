@@ -0,0 +1,179 @@
+//! Comparison of two dumps of the same process
+
+use std::ops::Range;
+
+use crate::cli::DiffOptions;
+use crate::hexdump;
+use crate::manifest::Entry;
+use crate::memory::Region;
+use crate::pagemap;
+use crate::snapshot::Snapshot;
+use crate::Result;
+
+fn describe(region: &Region) -> String {
+    format!(
+        "{:x}-{:x} {} {}",
+        region.start,
+        region.end,
+        region.perms.to_maps_string(),
+        region.path().unwrap_or("")
+    )
+}
+
+/// Returns the byte ranges which differ, over the common length of `old` and `new`
+fn changed_ranges(old: &[u8], new: &[u8]) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for (offset, _) in old.iter().zip(new).enumerate().filter(|(_, (a, b))| a != b) {
+        match ranges.last_mut() {
+            Some(last) if last.end == offset => last.end += 1,
+            _ => ranges.push(offset..offset + 1),
+        }
+    }
+    ranges
+}
+
+/// Returns the offsets of the pages which differ, over the common length of `old` and `new`
+fn changed_pages(old: &[u8], new: &[u8], page_size: usize) -> Vec<usize> {
+    old.chunks(page_size)
+        .zip(new.chunks(page_size))
+        .enumerate()
+        .filter(|(_, (a, b))| a != b)
+        .map(|(i, _)| i * page_size)
+        .collect()
+}
+
+/// Prints the lines of `range` as a unified hex diff
+fn print_hex_diff(base: usize, old: &[u8], new: &[u8], range: &Range<usize>) {
+    let start = range.start - range.start % hexdump::WIDTH;
+    let end = range.end.div_ceil(hexdump::WIDTH) * hexdump::WIDTH;
+    println!("    @@ 0x{:x} @@", base + start);
+    for line in (start..end).step_by(hexdump::WIDTH) {
+        let line_end = (line + hexdump::WIDTH).min(old.len()).min(new.len());
+        println!("    -{}", hexdump::line(base + line, &old[line..line_end]));
+        println!("    +{}", hexdump::line(base + line, &new[line..line_end]));
+    }
+}
+
+fn diff_region(options: &DiffOptions, old: &Entry, new: &Entry, old_data: &[u8], new_data: &[u8]) {
+    let base = new.region.start;
+    if options.pages {
+        let page_size = pagemap::page_size();
+        let pages = changed_pages(old_data, new_data, page_size);
+        if pages.is_empty() {
+            return;
+        }
+        println!("! {}: {} pages changed", describe(&new.region), pages.len());
+        for page in pages {
+            println!("    0x{:x}-0x{:x}", base + page, base + page + page_size);
+        }
+        return;
+    }
+
+    let ranges = changed_ranges(old_data, new_data);
+    if ranges.is_empty() {
+        return;
+    }
+    let bytes: usize = ranges.iter().map(|r| r.len()).sum();
+    println!(
+        "! {}: {} bytes changed in {} ranges",
+        describe(&new.region),
+        bytes,
+        ranges.len()
+    );
+    for range in &ranges {
+        if options.hex {
+            print_hex_diff(base, old_data, new_data, range);
+        } else {
+            println!(
+                "    0x{:x}-0x{:x} ({} bytes)",
+                base + range.start,
+                base + range.end,
+                range.len()
+            );
+        }
+    }
+    if old.region.size() != new.region.size() {
+        let compared = old_data.len().min(new_data.len());
+        println!("    (only the first 0x{:x} bytes were compared)", compared);
+    }
+}
+
+pub fn run(options: &DiffOptions) -> Result<()> {
+    let old = Snapshot::open(&options.old)?;
+    let new = Snapshot::open(&options.new)?;
+    if old.manifest.pid != new.manifest.pid {
+        warn!(
+            "Comparing dumps of different processes ({} and {})",
+            old.manifest.pid, new.manifest.pid
+        );
+    }
+
+    // Regions are matched by start address and backing file
+    let same = |a: &Entry, b: &Entry| {
+        a.region.start == b.region.start && a.region.path() == b.region.path()
+    };
+    let mut matched = vec![false; new.manifest.entries.len()];
+    for old_entry in &old.manifest.entries {
+        let found = new.manifest.entries.iter().position(|e| same(old_entry, e));
+        let Some(idx) = found else {
+            println!("- {}", describe(&old_entry.region));
+            continue;
+        };
+        matched[idx] = true;
+        let new_entry = &new.manifest.entries[idx];
+        if old_entry.region.end != new_entry.region.end {
+            println!(
+                "~ {} (resized from 0x{:x} to 0x{:x} bytes)",
+                describe(&new_entry.region),
+                old_entry.region.size(),
+                new_entry.region.size()
+            );
+        }
+        if old_entry.crc32.is_some() && old_entry.crc32 == new_entry.crc32 {
+            continue;
+        }
+        match (old.read(old_entry)?, new.read(new_entry)?) {
+            (Some(old_data), Some(new_data)) => {
+                diff_region(options, old_entry, new_entry, &old_data, &new_data)
+            }
+            _ => println!(
+                "? {}: not dumped in both snapshots",
+                describe(&new_entry.region)
+            ),
+        }
+    }
+    for (entry, _) in new
+        .manifest
+        .entries
+        .iter()
+        .zip(matched)
+        .filter(|(_, matched)| !matched)
+    {
+        println!("+ {}", describe(&entry.region));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changed_ranges() {
+        let old = b"hello world, hello!";
+        let new = b"hellO wOrld, hello?";
+        assert_eq!(changed_ranges(old, new), vec![4..5, 7..8, 18..19]);
+        assert_eq!(changed_ranges(b"abc", b"abcdef"), vec![]);
+        assert_eq!(changed_ranges(b"abcd", b"xyzd"), vec![0..3]);
+    }
+
+    #[test]
+    fn test_changed_pages() {
+        let old = vec![0u8; 10];
+        let mut new = old.clone();
+        new[5] = 1;
+        new[9] = 1;
+        assert_eq!(changed_pages(&old, &new, 4), vec![4, 8]);
+    }
+}
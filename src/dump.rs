@@ -0,0 +1,1607 @@
+use std::collections::{HashSet, VecDeque};
+use std::fs::File;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::backtrace;
+use crate::cgroup;
+use crate::checksum::{self, Crc32, Sha256};
+use crate::cli::DumpOptions;
+use crate::compress::Compression;
+use crate::coredump::CoreDump;
+use crate::corefile::CoreFile;
+use crate::dedup::Dedup;
+use crate::entropy;
+use crate::error::Chain;
+use crate::fds;
+use crate::logger;
+use crate::manifest::{Entry, Manifest, Status};
+use crate::maps::kib;
+#[cfg(feature = "secrets")]
+use crate::memmem;
+use crate::memory::{Memory, PermissionBits, Region, RegionKind};
+use crate::minidump::Minidump;
+use crate::output::{self, Directory, Format, Output};
+use crate::pagemap;
+use crate::pattern::Pattern;
+use crate::procfs;
+use crate::ptrace::{Attach, Ptrace};
+use crate::regs::Registers;
+use crate::remote::Remote;
+use crate::signal;
+use crate::tar::Tar;
+use crate::target::{Reader, Target};
+use crate::timestamp;
+use crate::{Error, Result};
+
+pub fn get_program_name(pid: u32) -> Result<String> {
+    let mut invocation = std::fs::read_to_string(format!("/proc/{}/cmdline", pid))?;
+
+    if let Some(nullbyte) = invocation.find('\0') {
+        invocation.truncate(nullbyte);
+    }
+
+    Ok(invocation)
+}
+
+/// Finds the stack region of each thread
+///
+/// The stack pointer comes from `/proc/PID/task/TID/stat` when the kernel fills it, and from the
+/// registers otherwise, or always for core files where `pid` is `None`. A region shared by several
+/// threads is only returned for the first one.
+fn find_stacks<'a>(
+    pid: Option<u32>,
+    memory: &'a Memory,
+    threads: &[Registers],
+) -> Result<Vec<(&'a Region, u32)>> {
+    let mut stacks: Vec<(&Region, u32)> = Vec::new();
+    for regs in threads {
+        let kstkesp = match pid {
+            Some(pid) => procfs::task_stat(pid, regs.tid)?.kstkesp,
+            None => 0,
+        };
+        let sp = match kstkesp {
+            0 => regs.gp.sp(),
+            sp => sp,
+        };
+        match memory.region_containing(sp as usize) {
+            Some(region) if !stacks.iter().any(|(r, _)| r.start == region.start) => {
+                stacks.push((region, regs.tid))
+            }
+            Some(_) => {}
+            None => warn!(
+                "Stack pointer 0x{:x} of thread {} is not mapped",
+                sp, regs.tid
+            ),
+        }
+    }
+    Ok(stacks)
+}
+
+/// Dumps `region`, only reading resident pages of sparse anonymous mappings of live processes
+///
+/// Returns the ranges which could not be read.
+fn dump_region(
+    reader: &mut Reader,
+    region: &Region,
+    sink: &mut dyn Write,
+) -> Result<Vec<Range<usize>>> {
+    if reader.is_live() && pagemap::is_sparse_candidate(region) {
+        if let Ok(ranges) = pagemap::resident_ranges(reader.pid(), region) {
+            debug!(
+                "Reading {} resident ranges of region {:x}-{:x}",
+                ranges.len(),
+                region.start,
+                region.end
+            );
+            return reader.dump_ranges(region, &ranges[..], sink);
+        }
+    }
+    reader.dump(region, sink)
+}
+
+#[cfg(feature = "secrets")]
+const SECRET_PATTERNS: [&str; 3] = ["private", "secret", "password"];
+
+/// Looks for [`SECRET_PATTERNS`] in data received in chunks, keeping the first match of each
+#[cfg(feature = "secrets")]
+#[derive(Debug, Default)]
+struct SecretScanner {
+    /// Offset of the next chunk
+    offset: usize,
+
+    /// End of the previous chunks, for matches spanning two chunks
+    tail: Vec<u8>,
+
+    found: [Option<usize>; SECRET_PATTERNS.len()],
+}
+
+#[cfg(feature = "secrets")]
+impl SecretScanner {
+    fn update(&mut self, data: &[u8]) {
+        let keep = SECRET_PATTERNS.iter().map(|p| p.len()).max().unwrap_or(1) - 1;
+        let mut window = std::mem::take(&mut self.tail);
+        let window_offset = self.offset - window.len();
+        window.extend_from_slice(&data[..data.len().min(keep)]);
+        for (pattern, found) in SECRET_PATTERNS.iter().zip(&mut self.found) {
+            if found.is_some() {
+                continue;
+            }
+            *found = memmem::find_no_case(&window[..], pattern.as_bytes())
+                .map(|idx| window_offset + idx)
+                .or_else(|| {
+                    memmem::find_no_case(data, pattern.as_bytes()).map(|idx| self.offset + idx)
+                });
+        }
+        self.tail = if data.len() >= keep {
+            data[data.len() - keep..].to_vec()
+        } else {
+            window[window.len().saturating_sub(keep)..].to_vec()
+        };
+        self.offset += data.len();
+    }
+
+    /// Patterns found, with the offset of their first match
+    fn found(&self) -> impl Iterator<Item = (&'static str, usize)> + '_ {
+        SECRET_PATTERNS
+            .iter()
+            .zip(&self.found)
+            .filter_map(|(pattern, found)| Some((*pattern, (*found)?)))
+    }
+}
+
+/// What is learnt about a region while it is written
+#[derive(Debug, Default)]
+struct Inspection {
+    bytes: u64,
+    crc32: Crc32,
+    sha256: Option<Sha256>,
+    entropy: Option<entropy::Meter>,
+
+    /// Whether the output failed, rather than the process memory
+    write_failed: bool,
+
+    #[cfg(feature = "secrets")]
+    secrets: SecretScanner,
+}
+
+/// Sink inspecting the data written to the output
+struct Inspect<'a> {
+    inner: &'a mut dyn Write,
+    inspection: &'a mut Inspection,
+}
+
+impl Write for Inspect<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Not `ErrorKind::Interrupted`, which `write_all` retries
+        if signal::interrupted() {
+            return Err(io::Error::other("interrupted by signal"));
+        }
+        let written = self.inner.write(buf).inspect_err(|_| {
+            self.inspection.write_failed = true;
+        })?;
+        let data = &buf[..written];
+        self.inspection.bytes += written as u64;
+        self.inspection.crc32.update(data);
+        if let Some(ref mut sha256) = self.inspection.sha256 {
+            sha256.update(data);
+        }
+        if let Some(ref mut entropy) = self.inspection.entropy {
+            entropy.update(data);
+        }
+        #[cfg(feature = "secrets")]
+        self.inspection.secrets.update(data);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Processes dumped by a single invocation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Targets {
+    /// Only the given process
+    #[default]
+    Process,
+
+    /// The process and its direct children
+    Children,
+
+    /// The process and all its descendants
+    Tree,
+}
+
+/// The processes dumped by each snapshot
+#[derive(Debug)]
+enum Processes {
+    /// A process, with its children or descendants for `--children` and `--tree`
+    Process(u32),
+
+    /// The processes of a cgroup directory
+    Cgroup(PathBuf),
+}
+
+impl Processes {
+    /// Whether a single process is dumped, to OUTPUT instead of a per-PID entry of OUTPUT
+    ///
+    /// Children forked during the dump are dumped too with `--follow-forks`.
+    fn is_single(&self, options: &DumpOptions) -> bool {
+        matches!(self, Self::Process(_))
+            && options.targets == Targets::Process
+            && !options.follow_forks
+    }
+
+    /// Default name of the output
+    fn name(&self) -> Result<String> {
+        match *self {
+            Self::Process(pid) => Ok(format!("{}-{}", get_program_name(pid)?, pid)),
+            Self::Cgroup(ref dir) => Ok(format!(
+                "cgroup-{}",
+                dir.file_name().unwrap_or_default().to_string_lossy()
+            )),
+        }
+    }
+
+    /// Name of the output when none is given
+    fn output_name(&self, options: &DumpOptions) -> Result<String> {
+        match *self {
+            Self::Process(pid) => {
+                output_name(options, self.name()?, Some((&procfs::stat(pid)?.comm, pid)))
+            }
+            Self::Cgroup(_) => output_name(options, self.name()?, None),
+        }
+    }
+
+    /// Lists the processes to dump now
+    ///
+    /// The first one, if any, must be dumped. The others may exit or fail in between, they are
+    /// reported and skipped.
+    fn list(&self, options: &DumpOptions) -> Result<(Option<u32>, Vec<u32>)> {
+        match *self {
+            Self::Process(pid) => {
+                let others = match options.targets {
+                    Targets::Process => Vec::new(),
+                    Targets::Children => procfs::children(pid)?,
+                    Targets::Tree => procfs::descendants(pid)?,
+                };
+                Ok((Some(pid), others))
+            }
+            Self::Cgroup(ref dir) => {
+                let pids = cgroup::procs(dir)?;
+                if pids.is_empty() {
+                    return Err(Error::Usage(format!(
+                        "No process in cgroup {}",
+                        dir.display()
+                    )));
+                }
+                Ok((None, pids))
+            }
+        }
+    }
+}
+
+/// Path of the dump named `name` in `dir`
+fn entry_path(dir: &Path, name: &str, format: Format) -> PathBuf {
+    match format {
+        Format::Directory | Format::Dedup => dir.join(name),
+        Format::Core => dir.join(format!("{}.core", name)),
+        Format::Tar => dir.join(format!("{}.tar", name)),
+        Format::Minidump => dir.join(format!("{}.dmp", name)),
+    }
+}
+
+/// Name of the output when none is given: the `--name` template with its fields replaced, or
+/// `default`
+///
+/// The fields are `{name}` (`default`), `{comm}` and `{pid}` of the process dumped if there is one,
+/// `{host}`, and `{time}`, the UTC time of the dump.
+fn output_name(
+    options: &DumpOptions,
+    default: String,
+    process: Option<(&str, u32)>,
+) -> Result<String> {
+    let Some(ref template) = options.name else {
+        return Ok(default);
+    };
+    let mut name = String::new();
+    let mut rest = template.as_str();
+    while let Some(start) = rest.find('{') {
+        name.push_str(&rest[..start]);
+        let (field, after) = rest[start + 1..]
+            .split_once('}')
+            .ok_or_else(|| Error::Usage(format!("Unclosed `{{` in --name {:?}", template)))?;
+        match (field, process) {
+            ("name", _) => name.push_str(&default),
+            ("comm", Some((comm, _))) => name.push_str(comm),
+            ("pid", Some((_, pid))) => name.push_str(&pid.to_string()),
+            ("comm" | "pid", None) => {
+                return Err(Error::Usage(format!(
+                    "{{{}}} of --name is not known when dumping a cgroup",
+                    field
+                )))
+            }
+            ("host", _) => {
+                name.push_str(std::fs::read_to_string("/proc/sys/kernel/hostname")?.trim())
+            }
+            ("time", _) => name.push_str(&timestamp::utc(SystemTime::now())),
+            _ => {
+                return Err(Error::Usage(format!(
+                    "Unknown field {{{}}} in --name {:?}",
+                    field, template
+                )))
+            }
+        }
+        rest = after;
+    }
+    name.push_str(rest);
+    Ok(name)
+}
+
+pub fn run(options: &DumpOptions) -> Result<()> {
+    if options.events {
+        let stream: Box<dyn Write + Send> = match options.events_output {
+            Some(ref path) => Box::new(File::create(path)?),
+            None => Box::new(io::stdout()),
+        };
+        logger::set_events(stream);
+    }
+    let processes = match options.target {
+        Target::Process(pid) => Processes::Process(pid),
+        Target::Cgroup(ref cgroup) => Processes::Cgroup(cgroup.resolve()?),
+        Target::Core(ref path) => return dump_core(options, CoreFile::open(path)?),
+        Target::Kernel => return dump_core(options, CoreFile::kernel()?),
+        Target::Remote(ref address, pid) => return dump_remote(options, address, pid),
+        Target::Dump(_) => return Err(Error::Usage("Dumps cannot be dumped again".into())),
+    };
+    if options.dry_run {
+        return dry_run(options, &processes);
+    }
+
+    let single = processes.is_single(options);
+    let Some(interval) = options.interval else {
+        let output_path = match options.output {
+            Some(ref path) => path.clone(),
+            None if single => entry_path(
+                Path::new(""),
+                &processes.output_name(options)?,
+                options.format,
+            ),
+            None => processes.output_name(options)?.into(),
+        };
+        return snapshot(
+            options,
+            &processes,
+            &output_path,
+            &output_path.join("pages"),
+        );
+    };
+
+    // Snapshots are written in timestamped entries of the output directory
+    let output_dir = match options.output {
+        Some(ref path) => path.clone(),
+        None => PathBuf::from(processes.output_name(options)?),
+    };
+    std::fs::create_dir_all(&output_dir)?;
+    let store = output_dir.join("pages");
+    let mut iteration = 0;
+    loop {
+        let timestamp = timestamp::utc(SystemTime::now());
+        if single {
+            snapshot(
+                options,
+                &processes,
+                &entry_path(&output_dir, &timestamp, options.format),
+                &store,
+            )?;
+        } else {
+            snapshot(options, &processes, &output_dir.join(timestamp), &store)?;
+        }
+        iteration += 1;
+        if options.count.is_some_and(|count| iteration >= count) {
+            return Ok(());
+        }
+        signal::sleep(interval)?;
+    }
+}
+
+/// Dumps the processes to `output_path`, in per-PID entries when dumping several ones
+///
+/// Pages of the `dedup` format are stored in `store`.
+fn snapshot(
+    options: &DumpOptions,
+    processes: &Processes,
+    output_path: &Path,
+    store: &Path,
+) -> Result<()> {
+    let (first, others) = processes.list(options)?;
+    if let (Some(pid), true) = (first, processes.is_single(options)) {
+        return dump_once(options, pid, output_path, store).map(drop);
+    }
+
+    std::fs::create_dir_all(output_path)?;
+    // With --follow-forks, the processes dumped are resumed but stay traced until the end, and
+    // the children they fork are dumped too. Once the others are dumped, they are all stopped
+    // again: the children forked until then are dumped while nothing runs anymore.
+    let mut traced = Vec::new();
+    let mut forked = VecDeque::new();
+    let mut stopped = false;
+    if let Some(pid) = first {
+        let process = dump_once(
+            options,
+            pid,
+            &entry_path(output_path, &pid.to_string(), options.format),
+            store,
+        )?;
+        keep_traced(&mut traced, process, stopped)?;
+    }
+    let mut last_error = None;
+    let mut dumped = first.is_some();
+    let mut others = others.into_iter();
+    loop {
+        for process in &mut traced {
+            process.poll()?;
+            forked.extend(process.take_forked());
+        }
+        let (pid, result) = if let Some(pid) = forked.pop_front() {
+            let path = entry_path(output_path, &pid.to_string(), options.format);
+            match adopt(options, pid) {
+                Ok(Some(process)) => (pid, dump_process(options, process, &path, store)),
+                // Short-lived children are common
+                Ok(None) => {
+                    info!("Process {} exited before it could be dumped", pid);
+                    continue;
+                }
+                Err(e) => (pid, Err(e)),
+            }
+        } else if let Some(pid) = others.next() {
+            let path = entry_path(output_path, &pid.to_string(), options.format);
+            (pid, dump_once(options, pid, &path, store))
+        } else if !stopped && !traced.is_empty() {
+            for process in &mut traced {
+                process.interrupt()?;
+                forked.extend(process.take_forked());
+            }
+            stopped = true;
+            continue;
+        } else {
+            break;
+        };
+        match result {
+            Ok(process) => {
+                keep_traced(&mut traced, process, stopped)?;
+                dumped = true;
+            }
+            Err(Error::Interrupted) => return Err(Error::Interrupted),
+            Err(e) if options.strict => return Err(e),
+            Err(e) => {
+                error!(
+                    event = "process_failed", pid, error:% = Chain(&e);
+                    "Could not dump process {}: {}", pid, Chain(&e)
+                );
+                last_error = Some(e);
+            }
+        }
+    }
+    // Without a first process, fail only if none of the others could be dumped
+    match last_error {
+        Some(e) if !dumped => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Keeps a process dumped with `--follow-forks` traced, running unless every process is `stopped`
+fn keep_traced(traced: &mut Vec<Ptrace>, process: Option<Ptrace>, stopped: bool) -> Result<()> {
+    if let Some(mut process) = process {
+        if !stopped {
+            process.resume()?;
+        }
+        traced.push(process);
+    }
+    Ok(())
+}
+
+/// Writes the SHA-256 of the region files of a `dir` dump, in the `sha256sum` format
+fn write_sha256sums(path: &Path, manifest: &Manifest) -> Result<()> {
+    let mut sums = String::new();
+    for entry in &manifest.entries {
+        if let (Some(file), Some(sha256)) = (&entry.file, &entry.sha256) {
+            sums.push_str(&format!("{}  {}\n", checksum::to_hex(sha256), file));
+        }
+    }
+    std::fs::write(path, sums)?;
+    Ok(())
+}
+
+/// Prints the entropy of each dumped region to `out`, marking suspicious ones with a `!`
+fn print_entropy_table(out: &mut dyn Write, manifest: &Manifest) -> io::Result<()> {
+    writeln!(
+        out,
+        "{:>16} {:>16} {:5} {:>7} {:>7}   PATH",
+        "START", "END", "PERMS", "ENTROPY", "MAX 4K"
+    )?;
+    for entry in &manifest.entries {
+        let Some(entropy) = entry.entropy else {
+            continue;
+        };
+        let region = &entry.region;
+        writeln!(
+            out,
+            "{:>16x} {:>16x} {:5} {:>7.3} {:>7.3} {} {}",
+            region.start,
+            region.end,
+            region.perms.to_maps_string(),
+            entropy.region,
+            entropy.max_window,
+            if entropy.is_suspicious(region) {
+                '!'
+            } else {
+                ' '
+            },
+            region.path().unwrap_or("")
+        )?;
+    }
+    Ok(())
+}
+
+/// Number of regions, and their size, by outcome of a dump
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Summary {
+    /// Dumped regions, including the partially dumped ones, and the bytes read
+    dumped: usize,
+    dumped_bytes: u64,
+
+    /// Dumped regions with pages which could not be read
+    partial: usize,
+
+    failed: usize,
+    failed_bytes: u64,
+
+    /// Regions which were not selected, excluded, or not reached because of an interruption or an
+    /// error
+    skipped: usize,
+    skipped_bytes: u64,
+
+    /// Time the process was stopped for, if it was
+    pause: Option<Duration>,
+}
+
+impl Summary {
+    fn new(regions: &[Region], entries: &[Entry]) -> Self {
+        let mut summary = Self::default();
+        for entry in entries {
+            match entry.status {
+                Status::Dumped => {
+                    summary.dumped += 1;
+                    summary.dumped_bytes += entry.bytes;
+                    summary.partial += !entry.unreadable.is_empty() as usize;
+                }
+                Status::Failed(_) => {
+                    summary.failed += 1;
+                    summary.failed_bytes += entry.region.size() as u64;
+                }
+                Status::Skipped(_) => {
+                    summary.skipped += 1;
+                    summary.skipped_bytes += entry.region.size() as u64;
+                }
+            }
+        }
+        let total_bytes: u64 = regions.iter().map(|r| r.size() as u64).sum();
+        let entries_bytes: u64 = entries.iter().map(|e| e.region.size() as u64).sum();
+        summary.skipped += regions.len().saturating_sub(entries.len());
+        summary.skipped_bytes += total_bytes.saturating_sub(entries_bytes);
+        summary
+    }
+
+    fn log(&self, pid: u32) {
+        let pause = self
+            .pause
+            .map(|p| format!(", stopped for {:.3} s", p.as_secs_f64()))
+            .unwrap_or_default();
+        info!(
+            event = "summary",
+            pid,
+            dumped = self.dumped,
+            dumped_bytes = self.dumped_bytes,
+            partial = self.partial,
+            failed = self.failed,
+            failed_bytes = self.failed_bytes,
+            skipped = self.skipped,
+            skipped_bytes = self.skipped_bytes,
+            pause_time = self.pause.map(|p| p.as_secs_f64());
+            "Process {}: {} regions dumped ({} bytes, {} partially), {} failed ({} bytes), {} \
+             skipped ({} bytes){}",
+            pid,
+            self.dumped,
+            self.dumped_bytes,
+            self.partial,
+            self.failed,
+            self.failed_bytes,
+            self.skipped,
+            self.skipped_bytes,
+            pause
+        );
+    }
+}
+
+/// Where the results of a dump are printed: the standard output, unless `--events` writes there
+fn results(options: &DumpOptions) -> Box<dyn Write> {
+    if options.events && options.events_output.is_none() {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::stdout())
+    }
+}
+
+/// Logs the event `$event` about `$region`, described by its key-values before the other ones
+macro_rules! region_event {
+    ($level:ident, $event:literal, $region:expr $(, $key:ident = $value:expr)*; $($arg:tt)+) => {{
+        let region: &Region = $region;
+        $level!(
+            event = $event,
+            start = region.start,
+            end = region.end,
+            perms = region.perms.to_maps_string(),
+            path = region.path()
+            $(, $key = $value)*;
+            $($arg)+
+        )
+    }};
+}
+
+/// Device mappings whose reads may hang or fail, skipped unless `--no-default-excludes` is given
+const DEFAULT_EXCLUDES: [&str; 2] = ["/dev/nvidia*", "/dev/dri/*"];
+
+/// Why `region` is excluded from the dump by the options, if it is
+fn skip_reason(options: &DumpOptions, region: &Region) -> Option<String> {
+    if let Some(max) = options.max_region_size {
+        if region.size() as u64 > max {
+            return Some(format!("larger than --max-region-size {}", max));
+        }
+    }
+    let path = region.path()?;
+    if let Some(pattern) = options.exclude_paths.iter().find(|p| p.is_match(path)) {
+        return Some(format!("path matches --exclude-path {}", pattern));
+    }
+    if options.default_excludes {
+        let pattern = DEFAULT_EXCLUDES
+            .into_iter()
+            .find(|p| Pattern::Glob(p.to_string()).is_match(path))?;
+        return Some(format!("device mapping matching {}", pattern));
+    }
+    None
+}
+
+/// Reason of the regions not dumped because the process was stopped for too long
+const PAUSE_EXCEEDED: &str = "process stopped for longer than --max-pause";
+
+/// Whether the process was stopped for `--max-pause` or longer, `paused` being the time it was
+/// stopped for before `reader` attached to it
+fn over_budget(options: &DumpOptions, paused: Duration, reader: Option<&Reader>) -> bool {
+    let stopped = paused + reader.map_or(Duration::ZERO, Reader::stopped_for);
+    options.max_pause.is_some_and(|max| stopped >= max)
+}
+
+/// Dumps `region` to `output`, returning its manifest entry
+///
+/// Failing to read the region is reported in the entry, only output errors are returned. Regions
+/// excluded by the options are not read.
+fn dump_entry(
+    options: &DumpOptions,
+    reader: &mut Reader,
+    output: &mut dyn Output,
+    region: &Region,
+    thread: Option<u32>,
+) -> Result<Entry> {
+    if let Some(reason) = skip_reason(options, region) {
+        region_event!(
+            info, "region_skipped", region, reason = reason.as_str();
+            "Skipped region {:x}-{:x} {} ({}): {}",
+            region.start,
+            region.end,
+            region.perms,
+            region.path().unwrap_or("no file"),
+            reason
+        );
+        return Ok(Entry::skipped(region, thread, reason));
+    }
+    region_event!(
+        debug, "region_start", region;
+        "Dumping region {:x}-{:x} {} ({})",
+        region.start,
+        region.end,
+        region.perms,
+        region.path().unwrap_or("no file")
+    );
+    let name = match thread {
+        Some(tid) => format!("stack-{}", tid),
+        None => region.to_string(),
+    };
+    let mut inspection = Inspection {
+        sha256: options.sha256.then(Sha256::new),
+        entropy: options.entropy.then(entropy::Meter::default),
+        ..Default::default()
+    };
+    let mut unreadable = Vec::new();
+    let started = Instant::now();
+    reader.set_deadline(options.read_timeout.map(|timeout| Instant::now() + timeout));
+    let stored = output.write_region(region, &name, &mut |sink| {
+        let mut sink = Inspect {
+            inner: sink,
+            inspection: &mut inspection,
+        };
+        unreadable = dump_region(reader, region, &mut sink)?;
+        Ok(())
+    });
+    reader.set_deadline(None);
+    let stored = match stored {
+        Ok(stored) => stored,
+        Err(e) if !inspection.write_failed => {
+            let error = Chain(&e).to_string();
+            region_event!(
+                warn, "region_failed", region, error = error.as_str();
+                "Could not dump region {:x}-{:x} {} ({}): {}",
+                region.start,
+                region.end,
+                region.perms,
+                region.path().unwrap_or("no file"),
+                error
+            );
+            return Ok(Entry::failed(region, thread, error));
+        }
+        Err(e) => return Err(e),
+    };
+    if !unreadable.is_empty() {
+        let bytes: usize = unreadable.iter().map(|r| r.len()).sum();
+        region_event!(
+            warn, "region_unreadable", region, unreadable_bytes = bytes;
+            "Could not read 0x{:x} bytes of region {:x}-{:x} {} ({}), dumped them as zeros",
+            bytes,
+            region.start,
+            region.end,
+            region.perms,
+            region.path().unwrap_or("no file")
+        );
+    }
+    let entry = Entry {
+        region: region.clone(),
+        status: Status::Dumped,
+        thread,
+        file: stored.file,
+        bytes: inspection.bytes,
+        stored_bytes: stored.size,
+        crc32: Some(inspection.crc32.finish()),
+        sha256: inspection.sha256.map(Sha256::finish),
+        unreadable,
+        entropy: inspection.entropy.map(entropy::Meter::finish),
+        may_be_inconsistent: false,
+        changed: false,
+        read_time: Some(started.elapsed()),
+    };
+    region_event!(
+        info, "region_dumped", region, bytes = entry.bytes;
+        "Dumped region {:x}-{:x} {} ({})",
+        region.start,
+        region.end,
+        region.perms,
+        region.path().unwrap_or("no file")
+    );
+    if entry.entropy.is_some_and(|e| e.is_suspicious(region)) {
+        region_event!(
+            warn, "high_entropy", region;
+            "Anonymous executable region {:x}-{:x} holds high-entropy data, it may be packed or \
+             injected code",
+            region.start,
+            region.end
+        );
+    }
+    #[cfg(feature = "secrets")]
+    for (pattern, idx) in inspection.secrets.found() {
+        region_event!(
+            info, "secret_found", region, pattern = pattern, offset = idx;
+            "Found pattern {} in {} at offset {}", pattern, region, idx
+        );
+    }
+    Ok(entry)
+}
+
+/// Dumps `regions` with `options.jobs` workers, each with its own handle on the process memory
+///
+/// The process must already be stopped, workers do not attach to it. The regions not started by
+/// `deadline` are skipped.
+fn dump_parallel(
+    options: &DumpOptions,
+    pid: u32,
+    directory: &Directory,
+    regions: &[(&Region, Option<u32>)],
+    deadline: Option<Instant>,
+) -> Result<Vec<Entry>> {
+    let next = AtomicUsize::new(0);
+    // Set on the first region which is not entirely dumped, with --strict
+    let failed = AtomicBool::new(false);
+    let worker = || -> Result<Vec<(usize, Entry)>> {
+        let mut reader = Reader::Process(Ptrace::attach(pid, Attach::NoStop)?);
+        let mut output = directory.clone();
+        let mut entries = Vec::new();
+        while !signal::interrupted() && !failed.load(Ordering::Relaxed) {
+            let idx = next.fetch_add(1, Ordering::Relaxed);
+            let Some(&(region, thread)) = regions.get(idx) else {
+                return Ok(entries);
+            };
+            let entry = match deadline {
+                Some(deadline) if Instant::now() >= deadline => {
+                    Entry::skipped(region, thread, PAUSE_EXCEEDED.into())
+                }
+                _ => dump_entry(options, &mut reader, &mut output, region, thread)?,
+            };
+            if options.strict && entry.error().is_some() {
+                failed.store(true, Ordering::Relaxed);
+            }
+            entries.push((idx, entry));
+        }
+        Ok(entries)
+    };
+
+    let mut entries = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..options.jobs).map(|_| scope.spawn(worker)).collect();
+        workers
+            .into_iter()
+            .map(|w| w.join().expect("Dump worker panicked"))
+            .collect::<Result<Vec<_>>>()
+    })?
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+    // Keep the manifest in address order, as with a single job
+    entries.sort_unstable_by_key(|(idx, _)| *idx);
+    Ok(entries.into_iter().map(|(_, entry)| entry).collect())
+}
+
+/// Returns the regions to dump, with the thread whose stack they hold for `--stacks`
+fn select_regions<'a>(
+    options: &'a DumpOptions,
+    pid: Option<u32>,
+    memory: &'a Memory,
+    threads: &[Registers],
+) -> Result<Vec<(&'a Region, Option<u32>)>> {
+    if options.stacks {
+        Ok(find_stacks(pid, memory, threads)?
+            .into_iter()
+            .filter(|(r, _)| options.filter.matches(r))
+            .map(|(r, tid)| (r, Some(tid)))
+            .collect())
+    } else {
+        Ok(memory.filter(&options.filter).map(|r| (r, None)).collect())
+    }
+}
+
+/// Estimated number of bytes read to dump `region`
+///
+/// Only the resident and swapped pages of sparse anonymous mappings are read.
+fn estimated_bytes(region: &Region) -> u64 {
+    match region.stats {
+        Some(ref stats) if pagemap::is_sparse_candidate(region) => {
+            (stats.rss + stats.swap).min(region.size() as u64)
+        }
+        _ => region.size() as u64,
+    }
+}
+
+/// Formats a size with a binary unit, for totals
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["bytes", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} bytes", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// Prints the regions of `pid` which would be dumped, returns the estimated bytes to read
+///
+/// The process is only attached to find the stacks of its threads.
+fn dry_run_once(out: &mut dyn Write, options: &DumpOptions, pid: u32) -> Result<u64> {
+    let threads = if options.stacks {
+        let process = Ptrace::attach(pid, options.attach)?;
+        process
+            .threads()
+            .iter()
+            .map(|&tid| process.registers(tid))
+            .collect::<Result<Vec<_>>>()?
+    } else {
+        Vec::new()
+    };
+    let memory = Memory::from_pid_smaps(pid)?;
+    let regions = select_regions(options, Some(pid), &memory, &threads[..])?;
+
+    writeln!(out, "Process {}:", pid)?;
+    writeln!(
+        out,
+        "{:>16} {:>16} {:5} {:>9} {:>9} {:>9} PATH",
+        "START", "END", "PERMS", "SIZE", "RSS", "ESTIMATE"
+    )?;
+    let mut total = 0;
+    for &(region, _) in &regions {
+        let skipped = skip_reason(options, region);
+        let estimate = match skipped {
+            Some(_) => 0,
+            None => estimated_bytes(region),
+        };
+        total += estimate;
+        writeln!(
+            out,
+            "{:>16x} {:>16x} {:5} {:>9} {:>9} {:>9} {}{}",
+            region.start,
+            region.end,
+            region.perms.to_maps_string(),
+            kib(region.size() as u64),
+            kib(region.stats.as_ref().map_or(0, |s| s.rss)),
+            kib(estimate),
+            region.path().unwrap_or(""),
+            skipped.map_or(String::new(), |reason| format!(" (skipped: {})", reason))
+        )?;
+    }
+    writeln!(
+        out,
+        "{} of {} regions selected, about {} to read",
+        regions.len(),
+        memory.len(),
+        human_size(total)
+    )?;
+    Ok(total)
+}
+
+/// Prints what would be dumped by one snapshot of the processes, without writing anything
+fn dry_run(options: &DumpOptions, processes: &Processes) -> Result<()> {
+    let out = &mut results(options);
+    let (first, others) = processes.list(options)?;
+    let mut total = match first {
+        Some(pid) => dry_run_once(out, options, pid)?,
+        None => 0,
+    };
+    for (idx, &pid) in others.iter().enumerate() {
+        if idx > 0 || first.is_some() {
+            writeln!(out)?;
+        }
+        match dry_run_once(out, options, pid) {
+            Ok(bytes) => total += bytes,
+            Err(e) if options.strict => return Err(e),
+            Err(e) => error!("Could not list process {}: {}", pid, Chain(&e)),
+        }
+    }
+    let count = others.len() + first.is_some() as usize;
+    if count > 1 {
+        writeln!(out)?;
+        writeln!(
+            out,
+            "About {} to read for {} processes",
+            human_size(total),
+            count
+        )?;
+    }
+    Ok(())
+}
+
+/// Attaches to `pid` as requested by the options, to read its memory
+fn attach(options: &DumpOptions, pid: u32) -> Result<Ptrace> {
+    let mut process = Ptrace::attach(pid, options.attach)?;
+    process.set_peek_fallback(options.peek_fallback);
+    process.set_backend(options.backend)?;
+    if options.follow_forks {
+        process.follow_forks()?;
+    }
+    Ok(process)
+}
+
+/// Takes over a child forked by a process followed with `--follow-forks`, `None` if it exited
+fn adopt(options: &DumpOptions, pid: u32) -> Result<Option<Ptrace>> {
+    let Some(mut process) = Ptrace::adopt(pid, options.attach)? else {
+        return Ok(None);
+    };
+    process.set_peek_fallback(options.peek_fallback);
+    process.set_backend(options.backend)?;
+    Ok(Some(process))
+}
+
+/// Attaches to the process, dumps it to `output_path` and detaches, see [`dump_process`]
+fn dump_once(
+    options: &DumpOptions,
+    pid: u32,
+    output_path: &Path,
+    store: &Path,
+) -> Result<Option<Ptrace>> {
+    dump_process(options, attach(options, pid)?, output_path, store)
+}
+
+/// Dumps the attached `process` to `output_path` and detaches
+///
+/// With `--follow-forks`, the process is returned instead, still attached and stopped.
+fn dump_process(
+    options: &DumpOptions,
+    process: Ptrace,
+    output_path: &Path,
+    store: &Path,
+) -> Result<Option<Ptrace>> {
+    let pid = process.pid();
+    if options.attach == Attach::NoStop {
+        warn!(
+            "Process {} is not stopped, its memory may change while it is dumped",
+            pid
+        );
+    }
+    let memory = Memory::from_pid_smaps(pid)?;
+    let threads = process
+        .threads()
+        .iter()
+        .map(|&tid| process.registers(tid))
+        .collect::<Result<Vec<_>>>()?;
+    dump_reader(
+        options,
+        Reader::Process(process),
+        &memory,
+        &threads[..],
+        output_path,
+        store,
+    )
+}
+
+/// Dumps the memory saved in a core file, or of the kernel, to another format
+fn dump_core(options: &DumpOptions, core: CoreFile) -> Result<()> {
+    let memory = core.memory();
+    let threads = core.threads().to_vec();
+    let output_path = match options.output {
+        Some(ref path) => path.clone(),
+        None => {
+            let name = match core.name() {
+                "" => "core",
+                name => name,
+            };
+            let default = format!("{}-{}", name, core.pid());
+            let name = output_name(options, default, Some((name, core.pid())))?;
+            entry_path(Path::new(""), &name, options.format)
+        }
+    };
+    dump_reader(
+        options,
+        Reader::Core(core),
+        &memory,
+        &threads[..],
+        &output_path,
+        &output_path.join("pages"),
+    )
+    .map(drop)
+}
+
+/// Dumps a process of another host, read through the agent at `address`
+fn dump_remote(options: &DumpOptions, address: &str, pid: u32) -> Result<()> {
+    let (remote, memory) = Remote::connect(address, pid, options.remote_compression)?;
+    let output_path = match options.output {
+        Some(ref path) => path.clone(),
+        None => {
+            let default = format!("{}-{}", remote.name(), pid);
+            let name = output_name(options, default, Some((remote.name(), pid)))?;
+            entry_path(Path::new(""), &name, options.format)
+        }
+    };
+    dump_reader(
+        options,
+        Reader::Remote(remote),
+        &memory,
+        &[],
+        &output_path,
+        &output_path.join("pages"),
+    )
+    .map(drop)
+}
+
+/// Copies each distinct file mapped by the running process `pid` with `write`
+///
+/// Files are opened with [`procfs::open_mapped_file`]. Those which cannot be opened are reported
+/// and skipped, as well as device files.
+fn copy_mapped_files(
+    pid: u32,
+    memory: &Memory,
+    mut write: impl FnMut(&str, &mut File) -> Result<u64>,
+) -> Result<()> {
+    let mut seen = HashSet::new();
+    let mut copied = 0;
+    let mut bytes = 0;
+    for region in memory.iter().filter(|r| r.kind == RegionKind::File) {
+        let Some(path) = region.path() else {
+            continue;
+        };
+        if !seen.insert(path) {
+            continue;
+        }
+        let Some(name) = output::mapped_file_name(path) else {
+            warn!("Not copying {}, whose path is not canonical", path);
+            continue;
+        };
+        match procfs::open_mapped_file(pid, region) {
+            Ok(mut file) if file.metadata().is_ok_and(|m| m.is_file()) => {
+                bytes += write(&name, &mut file)?;
+                copied += 1;
+            }
+            Ok(_) => debug!("Not copying {}, which is not a regular file", path),
+            Err(e) => warn!("Could not copy {}: {}", path, e),
+        }
+    }
+    info!("Copied {} mapped files ({})", copied, human_size(bytes));
+    Ok(())
+}
+
+/// Flags the dumped entries whose region is not mapped the same way in `current`, returning them
+fn flag_changed(entries: &mut [Entry], current: &Memory) -> usize {
+    let mut changed = 0;
+    for entry in entries.iter_mut() {
+        if entry.status == Status::Dumped && !current.has_mapping(&entry.region) {
+            entry.changed = true;
+            changed += 1;
+        }
+    }
+    changed
+}
+
+/// Reads the mappings of the process again, to find the regions which changed during the dump
+///
+/// With `--redump-changed`, these regions are dumped again as they are now mapped, and replace
+/// the stale entries. The new regions are not checked again.
+fn check_mappings(
+    options: &DumpOptions,
+    reader: &mut Option<Reader>,
+    output: &mut dyn Output,
+    manifest: &mut Manifest,
+    memory: &Memory,
+) -> Result<()> {
+    let pid = manifest.pid;
+    let mut current = memory.clone();
+    if let Err(e) = current.refresh() {
+        warn!(
+            "Cannot read the mappings of process {} again: {}",
+            pid,
+            Chain(&e)
+        );
+        return Ok(());
+    }
+    if flag_changed(&mut manifest.entries, &current) == 0 {
+        return Ok(());
+    }
+    for entry in manifest.entries.iter().filter(|e| e.changed) {
+        let region = &entry.region;
+        region_event!(
+            warn, "region_changed", region;
+            "Region {:x}-{:x} {} ({}) changed during the dump",
+            region.start,
+            region.end,
+            region.perms,
+            region.path().unwrap_or("no file")
+        );
+    }
+    if !options.redump_changed {
+        return Ok(());
+    }
+
+    let reader = match reader {
+        Some(reader) => reader,
+        None => reader.insert(Reader::Process(attach(options, pid)?)),
+    };
+    let (stale, mut entries): (Vec<_>, Vec<_>) = std::mem::take(&mut manifest.entries)
+        .into_iter()
+        .partition(|e| e.changed);
+    let mut redumped = 0;
+    for entry in &stale {
+        if let Some(ref file) = entry.file {
+            output.discard(file)?;
+        }
+        let range = entry.region.start..entry.region.end;
+        for region in current
+            .iter()
+            .filter(|r| r.start < range.end && range.start < r.end)
+        {
+            if entries.iter().any(|e| e.region.same_mapping(region)) {
+                continue;
+            }
+            let mut entry = dump_entry(options, reader, output, region, entry.thread)?;
+            entry.may_be_inconsistent = options.attach == Attach::NoStop
+                && entry.status == Status::Dumped
+                && region.perms.has_perm(PermissionBits::Write);
+            entries.push(entry);
+            redumped += 1;
+        }
+    }
+    entries.sort_by_key(|e| e.region.start);
+    manifest.entries = entries;
+    info!(
+        "Dumped {} regions again, replacing {} changed ones",
+        redumped,
+        stale.len()
+    );
+    Ok(())
+}
+
+/// Dumps the `memory` read by `reader` to `output_path`, with the registers of `threads`
+///
+/// With `--follow-forks`, the process is returned still attached and stopped, it is detached
+/// otherwise.
+fn dump_reader(
+    options: &DumpOptions,
+    mut reader: Reader,
+    memory: &Memory,
+    threads: &[Registers],
+    output_path: &Path,
+    store: &Path,
+) -> Result<Option<Ptrace>> {
+    let pid = reader.pid();
+    let live = reader.live_pid();
+    let regions = select_regions(options, live, memory, threads)?;
+
+    let proc_files = options.with_proc.then(|| procfs::metadata(pid));
+    let fds = if options.with_fds {
+        match fds::inventory(pid) {
+            Ok(fds) => Some(fds::to_json(&fds)),
+            Err(e) => {
+                warn!("Could not list the descriptors of {}: {}", pid, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let backtraces = options.backtraces.then(|| {
+        backtrace::format(live, threads, memory, &mut |address, buffer| {
+            reader.read(address, buffer)
+        })
+    });
+
+    let mut manifest = Manifest::new(pid, options.format, options.compression);
+    if live.is_some() {
+        manifest.namespaces = procfs::namespaces(pid);
+    }
+    let mut output: Box<dyn Output> = match options.format {
+        Format::Directory => {
+            let directory = Directory::create(output_path, options.compression)?;
+            if !threads.is_empty() {
+                directory.write_registers(threads)?;
+            }
+            if let Some(ref files) = proc_files {
+                directory.write_proc(&files[..])?;
+            }
+            if let Some(ref fds) = fds {
+                directory.write_fds(fds)?;
+            }
+            if let Some(ref text) = backtraces {
+                directory.write_backtraces(text)?;
+            }
+            if options.with_files {
+                copy_mapped_files(pid, memory, |name, file| {
+                    directory.write_mapped_file(name, file)
+                })?;
+            }
+            if options.jobs > 1 {
+                let deadline = options
+                    .max_pause
+                    .map(|max| Instant::now() + max.saturating_sub(reader.stopped_for()));
+                manifest.entries = dump_parallel(options, pid, &directory, &regions[..], deadline)?;
+            }
+            Box::new(directory)
+        }
+        Format::Dedup => {
+            let dedup = Dedup::create(output_path, store)?;
+            if !threads.is_empty() {
+                dedup.write_registers(threads)?;
+            }
+            if let Some(ref files) = proc_files {
+                dedup.write_proc(&files[..])?;
+            }
+            if let Some(ref fds) = fds {
+                dedup.write_fds(fds)?;
+            }
+            if let Some(ref text) = backtraces {
+                dedup.write_backtraces(text)?;
+            }
+            if options.with_files {
+                copy_mapped_files(pid, memory, |name, file| {
+                    dedup.write_mapped_file(name, file)
+                })?;
+            }
+            Box::new(dedup)
+        }
+        Format::Tar => {
+            let mut tar = Tar::create(output_path, options.compression)?;
+            tar.write_registers(threads)?;
+            if let Some(ref files) = proc_files {
+                tar.write_proc(&files[..])?;
+            }
+            if let Some(ref fds) = fds {
+                tar.write_fds(fds)?;
+            }
+            if let Some(ref text) = backtraces {
+                tar.write_backtraces(text)?;
+            }
+            if options.with_files {
+                copy_mapped_files(pid, memory, |name, file| tar.write_mapped_file(name, file))?;
+            }
+            Box::new(tar)
+        }
+        Format::Core => Box::new(CoreDump::create(
+            output_path,
+            pid,
+            memory,
+            threads,
+            regions.len(),
+        )?),
+        Format::Minidump => Box::new(Minidump::create(
+            output_path,
+            pid,
+            memory,
+            threads,
+            regions.len(),
+        )?),
+    };
+
+    // With --minimize-pause, the process runs between regions and is attached again for each, or
+    // resumed and stopped again with --follow-forks
+    let mut reader = Some(reader);
+    // Time the process was stopped for by the readers already detached
+    let mut paused = Duration::ZERO;
+    // Parallel dumps are only supported by the directory output, and are already done
+    if options.jobs == 1 {
+        let mut running = options.attach == Attach::NoStop;
+        for (idx, &(region, thread)) in regions.iter().enumerate() {
+            if signal::interrupted() {
+                break;
+            }
+            if over_budget(options, paused, reader.as_ref()) {
+                manifest.entries.extend(
+                    regions[idx..]
+                        .iter()
+                        .map(|&(r, tid)| Entry::skipped(r, tid, PAUSE_EXCEEDED.into())),
+                );
+                break;
+            }
+            if let Some(Reader::Process(ref mut process)) = reader {
+                process.interrupt()?;
+            }
+            let current = match reader {
+                Some(ref mut reader) => reader,
+                None => reader.insert(Reader::Process(attach(options, pid)?)),
+            };
+            let mut entry = dump_entry(options, current, &mut *output, region, thread)?;
+            entry.may_be_inconsistent = running
+                && entry.status == Status::Dumped
+                && region.perms.has_perm(PermissionBits::Write);
+            let failed = entry.error().is_some();
+            manifest.entries.push(entry);
+            if options.strict && failed {
+                break;
+            }
+            if options.minimize_pause {
+                match reader {
+                    Some(Reader::Process(ref mut process)) if options.follow_forks => {
+                        process.resume()?
+                    }
+                    _ => paused += reader.take().map_or(Duration::ZERO, |r| r.stopped_for()),
+                }
+                running = true;
+            }
+        }
+        if let Some(Reader::Process(ref mut process)) = reader {
+            process.interrupt()?;
+        }
+    }
+    // Past the budget, the process is detached right away, without checking its mappings
+    let exceeded = over_budget(options, paused, reader.as_ref());
+    if exceeded {
+        let max = options.max_pause.unwrap_or_default();
+        warn!(
+            event = "pause_exceeded", pid, max_pause = max.as_secs_f64();
+            "Process {} was stopped for longer than --max-pause {:.3} s, the regions not dumped \
+             yet are skipped",
+            pid,
+            max.as_secs_f64()
+        );
+    }
+    if live.is_some() && !signal::interrupted() && !exceeded {
+        check_mappings(options, &mut reader, &mut *output, &mut manifest, memory)?;
+    }
+    // What was dumped before an interruption is kept, the process goes on while the output is
+    // finished, unless it is followed
+    let reader = reader.take();
+    paused += reader.as_ref().map_or(Duration::ZERO, Reader::stopped_for);
+    let followed = match reader {
+        Some(Reader::Process(process)) if options.follow_forks => Some(process),
+        _ => None,
+    };
+    if live.is_some() && options.attach != Attach::NoStop {
+        manifest.pause_time = Some(paused);
+    }
+    manifest.interrupted = signal::interrupted();
+    output.finish(&manifest)?;
+    if options.sha256
+        && options.format == Format::Directory
+        && options.compression == Compression::None
+    {
+        write_sha256sums(&output_path.join("SHA256SUMS"), &manifest)?;
+    }
+    if options.entropy {
+        print_entropy_table(&mut results(options), &manifest)?;
+    }
+    let summary = Summary {
+        pause: manifest.pause_time,
+        ..Summary::new(memory, &manifest.entries[..])
+    };
+    summary.log(pid);
+
+    signal::check()?;
+    if options.strict {
+        if let Some((entry, reason)) = manifest
+            .entries
+            .iter()
+            .find_map(|e| e.error().map(|reason| (e, reason)))
+        {
+            return Err(Error::RegionFailed {
+                pid,
+                start: entry.region.start,
+                end: entry.region.end,
+                reason,
+            });
+        }
+    }
+    if summary.dumped == 0 {
+        return Err(Error::NothingDumped { pid });
+    }
+    Ok(followed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::RegionStats;
+
+    #[test]
+    fn test_summary() {
+        let region = |line: &str| -> Region { line.parse().unwrap() };
+        let regions = [
+            region("7f0000000000-7f0000002000 r-xp 00000000 fe:01 42 /tmp/lib.so"),
+            region("7f0000002000-7f0000003000 r--p 00002000 fe:01 42 /tmp/lib.so"),
+            region("7ffc00000000-7ffc00021000 rw-p 00000000 00:00 0 [stack]"),
+            region("ffffffffff600000-ffffffffff601000 --xp 00000000 00:00 0 [vsyscall]"),
+            region("ffffffffff700000-ffffffffff800000 rw-s 00000000 00:05 812 /dev/nvidia0"),
+        ];
+        let mut dumped = Entry::failed(&regions[0], None, String::new());
+        dumped.status = Status::Dumped;
+        dumped.bytes = 0x2000;
+        let mut partial = dumped.clone();
+        partial.region = regions[2].clone();
+        partial.bytes = 0x21000;
+        let unreadable = 0x7ffc00000000..0x7ffc00001000;
+        partial.unreadable = std::slice::from_ref(&unreadable).to_vec();
+        let failed = Entry::failed(&regions[3], None, "Input/output error".into());
+        let skipped = Entry::skipped(&regions[4], None, "device mapping".into());
+
+        assert_eq!(partial.error().unwrap(), "0x1000 bytes could not be read");
+        assert_eq!(dumped.error(), None);
+        assert_eq!(skipped.error(), None);
+        assert_eq!(
+            Summary::new(&regions, &[dumped, partial, failed, skipped]),
+            Summary {
+                dumped: 2,
+                dumped_bytes: 0x23000,
+                partial: 1,
+                failed: 1,
+                failed_bytes: 0x1000,
+                skipped: 2,
+                skipped_bytes: 0x101000,
+                pause: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_skip_reason() {
+        let region = |line: &str| -> Region { line.parse().unwrap() };
+        let nvidia = region("7f1000000000-7f1000100000 rw-s 00000000 00:05 812 /dev/nvidia0");
+        let dri = region("7f1000100000-7f1000101000 rw-s 100000000 00:05 530 /dev/dri/renderD128");
+        let lib = region("7f0000000000-7f0000002000 r-xp 00000000 fe:01 42 /tmp/lib.so");
+        let heap = region("55d000000000-55d000400000 rw-p 00000000 00:00 0 [heap]");
+        let parse = |args: &[&str]| DumpOptions::parse(args.iter().map(|a| a.to_string())).unwrap();
+
+        let options = parse(&["1234"]);
+        assert_eq!(
+            skip_reason(&options, &nvidia).unwrap(),
+            "device mapping matching /dev/nvidia*"
+        );
+        assert!(skip_reason(&options, &dri).is_some());
+        assert_eq!(skip_reason(&options, &lib), None);
+        assert_eq!(skip_reason(&options, &heap), None);
+
+        let options = parse(&[
+            "--no-default-excludes",
+            "--exclude-path",
+            "*.so",
+            "--max-region-size",
+            "2M",
+            "1234",
+        ]);
+        assert_eq!(skip_reason(&options, &nvidia), None);
+        assert_eq!(
+            skip_reason(&options, &lib).unwrap(),
+            "path matches --exclude-path *.so"
+        );
+        assert_eq!(
+            skip_reason(&options, &heap).unwrap(),
+            "larger than --max-region-size 2097152"
+        );
+    }
+
+    #[test]
+    fn test_flag_changed() {
+        let region = |line: &str| -> Region { line.parse().unwrap() };
+        let heap = region("55d000000000-55d000100000 rw-p 00000000 00:00 0 [heap]");
+        let lib = region("7f0000000000-7f0000002000 r-xp 00000000 fe:01 42 /tmp/lib.so");
+        let mut dumped = Entry::failed(&heap, None, String::new());
+        dumped.status = Status::Dumped;
+        let mut entries = [
+            dumped.clone(),
+            Entry {
+                region: lib.clone(),
+                ..dumped
+            },
+            Entry::failed(&lib, None, "Input/output error".into()),
+        ];
+        // The heap grew, the library did not change
+        let current = Memory::from_regions(
+            1,
+            vec![
+                region("55d000000000-55d000200000 rw-p 00000000 00:00 0 [heap]"),
+                lib,
+            ],
+        );
+        assert_eq!(flag_changed(&mut entries, &current), 1);
+        assert!(entries[0].changed);
+        assert!(!entries[1].changed);
+        assert!(!entries[2].changed);
+    }
+
+    #[test]
+    fn test_estimate() {
+        let mut heap: Region = "55d000000000-55d000100000 rw-p 00000000 00:00 0 [heap]"
+            .parse()
+            .unwrap();
+        assert_eq!(estimated_bytes(&heap), 0x100000);
+        heap.stats = Some(RegionStats {
+            rss: 0x3000,
+            swap: 0x1000,
+            ..Default::default()
+        });
+        assert_eq!(estimated_bytes(&heap), 0x4000);
+
+        assert_eq!(human_size(1000), "1000 bytes");
+        assert_eq!(human_size(0x180000), "1.5 MiB");
+        assert_eq!(human_size(60 << 30), "60.0 GiB");
+    }
+
+    #[cfg(feature = "secrets")]
+    #[test]
+    fn test_secret_scanner() {
+        let mut scanner = SecretScanner::default();
+        for chunk in [&b"xxSec"[..], b"ret..pa", b"s", b"sWord private"] {
+            scanner.update(chunk);
+        }
+        let found: Vec<_> = scanner.found().collect();
+        assert_eq!(
+            found,
+            vec![("private", 19), ("secret", 2), ("password", 10)]
+        );
+    }
+}
@@ -0,0 +1,611 @@
+//! Unwinding with the call frame information of `.eh_frame`, for x86_64
+//!
+//! `PT_GNU_EH_FRAME` locates `.eh_frame_hdr`, a table sorted by address of the frame description
+//! entries (FDE) of `.eh_frame`. The instructions of the FDE of a function, after those of its
+//! common information entry (CIE), tell for each address how to compute the canonical frame
+//! address (CFA, the stack pointer before the call) and where the registers of the caller are
+//! saved. Everything is read from the memory of the process, so deleted files are handled.
+//!
+//! Only the rules emitted by compilers for the CFA, `rbp` and the return address are supported,
+//! DWARF expressions are not: the walk falls back to the frame pointer for these functions.
+
+use crate::backtrace::Frame;
+use crate::elf::{self, Class, ElfHeader, ProgramHeader};
+use crate::memory::{self, Region};
+use crate::pagemap;
+use crate::symbols;
+use crate::target::{read_word, Read};
+
+/// Segment of `.eh_frame_hdr`
+const PT_GNU_EH_FRAME: u32 = 0x6474_e550;
+
+/// DWARF numbers of `rbp` and `rsp`
+const RBP: u64 = 6;
+const RSP: u64 = 7;
+
+/// Pointer encodings, the low nibble is the format and the high one what it is relative to
+const DW_EH_PE_OMIT: u8 = 0xff;
+const DW_EH_PE_PCREL: u8 = 0x10;
+const DW_EH_PE_DATAREL: u8 = 0x30;
+const DW_EH_PE_SDATA4: u8 = 0x0b;
+
+/// Bound on the size of a CIE or a FDE
+const MAX_ENTRY: u32 = 1 << 16;
+
+/// Bound on the depth of `DW_CFA_remember_state`
+const MAX_STATES: usize = 64;
+
+/// Parser of the data read at `address`
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+    address: u64,
+}
+
+impl<'a> Parser<'a> {
+    fn new(data: &'a [u8], address: u64) -> Self {
+        Self {
+            data,
+            pos: 0,
+            address,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.data.get(self.pos..self.pos + N)?.try_into().ok()?;
+        self.pos += N;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes::<1>().map(|b| b[0])
+    }
+
+    fn skip(&mut self, len: u64) -> Option<()> {
+        self.pos = self.pos.checked_add(usize::try_from(len).ok()?)?;
+        (self.pos <= self.data.len()).then_some(())
+    }
+
+    fn uleb128(&mut self) -> Option<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn sleb128(&mut self) -> Option<i64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as i64) << shift;
+            if byte & 0x80 == 0 {
+                if shift < 57 && byte & 0x40 != 0 {
+                    value |= -1 << (shift + 7);
+                }
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    /// Reads a pointer encoded as `encoding`, `datarel` being the base of data-relative ones
+    ///
+    /// Indirect pointers are returned as the address holding the pointer.
+    fn pointer(&mut self, encoding: u8, datarel: u64) -> Option<u64> {
+        let address = self.address + self.pos as u64;
+        let value = match encoding & 0x0f {
+            0x00 | 0x04 | 0x0c => u64::from_le_bytes(self.bytes()?),
+            0x01 => self.uleb128()?,
+            0x02 => u16::from_le_bytes(self.bytes()?) as u64,
+            0x03 => u32::from_le_bytes(self.bytes()?) as u64,
+            0x09 => self.sleb128()? as u64,
+            0x0a => i16::from_le_bytes(self.bytes()?) as u64,
+            0x0b => i32::from_le_bytes(self.bytes()?) as u64,
+            _ => return None,
+        };
+        let base = match encoding & 0x70 {
+            0 => 0,
+            DW_EH_PE_PCREL => address,
+            DW_EH_PE_DATAREL => datarel,
+            _ => return None,
+        };
+        Some(base.wrapping_add(value))
+    }
+}
+
+/// Where the value of a register of the caller is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rule {
+    Undefined,
+    SameValue,
+    /// Saved at CFA + offset
+    Offset(i64),
+    /// Is CFA + offset
+    ValOffset(i64),
+    /// Anything else
+    Unsupported,
+}
+
+/// Unwinding rules at an address
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Row {
+    /// Register and offset, `None` if the CFA is a DWARF expression
+    cfa: Option<(u64, i64)>,
+    rbp: Rule,
+    ra: Rule,
+}
+
+#[derive(Debug)]
+struct Cie {
+    code_align: u64,
+    data_align: i64,
+    ra_register: u64,
+    /// Whether FDEs have augmentation data
+    augmented: bool,
+    fde_encoding: u8,
+    instructions: Vec<u8>,
+}
+
+/// Reads the CIE or FDE at `address`, returning its content and where it starts
+fn read_entry(read: &mut Read, address: u64) -> Option<(Vec<u8>, u64)> {
+    let mut length = [0; 4];
+    read(address as usize, &mut length).ok()?;
+    // 0 ends the section, and 0xffffffff announces the 64-bit format, which is not used
+    let length = u32::from_le_bytes(length);
+    if length == 0 || length > MAX_ENTRY {
+        return None;
+    }
+    let mut data = vec![0; length as usize];
+    read(address as usize + 4, &mut data).ok()?;
+    Some((data, address + 4))
+}
+
+impl Cie {
+    fn parse(data: &[u8], address: u64) -> Option<Self> {
+        let mut parser = Parser::new(data, address);
+        if parser.bytes::<4>()? != [0; 4] {
+            return None;
+        }
+        let version = parser.u8()?;
+        let end = data[parser.pos..].iter().position(|&b| b == 0)?;
+        let augmentation = data[parser.pos..parser.pos + end].to_vec();
+        parser.pos += end + 1;
+        // The obsolete "eh" augmentation inserts a pointer here
+        if augmentation.starts_with(b"eh") {
+            return None;
+        }
+        let code_align = parser.uleb128()?;
+        let data_align = parser.sleb128()?;
+        let ra_register = match version {
+            1 => parser.u8()? as u64,
+            _ => parser.uleb128()?,
+        };
+
+        let mut cie = Self {
+            code_align,
+            data_align,
+            ra_register,
+            augmented: augmentation.first() == Some(&b'z'),
+            fde_encoding: 0,
+            instructions: Vec::new(),
+        };
+        if cie.augmented {
+            let len = parser.uleb128()?;
+            let end = parser.pos.checked_add(usize::try_from(len).ok()?)?;
+            for &c in &augmentation[1..] {
+                match c {
+                    b'R' => cie.fde_encoding = parser.u8()?,
+                    b'P' => {
+                        let encoding = parser.u8()?;
+                        parser.pointer(encoding, 0)?;
+                    }
+                    b'L' => {
+                        parser.u8()?;
+                    }
+                    b'S' => {}
+                    _ => break,
+                }
+            }
+            parser.pos = end;
+        }
+        cie.instructions = data.get(parser.pos..)?.to_vec();
+        Some(cie)
+    }
+}
+
+impl Row {
+    fn set(&mut self, cie: &Cie, register: u64, rule: Rule) {
+        if register == RBP {
+            self.rbp = rule;
+        } else if register == cie.ra_register {
+            self.ra = rule;
+        }
+    }
+
+    fn restore(&mut self, cie: &Cie, register: u64, initial: &Row) {
+        if register == RBP {
+            self.rbp = initial.rbp;
+        } else if register == cie.ra_register {
+            self.ra = initial.ra;
+        }
+    }
+}
+
+/// Runs the call frame `instructions` on `row`, up to the rules of `pc`
+///
+/// `initial` is the row of the CIE, to restore registers to.
+fn execute(
+    instructions: &[u8],
+    cie: &Cie,
+    start: u64,
+    pc: u64,
+    row: &mut Row,
+    initial: Option<&Row>,
+) -> Option<()> {
+    let mut parser = Parser::new(instructions, 0);
+    let mut location = start;
+    let mut states = Vec::new();
+    let factored = |offset: u64| (offset as i64).wrapping_mul(cie.data_align);
+
+    while !parser.is_empty() {
+        let opcode = parser.u8()?;
+        let advance = match (opcode >> 6, opcode & 0x3f) {
+            // DW_CFA_advance_loc
+            (1, delta) => Some(delta as u64),
+            // DW_CFA_offset
+            (2, register) => {
+                row.set(
+                    cie,
+                    register as u64,
+                    Rule::Offset(factored(parser.uleb128()?)),
+                );
+                None
+            }
+            // DW_CFA_restore
+            (3, register) => {
+                row.restore(cie, register as u64, initial?);
+                None
+            }
+            // DW_CFA_nop
+            (_, 0x00) => None,
+            // DW_CFA_set_loc
+            (_, 0x01) => {
+                location = parser.pointer(cie.fde_encoding, 0)?;
+                if location > pc {
+                    break;
+                }
+                None
+            }
+            // DW_CFA_advance_loc1, 2 and 4
+            (_, 0x02) => Some(parser.u8()? as u64),
+            (_, 0x03) => Some(u16::from_le_bytes(parser.bytes()?) as u64),
+            (_, 0x04) => Some(u32::from_le_bytes(parser.bytes()?) as u64),
+            // DW_CFA_offset_extended
+            (_, 0x05) => {
+                let register = parser.uleb128()?;
+                row.set(cie, register, Rule::Offset(factored(parser.uleb128()?)));
+                None
+            }
+            // DW_CFA_restore_extended
+            (_, 0x06) => {
+                row.restore(cie, parser.uleb128()?, initial?);
+                None
+            }
+            // DW_CFA_undefined and DW_CFA_same_value
+            (_, 0x07) => {
+                row.set(cie, parser.uleb128()?, Rule::Undefined);
+                None
+            }
+            (_, 0x08) => {
+                row.set(cie, parser.uleb128()?, Rule::SameValue);
+                None
+            }
+            // DW_CFA_register
+            (_, 0x09) => {
+                let register = parser.uleb128()?;
+                parser.uleb128()?;
+                row.set(cie, register, Rule::Unsupported);
+                None
+            }
+            // DW_CFA_remember_state and DW_CFA_restore_state
+            (_, 0x0a) => {
+                if states.len() == MAX_STATES {
+                    return None;
+                }
+                states.push(row.clone());
+                None
+            }
+            (_, 0x0b) => {
+                *row = states.pop()?;
+                None
+            }
+            // DW_CFA_def_cfa
+            (_, 0x0c) => {
+                let register = parser.uleb128()?;
+                row.cfa = Some((register, parser.uleb128()? as i64));
+                None
+            }
+            // DW_CFA_def_cfa_register
+            (_, 0x0d) => {
+                let register = parser.uleb128()?;
+                row.cfa = Some((register, row.cfa?.1));
+                None
+            }
+            // DW_CFA_def_cfa_offset
+            (_, 0x0e) => {
+                let offset = parser.uleb128()? as i64;
+                row.cfa = Some((row.cfa?.0, offset));
+                None
+            }
+            // DW_CFA_def_cfa_expression
+            (_, 0x0f) => {
+                let len = parser.uleb128()?;
+                parser.skip(len)?;
+                row.cfa = None;
+                None
+            }
+            // DW_CFA_expression and DW_CFA_val_expression
+            (_, 0x10) | (_, 0x16) => {
+                let register = parser.uleb128()?;
+                let len = parser.uleb128()?;
+                parser.skip(len)?;
+                row.set(cie, register, Rule::Unsupported);
+                None
+            }
+            // DW_CFA_offset_extended_sf
+            (_, 0x11) => {
+                let register = parser.uleb128()?;
+                let offset = parser.sleb128()?.wrapping_mul(cie.data_align);
+                row.set(cie, register, Rule::Offset(offset));
+                None
+            }
+            // DW_CFA_def_cfa_sf
+            (_, 0x12) => {
+                let register = parser.uleb128()?;
+                let offset = parser.sleb128()?.wrapping_mul(cie.data_align);
+                row.cfa = Some((register, offset));
+                None
+            }
+            // DW_CFA_def_cfa_offset_sf
+            (_, 0x13) => {
+                let offset = parser.sleb128()?.wrapping_mul(cie.data_align);
+                row.cfa = Some((row.cfa?.0, offset));
+                None
+            }
+            // DW_CFA_val_offset and DW_CFA_val_offset_sf
+            (_, 0x14) => {
+                let register = parser.uleb128()?;
+                row.set(cie, register, Rule::ValOffset(factored(parser.uleb128()?)));
+                None
+            }
+            (_, 0x15) => {
+                let register = parser.uleb128()?;
+                let offset = parser.sleb128()?.wrapping_mul(cie.data_align);
+                row.set(cie, register, Rule::ValOffset(offset));
+                None
+            }
+            // DW_CFA_GNU_args_size
+            (_, 0x2e) => {
+                parser.uleb128()?;
+                None
+            }
+            // DW_CFA_GNU_negative_offset_extended
+            (_, 0x2f) => {
+                let register = parser.uleb128()?;
+                row.set(cie, register, Rule::Offset(-factored(parser.uleb128()?)));
+                None
+            }
+            _ => return None,
+        };
+        if let Some(delta) = advance {
+            location = location.wrapping_add(delta.wrapping_mul(cie.code_align));
+            if location > pc {
+                break;
+            }
+        }
+    }
+    Some(())
+}
+
+/// Returns the rules at `pc` of the FDE at `address`
+fn find_row(read: &mut Read, address: u64, pc: u64) -> Option<Row> {
+    let (data, start) = read_entry(read, address)?;
+    let mut parser = Parser::new(&data, start);
+    // The CIE pointer is relative to its own address
+    let cie_address = start.checked_sub(u32::from_le_bytes(parser.bytes()?) as u64)?;
+    let (cie_data, cie_start) = read_entry(read, cie_address)?;
+    let cie = Cie::parse(&cie_data, cie_start)?;
+
+    let pc_begin = parser.pointer(cie.fde_encoding, 0)?;
+    let pc_range = parser.pointer(cie.fde_encoding & 0x0f, 0)?;
+    if pc < pc_begin || pc - pc_begin >= pc_range {
+        return None;
+    }
+    if cie.augmented {
+        let len = parser.uleb128()?;
+        parser.skip(len)?;
+    }
+
+    let mut row = Row {
+        cfa: None,
+        rbp: Rule::SameValue,
+        ra: Rule::Undefined,
+    };
+    execute(&cie.instructions, &cie, pc_begin, pc, &mut row, None)?;
+    let initial = row.clone();
+    execute(
+        &data[parser.pos..],
+        &cie,
+        pc_begin,
+        pc,
+        &mut row,
+        Some(&initial),
+    )?;
+    Some(row)
+}
+
+/// Returns the address of `.eh_frame_hdr` of the ELF file loaded at `base`
+fn eh_frame_hdr(read: &mut Read, base: u64) -> Option<u64> {
+    let mut data = vec![0; elf::EHDR_SIZE];
+    read(base as usize, &mut data).ok()?;
+    let header = ElfHeader::parse(&data).ok()?;
+    let mut data = vec![0; header.e_phnum as usize * elf::PHDR_SIZE];
+    read((base + header.e_phoff) as usize, &mut data).ok()?;
+    let headers = data
+        .chunks_exact(elf::PHDR_SIZE)
+        .map(ProgramHeader::parse)
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    let first = headers
+        .iter()
+        .find(|h| h.p_type == elf::PT_LOAD && h.p_offset == 0)?;
+    let bias = base.wrapping_sub(first.p_vaddr - first.p_vaddr % pagemap::page_size() as u64);
+    let hdr = headers.iter().find(|h| h.p_type == PT_GNU_EH_FRAME)?;
+    Some(bias.wrapping_add(hdr.p_vaddr))
+}
+
+/// Returns the address of the FDE of `pc`, from the table of `.eh_frame_hdr` at `hdr`
+fn find_fde(read: &mut Read, hdr: u64, pc: u64) -> Option<u64> {
+    let mut data = [0; 4 + 2 * 9];
+    read(hdr as usize, &mut data).ok()?;
+    let mut parser = Parser::new(&data, hdr);
+    let [version, eh_frame_encoding, count_encoding, table_encoding] = parser.bytes()?;
+    // Linkers always sort the table with 32-bit offsets from the header
+    if version != 1
+        || count_encoding == DW_EH_PE_OMIT
+        || table_encoding != (DW_EH_PE_DATAREL | DW_EH_PE_SDATA4)
+    {
+        return None;
+    }
+    parser.pointer(eh_frame_encoding, hdr)?;
+    let count = parser.pointer(count_encoding, hdr)?;
+    let table = hdr + parser.pos as u64;
+
+    let mut entry = |idx: u64| -> Option<(u64, u64)> {
+        let mut data = [0; 8];
+        read((table + idx * 8) as usize, &mut data).ok()?;
+        let mut parser = Parser::new(&data, 0);
+        Some((
+            parser.pointer(table_encoding, hdr)?,
+            parser.pointer(table_encoding, hdr)?,
+        ))
+    };
+    // The last entry starting at or before pc
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if entry(middle)?.0 <= pc {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    Some(entry(low.checked_sub(1)?)?.1)
+}
+
+/// Unwinds `frame` with the call frame information of its function
+///
+/// The instruction pointer of the first frame was not reached by a call, and may be the first
+/// instruction of a function.
+pub fn step(frame: &Frame, first: bool, regions: &[Region], read: &mut Read) -> Option<Frame> {
+    // A return address may follow a call ending the function, look up the call itself
+    let pc = if first { frame.pc } else { frame.pc - 1 };
+    let region = memory::region_containing(regions, pc as usize)?;
+    let base = symbols::module_base(regions, region)? as u64;
+    let hdr = eh_frame_hdr(read, base)?;
+    let fde = find_fde(read, hdr, pc)?;
+    let row = find_row(read, fde, pc)?;
+
+    let cfa = match row.cfa? {
+        (RSP, offset) => frame.sp.checked_add_signed(offset)?,
+        (RBP, offset) => frame.fp.checked_add_signed(offset)?,
+        _ => return None,
+    };
+    let pc = match row.ra {
+        Rule::Offset(offset) => {
+            read_word(read, Class::Elf64, cfa.checked_add_signed(offset)? as usize).ok()?
+        }
+        _ => return None,
+    };
+    let fp = match row.rbp {
+        Rule::SameValue => frame.fp,
+        Rule::Offset(offset) => {
+            read_word(read, Class::Elf64, cfa.checked_add_signed(offset)? as usize).ok()?
+        }
+        Rule::ValOffset(offset) => cfa.checked_add_signed(offset)?,
+        Rule::Undefined | Rule::Unsupported => return None,
+    };
+    Some(Frame { pc, sp: cfa, fp })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leb128() {
+        let mut parser = Parser::new(&[0xe5, 0x8e, 0x26, 0x7f, 0x80, 0x7f], 0);
+        assert_eq!(parser.uleb128(), Some(624485));
+        assert_eq!(parser.sleb128(), Some(-1));
+        assert_eq!(parser.sleb128(), Some(-128));
+        assert!(parser.is_empty());
+    }
+
+    #[test]
+    fn test_execute() {
+        // GCC's usual CIE: CFA is rsp+8, the return address is at CFA-8
+        let mut cie_data = vec![0, 0, 0, 0, 1, b'z', b'R', 0, 1, 0x78, 16, 1, 0x1b];
+        cie_data.extend_from_slice(&[0x0c, 7, 8, 0x90, 1]);
+        let cie = Cie::parse(&cie_data, 0x1000).unwrap();
+        assert_eq!(cie.data_align, -8);
+        assert_eq!(cie.fde_encoding, 0x1b);
+        assert!(cie.augmented);
+
+        // push rbp; mov rbp, rsp; ... ; pop rbp; ret
+        let fde = [
+            0x41, // advance 1
+            0x0e, 16, // CFA is rsp+16
+            0x86, 2,    // rbp at CFA-16
+            0x43, // advance 3
+            0x0d, 6,    // CFA is rbp+16
+            0x0a, // remember
+            0x48, // advance 8
+            0x0c, 7, 8,    // CFA is rsp+8
+            0x41, // advance 1
+            0x0b, // restore
+        ];
+        let row = |pc| {
+            let mut row = Row {
+                cfa: None,
+                rbp: Rule::SameValue,
+                ra: Rule::Undefined,
+            };
+            execute(&cie.instructions, &cie, 0x400, pc, &mut row, None).unwrap();
+            let initial = row.clone();
+            execute(&fde, &cie, 0x400, pc, &mut row, Some(&initial)).unwrap();
+            row
+        };
+        assert_eq!(
+            row(0x400),
+            Row {
+                cfa: Some((RSP, 8)),
+                rbp: Rule::SameValue,
+                ra: Rule::Offset(-8),
+            }
+        );
+        assert_eq!(row(0x401).cfa, Some((RSP, 16)));
+        assert_eq!(row(0x401).rbp, Rule::Offset(-16));
+        assert_eq!(row(0x404).cfa, Some((RBP, 16)));
+        assert_eq!(row(0x40c).cfa, Some((RSP, 8)));
+        assert_eq!(row(0x40d).cfa, Some((RBP, 16)));
+    }
+}
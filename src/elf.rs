@@ -0,0 +1,525 @@
+//! Parsing of the ELF structures found in memory or in files, for 64-bit little-endian programs
+//!
+//! 32-bit programs are only recognized by their [`Class`], and their program headers parsed.
+
+use crate::{Error, Result};
+
+pub const ET_CORE: u16 = 4;
+
+pub const EM_386: u16 = 3;
+
+/// Machine of this architecture, the only one whose core files are read and written
+#[cfg(target_arch = "x86_64")]
+pub const EM_HOST: u16 = 62;
+#[cfg(target_arch = "aarch64")]
+pub const EM_HOST: u16 = 183;
+#[cfg(target_arch = "riscv64")]
+pub const EM_HOST: u16 = 243;
+
+pub const PT_LOAD: u32 = 1;
+pub const PT_DYNAMIC: u32 = 2;
+pub const PT_NOTE: u32 = 4;
+pub const PT_PHDR: u32 = 6;
+
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+pub const NT_PRSTATUS: u32 = 1;
+pub const NT_PRFPREG: u32 = 2;
+pub const NT_PRPSINFO: u32 = 3;
+pub const NT_AUXV: u32 = 6;
+pub const NT_FILE: u32 = 0x46494c45;
+pub const NT_GNU_BUILD_ID: u32 = 3;
+
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_DYNSYM: u32 = 11;
+
+pub const STT_OBJECT: u8 = 1;
+pub const STT_FUNC: u8 = 2;
+
+pub const DT_NULL: i64 = 0;
+pub const DT_PLTRELSZ: i64 = 2;
+pub const DT_RELA: i64 = 7;
+pub const DT_RELASZ: i64 = 8;
+pub const DT_RELAENT: i64 = 9;
+pub const DT_REL: i64 = 17;
+pub const DT_RELSZ: i64 = 18;
+pub const DT_RELENT: i64 = 19;
+pub const DT_PLTREL: i64 = 20;
+pub const DT_DEBUG: i64 = 21;
+pub const DT_JMPREL: i64 = 23;
+pub const DT_RELRSZ: i64 = 35;
+pub const DT_RELR: i64 = 36;
+
+pub const AT_PHDR: u64 = 3;
+pub const AT_PHNUM: u64 = 5;
+
+/// Size of the ELF header
+pub const EHDR_SIZE: usize = 64;
+
+/// Size of a program header
+pub const PHDR_SIZE: usize = 56;
+
+/// Size of a section header
+pub const SHDR_SIZE: usize = 64;
+
+/// Size of an entry of a symbol table
+pub const SYM_SIZE: usize = 24;
+
+/// Size of a relocation with an addend
+pub const RELA_SIZE: usize = 24;
+
+/// Size of a relocation without addend
+pub const REL_SIZE: usize = 16;
+
+/// Size of the ELF header of 32-bit files
+pub const EHDR32_SIZE: usize = 52;
+
+/// Size of a program header of 32-bit files
+pub const PHDR32_SIZE: usize = 32;
+
+/// Word size of a program, from `EI_CLASS`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    Elf32,
+    Elf64,
+}
+
+impl Class {
+    /// Reads the class from the identification bytes at the start of `data`
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if !data.starts_with(b"\x7fELF") {
+            return Err(Error::Elf("no ELF header".into()));
+        }
+        match data.get(4) {
+            Some(1) => Ok(Self::Elf32),
+            Some(2) => Ok(Self::Elf64),
+            _ => Err(Error::Elf("invalid ELF class".into())),
+        }
+    }
+
+    /// Size of pointers, in bytes
+    pub fn word_size(self) -> usize {
+        match self {
+            Self::Elf32 => 4,
+            Self::Elf64 => 8,
+        }
+    }
+
+    /// Size of the ELF header
+    pub fn ehdr_size(self) -> usize {
+        match self {
+            Self::Elf32 => EHDR32_SIZE,
+            Self::Elf64 => EHDR_SIZE,
+        }
+    }
+
+    /// Size of a program header
+    pub fn phdr_size(self) -> usize {
+        match self {
+            Self::Elf32 => PHDR32_SIZE,
+            Self::Elf64 => PHDR_SIZE,
+        }
+    }
+
+    /// Reads the native-endian word at `offset` of `data`
+    pub fn word_at(self, data: &[u8], offset: usize) -> u64 {
+        match self {
+            Self::Elf32 => u32_at(data, offset) as u64,
+            Self::Elf64 => u64_at(data, offset),
+        }
+    }
+}
+
+/// Reads the native-endian `u64` at `offset` of `data`
+pub fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_ne_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// Reads the native-endian `u32` at `offset` of `data`
+pub fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Fields of the ELF header locating the program and section headers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ElfHeader {
+    pub e_phoff: u64,
+    pub e_shoff: u64,
+    pub e_phnum: u16,
+    pub e_shnum: u16,
+}
+
+impl ElfHeader {
+    /// Parses the ELF header at the start of `data`, only accepting 64-bit little-endian files
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < EHDR_SIZE || !data.starts_with(b"\x7fELF") {
+            return Err(Error::Elf("no ELF header".into()));
+        }
+        // ELFCLASS64 and ELFDATA2LSB
+        if data[4] != 2 || data[5] != 1 {
+            return Err(Error::Elf("not a 64-bit little-endian file".into()));
+        }
+        if u16::from_le_bytes([data[54], data[55]]) as usize != PHDR_SIZE {
+            return Err(Error::Elf("unexpected program header size".into()));
+        }
+        let e_shnum = u16::from_le_bytes([data[60], data[61]]);
+        if e_shnum != 0 && u16::from_le_bytes([data[58], data[59]]) as usize != SHDR_SIZE {
+            return Err(Error::Elf("unexpected section header size".into()));
+        }
+        Ok(Self {
+            e_phoff: u64_at(data, 32),
+            e_shoff: u64_at(data, 40),
+            e_phnum: u16::from_le_bytes([data[56], data[57]]),
+            e_shnum,
+        })
+    }
+
+    /// Clears the section header fields of the ELF header at the start of `data`
+    ///
+    /// Section headers are not loaded in memory, they would point to garbage.
+    pub fn strip_sections(data: &mut [u8]) {
+        // e_shoff, then e_shentsize, e_shnum and e_shstrndx after e_flags, e_ehsize, e_phentsize
+        // and e_phnum
+        data[40..48].fill(0);
+        data[58..64].fill(0);
+    }
+}
+
+/// Program header, describing a segment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub p_flags: u32,
+    pub p_offset: u64,
+    pub p_vaddr: u64,
+    /// Physical address, `/proc/kcore` sets it for segments of physical memory only
+    pub p_paddr: u64,
+    pub p_filesz: u64,
+    pub p_memsz: u64,
+}
+
+impl ProgramHeader {
+    /// Parses the program header at the start of `data`
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < PHDR_SIZE {
+            return Err(Error::Elf("truncated program header".into()));
+        }
+        Ok(Self {
+            p_type: u32_at(data, 0),
+            p_flags: u32_at(data, 4),
+            p_offset: u64_at(data, 8),
+            p_vaddr: u64_at(data, 16),
+            p_paddr: u64_at(data, 24),
+            p_filesz: u64_at(data, 32),
+            p_memsz: u64_at(data, 40),
+        })
+    }
+
+    /// Parses the program header of a file of `class` at the start of `data`
+    pub fn parse_class(class: Class, data: &[u8]) -> Result<Self> {
+        match class {
+            Class::Elf32 => Self::parse32(data),
+            Class::Elf64 => Self::parse(data),
+        }
+    }
+
+    /// Parses the program header of a 32-bit file at the start of `data`
+    fn parse32(data: &[u8]) -> Result<Self> {
+        if data.len() < PHDR32_SIZE {
+            return Err(Error::Elf("truncated program header".into()));
+        }
+        Ok(Self {
+            p_type: u32_at(data, 0),
+            p_offset: u32_at(data, 4) as u64,
+            p_vaddr: u32_at(data, 8) as u64,
+            p_paddr: u32_at(data, 12) as u64,
+            p_filesz: u32_at(data, 16) as u64,
+            p_memsz: u32_at(data, 20) as u64,
+            p_flags: u32_at(data, 24),
+        })
+    }
+}
+
+/// Section header, only found in files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SectionHeader {
+    pub sh_type: u32,
+    pub sh_offset: u64,
+    pub sh_size: u64,
+    /// Index of the associated section, the string table for symbol tables
+    pub sh_link: u32,
+}
+
+impl SectionHeader {
+    /// Parses the section header at the start of `data`
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < SHDR_SIZE {
+            return Err(Error::Elf("truncated section header".into()));
+        }
+        Ok(Self {
+            sh_type: u32_at(data, 4),
+            sh_offset: u64_at(data, 24),
+            sh_size: u64_at(data, 32),
+            sh_link: u32_at(data, 40),
+        })
+    }
+}
+
+/// Entry of a symbol table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SymbolEntry {
+    /// Offset of the name in the string table
+    pub st_name: u32,
+    pub st_info: u8,
+    /// Index of the section of the symbol, 0 if undefined
+    pub st_shndx: u16,
+    pub st_value: u64,
+    pub st_size: u64,
+}
+
+impl SymbolEntry {
+    /// Parses the symbol at the start of `data`
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < SYM_SIZE {
+            return Err(Error::Elf("truncated symbol".into()));
+        }
+        Ok(Self {
+            st_name: u32_at(data, 0),
+            st_info: data[4],
+            st_shndx: u16::from_le_bytes([data[6], data[7]]),
+            st_value: u64_at(data, 8),
+            st_size: u64_at(data, 16),
+        })
+    }
+
+    /// Type of the symbol, as `STT_*`
+    pub fn kind(&self) -> u8 {
+        self.st_info & 0xf
+    }
+}
+
+/// Parses `/proc/PID/auxv` into `(type, value)` pairs, up to `AT_NULL`
+///
+/// Entries are made of two words of the `class` of the process.
+pub fn parse_auxv(data: &[u8], class: Class) -> Vec<(u64, u64)> {
+    let word = class.word_size();
+    data.chunks_exact(2 * word)
+        .map(|entry| (class.word_at(entry, 0), class.word_at(entry, word)))
+        .take_while(|&(key, _)| key != 0)
+        .collect()
+}
+
+/// Returns the value of `key` in the parsed auxiliary vector
+pub fn auxv_value(auxv: &[(u64, u64)], key: u64) -> Option<u64> {
+    auxv.iter().find(|&&(k, _)| k == key).map(|&(_, v)| v)
+}
+
+/// Returns the GNU build ID found in `data`, the content of a `PT_NOTE` segment
+pub fn build_id(data: &[u8]) -> Option<&[u8]> {
+    let mut pos = 0;
+    while pos + 12 <= data.len() {
+        let namesz = u32_at(data, pos) as usize;
+        let descsz = u32_at(data, pos + 4) as usize;
+        let n_type = u32_at(data, pos + 8);
+        let name = data.get(pos + 12..(pos + 12).saturating_add(namesz))?;
+        let start = (pos + 12 + namesz).next_multiple_of(4);
+        let desc = data.get(start..start.saturating_add(descsz))?;
+        if n_type == NT_GNU_BUILD_ID && name == b"GNU\0" {
+            return Some(desc);
+        }
+        pos = (start + descsz).next_multiple_of(4);
+    }
+    None
+}
+
+/// Returns the file offsets of the words of the ELF file `data` written by the dynamic linker
+///
+/// Relocations are listed by the dynamic section, in `DT_RELA`, `DT_REL` and `DT_JMPREL` tables,
+/// and in the `DT_RELR` bitmaps of relative relocations. Words out of the file content of the
+/// segments, like those of `.bss`, are left out.
+pub fn relocated_offsets(data: &[u8]) -> Result<Vec<u64>> {
+    let header = ElfHeader::parse(data)?;
+    let segments = data
+        .get(header.e_phoff as usize..)
+        .and_then(|d| d.get(..header.e_phnum as usize * PHDR_SIZE))
+        .ok_or_else(|| Error::Elf("truncated program headers".into()))?
+        .chunks_exact(PHDR_SIZE)
+        .map(ProgramHeader::parse)
+        .collect::<Result<Vec<_>>>()?;
+    let file_offset = |vaddr: u64| {
+        segments
+            .iter()
+            .filter(|s| s.p_type == PT_LOAD)
+            .find(|s| vaddr >= s.p_vaddr && vaddr - s.p_vaddr < s.p_filesz)
+            .map(|s| s.p_offset + (vaddr - s.p_vaddr))
+    };
+    let Some(dynamic) = segments.iter().find(|s| s.p_type == PT_DYNAMIC) else {
+        return Ok(Vec::new());
+    };
+    let entries: Vec<(i64, u64)> = data
+        .get(dynamic.p_offset as usize..)
+        .and_then(|d| d.get(..dynamic.p_filesz as usize))
+        .ok_or_else(|| Error::Elf("truncated dynamic section".into()))?
+        .chunks_exact(16)
+        .map(|entry| (u64_at(entry, 0) as i64, u64_at(entry, 8)))
+        .take_while(|&(tag, _)| tag != DT_NULL)
+        .collect();
+    let value = |tag| entries.iter().find(|&&(t, _)| t == tag).map(|&(_, v)| v);
+    // The table at the address of tag `start`, of the size of tag `size`, empty if there is none
+    let table = |start, size| match (value(start), value(size)) {
+        (Some(address), Some(size)) => file_offset(address)
+            .and_then(|offset| data.get(offset as usize..))
+            .and_then(|d| d.get(..size as usize))
+            .ok_or_else(|| Error::Elf("relocation table out of the file".into())),
+        _ => Ok(&[][..]),
+    };
+
+    let plt_size = match value(DT_PLTREL) {
+        Some(kind) if kind == DT_RELA as u64 => RELA_SIZE,
+        _ => REL_SIZE,
+    };
+    let mut targets = Vec::new();
+    for (relocations, size) in [
+        (table(DT_RELA, DT_RELASZ)?, value(DT_RELAENT), RELA_SIZE),
+        (table(DT_REL, DT_RELSZ)?, value(DT_RELENT), REL_SIZE),
+        (table(DT_JMPREL, DT_PLTRELSZ)?, None, plt_size),
+    ]
+    .map(|(table, size, default)| (table, size.map_or(default, |s| s as usize)))
+    {
+        if size < 8 {
+            return Err(Error::Elf("invalid size of relocations".into()));
+        }
+        // r_offset comes first
+        targets.extend(relocations.chunks_exact(size).map(|r| u64_at(r, 0)));
+    }
+    // An even word is the address of a relocation, an odd one the bitmap of the 63 words after
+    // the previous address or bitmap
+    let mut next = 0u64;
+    for word in table(DT_RELR, DT_RELRSZ)?
+        .chunks_exact(8)
+        .map(|w| u64_at(w, 0))
+    {
+        if word & 1 == 0 {
+            targets.push(word);
+            next = word.wrapping_add(8);
+        } else {
+            for bit in 1..64 {
+                if word >> bit & 1 == 1 {
+                    targets.push(next.wrapping_add((bit - 1) * 8));
+                }
+            }
+            next = next.wrapping_add(63 * 8);
+        }
+    }
+    Ok(targets.into_iter().filter_map(file_offset).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_auxv() {
+        let mut data = Vec::new();
+        for value in [AT_PHDR, 0x1040, AT_PHNUM, 13, 0, 0, 42, 42] {
+            data.extend_from_slice(&value.to_ne_bytes());
+        }
+        let auxv = parse_auxv(&data, Class::Elf64);
+        assert_eq!(auxv, [(AT_PHDR, 0x1040), (AT_PHNUM, 13)]);
+        assert_eq!(auxv_value(&auxv, AT_PHNUM), Some(13));
+        assert_eq!(auxv_value(&auxv, 42), None);
+
+        let data: Vec<u8> = [AT_PHDR as u32, 0x8048034, AT_PHNUM as u32, 9, 0, 0]
+            .iter()
+            .flat_map(|value| value.to_ne_bytes())
+            .collect();
+        assert_eq!(
+            parse_auxv(&data, Class::Elf32),
+            [(AT_PHDR, 0x8048034), (AT_PHNUM, 9)]
+        );
+    }
+
+    #[test]
+    fn test_relocated_offsets() {
+        let mut data = vec![0; 0x2000];
+        data[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        data[32] = EHDR_SIZE as u8;
+        data[54] = PHDR_SIZE as u8;
+        data[56] = 2;
+        // The whole file at 0x400000, with the dynamic section at offset 0x1000
+        for (index, (p_type, offset, filesz)) in [(PT_LOAD, 0, 0x2000), (PT_DYNAMIC, 0x1000, 0x60)]
+            .into_iter()
+            .enumerate()
+        {
+            let phdr = EHDR_SIZE + index * PHDR_SIZE;
+            data[phdr..phdr + 4].copy_from_slice(&p_type.to_ne_bytes());
+            data[phdr + 8..phdr + 16].copy_from_slice(&(offset as u64).to_ne_bytes());
+            data[phdr + 16..phdr + 24].copy_from_slice(&(0x400000 + offset as u64).to_ne_bytes());
+            data[phdr + 32..phdr + 40].copy_from_slice(&(filesz as u64).to_ne_bytes());
+        }
+        let mut write = |offset: usize, words: &[u64]| {
+            for (i, word) in words.iter().enumerate() {
+                data[offset + i * 8..offset + i * 8 + 8].copy_from_slice(&word.to_ne_bytes());
+            }
+        };
+        write(
+            0x1000,
+            &[
+                DT_RELA as u64,
+                0x401100,
+                DT_RELASZ as u64,
+                2 * RELA_SIZE as u64,
+                DT_RELR as u64,
+                0x401200,
+                DT_RELRSZ as u64,
+                16,
+            ],
+        );
+        // The second relocation is in .bss
+        write(0x1100, &[0x400010, 8, 0, 0x500000, 8, 0]);
+        // 0x400100, then the first and third words after it
+        write(0x1200, &[0x400100, 0b1011]);
+
+        assert_eq!(
+            relocated_offsets(&data).unwrap(),
+            [0x10, 0x100, 0x108, 0x118]
+        );
+        assert!(relocated_offsets(&data[..0x800]).is_err());
+    }
+
+    #[test]
+    fn test_build_id() {
+        let mut data = Vec::new();
+        for (name, n_type, desc) in [
+            (&b"stapsdt\0"[..], NT_GNU_BUILD_ID, &b"probe\0\0\0"[..]),
+            (b"GNU\0", 5, b"\x02\0\0\xc0\x04\0\0\0\x03\0\0\0\0\0\0\0"),
+            (b"GNU\0", NT_GNU_BUILD_ID, b"\x12\x34\x56\x78\x9a"),
+        ] {
+            for value in [name.len() as u32, desc.len() as u32, n_type] {
+                data.extend_from_slice(&value.to_ne_bytes());
+            }
+            data.extend_from_slice(name);
+            data.extend_from_slice(desc);
+            data.resize(data.len().next_multiple_of(4), 0);
+        }
+        assert_eq!(build_id(&data), Some(&b"\x12\x34\x56\x78\x9a"[..]));
+        assert_eq!(build_id(&data[..data.len() - 8]), None);
+        assert_eq!(build_id(&[]), None);
+    }
+
+    #[test]
+    fn test_class() {
+        assert_eq!(
+            Class::parse(b"\x7fELF\x01\x01\x01").ok(),
+            Some(Class::Elf32)
+        );
+        assert_eq!(
+            Class::parse(b"\x7fELF\x02\x01\x01").ok(),
+            Some(Class::Elf64)
+        );
+        assert!(Class::parse(b"\x7fELF").is_err());
+        assert!(Class::parse(b"#!/bin/sh").is_err());
+    }
+}
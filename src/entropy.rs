@@ -0,0 +1,134 @@
+//! Shannon entropy of dumped regions
+//!
+//! Compressed or encrypted data is close to 8 bits per byte, while code and usual data are
+//! well below. Anonymous executable memory holding such data was usually unpacked or injected at
+//! runtime.
+
+use crate::memory::{PermissionBits, Region, RegionKind};
+
+/// Size of the windows over which the highest entropy is looked for
+const WINDOW_SIZE: usize = 4096;
+
+/// Entropy above which a window of anonymous executable memory is reported, in bits per byte
+const HIGH_ENTROPY: f64 = 7.2;
+
+/// Returns the entropy of the byte distribution `counts`, in bits per byte
+fn shannon<T: Copy + Into<u64>>(counts: &[T; 256]) -> f64 {
+    let total: u64 = counts.iter().map(|&c| c.into()).sum();
+    if total == 0 {
+        return 0.0;
+    }
+    let total = total as f64;
+    counts
+        .iter()
+        .map(|&c| c.into())
+        .filter(|&c| c > 0)
+        .map(|c| {
+            let p = c as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Entropy of a region, in bits per byte
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Entropy {
+    /// Entropy of the whole region
+    pub region: f64,
+
+    /// Highest entropy of the [`WINDOW_SIZE`] windows of the region
+    pub max_window: f64,
+}
+
+impl Entropy {
+    /// Whether `region` is anonymous executable memory holding high-entropy data
+    pub fn is_suspicious(&self, region: &Region) -> bool {
+        matches!(
+            region.kind,
+            RegionKind::Anonymous | RegionKind::AnonNamed(_)
+        ) && region.perms.has_perm(PermissionBits::Exec)
+            && self.max_window >= HIGH_ENTROPY
+    }
+}
+
+/// Computes the entropy of data received in chunks
+#[derive(Debug)]
+pub struct Meter {
+    counts: [u64; 256],
+    window: [u16; 256],
+    window_len: usize,
+    max_window: f64,
+}
+
+impl Default for Meter {
+    fn default() -> Self {
+        Self {
+            counts: [0; 256],
+            window: [0; 256],
+            window_len: 0,
+            max_window: 0.0,
+        }
+    }
+}
+
+impl Meter {
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let n = (WINDOW_SIZE - self.window_len).min(data.len());
+            for &b in &data[..n] {
+                self.window[b as usize] += 1;
+            }
+            self.window_len += n;
+            data = &data[n..];
+            if self.window_len == WINDOW_SIZE {
+                self.end_window();
+            }
+        }
+    }
+
+    fn end_window(&mut self) {
+        self.max_window = self.max_window.max(shannon(&self.window));
+        for (count, window) in self.counts.iter_mut().zip(&mut self.window) {
+            *count += *window as u64;
+            *window = 0;
+        }
+        self.window_len = 0;
+    }
+
+    pub fn finish(mut self) -> Entropy {
+        if self.window_len > 0 {
+            self.end_window();
+        }
+        Entropy {
+            region: shannon(&self.counts),
+            max_window: self.max_window,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_meter() {
+        let mut meter = Meter::default();
+        let all_bytes: Vec<u8> = (0..WINDOW_SIZE).map(|i| i as u8).collect();
+        for chunk in all_bytes.chunks(1000) {
+            meter.update(chunk);
+        }
+        let entropy = meter.finish();
+        assert_eq!(entropy.region, 8.0);
+        assert_eq!(entropy.max_window, 8.0);
+
+        // Each window holds a single value, but the region holds two evenly
+        let mut meter = Meter::default();
+        meter.update(&[0; WINDOW_SIZE]);
+        meter.update(&[1; WINDOW_SIZE]);
+        let entropy = meter.finish();
+        assert_eq!(entropy.region, 1.0);
+        assert_eq!(entropy.max_window, 0.0);
+
+        assert_eq!(Meter::default().finish().region, 0.0);
+    }
+}
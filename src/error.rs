@@ -1,9 +1,12 @@
+use std::error;
 use std::fmt;
 use std::io;
 use std::num::ParseIntError;
+use std::path::PathBuf;
 
 /// Errors for this crate
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
     /// Underlying I/O error
     IO(io::Error),
@@ -17,11 +20,106 @@ pub enum Error {
     /// Malformed field in region parsing
     MalformedRegionField { field: &'static str, value: String },
 
+    /// Line of `/proc/PID/maps` which cannot be parsed
+    MapsLine {
+        line: usize,
+        text: String,
+        source: Box<Error>,
+    },
+
+    /// Malformed field in a /proc file
+    MalformedProcField { field: &'static str, value: String },
+
     /// Region not found
     RegionNotFound { start: usize, end: usize },
 
     /// Ptrace error
     Ptrace(io::Error),
+
+    /// Cannot attach to a thread
+    Attach { pid: u32, source: io::Error },
+
+    /// Cannot wait for a traced thread to stop
+    Wait { pid: u32, source: io::Error },
+
+    /// Cannot read the memory of a process
+    Read {
+        pid: u32,
+        address: usize,
+        source: io::Error,
+    },
+
+    /// Reading the memory of a process did not end in time, with `--read-timeout`
+    ReadTimeout { pid: u32, address: usize },
+
+    /// Not allowed to trace a process, with the likely reason and how to fix it
+    PermissionDenied { pid: u32, reason: String },
+
+    /// Invalid glob or regular expression
+    InvalidPattern {
+        pattern: String,
+        reason: &'static str,
+    },
+
+    /// Value cannot be represented with the requested type
+    InvalidValue { ty: &'static str, value: String },
+
+    /// Malformed JSON document
+    Json { reason: &'static str, offset: usize },
+
+    /// Missing or malformed field in a saved state or manifest
+    MalformedState { field: &'static str, value: String },
+
+    /// Dump written with another version of the layout
+    UnsupportedManifest { version: u64 },
+
+    /// Address is not mapped in a writable region
+    NotWritable { address: usize },
+
+    /// Corrupted or unsupported compressed data
+    Decompress {
+        algorithm: &'static str,
+        reason: &'static str,
+    },
+
+    /// Cannot dump a region, with `--strict`
+    RegionFailed {
+        pid: u32,
+        start: usize,
+        end: usize,
+        reason: String,
+    },
+
+    /// No region of a process could be dumped
+    NothingDumped { pid: u32 },
+
+    /// Pages of code of a process differ from their files, found by `verify`
+    CodeModified { pid: u32, pages: usize },
+
+    /// Invalid configuration file
+    Config { path: PathBuf, message: String },
+
+    /// Dumps of `selftest` which were wrong, out of all those taken
+    SelftestFailed { failed: usize, total: usize },
+
+    /// Unexpected or unsupported ELF structure
+    Elf(String),
+
+    /// Invalid command line
+    Usage(String),
+
+    /// Malformed or unexpected message from a remote agent or client
+    Protocol(String),
+
+    /// Error reported by a remote agent, or lost connection to it
+    Remote { address: String, reason: String },
+
+    /// Stopped by SIGINT or SIGTERM
+    Interrupted,
+
+    /// libyara error, or invalid rules
+    #[cfg(feature = "yara")]
+    Yara(String),
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
@@ -35,14 +133,127 @@ impl fmt::Display for Error {
             Self::MalformedRegionField { field, ref value } => {
                 write!(f, "Malformed field {} in region: {:?}", field, value)
             }
+            Self::MapsLine { line, ref text, .. } => {
+                write!(f, "Cannot parse line {} of maps {:?}", line, text)
+            }
+            Self::MalformedProcField { field, ref value } => {
+                write!(f, "Malformed field {} in /proc: {:?}", field, value)
+            }
             Self::RegionNotFound { start, end } => {
                 write!(f, "Region mapped with 0x{:x}..0x{:x} not found", start, end)
             }
-            Self::Ptrace(ref e) => write!(f, "ptrace error: {}", e),
+            Self::Ptrace(_) => f.write_str("ptrace error"),
+            Self::Attach { pid, .. } => write!(f, "Cannot attach to thread {}", pid),
+            Self::Wait { pid, .. } => write!(f, "Cannot wait for thread {}", pid),
+            Self::Read { pid, address, .. } => write!(
+                f,
+                "Cannot read memory of process {} at 0x{:x}",
+                pid, address
+            ),
+            Self::ReadTimeout { pid, address } => write!(
+                f,
+                "Timed out reading memory of process {} at 0x{:x}",
+                pid, address
+            ),
+            Self::PermissionDenied { pid, ref reason } => {
+                write!(f, "Cannot trace process {}: {}", pid, reason)
+            }
+            Self::InvalidPattern {
+                ref pattern,
+                reason,
+            } => write!(f, "Invalid pattern {:?}: {}", pattern, reason),
+            Self::InvalidValue { ty, ref value } => {
+                write!(f, "Invalid {} value: {:?}", ty, value)
+            }
+            Self::Json { reason, offset } => {
+                write!(f, "Invalid JSON at offset {}: {}", offset, reason)
+            }
+            Self::MalformedState { field, ref value } => {
+                write!(f, "Missing or malformed field {} in {}", field, value)
+            }
+            Self::UnsupportedManifest { version } => write!(
+                f,
+                "Dump layout version {} is not supported, only version {} is",
+                version,
+                crate::manifest::MANIFEST_VERSION
+            ),
+            Self::NotWritable { address } => {
+                write!(f, "Address 0x{:x} is not in a writable region", address)
+            }
+            Self::Decompress { algorithm, reason } => {
+                write!(f, "Cannot decompress {} data: {}", algorithm, reason)
+            }
+            Self::RegionFailed {
+                pid,
+                start,
+                end,
+                ref reason,
+            } => write!(
+                f,
+                "Could not dump region {:x}-{:x} of process {}: {}",
+                start, end, pid, reason
+            ),
+            Self::NothingDumped { pid } => write!(f, "No region of process {} was dumped", pid),
+            Self::CodeModified { pid, pages } => write!(
+                f,
+                "{} pages of code of process {} differ from their files",
+                pages, pid
+            ),
+            Self::Config {
+                ref path,
+                ref message,
+            } => write!(f, "Invalid configuration {}: {}", path.display(), message),
+            Self::SelftestFailed { failed, total } => {
+                write!(f, "{} of {} self-test dumps failed", failed, total)
+            }
+            Self::Elf(ref msg) => write!(f, "ELF error: {}", msg),
+            Self::Usage(ref msg) => f.write_str(msg),
+            Self::Protocol(ref msg) => write!(f, "Remote protocol error: {}", msg),
+            Self::Remote {
+                ref address,
+                ref reason,
+            } => write!(f, "Remote agent {}: {}", address, reason),
+            Self::Interrupted => f.write_str("Interrupted"),
+            #[cfg(feature = "yara")]
+            Self::Yara(ref msg) => write!(f, "YARA error: {}", msg),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            // Displayed as the wrapped error, whose source is theirs
+            Self::IO(ref e) => e.source(),
+            Self::ParseIntError(ref e) => e.source(),
+            Self::Ptrace(ref e) => Some(e),
+            Self::Attach { ref source, .. }
+            | Self::Wait { ref source, .. }
+            | Self::Read { ref source, .. } => Some(source),
+            Self::MapsLine { ref source, .. } => Some(source.as_ref()),
+            _ => None,
         }
     }
 }
 
+/// Displays an error followed by its sources, `error: source: source of the source`
+///
+/// Errors with a source do not repeat it in their own message, as error reporters would print it
+/// twice, so diagnostics show them through this.
+pub struct Chain<'a>(pub &'a dyn error::Error);
+
+impl fmt::Display for Chain<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.0, f)?;
+        let mut source = self.0.source();
+        while let Some(e) = source {
+            write!(f, ": {}", e)?;
+            source = e.source();
+        }
+        Ok(())
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(e: io::Error) -> Self {
         Self::IO(e)
@@ -54,3 +265,48 @@ impl From<ParseIntError> for Error {
         Self::ParseIntError(e)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn test_source() {
+        let e = Error::Read {
+            pid: 1,
+            address: 0x1000,
+            source: io::Error::from_raw_os_error(5),
+        };
+        let source = e.source().and_then(|s| s.downcast_ref::<io::Error>());
+        assert_eq!(source.and_then(io::Error::raw_os_error), Some(5));
+        assert!(Error::Interrupted.source().is_none());
+    }
+
+    #[test]
+    fn test_chain() {
+        let e = Error::MapsLine {
+            line: 3,
+            text: "zz".into(),
+            source: Box::new(Error::Read {
+                pid: 1,
+                address: 0x1000,
+                source: io::Error::other("gone"),
+            }),
+        };
+        assert_eq!(e.to_string(), "Cannot parse line 3 of maps \"zz\"");
+        assert_eq!(
+            Chain(&e).to_string(),
+            "Cannot parse line 3 of maps \"zz\": Cannot read memory of process 1 at 0x1000: gone"
+        );
+        assert_eq!(Chain(&Error::Interrupted).to_string(), "Interrupted");
+        assert_eq!(
+            Chain(&Error::Ptrace(io::Error::other("gone"))).to_string(),
+            "ptrace error: gone"
+        );
+        assert_eq!(
+            Chain(&Error::from(io::Error::other("gone"))).to_string(),
+            "gone"
+        );
+    }
+}
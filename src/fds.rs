@@ -0,0 +1,370 @@
+//! Inventory of the file descriptors of a process, with the endpoints of its sockets
+//!
+//! Sockets are looked up by inode in the tables of `/proc/PID/net`, which are those of the network
+//! namespace of the process.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::json::Value;
+use crate::Result;
+
+/// TCP states, as numbered in `/proc/net/tcp`
+const TCP_STATES: [&str; 12] = [
+    "UNKNOWN",
+    "ESTABLISHED",
+    "SYN_SENT",
+    "SYN_RECV",
+    "FIN_WAIT1",
+    "FIN_WAIT2",
+    "TIME_WAIT",
+    "CLOSE",
+    "CLOSE_WAIT",
+    "LAST_ACK",
+    "LISTEN",
+    "CLOSING",
+];
+
+/// Tables of TCP and UDP sockets in `/proc/PID/net`, also the name of their protocol
+const INET_TABLES: [&str; 4] = ["tcp", "tcp6", "udp", "udp6"];
+
+/// `__SO_ACCEPTCON`, in the flags of a listening unix socket
+const SO_ACCEPTCON: u32 = 1 << 16;
+
+/// Endpoints of a socket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Socket {
+    /// TCP or UDP socket, over IPv4 or IPv6
+    Inet {
+        protocol: &'static str,
+        local: SocketAddr,
+        remote: SocketAddr,
+        state: &'static str,
+    },
+
+    /// Unix socket, with the path it is bound to if any (`@` first for abstract ones)
+    Unix {
+        kind: &'static str,
+        state: &'static str,
+        path: Option<String>,
+    },
+}
+
+impl Socket {
+    fn to_json(&self) -> Value {
+        match self {
+            Self::Inet {
+                protocol,
+                local,
+                remote,
+                state,
+            } => Value::object([
+                ("protocol", (*protocol).into()),
+                ("local", local.to_string().into()),
+                ("remote", remote.to_string().into()),
+                ("state", (*state).into()),
+            ]),
+            Self::Unix { kind, state, path } => Value::object([
+                ("protocol", "unix".into()),
+                ("type", (*kind).into()),
+                ("state", (*state).into()),
+                ("path", path.as_deref().into()),
+            ]),
+        }
+    }
+}
+
+/// An open file descriptor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fd {
+    pub fd: u32,
+
+    /// Target of the `/proc/PID/fd` link, a path or a pseudo-file like `socket:[INODE]`
+    pub target: String,
+
+    /// Position, flags (in octal, as in `fdinfo`) and mount id
+    pub pos: Option<u64>,
+    pub flags: Option<String>,
+    pub mnt_id: Option<u64>,
+
+    /// Endpoints, if the descriptor is a TCP, UDP or unix socket
+    pub socket: Option<Socket>,
+}
+
+impl Fd {
+    fn to_json(&self) -> Value {
+        Value::object([
+            ("fd", self.fd.into()),
+            ("target", self.target.as_str().into()),
+            ("pos", self.pos.into()),
+            ("flags", self.flags.as_deref().into()),
+            ("mnt_id", self.mnt_id.into()),
+            (
+                "socket",
+                self.socket.as_ref().map_or(Value::Null, Socket::to_json),
+            ),
+        ])
+    }
+}
+
+/// Parses an address of `/proc/net/tcp`, `0100007F:1F90`
+///
+/// The address is written as 32-bit words in host order.
+fn parse_inet_address(s: &str) -> Option<SocketAddr> {
+    let (address, port) = s.split_once(':')?;
+    let mut bytes = Vec::with_capacity(16);
+    for word in address.as_bytes().chunks(8) {
+        let word = u32::from_str_radix(std::str::from_utf8(word).ok()?, 16).ok()?;
+        bytes.extend(word.to_ne_bytes());
+    }
+    let ip = match bytes.len() {
+        4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(bytes).ok()?)),
+        16 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(bytes).ok()?)),
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, u16::from_str_radix(port, 16).ok()?))
+}
+
+/// Adds the sockets of a `/proc/net/tcp`-like table to `sockets`, by inode
+fn parse_inet_table(protocol: &'static str, table: &str, sockets: &mut HashMap<u64, Socket>) {
+    for line in table.lines().skip(1) {
+        let fields: Vec<_> = line.split_ascii_whitespace().collect();
+        let (Some(local), Some(remote), Some(state), Some(inode)) = (
+            fields.get(1).and_then(|a| parse_inet_address(a)),
+            fields.get(2).and_then(|a| parse_inet_address(a)),
+            fields
+                .get(3)
+                .and_then(|s| usize::from_str_radix(s, 16).ok()),
+            fields.get(9).and_then(|i| i.parse().ok()),
+        ) else {
+            continue;
+        };
+        // UDP sockets are TCP_CLOSE until connected
+        let state = match state {
+            7 if protocol.starts_with("udp") => "UNCONN",
+            _ => TCP_STATES.get(state).copied().unwrap_or("UNKNOWN"),
+        };
+        sockets.insert(
+            inode,
+            Socket::Inet {
+                protocol,
+                local,
+                remote,
+                state,
+            },
+        );
+    }
+}
+
+/// Adds the sockets of a `/proc/net/unix` table to `sockets`, by inode
+fn parse_unix_table(table: &str, sockets: &mut HashMap<u64, Socket>) {
+    for line in table.lines().skip(1) {
+        let mut fields = line.split_ascii_whitespace().skip(3);
+        let (Some(flags), Some(kind), Some(state), Some(inode)) = (
+            fields.next().and_then(|f| u32::from_str_radix(f, 16).ok()),
+            fields.next().and_then(|t| u32::from_str_radix(t, 16).ok()),
+            fields.next().and_then(|s| u32::from_str_radix(s, 16).ok()),
+            fields.next().and_then(|i| i.parse().ok()),
+        ) else {
+            continue;
+        };
+        let path: Vec<_> = fields.collect();
+        let kind = match kind {
+            1 => "STREAM",
+            2 => "DGRAM",
+            5 => "SEQPACKET",
+            _ => "UNKNOWN",
+        };
+        let state = match state {
+            1 if flags & SO_ACCEPTCON != 0 => "LISTEN",
+            1 => "UNCONNECTED",
+            2 => "CONNECTING",
+            3 => "CONNECTED",
+            4 => "DISCONNECTING",
+            _ => "UNKNOWN",
+        };
+        sockets.insert(
+            inode,
+            Socket::Unix {
+                kind,
+                state,
+                path: (!path.is_empty()).then(|| path.join(" ")),
+            },
+        );
+    }
+}
+
+/// Returns the sockets of the network namespace of `pid`, by inode
+///
+/// Missing tables, like those of IPv6 when it is disabled, are skipped.
+fn sockets(pid: u32) -> HashMap<u64, Socket> {
+    let mut sockets = HashMap::new();
+    let read = |name| std::fs::read_to_string(format!("/proc/{}/net/{}", pid, name));
+    for protocol in INET_TABLES {
+        match read(protocol) {
+            Ok(table) => parse_inet_table(protocol, &table, &mut sockets),
+            Err(e) => debug!("Could not read /proc/{}/net/{}: {}", pid, protocol, e),
+        }
+    }
+    match read("unix") {
+        Ok(table) => parse_unix_table(&table, &mut sockets),
+        Err(e) => debug!("Could not read /proc/{}/net/unix: {}", pid, e),
+    }
+    sockets
+}
+
+/// Parses the position, flags and mount id of a `/proc/PID/fdinfo` file
+fn parse_fdinfo(fdinfo: &str) -> (Option<u64>, Option<String>, Option<u64>) {
+    let (mut pos, mut flags, mut mnt_id) = (None, None, None);
+    for line in fdinfo.lines() {
+        match line.split_once(':') {
+            Some(("pos", value)) => pos = value.trim().parse().ok(),
+            Some(("flags", value)) => flags = Some(value.trim().to_owned()),
+            Some(("mnt_id", value)) => mnt_id = value.trim().parse().ok(),
+            _ => {}
+        }
+    }
+    (pos, flags, mnt_id)
+}
+
+/// Returns the open file descriptors of `pid`, sorted
+pub fn inventory(pid: u32) -> Result<Vec<Fd>> {
+    let sockets = sockets(pid);
+    let mut fds = Vec::new();
+    for entry in std::fs::read_dir(format!("/proc/{}/fd", pid))? {
+        let entry = entry?;
+        let Some(fd) = entry
+            .file_name()
+            .to_str()
+            .and_then(|n| n.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        // The descriptor may be closed in the meantime
+        let Ok(target) = std::fs::read_link(entry.path()) else {
+            continue;
+        };
+        let target = target.to_string_lossy().into_owned();
+        let (pos, flags, mnt_id) =
+            match std::fs::read_to_string(format!("/proc/{}/fdinfo/{}", pid, fd)) {
+                Ok(fdinfo) => parse_fdinfo(&fdinfo),
+                Err(_) => (None, None, None),
+            };
+        let socket = target
+            .strip_prefix("socket:[")
+            .and_then(|inode| inode.strip_suffix(']')?.parse::<u64>().ok())
+            .and_then(|inode| sockets.get(&inode).cloned());
+        fds.push(Fd {
+            fd,
+            target,
+            pos,
+            flags,
+            mnt_id,
+            socket,
+        });
+    }
+    fds.sort_unstable_by_key(|fd| fd.fd);
+    Ok(fds)
+}
+
+/// Describes `fds` as a JSON array, as written to `fds.json`
+pub fn to_json(fds: &[Fd]) -> Value {
+    Value::Array(fds.iter().map(Fd::to_json).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_endian = "little")]
+    fn test_parse_inet_table() {
+        let mut sockets = HashMap::new();
+        parse_inet_table(
+            "tcp",
+            "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode\n   \
+             0: 0100007F:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 4242 1 0000000000000000 100 0 0 10 0\n   \
+             1: 0100007F:1F90 0100007F:D431 01 00000000:00000000 00:00000000 00000000  1000        0 4243 1 0000000000000000 20 4 30 10 -1\n",
+            &mut sockets,
+        );
+        parse_inet_table(
+            "udp6",
+            "  sl  local_address                         remote_address                        st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops\n  \
+             0: 00000000000000000000000001000000:0035 00000000000000000000000000000000:0000 07 00000000:00000000 00:00000000 00000000     0        0 4244 2 0000000000000000 0\n",
+            &mut sockets,
+        );
+        assert_eq!(sockets.len(), 3);
+        assert_eq!(
+            sockets[&4242],
+            Socket::Inet {
+                protocol: "tcp",
+                local: "127.0.0.1:8080".parse().unwrap(),
+                remote: "0.0.0.0:0".parse().unwrap(),
+                state: "LISTEN",
+            }
+        );
+        assert_eq!(
+            sockets[&4243],
+            Socket::Inet {
+                protocol: "tcp",
+                local: "127.0.0.1:8080".parse().unwrap(),
+                remote: "127.0.0.1:54321".parse().unwrap(),
+                state: "ESTABLISHED",
+            }
+        );
+        assert_eq!(
+            sockets[&4244],
+            Socket::Inet {
+                protocol: "udp6",
+                local: "[::1]:53".parse().unwrap(),
+                remote: "[::]:0".parse().unwrap(),
+                state: "UNCONN",
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_unix_table() {
+        let mut sockets = HashMap::new();
+        parse_unix_table(
+            "Num       RefCount Protocol Flags    Type St Inode Path\n\
+             0000000000000000: 00000002 00000000 00010000 0001 01 17001 /run/app.sock\n\
+             0000000000000000: 00000003 00000000 00000000 0001 03 17002\n\
+             0000000000000000: 00000002 00000000 00000000 0002 01  1703 @abstract\n",
+            &mut sockets,
+        );
+        assert_eq!(
+            sockets[&17001],
+            Socket::Unix {
+                kind: "STREAM",
+                state: "LISTEN",
+                path: Some("/run/app.sock".into()),
+            }
+        );
+        assert_eq!(
+            sockets[&17002],
+            Socket::Unix {
+                kind: "STREAM",
+                state: "CONNECTED",
+                path: None,
+            }
+        );
+        assert_eq!(
+            sockets[&1703],
+            Socket::Unix {
+                kind: "DGRAM",
+                state: "UNCONNECTED",
+                path: Some("@abstract".into()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_fdinfo() {
+        assert_eq!(
+            parse_fdinfo("pos:\t4096\nflags:\t02100002\nmnt_id:\t29\nino:\t1234\n"),
+            (Some(4096), Some("02100002".into()), Some(29))
+        );
+        assert_eq!(parse_fdinfo(""), (None, None, None));
+    }
+}
@@ -0,0 +1,503 @@
+//! Regions of a process as the files of a FUSE filesystem
+//!
+//! `dump-memory mount PID MOUNTPOINT` mounts a read-only directory holding a file per region, named
+//! as in `dir` dumps, whose content is only read from the process when the file is read. The process
+//! stays attached, and stopped, until the filesystem is unmounted, with `umount` or by interrupting
+//! the command.
+//!
+//! The kernel's protocol is spoken over `/dev/fuse`, mounted with `mount(2)` as `fusermount` is not
+//! used: mounting needs the `CAP_SYS_ADMIN` capability. Requests are answered one at a time.
+
+use std::ffi::{c_char, c_void, CString};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cli::MountOptions;
+use crate::error::Chain;
+use crate::memory::Region;
+use crate::signal;
+use crate::target::Reader;
+use crate::Result;
+
+const FUSE_KERNEL_VERSION: u32 = 7;
+const FUSE_KERNEL_MINOR_VERSION: u32 = 31;
+
+const FUSE_LOOKUP: u32 = 1;
+const FUSE_FORGET: u32 = 2;
+const FUSE_GETATTR: u32 = 3;
+const FUSE_OPEN: u32 = 14;
+const FUSE_READ: u32 = 15;
+const FUSE_STATFS: u32 = 17;
+const FUSE_RELEASE: u32 = 18;
+const FUSE_FLUSH: u32 = 25;
+const FUSE_INIT: u32 = 26;
+const FUSE_OPENDIR: u32 = 27;
+const FUSE_READDIR: u32 = 28;
+const FUSE_RELEASEDIR: u32 = 29;
+const FUSE_INTERRUPT: u32 = 36;
+const FUSE_DESTROY: u32 = 38;
+const FUSE_BATCH_FORGET: u32 = 42;
+
+/// Reads bypass the page cache, memory is read again each time
+const FOPEN_DIRECT_IO: u32 = 1;
+
+const ENOENT: i32 = 2;
+const EIO: i32 = 5;
+const ENOTDIR: i32 = 20;
+const EISDIR: i32 = 21;
+const ENOSYS: i32 = 38;
+
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const DT_DIR: u32 = 4;
+const DT_REG: u32 = 8;
+
+const MS_RDONLY: u64 = 1;
+const MS_NOSUID: u64 = 2;
+const MS_NODEV: u64 = 4;
+const MS_NOEXEC: u64 = 8;
+const MNT_DETACH: i32 = 2;
+
+const POLLIN: i16 = 1;
+
+const ROOT_INODE: u64 = 1;
+
+/// Size of `struct fuse_in_header`, before the arguments of each request
+const IN_HEADER_SIZE: usize = 40;
+
+/// Largest read the kernel sends, in one request
+const MAX_READ: u32 = 1 << 20;
+
+/// Size of the buffer for requests, which the kernel wants large enough for the largest write
+const BUFFER_SIZE: usize = MAX_READ as usize + 4096;
+
+/// How long the kernel may cache names and attributes, in seconds: the process is stopped
+const TIMEOUT: u64 = 60;
+
+/// How often interruptions are checked while waiting for requests, in milliseconds
+const POLL_INTERVAL: i32 = 100;
+
+#[repr(C)]
+struct PollFd {
+    fd: i32,
+    events: i16,
+    revents: i16,
+}
+
+extern "C" {
+    fn mount(
+        source: *const c_char,
+        target: *const c_char,
+        fstype: *const c_char,
+        flags: u64,
+        data: *const c_void,
+    ) -> i32;
+    fn umount2(target: *const c_char, flags: i32) -> i32;
+    fn getuid() -> u32;
+    fn getgid() -> u32;
+    fn poll(fds: *mut PollFd, nfds: u64, timeout: i32) -> i32;
+}
+
+/// Appends the native-endian bytes of integers to a reply
+trait Put {
+    fn put32(&mut self, value: u32) -> &mut Self;
+    fn put64(&mut self, value: u64) -> &mut Self;
+}
+
+impl Put for Vec<u8> {
+    fn put32(&mut self, value: u32) -> &mut Self {
+        self.extend_from_slice(&value.to_ne_bytes());
+        self
+    }
+
+    fn put64(&mut self, value: u64) -> &mut Self {
+        self.extend_from_slice(&value.to_ne_bytes());
+        self
+    }
+}
+
+fn get32(data: &[u8], offset: usize) -> u32 {
+    data.get(offset..offset + 4)
+        .map_or(0, |b| u32::from_ne_bytes(b.try_into().unwrap()))
+}
+
+fn get64(data: &[u8], offset: usize) -> u64 {
+    data.get(offset..offset + 8)
+        .map_or(0, |b| u64::from_ne_bytes(b.try_into().unwrap()))
+}
+
+/// The directory of regions, inode `index + 2` being the region at `index`
+#[derive(Debug)]
+struct Filesystem<'a> {
+    files: Vec<(String, &'a Region)>,
+    uid: u32,
+    gid: u32,
+
+    /// Time of the mount, in seconds since the epoch, given as the times of every file
+    time: u64,
+}
+
+impl<'a> Filesystem<'a> {
+    fn new(regions: impl Iterator<Item = &'a Region>, uid: u32, gid: u32, time: u64) -> Self {
+        Self {
+            files: regions.map(|r| (r.to_string(), r)).collect(),
+            uid,
+            gid,
+            time,
+        }
+    }
+
+    fn region(&self, inode: u64) -> Option<&'a Region> {
+        let index = usize::try_from(inode.checked_sub(2)?).ok()?;
+        self.files.get(index).map(|&(_, region)| region)
+    }
+
+    fn lookup(&self, name: &[u8]) -> Option<u64> {
+        self.files
+            .iter()
+            .position(|(n, _)| n.as_bytes() == name)
+            .map(|index| index as u64 + 2)
+    }
+
+    /// `struct fuse_attr` of `inode`
+    fn attr(&self, inode: u64) -> Option<Vec<u8>> {
+        let (size, mode, nlink) = match inode {
+            ROOT_INODE => (0, S_IFDIR | 0o500, 2),
+            _ => (self.region(inode)?.size() as u64, S_IFREG | 0o400, 1),
+        };
+        let mut attr = Vec::with_capacity(88);
+        attr.put64(inode).put64(size).put64(size.div_ceil(512));
+        attr.put64(self.time).put64(self.time).put64(self.time);
+        attr.put32(0).put32(0).put32(0);
+        attr.put32(mode)
+            .put32(nlink)
+            .put32(self.uid)
+            .put32(self.gid);
+        attr.put32(0).put32(4096).put32(0);
+        Some(attr)
+    }
+
+    /// `struct fuse_entry_out` of `inode`
+    fn entry(&self, inode: u64) -> Option<Vec<u8>> {
+        let mut entry = Vec::with_capacity(128);
+        entry.put64(inode).put64(0).put64(TIMEOUT).put64(TIMEOUT);
+        entry.put32(0).put32(0);
+        entry.extend(self.attr(inode)?);
+        Some(entry)
+    }
+
+    /// Entries of the root directory from the `offset`-th one, as many `struct fuse_dirent` as fit
+    /// in `size` bytes
+    fn readdir(&self, offset: u64, size: usize) -> Vec<u8> {
+        let entries = [(".", ROOT_INODE, DT_DIR), ("..", ROOT_INODE, DT_DIR)]
+            .into_iter()
+            .chain(
+                self.files
+                    .iter()
+                    .enumerate()
+                    .map(|(index, (name, _))| (name.as_str(), index as u64 + 2, DT_REG)),
+            );
+        let mut dirents = Vec::new();
+        for (next, (name, inode, kind)) in entries.enumerate().skip(offset as usize) {
+            let len = (24 + name.len()).next_multiple_of(8);
+            if dirents.len() + len > size {
+                break;
+            }
+            dirents.put64(inode).put64(next as u64 + 1);
+            dirents.put32(name.len() as u32).put32(kind);
+            dirents.extend_from_slice(name.as_bytes());
+            dirents.resize(dirents.len().next_multiple_of(8), 0);
+        }
+        dirents
+    }
+
+    /// Answers a request, `None` for those which have no answer
+    ///
+    /// Errors are negative errnos, as sent to the kernel.
+    fn handle(
+        &self,
+        reader: &mut Reader,
+        opcode: u32,
+        inode: u64,
+        args: &[u8],
+    ) -> Option<std::result::Result<Vec<u8>, i32>> {
+        let reply = match opcode {
+            FUSE_INIT => {
+                // `struct fuse_init_out`, without any optional feature
+                let mut init = Vec::with_capacity(64);
+                init.put32(FUSE_KERNEL_VERSION)
+                    .put32(FUSE_KERNEL_MINOR_VERSION)
+                    .put32(get32(args, 8))
+                    .put32(0);
+                init.put32(0).put32(4096).put32(1);
+                init.resize(64, 0);
+                Ok(init)
+            }
+            FUSE_LOOKUP if inode == ROOT_INODE => {
+                let name = args.split(|&b| b == 0).next().unwrap_or_default();
+                self.lookup(name)
+                    .and_then(|inode| self.entry(inode))
+                    .ok_or(ENOENT)
+            }
+            FUSE_LOOKUP => Err(ENOTDIR),
+            FUSE_GETATTR => match self.attr(inode) {
+                Some(attr) => {
+                    let mut out = Vec::with_capacity(104);
+                    out.put64(TIMEOUT).put32(0).put32(0);
+                    out.extend(attr);
+                    Ok(out)
+                }
+                None => Err(ENOENT),
+            },
+            FUSE_OPEN if inode == ROOT_INODE => Err(EISDIR),
+            FUSE_OPEN | FUSE_OPENDIR => {
+                let flags = if opcode == FUSE_OPEN {
+                    FOPEN_DIRECT_IO
+                } else if inode == ROOT_INODE {
+                    0
+                } else {
+                    return Some(Err(ENOTDIR));
+                };
+                let mut out = Vec::with_capacity(16);
+                out.put64(0).put32(flags).put32(0);
+                Ok(out)
+            }
+            FUSE_READ => self.read(reader, inode, get64(args, 8), get32(args, 16)),
+            FUSE_READDIR => Ok(self.readdir(get64(args, 8), get32(args, 16) as usize)),
+            FUSE_STATFS => {
+                // `struct fuse_kstatfs`
+                let mut out = Vec::with_capacity(80);
+                out.put64(0).put64(0).put64(0);
+                out.put64(self.files.len() as u64).put64(0);
+                out.put32(4096).put32(255).put32(4096);
+                out.resize(80, 0);
+                Ok(out)
+            }
+            FUSE_RELEASE | FUSE_RELEASEDIR | FUSE_FLUSH | FUSE_DESTROY => Ok(Vec::new()),
+            FUSE_FORGET | FUSE_BATCH_FORGET | FUSE_INTERRUPT => return None,
+            _ => Err(ENOSYS),
+        };
+        Some(reply.map_err(|errno| -errno))
+    }
+
+    fn read(
+        &self,
+        reader: &mut Reader,
+        inode: u64,
+        offset: u64,
+        size: u32,
+    ) -> std::result::Result<Vec<u8>, i32> {
+        let region = self.region(inode).ok_or(ENOENT)?;
+        let offset = (offset as usize).min(region.size());
+        let mut data = vec![0; (size as usize).min(region.size() - offset)];
+        if let Err(e) = reader.read(region.start + offset, &mut data) {
+            debug!(
+                "Could not read 0x{:x}..0x{:x}: {}",
+                region.start + offset,
+                region.start + offset + data.len(),
+                Chain(&e)
+            );
+            return Err(EIO);
+        }
+        Ok(data)
+    }
+}
+
+/// A mounted filesystem, unmounted when dropped
+struct Mount {
+    path: CString,
+    fuse: File,
+}
+
+impl Mount {
+    fn new(path: &Path, uid: u32, gid: u32) -> Result<Self> {
+        let fuse = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/fuse")?;
+        let c_path = CString::new(path.as_os_str().as_bytes()).map_err(io::Error::other)?;
+        let data = CString::new(format!(
+            "fd={},rootmode={:o},user_id={},group_id={},default_permissions,max_read={}",
+            fuse.as_raw_fd(),
+            S_IFDIR,
+            uid,
+            gid,
+            MAX_READ
+        ))
+        .unwrap();
+        // SAFETY: the strings are NUL-terminated, and outlive the call
+        let ret = unsafe {
+            mount(
+                c"dump-memory".as_ptr(),
+                c_path.as_ptr(),
+                c"fuse.dump-memory".as_ptr(),
+                MS_RDONLY | MS_NOSUID | MS_NODEV | MS_NOEXEC,
+                data.as_ptr().cast(),
+            )
+        };
+        if ret != 0 {
+            let e = io::Error::last_os_error();
+            return Err(io::Error::new(
+                e.kind(),
+                format!("cannot mount {}: {}", path.display(), e),
+            )
+            .into());
+        }
+        Ok(Self { path: c_path, fuse })
+    }
+
+    /// Waits for a request, `false` if none came in time
+    ///
+    /// Interruptions are checked while waiting.
+    fn wait_request(&self) -> Result<bool> {
+        signal::check()?;
+        let mut fd = PollFd {
+            fd: self.fuse.as_raw_fd(),
+            events: POLLIN,
+            revents: 0,
+        };
+        // SAFETY: `fd` is a single `struct pollfd`
+        match unsafe { poll(&mut fd, 1, POLL_INTERVAL) } {
+            -1 => match io::Error::last_os_error() {
+                e if e.kind() == io::ErrorKind::Interrupted => Ok(false),
+                e => Err(e.into()),
+            },
+            n => Ok(n > 0),
+        }
+    }
+}
+
+impl Drop for Mount {
+    fn drop(&mut self) {
+        // Fails if it was unmounted already. The device is closed afterwards, once the
+        // filesystem is detached.
+        // SAFETY: the path is NUL-terminated
+        unsafe { umount2(self.path.as_ptr(), MNT_DETACH) };
+    }
+}
+
+/// Answers requests until the filesystem is unmounted
+fn serve(mount: &Mount, filesystem: &Filesystem, reader: &mut Reader) -> Result<()> {
+    let mut buffer = vec![0; BUFFER_SIZE];
+    loop {
+        if !mount.wait_request()? {
+            continue;
+        }
+        let len = match (&mount.fuse).read(&mut buffer) {
+            Ok(len) => len,
+            // ENODEV once unmounted
+            Err(e) if e.raw_os_error() == Some(19) => return Ok(()),
+            // ENOENT if the request was interrupted in between
+            Err(e)
+                if e.raw_os_error() == Some(ENOENT)
+                    || matches!(
+                        e.kind(),
+                        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+                    ) =>
+            {
+                continue
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if len < IN_HEADER_SIZE {
+            continue;
+        }
+        let request = &buffer[..len];
+        let opcode = get32(request, 4);
+        let unique = get64(request, 8);
+        let inode = get64(request, 16);
+        let Some(reply) = filesystem.handle(reader, opcode, inode, &request[IN_HEADER_SIZE..])
+        else {
+            continue;
+        };
+        let (error, payload) = match reply {
+            Ok(payload) => (0, payload),
+            Err(error) => (error, Vec::new()),
+        };
+        let mut out = Vec::with_capacity(16 + payload.len());
+        out.put32(16 + payload.len() as u32)
+            .put32(error as u32)
+            .put64(unique);
+        out.extend(payload);
+        // The reply is written at once, it fails with ENOENT if the request was interrupted
+        if let Err(e) = (&mount.fuse).write(&out) {
+            if e.raw_os_error() != Some(ENOENT) {
+                return Err(e.into());
+            }
+        }
+        if opcode == FUSE_DESTROY {
+            return Ok(());
+        }
+    }
+}
+
+pub fn run(options: &MountOptions) -> Result<()> {
+    let (mut reader, memory) = options.target.open()?;
+    // SAFETY: these calls cannot fail
+    let (uid, gid) = unsafe { (getuid(), getgid()) };
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let filesystem = Filesystem::new(memory.filter(&options.filter), uid, gid, time);
+    let mount = Mount::new(&options.mountpoint, uid, gid)?;
+    info!(
+        "{} regions of process {} mounted on {}, until it is unmounted",
+        filesystem.files.len(),
+        reader.pid(),
+        options.mountpoint.display()
+    );
+    serve(&mount, &filesystem, &mut reader)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filesystem() {
+        let regions: Vec<Region> = [
+            "1000-3000 r-xp 00000000 fe:00 42 /usr/bin/cat",
+            "7ffc0000-7ffc1000 rw-p 00000000 00:00 0 [stack]",
+        ]
+        .iter()
+        .map(|r| r.parse().unwrap())
+        .collect();
+        let filesystem = Filesystem::new(regions.iter(), 1000, 100, 0);
+        let stack = regions[1].to_string();
+        assert_eq!(filesystem.lookup(stack.as_bytes()), Some(3));
+        assert_eq!(filesystem.lookup(b"stack"), None);
+
+        let attr = filesystem.attr(3).unwrap();
+        assert_eq!(attr.len(), 88);
+        assert_eq!(get64(&attr, 8), 0x1000);
+        assert_eq!(get32(&attr, 60), S_IFREG | 0o400);
+        assert_eq!(get32(&attr, 68), 1000);
+        assert!(filesystem.attr(4).is_none());
+        assert_eq!(filesystem.entry(ROOT_INODE).unwrap().len(), 128);
+
+        let dirents = filesystem.readdir(0, 4096);
+        let mut names = Vec::new();
+        let mut offset = 0;
+        while offset < dirents.len() {
+            let len = get32(&dirents, offset + 16) as usize;
+            names
+                .push(String::from_utf8(dirents[offset + 24..offset + 24 + len].to_vec()).unwrap());
+            offset += (24 + len).next_multiple_of(8);
+        }
+        assert_eq!(
+            names,
+            [".".into(), "..".into(), regions[0].to_string(), stack]
+        );
+        // Continued from the last entry returned, with room for a single one
+        let dirents = filesystem.readdir(2, 64);
+        assert_eq!(get64(&dirents, 0), 2);
+        assert_eq!(get64(&dirents, 8), 3);
+        assert_eq!(dirents.len(), 56);
+        assert!(filesystem.readdir(4, 4096).is_empty());
+    }
+}
@@ -0,0 +1,102 @@
+//! Classic hexdump formatting, and the hexdump command
+
+use std::ops::Range;
+
+use crate::cli::HexdumpOptions;
+use crate::memory::Memory;
+use crate::target::Reader;
+use crate::{Error, Result};
+
+/// Number of bytes per line
+pub const WIDTH: usize = 16;
+
+/// Formats up to `WIDTH` bytes as `0xADDRESS: hex bytes |ascii|`
+pub fn line(address: usize, bytes: &[u8]) -> String {
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    let ascii: String = bytes
+        .iter()
+        .map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            }
+        })
+        .collect();
+    format!(
+        "0x{:x}: {:<width$} |{}|",
+        address,
+        hex.join(" "),
+        ascii,
+        width = WIDTH * 3 - 1
+    )
+}
+
+/// Formats `bytes` as lines, like `hexdump -C`
+///
+/// Lines repeating the previous one are replaced by a single `*`, and the address following the
+/// data is printed last.
+pub fn lines(address: usize, bytes: &[u8]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut previous: Option<&[u8]> = None;
+    for (i, chunk) in bytes.chunks(WIDTH).enumerate() {
+        if previous == Some(chunk) {
+            if lines.last().is_none_or(|l| l != "*") {
+                lines.push("*".into());
+            }
+            continue;
+        }
+        lines.push(line(address + i * WIDTH, chunk));
+        previous = Some(chunk);
+    }
+    lines.push(format!("0x{:x}", address + bytes.len()));
+    lines
+}
+
+/// Reads `range`, after checking that it is entirely mapped
+pub fn read_range(reader: &mut Reader, memory: &Memory, range: &Range<usize>) -> Result<Vec<u8>> {
+    if let Some(gap) = memory.unmapped(range).first() {
+        return Err(Error::RegionNotFound {
+            start: gap.start,
+            end: gap.end,
+        });
+    }
+    let mut buffer = vec![0; range.len()];
+    reader.read(range.start, &mut buffer[..])?;
+    Ok(buffer)
+}
+
+pub fn run(options: &HexdumpOptions) -> Result<()> {
+    let range = &options.range;
+    let (mut reader, memory) = options.target.open()?;
+    let buffer = read_range(&mut reader, &memory, range)?;
+    for line in lines(range.start, &buffer[..]) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line() {
+        assert_eq!(
+            line(0x1000, b"AB\x00"),
+            format!("0x1000: 41 42 00{} |AB.|", " ".repeat(39))
+        );
+    }
+
+    #[test]
+    fn test_lines() {
+        let mut bytes = vec![0u8; 4 * WIDTH];
+        bytes.extend_from_slice(b"hello");
+        let lines = lines(0x2000, &bytes);
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("0x2000: 00 00"));
+        assert_eq!(lines[1], "*");
+        assert!(lines[2].starts_with("0x2040: 68 65 6c 6c 6f "));
+        assert_eq!(lines[3], "0x2045");
+    }
+}
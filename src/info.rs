@@ -0,0 +1,115 @@
+//! Description of a dump written earlier, or of a core file
+
+use crate::cli::InfoOptions;
+use crate::dump::human_size;
+use crate::manifest::{Manifest, Status, MANIFEST_VERSION};
+use crate::snapshot::Snapshot;
+use crate::Result;
+
+/// Optional files of `dir` and `dedup` dumps
+const EXTRAS: [&str; 4] = ["backtraces.txt", "proc", "files", "SHA256SUMS"];
+
+/// Counts and sizes of the regions, by outcome
+fn regions_line(manifest: &Manifest) -> String {
+    let mut dumped = 0;
+    let mut partial = 0;
+    let mut failed = 0;
+    let mut skipped = 0;
+    let mut bytes = 0;
+    for entry in &manifest.entries {
+        match entry.status {
+            Status::Dumped => {
+                dumped += 1;
+                partial += !entry.unreadable.is_empty() as usize;
+                bytes += entry.bytes;
+            }
+            Status::Failed(_) => failed += 1,
+            Status::Skipped(_) => skipped += 1,
+        }
+    }
+    let mut line = format!(
+        "{} dumped ({}, {} partially), {} failed",
+        dumped,
+        human_size(bytes),
+        partial,
+        failed
+    );
+    if skipped > 0 {
+        line.push_str(&format!(", {} skipped", skipped));
+    }
+    line
+}
+
+pub fn run(options: &InfoOptions) -> Result<()> {
+    let snapshot = Snapshot::open(&options.dump)?;
+    let manifest = &snapshot.manifest;
+    let stored: u64 = manifest.entries.iter().map(|e| e.stored_bytes).sum();
+
+    println!("Process:      {}", manifest.pid);
+    match snapshot.path("manifest.json") {
+        Some(_) => println!(
+            "Format:       {} (layout version {})",
+            manifest.format, MANIFEST_VERSION
+        ),
+        None => println!("Format:       ELF core file"),
+    }
+    println!("Compression:  {}", manifest.compression);
+    if !manifest.namespaces.is_empty() {
+        let namespaces: Vec<String> = manifest
+            .namespaces
+            .iter()
+            .map(|(ns, id)| format!("{}:[{}]", ns, id))
+            .collect();
+        println!("Namespaces:   {}", namespaces.join(" "));
+    }
+    println!("Regions:      {}", regions_line(manifest));
+    let changed = manifest.entries.iter().filter(|e| e.changed).count();
+    if changed > 0 {
+        println!(
+            "Changed:      {} regions were mapped differently after the dump",
+            changed
+        );
+    }
+    if let Some(pause) = manifest.pause_time {
+        let slowest = manifest
+            .entries
+            .iter()
+            .filter_map(|e| Some((e.read_time?, &e.region)))
+            .max_by_key(|(time, _)| *time);
+        match slowest {
+            Some((time, region)) => println!(
+                "Paused:       {:.3} s, the slowest region {:x}-{:x} read in {:.3} s",
+                pause.as_secs_f64(),
+                region.start,
+                region.end,
+                time.as_secs_f64()
+            ),
+            None => println!("Paused:       {:.3} s", pause.as_secs_f64()),
+        }
+    }
+    println!("Stored:       {}", human_size(stored));
+    if let Some(ref store) = manifest.store {
+        println!(
+            "Page store:   {} ({} bytes pages)",
+            store.path.display(),
+            store.page_size
+        );
+    }
+    if let Some(dir) = snapshot.path("registers") {
+        let threads = std::fs::read_dir(dir).map_or(0, |entries| entries.count());
+        if threads > 0 {
+            println!("Threads:      {} (registers/)", threads);
+        }
+    }
+    let extras: Vec<&str> = EXTRAS
+        .into_iter()
+        .filter(|name| snapshot.path(name).is_some_and(|path| path.exists()))
+        .collect();
+    if !extras.is_empty() {
+        println!("Extras:       {}", extras.join(", "));
+    }
+    if manifest.interrupted {
+        println!("Interrupted:  yes, the regions after the last one were not dumped");
+    }
+    Ok(())
+}
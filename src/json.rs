@@ -0,0 +1,483 @@
+//! Minimal JSON values, serialization and parsing
+
+use std::fmt::{self, Write};
+use std::str::FromStr;
+
+use crate::{Error, Result};
+
+/// A JSON value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    UInt(u64),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Builds an object from key/value pairs, keeping order
+    pub fn object<'a>(fields: impl IntoIterator<Item = (&'a str, Value)>) -> Self {
+        Self::Object(fields.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+
+    /// Appends a field to an object, does nothing on other values
+    pub fn push(&mut self, key: &str, value: impl Into<Value>) {
+        if let Self::Object(ref mut fields) = self {
+            fields.push((key.into(), value.into()));
+        }
+    }
+
+    /// Returns the field `key` of an object
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Self::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Self::UInt(n) => Some(n),
+            Self::Int(n) => n.try_into().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Self::UInt(n) => Some(n as f64),
+            Self::Int(n) => Some(n as f64),
+            Self::Float(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Self::Array(items) => Some(&items[..]),
+            _ => None,
+        }
+    }
+
+    /// Serializes with two-space indentation
+    pub fn to_pretty_string(&self) -> String {
+        let mut s = String::new();
+        self.write(&mut s, Some(0))
+            .expect("Writing to a String cannot fail");
+        s
+    }
+
+    fn write<W: Write>(&self, w: &mut W, indent: Option<usize>) -> fmt::Result {
+        let newline = |w: &mut W, level: usize| -> fmt::Result {
+            if indent.is_some() {
+                w.write_char('\n')?;
+                for _ in 0..level {
+                    w.write_str("  ")?;
+                }
+            }
+            Ok(())
+        };
+        let level = indent.unwrap_or(0);
+        let inner = indent.map(|i| i + 1);
+
+        match self {
+            Self::Null => w.write_str("null"),
+            Self::Bool(b) => write!(w, "{}", b),
+            Self::UInt(n) => write!(w, "{}", n),
+            Self::Int(n) => write!(w, "{}", n),
+            Self::Float(n) if n.is_finite() => write!(w, "{}", n),
+            Self::Float(_) => w.write_str("null"),
+            Self::String(s) => write_string(w, s),
+            Self::Array(items) if items.is_empty() => w.write_str("[]"),
+            Self::Array(items) => {
+                w.write_char('[')?;
+                for (i, item) in items.iter().enumerate() {
+                    if i != 0 {
+                        w.write_char(',')?;
+                    }
+                    newline(w, level + 1)?;
+                    item.write(w, inner)?;
+                }
+                newline(w, level)?;
+                w.write_char(']')
+            }
+            Self::Object(fields) if fields.is_empty() => w.write_str("{}"),
+            Self::Object(fields) => {
+                w.write_char('{')?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i != 0 {
+                        w.write_char(',')?;
+                    }
+                    newline(w, level + 1)?;
+                    write_string(w, key)?;
+                    w.write_str(if indent.is_some() { ": " } else { ":" })?;
+                    value.write(w, inner)?;
+                }
+                newline(w, level)?;
+                w.write_char('}')
+            }
+        }
+    }
+}
+
+fn write_string<W: Write>(w: &mut W, s: &str) -> fmt::Result {
+    w.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => w.write_str("\\\"")?,
+            '\\' => w.write_str("\\\\")?,
+            '\n' => w.write_str("\\n")?,
+            '\r' => w.write_str("\\r")?,
+            '\t' => w.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(w, "\\u{:04x}", c as u32)?,
+            c => w.write_char(c)?,
+        }
+    }
+    w.write_char('"')
+}
+
+/// Compact serialization
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, None)
+    }
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, reason: &'static str) -> Error {
+        Error::Json {
+            reason,
+            offset: self.pos,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .input
+            .get(self.pos)
+            .is_some_and(|b| b.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, literal: &'static str) -> Result<()> {
+        if self.input[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            Ok(())
+        } else {
+            Err(self.error("unexpected token"))
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value> {
+        self.skip_whitespace();
+        let value = match self.input.get(self.pos) {
+            Some(b'n') => self.expect("null").map(|_| Value::Null)?,
+            Some(b't') => self.expect("true").map(|_| Value::Bool(true))?,
+            Some(b'f') => self.expect("false").map(|_| Value::Bool(false))?,
+            Some(b'"') => Value::String(self.parse_string()?),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.input.get(self.pos) == Some(&b']') {
+                    self.pos += 1;
+                } else {
+                    loop {
+                        items.push(self.parse_value()?);
+                        self.skip_whitespace();
+                        match self.input.get(self.pos) {
+                            Some(b',') => self.pos += 1,
+                            Some(b']') => {
+                                self.pos += 1;
+                                break;
+                            }
+                            _ => return Err(self.error("expected ',' or ']'")),
+                        }
+                    }
+                }
+                Value::Array(items)
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                self.skip_whitespace();
+                if self.input.get(self.pos) == Some(&b'}') {
+                    self.pos += 1;
+                } else {
+                    loop {
+                        self.skip_whitespace();
+                        if self.input.get(self.pos) != Some(&b'"') {
+                            return Err(self.error("expected string key"));
+                        }
+                        let key = self.parse_string()?;
+                        self.skip_whitespace();
+                        self.expect(":")?;
+                        fields.push((key, self.parse_value()?));
+                        self.skip_whitespace();
+                        match self.input.get(self.pos) {
+                            Some(b',') => self.pos += 1,
+                            Some(b'}') => {
+                                self.pos += 1;
+                                break;
+                            }
+                            _ => return Err(self.error("expected ',' or '}'")),
+                        }
+                    }
+                }
+                Value::Object(fields)
+            }
+            Some(b'-' | b'0'..=b'9') => self.parse_number()?,
+            Some(_) => return Err(self.error("unexpected character")),
+            None => return Err(self.error("unexpected end")),
+        };
+        Ok(value)
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32> {
+        let hex = self
+            .input
+            .get(self.pos..self.pos + 4)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u32::from_str_radix(h, 16).ok())
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        self.pos += 4;
+        Ok(hex)
+    }
+
+    fn parse_string(&mut self) -> Result<String> {
+        self.pos += 1;
+        let mut s = Vec::new();
+        loop {
+            match self.input.get(self.pos) {
+                Some(b'"') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escaped = *self
+                        .input
+                        .get(self.pos)
+                        .ok_or_else(|| self.error("unexpected end"))?;
+                    self.pos += 1;
+                    let c = match escaped {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.parse_hex4()?;
+                            if (0xd800..0xdc00).contains(&code) {
+                                self.expect("\\u")?;
+                                let low = self.parse_hex4()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(self.error("invalid surrogate pair"));
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            char::from_u32(code)
+                                .ok_or_else(|| self.error("invalid unicode escape"))?
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    let mut buf = [0; 4];
+                    s.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+                Some(b) => {
+                    s.push(*b);
+                    self.pos += 1;
+                }
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+        String::from_utf8(s).map_err(|_| self.error("invalid UTF-8"))
+    }
+
+    fn parse_number(&mut self) -> Result<Value> {
+        let start = self.pos;
+        while self
+            .input
+            .get(self.pos)
+            .is_some_and(|b| b.is_ascii_digit() || b"+-.eE".contains(b))
+        {
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(&self.input[start..self.pos])
+            .map_err(|_| self.error("invalid number"))?;
+        if let Ok(n) = text.parse::<u64>() {
+            Ok(Value::UInt(n))
+        } else if let Ok(n) = text.parse::<i64>() {
+            Ok(Value::Int(n))
+        } else {
+            text.parse::<f64>()
+                .map(Value::Float)
+                .map_err(|_| self.error("invalid number"))
+        }
+    }
+}
+
+impl FromStr for Value {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parser = Parser {
+            input: s.as_bytes(),
+            pos: 0,
+        };
+        let value = parser.parse_value()?;
+        parser.skip_whitespace();
+        if parser.pos != parser.input.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Self::Bool(b)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(n: u64) -> Self {
+        Self::UInt(n)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(n: u32) -> Self {
+        Self::UInt(n.into())
+    }
+}
+
+impl From<usize> for Value {
+    fn from(n: usize) -> Self {
+        Self::UInt(n as u64)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Self::Int(n)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Self::Float(n)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Self::String(s.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Self::String(s)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(o: Option<T>) -> Self {
+        o.map_or(Self::Null, Into::into)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(v: Vec<T>) -> Self {
+        Self::Array(v.into_iter().map(Into::into).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize() {
+        let mut value = Value::object([
+            ("name", "a \"quoted\"\nline".into()),
+            ("size", 4096u64.into()),
+            ("path", Value::Null),
+            ("tags", vec!["x", "y"].into()),
+        ]);
+        value.push("ok", true);
+        assert_eq!(
+            value.to_string(),
+            r#"{"name":"a \"quoted\"\nline","size":4096,"path":null,"tags":["x","y"],"ok":true}"#
+        );
+        assert_eq!(
+            Value::object([("a", vec![1u64].into())]).to_pretty_string(),
+            "{\n  \"a\": [\n    1\n  ]\n}"
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        let value: Value =
+            r#" {"a": [1, -2, 3.5, true, null], "b": "x\"\u00e9\ud83d\ude00", "c": {}} "#
+                .parse()
+                .unwrap();
+        assert_eq!(
+            value,
+            Value::object([
+                (
+                    "a",
+                    Value::Array(vec![
+                        Value::UInt(1),
+                        Value::Int(-2),
+                        Value::Float(3.5),
+                        Value::Bool(true),
+                        Value::Null
+                    ])
+                ),
+                ("b", "x\"\u{e9}\u{1f600}".into()),
+                ("c", Value::Object(Vec::new())),
+            ])
+        );
+        assert_eq!(
+            value.get("b").and_then(Value::as_str),
+            Some("x\"\u{e9}\u{1f600}")
+        );
+        assert!("[1,]".parse::<Value>().is_err());
+        assert!("{\"a\" 1}".parse::<Value>().is_err());
+        assert!("[1] x".parse::<Value>().is_err());
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let value = Value::object([
+            ("s", "tab\there\u{1}".into()),
+            ("n", Value::UInt(u64::MAX)),
+            ("l", vec![Value::Int(-1), Value::Null].into()),
+        ]);
+        assert_eq!(value.to_pretty_string().parse::<Value>().unwrap(), value);
+        assert_eq!(value.to_string().parse::<Value>().unwrap(), value);
+    }
+}
@@ -0,0 +1,49 @@
+//! Which mapping an address belongs to, and where it is in the mapped file
+
+use crate::cli::WhereOptions;
+use crate::memory::Memory;
+use crate::symbols::Symbolizer;
+use crate::target::Target;
+use crate::{Error, Result};
+
+pub fn run(options: &WhereOptions) -> Result<()> {
+    let address = options.address;
+    // Regions of a running process are enough, it is not attached
+    let (memory, pid) = match options.target {
+        Target::Process(pid) => (Memory::from_pid_lenient(pid)?, Some(pid)),
+        ref target => {
+            let (reader, memory) = target.open()?;
+            (memory, reader.live_pid())
+        }
+    };
+    let region = memory
+        .region_containing(address)
+        .ok_or(Error::RegionNotFound {
+            start: address,
+            end: address + 1,
+        })?;
+
+    println!("address       0x{:x}", address);
+    println!(
+        "region        0x{:x}..0x{:x} {} ({})",
+        region.start,
+        region.end,
+        region.perms.to_maps_string(),
+        region.kind
+    );
+    println!("region offset 0x{:x}", address - region.start);
+    if let Some((region, offset)) = memory.translate(address) {
+        println!(
+            "file          {}{}",
+            region.path().unwrap_or(""),
+            if region.deleted { " (deleted)" } else { "" }
+        );
+        println!("file offset   0x{:x}", offset);
+        if let Some(shared) = region.shared_memory() {
+            println!("shared memory {}", shared);
+        }
+        let mut symbols = Symbolizer::new(&memory, pid);
+        println!("module        {}", symbols.describe(region, address));
+    }
+    Ok(())
+}
@@ -0,0 +1,283 @@
+//! Sink of the `log` facade, writing to the standard error as text or JSON lines
+//!
+//! Results of the commands go to the standard output. Progress, warnings and errors are logged with
+//! the macros of the `log` crate, and written here, so that `-v`, `-q` and `--log-format` apply to
+//! them. In JSON, each line is an object with the `level`, the `event` name, the `message` and the
+//! key-values of the record.
+//!
+//! Records with an `event` key-value are named events. With `--events`, they are also written as
+//! JSON lines to a separate stream, with their `time`, whatever the level, for tools following a
+//! dump as it goes.
+
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use log::kv::{self, VisitSource, VisitValue};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::json::Value;
+use crate::timestamp;
+use crate::{Error, Result};
+
+/// Default level, moved by `verbosity` steps
+pub fn level_from_verbosity(verbosity: i8) -> LevelFilter {
+    const LEVELS: [LevelFilter; 4] = [
+        LevelFilter::Error,
+        LevelFilter::Warn,
+        LevelFilter::Info,
+        LevelFilter::Debug,
+    ];
+    LEVELS[2i8.saturating_add(verbosity).clamp(0, 3) as usize]
+}
+
+/// Format of the messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// One line per message, prefixed for warnings and errors
+    #[default]
+    Text,
+
+    /// One JSON object per line
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(Error::Usage(format!("Unknown log format {:?}", s))),
+        }
+    }
+}
+
+/// Stream of the named events, with `--events`
+static EVENTS: Mutex<Option<Box<dyn Write + Send>>> = Mutex::new(None);
+
+/// Installs the logger, writing the records up to `level` in `format`
+pub fn init(level: LevelFilter, format: LogFormat) {
+    // Only fails if a logger is installed already
+    let _ = log::set_boxed_logger(Box::new(Logger { level, format }));
+    log::set_max_level(level);
+}
+
+/// Also writes the named events to `stream`, one JSON object per line
+pub fn set_events(stream: Box<dyn Write + Send>) {
+    *EVENTS.lock().unwrap_or_else(|e| e.into_inner()) = Some(stream);
+    // Events of every level must reach the logger
+    log::set_max_level(LevelFilter::Debug);
+}
+
+/// JSON of a key-value, its text if it is not null, a number, a boolean or a string
+fn to_json_value(value: &kv::Value) -> Value {
+    struct Visitor(Value);
+
+    impl VisitValue<'_> for Visitor {
+        fn visit_any(&mut self, value: kv::Value) -> std::result::Result<(), kv::Error> {
+            self.0 = value.to_string().into();
+            Ok(())
+        }
+
+        fn visit_null(&mut self) -> std::result::Result<(), kv::Error> {
+            self.0 = Value::Null;
+            Ok(())
+        }
+
+        fn visit_u64(&mut self, value: u64) -> std::result::Result<(), kv::Error> {
+            self.0 = value.into();
+            Ok(())
+        }
+
+        fn visit_i64(&mut self, value: i64) -> std::result::Result<(), kv::Error> {
+            self.0 = value.into();
+            Ok(())
+        }
+
+        fn visit_f64(&mut self, value: f64) -> std::result::Result<(), kv::Error> {
+            self.0 = value.into();
+            Ok(())
+        }
+
+        fn visit_bool(&mut self, value: bool) -> std::result::Result<(), kv::Error> {
+            self.0 = value.into();
+            Ok(())
+        }
+
+        fn visit_str(&mut self, value: &str) -> std::result::Result<(), kv::Error> {
+            self.0 = value.into();
+            Ok(())
+        }
+    }
+
+    let mut visitor = Visitor(Value::Null);
+    // Visitors above do not fail
+    let _ = value.visit(&mut visitor);
+    visitor.0
+}
+
+/// Key-values of a record, in order
+#[derive(Default)]
+struct Fields(Vec<(String, Value)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(
+        &mut self,
+        key: kv::Key<'kvs>,
+        value: kv::Value<'kvs>,
+    ) -> std::result::Result<(), kv::Error> {
+        self.0.push((key.to_string(), to_json_value(&value)));
+        Ok(())
+    }
+}
+
+fn to_json(level: Level, name: &str, fields: &[(String, Value)], message: &str) -> Value {
+    let mut json = Value::object([
+        ("level", level.as_str().to_ascii_lowercase().into()),
+        ("event", name.into()),
+        ("message", message.into()),
+    ]);
+    for (key, value) in fields {
+        json.push(key, value.clone());
+    }
+    json
+}
+
+/// Writes the event to the `--events` stream, if any
+fn stream_event(level: Level, name: &str, fields: &[(String, Value)], message: &str) {
+    let mut events = EVENTS.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(ref mut stream) = *events else {
+        return;
+    };
+    let mut json = to_json(level, name, fields, message);
+    json.push("time", timestamp::utc(SystemTime::now()));
+    // Followed as it is written
+    if let Err(e) = writeln!(stream, "{}", json).and_then(|()| stream.flush()) {
+        *events = None;
+        drop(events);
+        warn!("Cannot write events anymore: {}", e);
+    }
+}
+
+/// Writes the records to the standard error, and the named events to the `--events` stream
+struct Logger {
+    /// Records above it are not written to the standard error
+    level: LevelFilter,
+    format: LogFormat,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level || EVENTS.lock().is_ok_and(|events| events.is_some())
+    }
+
+    fn log(&self, record: &Record) {
+        let mut fields = Fields::default();
+        // Visiting the key-values given to the macros does not fail
+        let _ = record.key_values().visit(&mut fields);
+        let mut fields = fields.0;
+        let name = fields
+            .iter()
+            .position(|(key, _)| key == "event")
+            .map(|idx| fields.remove(idx).1);
+        let name = name.as_ref().and_then(Value::as_str);
+        let message = record.args().to_string();
+
+        if let Some(name) = name {
+            stream_event(record.level(), name, &fields, &message);
+        }
+        if record.level() > self.level {
+            return;
+        }
+        let line = match self.format {
+            LogFormat::Json => {
+                to_json(record.level(), name.unwrap_or("message"), &fields, &message).to_string()
+            }
+            LogFormat::Text => match record.level() {
+                Level::Error => format!("Error: {}", message),
+                Level::Warn => format!("Warning: {}", message),
+                Level::Info | Level::Debug | Level::Trace => message,
+            },
+        };
+        // Nothing sensible can be done if the standard error is gone
+        let _ = writeln!(std::io::stderr().lock(), "{}", line);
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::kv::ToValue;
+
+    #[test]
+    fn test_level_from_verbosity() {
+        assert_eq!(level_from_verbosity(0), LevelFilter::Info);
+        assert_eq!(level_from_verbosity(-1), LevelFilter::Warn);
+        assert_eq!(level_from_verbosity(-5), LevelFilter::Error);
+        assert_eq!(level_from_verbosity(3), LevelFilter::Debug);
+    }
+
+    /// Events stream kept in memory
+    #[derive(Clone, Default)]
+    struct Shared(std::sync::Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_events() {
+        let events = Shared::default();
+        set_events(Box::new(events.clone()));
+        let logger = Logger {
+            level: LevelFilter::Off,
+            format: LogFormat::Json,
+        };
+        logger.log(
+            &Record::builder()
+                .level(Level::Debug)
+                .args(format_args!("Not an event"))
+                .build(),
+        );
+        let fields = [
+            ("event", "test_event".to_value()),
+            ("pid", 42u32.to_value()),
+            ("path", None::<&str>.to_value()),
+        ];
+        logger.log(
+            &Record::builder()
+                .level(Level::Debug)
+                .args(format_args!("Test {}", 1))
+                .key_values(&fields)
+                .build(),
+        );
+
+        let data = String::from_utf8(events.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<Value> = data.lines().map(|line| line.parse().unwrap()).collect();
+        let name = |line: &Value| line.get("event").and_then(Value::as_str).map(str::to_owned);
+        assert!(lines
+            .iter()
+            .all(|line| name(line).as_deref() != Some("message")));
+        // Other tests may log events meanwhile
+        let line = lines
+            .iter()
+            .find(|line| name(line).as_deref() == Some("test_event"))
+            .unwrap();
+        assert_eq!(line.get("level").and_then(Value::as_str), Some("debug"));
+        assert_eq!(line.get("message").and_then(Value::as_str), Some("Test 1"));
+        assert_eq!(line.get("pid").and_then(Value::as_u64), Some(42));
+        assert_eq!(line.get("path"), Some(&Value::Null));
+        assert!(line.get("time").and_then(Value::as_str).is_some());
+    }
+}
@@ -1,78 +1,128 @@
 use std::env;
 
+// First, for its macros to be visible in the other modules
+#[macro_use]
+extern crate log;
+
+mod backtrace;
+mod carve;
+mod cgroup;
+mod checksum;
+mod cli;
+mod compress;
+mod config;
+mod coredump;
+mod corefile;
+mod dedup;
+mod diff;
+mod dump;
+#[cfg(all(feature = "dwarf", target_arch = "x86_64"))]
+mod dwarf;
+mod elf;
+mod entropy;
 mod error;
+mod fds;
+#[cfg(feature = "fuse")]
+mod fuse;
+mod hexdump;
+mod info;
+mod json;
+mod locate;
+mod logger;
+mod manifest;
+mod maps;
+mod memmem;
 mod memory;
+mod minidump;
+mod modules;
+mod output;
+mod pagemap;
+mod pattern;
+mod permissions;
+mod procfs;
 mod ptrace;
-
+mod read;
+mod regex;
+mod regs;
+mod remote;
+mod repl;
+mod restore;
+mod scan;
+mod search;
 #[cfg(feature = "secrets")]
-mod memmem;
+mod secrets;
+mod selftest;
+mod signal;
+mod snapshot;
+mod summary;
+mod symbols;
+mod tar;
+mod target;
+mod timestamp;
+#[cfg(feature = "uring")]
+mod uring;
+mod verify;
+mod watch;
+mod worker;
+mod write;
+#[cfg(feature = "yara")]
+mod yara;
 
 pub use error::{Error, Result};
 
-use memory::Memory;
-use ptrace::Ptrace;
+use cli::{Command, LogOptions};
 
-fn get_program_name(pid: u32) -> Result<String> {
-    let mut invocation = std::fs::read_to_string(format!("/proc/{}/cmdline", pid))?;
-
-    if let Some(nullbyte) = invocation.find('\0') {
-        invocation.truncate(nullbyte);
-    }
-
-    Ok(invocation)
-}
-
-fn main() -> Result<()> {
-    let mut args = env::args().skip(1);
-    let pid: u32 = args
-        .next()
-        .expect("Usage: dump-memory PID [OUTPUT_DIR]")
-        .parse()?;
+fn main() {
+    let parsed = LogOptions::extract(env::args().skip(1))
+        .and_then(|(log_options, args)| Ok((log_options, Command::parse(args)?)));
+    let (log_options, command) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(1);
+        }
+    };
+    logger::init(
+        logger::level_from_verbosity(log_options.verbosity),
+        log_options.format,
+    );
 
-    let output_dir = std::path::PathBuf::from(if let Some(dir) = args.next() {
-        dir
-    } else {
-        let invocation = get_program_name(pid)?;
-        format!("{}-{}", invocation, pid)
+    let result = signal::install().and_then(|()| match command {
+        Command::Dump(ref options) => dump::run(options),
+        Command::Search(ref options) => search::run(options),
+        Command::Scan(ref options) => scan::run(options),
+        Command::Write(ref options) => write::run(options),
+        Command::Diff(ref options) => diff::run(options),
+        Command::Info(ref options) => info::run(options),
+        Command::Watch(ref options) => watch::run(options),
+        Command::Hexdump(ref options) => hexdump::run(options),
+        Command::Read(ref options) => read::run(options),
+        Command::Restore(ref options) => restore::run(options),
+        Command::Maps(ref options) => maps::run(options),
+        Command::Where(ref options) => locate::run(options),
+        Command::Summary(ref options) => summary::run(options),
+        Command::Modules(ref options) => modules::run(options),
+        Command::Carve(ref options) => carve::run(options),
+        Command::Verify(ref options) => verify::run(options),
+        Command::Serve(ref options) => remote::run(options),
+        Command::Repl(ref options) => repl::run(options),
+        Command::Selftest(ref options) => selftest::run(options),
+        #[cfg(feature = "fuse")]
+        Command::Mount(ref options) => fuse::run(options),
+        #[cfg(feature = "yara")]
+        Command::ScanYara(ref options) => yara::run(options),
+        #[cfg(feature = "secrets")]
+        Command::ScanSecrets(ref options) => secrets::run(options),
     });
-
-    std::fs::create_dir_all(&output_dir)?;
-    let mut process = Ptrace::new(pid)?;
-    let memory = Memory::from_pid(pid)?;
-
-    let mut buffer = Vec::new();
-    for region in memory.iter() {
-        buffer.clear();
-        if let Err(e) = process.dump(region, &mut buffer) {
-            eprintln!(
-                "Could not dump region {:x}-{:x} {} ({}): {}",
-                region.start,
-                region.end,
-                region.perms,
-                region.path().unwrap_or("no file"),
-                e
-            );
-            continue;
+    match result {
+        Ok(()) => {}
+        Err(Error::Interrupted) => {
+            warn!("Interrupted");
+            std::process::exit(signal::EXIT_STATUS);
         }
-        let outfile = format!("{}", region);
-        std::fs::write(output_dir.join(outfile), &buffer[..])?;
-        println!(
-            "Dumped region {:x}-{:x} {} ({})",
-            region.start,
-            region.end,
-            region.perms,
-            region.path().unwrap_or("no file")
-        );
-        #[cfg(feature = "secrets")]
-        {
-            let patterns = ["private", "secret", "password"];
-            for pattern in &patterns {
-                if let Some(idx) = memmem::search_no_case(&buffer[..], pattern.as_bytes()) {
-                    println!("Found pattern {} in {} at offset {}", pattern, &region, idx);
-                }
-            }
+        Err(e) => {
+            error!("{}", error::Chain(&e));
+            std::process::exit(1);
         }
     }
-
-    Ok(())
 }
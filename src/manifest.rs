@@ -0,0 +1,611 @@
+//! Description of a dump, written as `manifest.json`
+
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::checksum;
+use crate::compress::Compression;
+use crate::entropy::Entropy;
+use crate::json::Value;
+use crate::memory::{Region, RegionStats, SharedMemory};
+use crate::output::Format;
+use crate::{Error, Result};
+
+/// Version of the dump layout, increased when older versions cannot read it anymore
+pub const MANIFEST_VERSION: u64 = 1;
+
+/// Outcome of dumping a region
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    Dumped,
+    Failed(String),
+
+    /// Not read on purpose, for the given reason
+    Skipped(String),
+}
+
+/// Manifest entry for one region
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub region: Region,
+    pub status: Status,
+
+    /// Thread whose stack is held by the region, if selected with `--stacks`
+    pub thread: Option<u32>,
+
+    /// Name of the file holding the region content, if any
+    pub file: Option<String>,
+
+    /// Number of bytes dumped
+    pub bytes: u64,
+
+    /// Number of bytes stored on disk, after compression
+    pub stored_bytes: u64,
+
+    /// CRC-32 of the dumped bytes
+    pub crc32: Option<u32>,
+
+    /// SHA-256 of the dumped bytes, if requested with `--sha256`
+    pub sha256: Option<[u8; 32]>,
+
+    /// Address ranges which could not be read, and were dumped as zeros
+    pub unreadable: Vec<Range<usize>>,
+
+    /// Entropy of the dumped bytes, if requested with `--entropy`
+    pub entropy: Option<Entropy>,
+
+    /// Whether the region is writable and was read while the process ran, or after it was
+    /// resumed: it may be inconsistent with the registers and the other regions
+    pub may_be_inconsistent: bool,
+
+    /// Whether the region was not mapped the same way anymore after the dump
+    pub changed: bool,
+
+    /// Time taken to read and write the region, if it was read
+    pub read_time: Option<Duration>,
+}
+
+fn stats_to_json(stats: &RegionStats) -> Value {
+    Value::object([
+        ("rss", stats.rss.into()),
+        ("pss", stats.pss.into()),
+        ("swap", stats.swap.into()),
+        ("shared_dirty", stats.shared_dirty.into()),
+        ("private_dirty", stats.private_dirty.into()),
+        (
+            "vm_flags",
+            Value::Array(stats.vm_flags.iter().map(|f| f.as_str().into()).collect()),
+        ),
+    ])
+}
+
+/// Parses a duration in seconds, `null` as `None`
+fn duration_field(json: &Value, field: &'static str) -> Result<Option<Duration>> {
+    match json.get(field) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => value
+            .as_f64()
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            .map(Some)
+            .ok_or_else(|| malformed_manifest(field, json)),
+    }
+}
+
+fn malformed_manifest(field: &'static str, value: &Value) -> Error {
+    Error::MalformedState {
+        field,
+        value: value.to_string(),
+    }
+}
+
+/// Parses a `0x`-prefixed hexadecimal string
+fn hex_field(json: &Value, field: &'static str) -> Result<u64> {
+    json.get(field)
+        .and_then(Value::as_str)
+        .and_then(|v| v.strip_prefix("0x"))
+        .and_then(|v| u64::from_str_radix(v, 16).ok())
+        .ok_or_else(|| malformed_manifest(field, json))
+}
+
+fn parse_sha256(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 {
+        return None;
+    }
+    let mut hash = [0u8; 32];
+    for (byte, digits) in hash.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+    Some(hash)
+}
+
+/// Describes a region as in `/proc/PID/maps`, with its smaps statistics
+fn shared_memory_to_json(shared: &SharedMemory) -> Value {
+    match shared {
+        SharedMemory::SysV { key, id } => Value::object([
+            ("type", "sysv".into()),
+            ("key", format!("0x{:08x}", key).into()),
+            ("id", (*id).into()),
+        ]),
+        SharedMemory::Posix(name) => {
+            Value::object([("type", "posix".into()), ("name", name.as_str().into())])
+        }
+        SharedMemory::Memfd(name) => {
+            Value::object([("type", "memfd".into()), ("name", name.as_str().into())])
+        }
+    }
+}
+
+pub fn region_to_json(region: &Region) -> Value {
+    Value::object([
+        ("start", format!("0x{:x}", region.start).into()),
+        ("end", format!("0x{:x}", region.end).into()),
+        ("size", region.size().into()),
+        ("perms", region.perms.to_maps_string().into()),
+        ("offset", format!("0x{:x}", region.offset).into()),
+        (
+            "device",
+            format!("{:02x}:{:02x}", region.dev.major, region.dev.minor).into(),
+        ),
+        ("inode", region.inode.into()),
+        ("path", region.path().into()),
+        ("deleted", region.deleted.into()),
+        ("kind", region.kind.to_string().into()),
+        (
+            "shared_memory",
+            region
+                .shared_memory()
+                .as_ref()
+                .map_or(Value::Null, shared_memory_to_json),
+        ),
+        (
+            "stats",
+            region.stats.as_ref().map_or(Value::Null, stats_to_json),
+        ),
+    ])
+}
+
+impl Entry {
+    /// Entry of a region which could not be dumped
+    pub fn failed(region: &Region, thread: Option<u32>, reason: String) -> Self {
+        Self {
+            region: region.clone(),
+            status: Status::Failed(reason),
+            thread,
+            file: None,
+            bytes: 0,
+            stored_bytes: 0,
+            crc32: None,
+            sha256: None,
+            unreadable: Vec::new(),
+            entropy: None,
+            may_be_inconsistent: false,
+            changed: false,
+            read_time: None,
+        }
+    }
+
+    /// Entry of a region which was excluded from the dump
+    pub fn skipped(region: &Region, thread: Option<u32>, reason: String) -> Self {
+        Self {
+            status: Status::Skipped(reason),
+            ..Self::failed(region, thread, String::new())
+        }
+    }
+
+    /// Why the region was not entirely dumped, if so, skipped regions were not meant to be
+    pub fn error(&self) -> Option<String> {
+        match self.status {
+            Status::Failed(ref reason) => Some(reason.clone()),
+            Status::Dumped if !self.unreadable.is_empty() => {
+                let bytes: usize = self.unreadable.iter().map(|r| r.len()).sum();
+                Some(format!("0x{:x} bytes could not be read", bytes))
+            }
+            Status::Dumped | Status::Skipped(_) => None,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut value = region_to_json(&self.region);
+        value.push("thread", self.thread);
+        match self.status {
+            Status::Dumped => value.push("status", "dumped"),
+            Status::Failed(ref e) => {
+                value.push("status", "failed");
+                value.push("error", e.as_str());
+            }
+            Status::Skipped(ref reason) => {
+                value.push("status", "skipped");
+                value.push("reason", reason.as_str());
+            }
+        }
+        value.push("file", self.file.clone());
+        value.push("bytes", self.bytes);
+        value.push("stored_bytes", self.stored_bytes);
+        value.push("checksum", self.crc32.map(|c| format!("crc32:{:08x}", c)));
+        value.push("sha256", self.sha256.as_ref().map(|h| checksum::to_hex(h)));
+        value.push(
+            "unreadable",
+            Value::Array(
+                self.unreadable
+                    .iter()
+                    .map(|r| {
+                        Value::object([
+                            ("start", format!("0x{:x}", r.start).into()),
+                            ("end", format!("0x{:x}", r.end).into()),
+                        ])
+                    })
+                    .collect(),
+            ),
+        );
+        value.push("may_be_inconsistent", self.may_be_inconsistent);
+        value.push("changed", self.changed);
+        value.push("read_time", self.read_time.map(|t| t.as_secs_f64()));
+        value.push(
+            "entropy",
+            self.entropy.as_ref().map_or(Value::Null, |entropy| {
+                Value::object([
+                    ("region", entropy.region.into()),
+                    ("max_window", entropy.max_window.into()),
+                    ("suspicious", entropy.is_suspicious(&self.region).into()),
+                ])
+            }),
+        );
+        value
+    }
+
+    pub fn from_json(json: &Value) -> Result<Self> {
+        let str_field = |field: &'static str| {
+            json.get(field)
+                .and_then(Value::as_str)
+                .ok_or_else(|| malformed_manifest(field, json))
+        };
+        let u64_field = |field: &'static str| {
+            json.get(field)
+                .and_then(Value::as_u64)
+                .ok_or_else(|| malformed_manifest(field, json))
+        };
+
+        // Going through the maps syntax keeps a single region parser
+        let maps_line = format!(
+            "{:x}-{:x} {} {:x} {} {} {}{}",
+            hex_field(json, "start")?,
+            hex_field(json, "end")?,
+            str_field("perms")?,
+            hex_field(json, "offset")?,
+            str_field("device")?,
+            u64_field("inode")?,
+            json.get("path").and_then(Value::as_str).unwrap_or(""),
+            if json.get("deleted") == Some(&Value::Bool(true)) {
+                " (deleted)"
+            } else {
+                ""
+            }
+        );
+        let region = maps_line
+            .parse()
+            .map_err(|_| malformed_manifest("region", json))?;
+        let status = match str_field("status")? {
+            "dumped" => Status::Dumped,
+            "failed" => Status::Failed(
+                json.get("error")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .into(),
+            ),
+            "skipped" => Status::Skipped(
+                json.get("reason")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .into(),
+            ),
+            _ => return Err(malformed_manifest("status", json)),
+        };
+        let crc32 = match json.get("checksum").and_then(Value::as_str) {
+            Some(checksum) => Some(
+                checksum
+                    .strip_prefix("crc32:")
+                    .and_then(|c| u32::from_str_radix(c, 16).ok())
+                    .ok_or_else(|| malformed_manifest("checksum", json))?,
+            ),
+            None => None,
+        };
+        let sha256 = match json.get("sha256").and_then(Value::as_str) {
+            Some(hex) => Some(parse_sha256(hex).ok_or_else(|| malformed_manifest("sha256", json))?),
+            None => None,
+        };
+        // Missing from manifests written before partial reads were recovered
+        let unreadable = match json.get("unreadable") {
+            Some(ranges) => ranges
+                .as_array()
+                .ok_or_else(|| malformed_manifest("unreadable", ranges))?
+                .iter()
+                .map(|r| Ok(hex_field(r, "start")? as usize..hex_field(r, "end")? as usize))
+                .collect::<Result<_>>()?,
+            None => Vec::new(),
+        };
+        let entropy = match json.get("entropy") {
+            Some(entropy) if *entropy != Value::Null => {
+                let field = |field: &'static str| {
+                    entropy
+                        .get(field)
+                        .and_then(Value::as_f64)
+                        .ok_or_else(|| malformed_manifest("entropy", entropy))
+                };
+                Some(Entropy {
+                    region: field("region")?,
+                    max_window: field("max_window")?,
+                })
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            region,
+            status,
+            thread: json
+                .get("thread")
+                .and_then(Value::as_u64)
+                .and_then(|t| t.try_into().ok()),
+            file: json.get("file").and_then(Value::as_str).map(String::from),
+            bytes: u64_field("bytes")?,
+            stored_bytes: u64_field("stored_bytes")?,
+            crc32,
+            sha256,
+            unreadable,
+            entropy,
+            may_be_inconsistent: json.get("may_be_inconsistent") == Some(&Value::Bool(true)),
+            changed: json.get("changed") == Some(&Value::Bool(true)),
+            // Missing from manifests written before read times were recorded
+            read_time: duration_field(json, "read_time")?,
+        })
+    }
+}
+
+/// Page store of a dump in the `dedup` format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageStore {
+    /// Path of the store, relative to the dump directory
+    pub path: PathBuf,
+    pub page_size: usize,
+}
+
+/// Description of every region of a dump
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    pub pid: u32,
+
+    /// Namespaces of the process, by type, as their inode number: the paths of its regions are
+    /// those of its mount namespace
+    pub namespaces: Vec<(String, u64)>,
+
+    pub format: Format,
+    pub compression: Compression,
+    pub store: Option<PageStore>,
+    pub entries: Vec<Entry>,
+
+    /// Whether the dump was interrupted, and misses regions
+    pub interrupted: bool,
+
+    /// Time the process was stopped for, over all the attachments of the dump, `None` if it was
+    /// not stopped
+    pub pause_time: Option<Duration>,
+}
+
+impl Manifest {
+    pub fn new(pid: u32, format: Format, compression: Compression) -> Self {
+        Self {
+            pid,
+            namespaces: Vec::new(),
+            format,
+            compression,
+            store: None,
+            entries: Vec::new(),
+            interrupted: false,
+            pause_time: None,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        Value::object([
+            ("version", MANIFEST_VERSION.into()),
+            ("pid", self.pid.into()),
+            (
+                "namespaces",
+                Value::Object(
+                    self.namespaces
+                        .iter()
+                        .map(|(ns, id)| (ns.clone(), (*id).into()))
+                        .collect(),
+                ),
+            ),
+            ("format", self.format.to_string().into()),
+            ("compression", self.compression.to_string().into()),
+            (
+                "store",
+                self.store.as_ref().map_or(Value::Null, |store| {
+                    Value::object([
+                        ("path", store.path.to_string_lossy().as_ref().into()),
+                        ("page_size", store.page_size.into()),
+                    ])
+                }),
+            ),
+            ("interrupted", self.interrupted.into()),
+            (
+                "pause_time",
+                self.pause_time.map(|t| t.as_secs_f64()).into(),
+            ),
+            (
+                "regions",
+                Value::Array(self.entries.iter().map(Entry::to_json).collect()),
+            ),
+        ])
+    }
+
+    pub fn from_json(json: &Value) -> Result<Self> {
+        let field =
+            |name: &'static str| json.get(name).ok_or_else(|| malformed_manifest(name, json));
+        let version = field("version")?;
+        match version.as_u64() {
+            Some(MANIFEST_VERSION) => {}
+            Some(version) => return Err(Error::UnsupportedManifest { version }),
+            None => return Err(malformed_manifest("version", version)),
+        }
+        let pid = field("pid")?;
+        let pid = pid
+            .as_u64()
+            .and_then(|p| p.try_into().ok())
+            .ok_or_else(|| malformed_manifest("pid", pid))?;
+        // Missing from manifests written before namespaces were recorded
+        let namespaces = match json.get("namespaces") {
+            None => Vec::new(),
+            Some(Value::Object(fields)) => fields
+                .iter()
+                .map(|(ns, id)| {
+                    let id = id
+                        .as_u64()
+                        .ok_or_else(|| malformed_manifest("namespaces", id))?;
+                    Ok((ns.clone(), id))
+                })
+                .collect::<Result<_>>()?,
+            Some(namespaces) => return Err(malformed_manifest("namespaces", namespaces)),
+        };
+        let format = field("format")?;
+        let format = format
+            .as_str()
+            .and_then(|f| f.parse().ok())
+            .ok_or_else(|| malformed_manifest("format", format))?;
+        let compression = field("compression")?;
+        let compression = compression
+            .as_str()
+            .and_then(|c| c.parse().ok())
+            .ok_or_else(|| malformed_manifest("compression", compression))?;
+        let store = match json.get("store") {
+            None | Some(Value::Null) => None,
+            Some(store) => Some(PageStore {
+                path: store
+                    .get("path")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| malformed_manifest("store", store))?
+                    .into(),
+                page_size: store
+                    .get("page_size")
+                    .and_then(Value::as_u64)
+                    .ok_or_else(|| malformed_manifest("store", store))?
+                    as usize,
+            }),
+        };
+        let regions = field("regions")?;
+        let entries = regions
+            .as_array()
+            .ok_or_else(|| malformed_manifest("regions", regions))?
+            .iter()
+            .map(Entry::from_json)
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            pid,
+            namespaces,
+            format,
+            compression,
+            store,
+            entries,
+            // Missing from manifests written before interruptions were handled
+            interrupted: json.get("interrupted") == Some(&Value::Bool(true)),
+            pause_time: duration_field(json, "pause_time")?,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::from_json(&std::fs::read_to_string(path)?.parse()?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut json = self.to_json().to_pretty_string();
+        json.push('\n');
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let mut manifest = Manifest::new(42, Format::Directory, Compression::Gzip);
+        manifest.pause_time = Some(Duration::from_millis(2500));
+        manifest.namespaces = vec![("mnt".into(), 4026532201), ("pid".into(), 4026532204)];
+        manifest.store = Some(PageStore {
+            path: "../pages".into(),
+            page_size: 4096,
+        });
+        manifest.entries.push(Entry {
+            region: "7f0000000000-7f0000001000 r-xp 00001000 fe:01 42 /tmp/lib ssl.so (deleted)"
+                .parse()
+                .unwrap(),
+            status: Status::Dumped,
+            thread: None,
+            file: Some("region.gz".into()),
+            bytes: 4096,
+            stored_bytes: 100,
+            crc32: Some(0xcbf43926),
+            sha256: Some([0xab; 32]),
+            unreadable: vec![
+                0x7f0000000000..0x7f0000000100,
+                0x7f0000000800..0x7f0000001000,
+            ],
+            entropy: Some(Entropy {
+                region: 5.25,
+                max_window: 7.5,
+            }),
+            may_be_inconsistent: true,
+            changed: true,
+            read_time: Some(Duration::from_millis(250)),
+        });
+        manifest.entries.push(Entry {
+            region: "7ffc00000000-7ffc00021000 rw-p 00000000 00:00 0 [stack]"
+                .parse()
+                .unwrap(),
+            status: Status::Failed("Input/output error".into()),
+            thread: Some(43),
+            file: None,
+            bytes: 0,
+            stored_bytes: 0,
+            crc32: None,
+            sha256: None,
+            unreadable: Vec::new(),
+            entropy: None,
+            may_be_inconsistent: false,
+            changed: false,
+            read_time: Some(Duration::from_secs(2)),
+        });
+        let device = "7f1000000000-7f1000100000 rw-s 00000000 00:05 812 /dev/nvidia0"
+            .parse()
+            .unwrap();
+        manifest.entries.push(Entry::skipped(
+            &device,
+            None,
+            "device mapping matching /dev/nvidia*".into(),
+        ));
+
+        let json = manifest.to_json().to_pretty_string();
+        let loaded = Manifest::from_json(&json.parse().unwrap()).unwrap();
+        assert_eq!(loaded.pid, 42);
+        assert_eq!(loaded.compression, Compression::Gzip);
+        assert_eq!(loaded.to_json(), manifest.to_json());
+        assert_eq!(loaded.entries[2].status, manifest.entries[2].status);
+        assert_eq!(
+            loaded.entries[0].read_time,
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(loaded.pause_time, Some(Duration::from_millis(2500)));
+
+        let newer = json.replacen("\"version\": 1", "\"version\": 2", 1);
+        assert!(matches!(
+            Manifest::from_json(&newer.parse().unwrap()),
+            Err(Error::UnsupportedManifest { version: 2 })
+        ));
+    }
+}
@@ -0,0 +1,152 @@
+//! Listing of the memory regions of a process
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::cli::MapsOptions;
+use crate::json::Value;
+use crate::manifest;
+use crate::memory::{Memory, Region};
+use crate::target::Target;
+use crate::{Error, Result};
+
+/// Output format of the listing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ListFormat {
+    /// Aligned columns, for humans
+    #[default]
+    Table,
+
+    /// An array of regions, described as in the manifest
+    Json,
+
+    /// One line per region, after a header line
+    Csv,
+}
+
+impl FromStr for ListFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "table" => Ok(Self::Table),
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            _ => Err(Error::Usage(format!("Unknown format {:?}", s))),
+        }
+    }
+}
+
+impl fmt::Display for ListFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Table => f.write_str("table"),
+            Self::Json => f.write_str("json"),
+            Self::Csv => f.write_str("csv"),
+        }
+    }
+}
+
+const CSV_HEADER: &str = "start,end,size,perms,offset,device,inode,rss,pss,swap,shared_dirty,\
+                          private_dirty,vm_flags,path";
+
+/// Quotes a CSV field if needed
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.into()
+    }
+}
+
+fn csv_line(region: &Region) -> String {
+    let stats = region.stats.clone().unwrap_or_default();
+    format!(
+        "0x{:x},0x{:x},{},{},0x{:x},{:02x}:{:02x},{},{},{},{},{},{},{},{}",
+        region.start,
+        region.end,
+        region.size(),
+        region.perms.to_maps_string(),
+        region.offset,
+        region.dev.major,
+        region.dev.minor,
+        region.inode,
+        stats.rss,
+        stats.pss,
+        stats.swap,
+        stats.shared_dirty,
+        stats.private_dirty,
+        stats.vm_flags.join(" "),
+        csv_field(region.path().unwrap_or(""))
+    )
+}
+
+/// Formats a size in bytes as KiB, like `/proc/PID/smaps`
+pub fn kib(bytes: u64) -> String {
+    format!("{}K", bytes / 1024)
+}
+
+pub fn print_table<'a>(regions: impl Iterator<Item = &'a Region>) {
+    println!(
+        "{:>16} {:>16} {:5} {:>8} {:5} {:>9} {:>9} {:>9} {:>9} PATH",
+        "START", "END", "PERMS", "OFFSET", "DEV", "SIZE", "RSS", "PSS", "SWAP"
+    );
+    for region in regions {
+        let stats = region.stats.clone().unwrap_or_default();
+        println!(
+            "{:>16x} {:>16x} {:5} {:>8x} {:02x}:{:02x} {:>9} {:>9} {:>9} {:>9} {}{}",
+            region.start,
+            region.end,
+            region.perms.to_maps_string(),
+            region.offset,
+            region.dev.major,
+            region.dev.minor,
+            kib(region.size() as u64),
+            kib(stats.rss),
+            kib(stats.pss),
+            kib(stats.swap),
+            region.path().unwrap_or(""),
+            if region.deleted { " (deleted)" } else { "" }
+        );
+    }
+}
+
+pub fn run(options: &MapsOptions) -> Result<()> {
+    // Core files have no statistics, which are printed as zeros
+    let memory = match options.target {
+        Target::Process(pid) => Memory::from_pid_smaps(pid)?,
+        ref target => target.open()?.1,
+    };
+    let regions = memory.filter(&options.filter);
+    match options.format {
+        ListFormat::Table => print_table(regions),
+        ListFormat::Json => {
+            let regions = Value::Array(regions.map(manifest::region_to_json).collect());
+            println!("{}", regions.to_pretty_string());
+        }
+        ListFormat::Csv => {
+            println!("{}", CSV_HEADER);
+            for region in regions {
+                println!("{}", csv_line(region));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_line() {
+        let region: Region = "7f0000000000-7f0000002000 r-xp 00001000 fe:01 42 /tmp/a,\"b\""
+            .parse()
+            .unwrap();
+        assert_eq!(
+            csv_line(&region),
+            "0x7f0000000000,0x7f0000002000,8192,r-xp,0x1000,fe:01,42,0,0,0,0,0,,\"/tmp/a,\"\"b\"\"\""
+        );
+        assert_eq!(CSV_HEADER.split(',').count(), 14);
+    }
+}
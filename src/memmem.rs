@@ -1,23 +1,155 @@
-use std::os::raw::c_char;
+//! Binary-safe substring search (Boyer-Moore-Horspool)
 
-extern "C" {
-    fn strcasestr(haystack: *const c_char, needle: *const c_char) -> *const c_char;
+/// Precomputed searcher for a needle
+#[derive(Debug, Clone)]
+pub struct Finder {
+    needle: Vec<u8>,
+    shift: [usize; 256],
+    ignore_case: bool,
 }
 
-/// Returns the optional index of where needle is found ignore case
-pub fn search_no_case(slice: &[u8], needle: &[u8]) -> Option<usize> {
-    let ptr: *const u8 =
-        unsafe { strcasestr(slice.as_ptr().cast(), needle.as_ptr().cast()) }.cast();
+impl Finder {
+    pub fn new(needle: &[u8]) -> Self {
+        Self::with_case(needle, false)
+    }
+
+    /// Searcher ignoring ASCII case
+    pub fn new_no_case(needle: &[u8]) -> Self {
+        Self::with_case(needle, true)
+    }
+
+    fn with_case(needle: &[u8], ignore_case: bool) -> Self {
+        let needle: Vec<u8> = if ignore_case {
+            needle.to_ascii_lowercase()
+        } else {
+            needle.to_vec()
+        };
+        let mut shift = [needle.len().max(1); 256];
+        if let Some((_, init)) = needle.split_last() {
+            for (i, b) in init.iter().enumerate() {
+                let distance = needle.len() - 1 - i;
+                shift[*b as usize] = distance;
+                if ignore_case {
+                    shift[b.to_ascii_uppercase() as usize] = distance;
+                }
+            }
+        }
+        Self {
+            needle,
+            shift,
+            ignore_case,
+        }
+    }
+
+    fn fold(&self, b: u8) -> u8 {
+        if self.ignore_case {
+            b.to_ascii_lowercase()
+        } else {
+            b
+        }
+    }
 
-    if ptr.is_null() {
+    /// Returns the index of the first occurrence of the needle in `haystack`
+    pub fn find(&self, haystack: &[u8]) -> Option<usize> {
+        let n = self.needle.len();
+        if n == 0 {
+            return Some(0);
+        }
+        let last = self.needle[n - 1];
+        let mut pos = 0;
+        while pos + n <= haystack.len() {
+            let b = haystack[pos + n - 1];
+            if self.fold(b) == last
+                && haystack[pos..pos + n - 1]
+                    .iter()
+                    .zip(&self.needle[..n - 1])
+                    .all(|(h, n)| self.fold(*h) == *n)
+            {
+                return Some(pos);
+            }
+            pos += self.shift[b as usize];
+        }
         None
-    } else {
-        // SAFETY:
-        // * `ptr` is issued from `slice`
-        // * `ptr` and `slice` are pointers to u8 so offset is a multiple of 1
-        let offset: usize = unsafe { ptr.offset_from(slice.as_ptr()) }
-            .try_into()
-            .expect("Offset should be positive");
-        Some(offset)
+    }
+
+    /// Iterates over occurrences of the needle in `haystack`
+    pub fn find_iter<'h>(&self, haystack: &'h [u8]) -> FindIter<'h> {
+        FindIter {
+            finder: self.clone(),
+            haystack,
+            pos: 0,
+        }
+    }
+}
+
+/// Iterator over occurrences of a needle, see [`find_iter`]
+#[derive(Debug, Clone)]
+pub struct FindIter<'h> {
+    finder: Finder,
+    haystack: &'h [u8],
+    pos: usize,
+}
+
+impl Iterator for FindIter<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        let idx = self.pos + self.finder.find(self.haystack.get(self.pos..)?)?;
+        // Occurrences may overlap, as `aa` twice in `aaa`
+        self.pos = idx + 1;
+        Some(idx)
+    }
+}
+
+/// Returns the index of the first occurrence of `needle`, ignoring ASCII case
+#[cfg(feature = "secrets")]
+pub fn find_no_case(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    Finder::new_no_case(needle).find(haystack)
+}
+
+/// Iterates over occurrences of `needle`
+pub fn find_iter<'h>(haystack: &'h [u8], needle: &[u8]) -> FindIter<'h> {
+    Finder::new(needle).find_iter(haystack)
+}
+
+/// Iterates over occurrences of `needle`, ignoring ASCII case
+pub fn find_iter_no_case<'h>(haystack: &'h [u8], needle: &[u8]) -> FindIter<'h> {
+    Finder::new_no_case(needle).find_iter(haystack)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+        Finder::new(needle).find(haystack)
+    }
+
+    #[test]
+    fn test_find() {
+        assert_eq!(find(b"hello world", b"world"), Some(6));
+        assert_eq!(find(b"hello world", b"World"), None);
+        assert_eq!(find(b"abc", b"abcd"), None);
+        assert_eq!(find(b"abc", b""), Some(0));
+        assert_eq!(find(b"\0\0secret\0", b"secret"), Some(2));
+        assert_eq!(find(b"\xde\xad\x00\xbe\xef", b"\x00\xbe"), Some(2));
+    }
+
+    #[test]
+    #[cfg(feature = "secrets")]
+    fn test_find_no_case() {
+        assert_eq!(find_no_case(b"\0My PassWord\0", b"password"), Some(4));
+        assert_eq!(find_no_case(b"\0My PassWord\0", b"PASSWORD"), Some(4));
+        assert_eq!(find_no_case(b"passwor", b"password"), None);
+    }
+
+    #[test]
+    fn test_find_iter() {
+        let hits: Vec<_> = find_iter(b"abcabcab\0abc", b"abc").collect();
+        assert_eq!(hits, vec![0, 3, 9]);
+        let hits: Vec<_> = find_iter(b"aaaa", b"aa").collect();
+        assert_eq!(hits, vec![0, 1, 2]);
+        let hits: Vec<_> = find_iter_no_case(b"Key KEY kEy", b"key").collect();
+        assert_eq!(hits, vec![0, 4, 8]);
     }
 }
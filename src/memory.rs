@@ -1,7 +1,12 @@
 use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines};
+use std::ops::Range;
 use std::path::Path;
 use std::str::FromStr;
 
+use crate::error::Chain;
+use crate::pattern::Pattern;
 use crate::{Error, Result};
 
 /// Standard permissions for a region
@@ -43,6 +48,23 @@ impl Permissions {
         self.0 |= pbit as u32;
         self
     }
+
+    /// Formats permissions as in `/proc/PID/maps` (`r-xp`)
+    pub fn to_maps_string(self) -> String {
+        let flag = |pbit, c| if self.has_perm(pbit) { c } else { '-' };
+        [
+            flag(PermissionBits::Read, 'r'),
+            flag(PermissionBits::Write, 'w'),
+            flag(PermissionBits::Exec, 'x'),
+            if self.has_perm(PermissionBits::Shared) {
+                's'
+            } else {
+                'p'
+            },
+        ]
+        .iter()
+        .collect()
+    }
 }
 
 impl fmt::Display for Permissions {
@@ -109,8 +131,122 @@ pub struct Device {
     pub minor: u8,
 }
 
+/// What a region maps, told by its path in `/proc/PID/maps`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RegionKind {
+    /// A file, possibly deleted
+    File,
+
+    /// The `[heap]` grown by `brk`
+    Heap,
+
+    /// The main thread stack, or a thread stack (`[stack:TID]`) on old kernels
+    Stack,
+
+    /// Kernel provided code, `[vdso]`
+    Vdso,
+
+    /// Kernel provided data for the vDSO, `[vvar]` and `[vvar_vclock]`
+    Vvar,
+
+    /// Legacy `[vsyscall]` page
+    Vsyscall,
+
+    /// Anonymous mapping named with `PR_SET_VMA_ANON_NAME` (`[anon:NAME]` or `[anon_shmem:NAME]`)
+    AnonNamed(String),
+
+    /// Anonymous mapping, or another pseudo-path
+    Anonymous,
+}
+
+impl RegionKind {
+    fn from_path(path: Option<&str>) -> Self {
+        let Some(path) = path else {
+            return Self::Anonymous;
+        };
+        let Some(pseudo) = path.strip_prefix('[').and_then(|p| p.strip_suffix(']')) else {
+            return Self::File;
+        };
+        match pseudo {
+            "heap" => Self::Heap,
+            "vdso" => Self::Vdso,
+            "vvar" | "vvar_vclock" => Self::Vvar,
+            "vsyscall" => Self::Vsyscall,
+            _ if pseudo == "stack" || pseudo.starts_with("stack:") => Self::Stack,
+            _ => match pseudo
+                .strip_prefix("anon:")
+                .or_else(|| pseudo.strip_prefix("anon_shmem:"))
+            {
+                Some(name) => Self::AnonNamed(name.into()),
+                None => Self::Anonymous,
+            },
+        }
+    }
+}
+
+impl FromStr for RegionKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "file" => Ok(Self::File),
+            "heap" => Ok(Self::Heap),
+            "stack" => Ok(Self::Stack),
+            "vdso" => Ok(Self::Vdso),
+            "vvar" => Ok(Self::Vvar),
+            "vsyscall" => Ok(Self::Vsyscall),
+            "anonymous" => Ok(Self::Anonymous),
+            _ => match s.strip_prefix("anon:") {
+                Some(name) if !name.is_empty() => Ok(Self::AnonNamed(name.into())),
+                _ => Err(Error::InvalidValue {
+                    ty: "region kind",
+                    value: s.into(),
+                }),
+            },
+        }
+    }
+}
+
+impl fmt::Display for RegionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File => f.write_str("file"),
+            Self::Heap => f.write_str("heap"),
+            Self::Stack => f.write_str("stack"),
+            Self::Vdso => f.write_str("vdso"),
+            Self::Vvar => f.write_str("vvar"),
+            Self::Vsyscall => f.write_str("vsyscall"),
+            Self::AnonNamed(ref name) => write!(f, "anon:{}", name),
+            Self::Anonymous => f.write_str("anonymous"),
+        }
+    }
+}
+
+/// Shared memory object mapped by a region, which `/proc/PID/maps` shows as a file
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SharedMemory {
+    /// System V segment (`shmget`), with its key and identifier
+    SysV { key: u32, id: u64 },
+
+    /// POSIX object (`shm_open`), named after its file in `/dev/shm`
+    Posix(String),
+
+    /// File created by `memfd_create`, with its name
+    Memfd(String),
+}
+
+impl fmt::Display for SharedMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SysV { key, id } => write!(f, "sysv:0x{:08x} (id {})", key, id),
+            Self::Posix(ref name) => write!(f, "posix:{}", name),
+            Self::Memfd(ref name) => write!(f, "memfd:{}", name),
+        }
+    }
+}
+
 /// A memory region
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Region {
     /// Start address
     pub start: usize,
@@ -130,24 +266,97 @@ pub struct Region {
     /// Inode on the device
     pub inode: u64,
 
-    /// Backing file if any
+    /// Backing file if any, without the ` (deleted)` suffix
     path: Option<String>,
+
+    /// Whether the backing file was deleted
+    pub deleted: bool,
+
+    /// What the region maps, from `path`
+    pub kind: RegionKind,
+
+    /// Usage statistics, when parsed from `/proc/PID/smaps`
+    pub stats: Option<RegionStats>,
+}
+
+/// Usage of a region, from `/proc/PID/smaps`. Sizes are in bytes
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct RegionStats {
+    /// Resident memory
+    pub rss: u64,
+
+    /// Proportional share of resident memory
+    pub pss: u64,
+
+    /// Swapped out memory
+    pub swap: u64,
+
+    /// Modified pages shared with other processes
+    pub shared_dirty: u64,
+
+    /// Modified pages private to this process
+    pub private_dirty: u64,
+
+    /// Kernel flags, like `rd`, `wr` or `dd` (excluded from core dumps)
+    pub vm_flags: Vec<String>,
+}
+
+impl RegionStats {
+    /// Parses a `Key: value` line following a region in smaps, ignoring unknown keys
+    fn parse_line(&mut self, line: &str) -> Result<()> {
+        let (key, value) = line
+            .split_once(':')
+            .ok_or_else(|| Error::MalformedProcField {
+                field: "smaps",
+                value: line.into(),
+            })?;
+        let field = match key {
+            "Rss" => &mut self.rss,
+            "Pss" => &mut self.pss,
+            "Swap" => &mut self.swap,
+            "Shared_Dirty" => &mut self.shared_dirty,
+            "Private_Dirty" => &mut self.private_dirty,
+            "VmFlags" => {
+                self.vm_flags = value.split_ascii_whitespace().map(String::from).collect();
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+        let kb = value
+            .trim()
+            .strip_suffix(" kB")
+            .ok_or_else(|| Error::MalformedProcField {
+                field: "smaps",
+                value: line.into(),
+            })?;
+        *field = kb.trim().parse::<u64>()? * 1024;
+        Ok(())
+    }
+}
+
+/// Splits the first whitespace-separated field off `s`, returning it with the rest of `s`
+fn next_field(s: &str) -> Option<(&str, &str)> {
+    let s = s.trim_start_matches(|c: char| c.is_ascii_whitespace());
+    if s.is_empty() {
+        return None;
+    }
+    let end = s.find(|c: char| c.is_ascii_whitespace()).unwrap_or(s.len());
+    Some(s.split_at(end))
 }
 
 impl FromStr for Region {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self> {
-        let mut parts = s.split_ascii_whitespace();
+        let (start_end, rest) = next_field(s).ok_or(Error::MissingRegionField("start-end"))?;
+        let (permissions, rest) =
+            next_field(rest).ok_or(Error::MissingRegionField("permissions"))?;
+        let (offset, rest) = next_field(rest).ok_or(Error::MissingRegionField("offset"))?;
+        let (device, rest) = next_field(rest).ok_or(Error::MissingRegionField("device"))?;
+        let (inode, rest) = next_field(rest).ok_or(Error::MissingRegionField("inode"))?;
 
-        let start_end = parts.next().ok_or(Error::MissingRegionField("start-end"))?;
-        let permissions = parts
-            .next()
-            .ok_or(Error::MissingRegionField("permissions"))?;
-        let offset = parts.next().ok_or(Error::MissingRegionField("offset"))?;
-        let device = parts.next().ok_or(Error::MissingRegionField("device"))?;
-        let inode = parts.next().ok_or(Error::MissingRegionField("inode"))?;
-        let path = parts.next().map(|f| f.to_owned());
+        // The path is the rest of the line, spaces included
+        let path = rest.trim_start_matches(|c: char| c.is_ascii_whitespace());
 
         let (start, end) =
             start_end
@@ -178,54 +387,71 @@ impl FromStr for Region {
         let inode = inode.parse()?;
 
         Ok(Self {
-            start,
-            end,
-            perms,
-            offset,
             dev,
             inode,
-            path,
+            ..Self::new(start, end, perms, offset, path)
         })
     }
 }
 
 impl fmt::Display for Region {
+    /// Name of the region in dumps: its address range, permissions, mapping and kind
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if let Some(filename) = self.filename() {
-            write!(
-                f,
-                "{:x}-{:x}_{}_+{:x}_{:02x}:{:02x}_{:x}_{}",
-                self.start,
-                self.end,
-                self.perms,
-                self.offset,
-                self.dev.major,
-                self.dev.minor,
-                self.inode,
-                filename
-            )
-        } else {
-            write!(
-                f,
-                "{:x}-{:x}_{}_+{:x}_{:02x}:{:02x}_{:x}",
-                self.start,
-                self.end,
-                self.perms,
-                self.offset,
-                self.dev.major,
-                self.dev.minor,
-                self.inode,
-            )
+        write!(
+            f,
+            "{:x}-{:x}_{}_+{:x}_{:02x}:{:02x}_{:x}",
+            self.start,
+            self.end,
+            self.perms,
+            self.offset,
+            self.dev.major,
+            self.dev.minor,
+            self.inode,
+        )?;
+        match self.kind {
+            RegionKind::File => match self.filename() {
+                Some(filename) => write!(f, "_{}", filename),
+                None => Ok(()),
+            },
+            RegionKind::Anonymous => Ok(()),
+            // Names may hold any printable character but brackets
+            RegionKind::AnonNamed(ref name) => write!(f, "_anon-{}", name.replace('/', "_")),
+            ref kind => write!(f, "_{}", kind),
         }
     }
 }
 
 impl Region {
+    /// A region mapping `path` (as written in `/proc/PID/maps`, empty if none), without device
+    pub fn new(start: usize, end: usize, perms: Permissions, offset: usize, path: &str) -> Self {
+        let (path, deleted) = match path.strip_suffix(" (deleted)") {
+            Some(path) => (path, true),
+            None => (path, false),
+        };
+        let path = (!path.is_empty()).then(|| path.to_owned());
+        Self {
+            start,
+            end,
+            perms,
+            offset,
+            dev: Device { major: 0, minor: 0 },
+            inode: 0,
+            kind: RegionKind::from_path(path.as_deref()),
+            path,
+            deleted,
+            stats: None,
+        }
+    }
+
     pub fn size(&self) -> usize {
         self.end - self.start
     }
 
+    /// Name of the backing file, pseudo-paths like `[heap]` excluded
     pub fn filename(&self) -> Option<&str> {
+        if self.kind != RegionKind::File {
+            return None;
+        }
         self.path()
             .map(Path::new)
             .and_then(|p| p.file_name())
@@ -234,9 +460,186 @@ impl Region {
     pub fn path(&self) -> Option<&str> {
         self.path.as_deref()
     }
+
+    /// Shared memory object the region maps, if any
+    pub fn shared_memory(&self) -> Option<SharedMemory> {
+        if self.kind != RegionKind::File {
+            return None;
+        }
+        let path = self.path()?;
+        if let Some(key) = path.strip_prefix("/SYSV") {
+            // The inode of a segment is its identifier, as listed by `ipcs`
+            let key = u32::from_str_radix(key, 16).ok()?;
+            Some(SharedMemory::SysV {
+                key,
+                id: self.inode,
+            })
+        } else if let Some(name) = path.strip_prefix("/dev/shm/") {
+            Some(SharedMemory::Posix(name.into()))
+        } else {
+            path.strip_prefix("/memfd:")
+                .map(|name| SharedMemory::Memfd(name.into()))
+        }
+    }
+
+    /// Returns true if the region is not backed by a file (pseudo-paths like `[heap]` included)
+    pub fn is_anonymous(&self) -> bool {
+        self.kind != RegionKind::File
+    }
+
+    /// Returns true if both regions map the same file
+    pub fn same_file(&self, other: &Self) -> bool {
+        self.dev == other.dev && self.inode == other.inode && self.path == other.path
+    }
+
+    /// Whether `other` is the same mapping, statistics aside
+    pub fn same_mapping(&self, other: &Self) -> bool {
+        self.start == other.start
+            && self.end == other.end
+            && self.perms == other.perms
+            && self.offset == other.offset
+            && self.same_file(other)
+    }
+}
+
+/// Selects regions to operate on
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegionFilter {
+    paths: Vec<Pattern>,
+    kinds: Vec<RegionKind>,
+    anonymous_only: bool,
+    shared_only: bool,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+impl RegionFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only keep regions whose backing path matches one of the added patterns
+    pub fn add_path(&mut self, pattern: Pattern) -> &mut Self {
+        self.paths.push(pattern);
+        self
+    }
+
+    /// Only keep regions of one of the added kinds
+    pub fn add_kind(&mut self, kind: RegionKind) -> &mut Self {
+        self.kinds.push(kind);
+        self
+    }
+
+    /// Only keep regions without a backing file
+    pub fn anonymous_only(&mut self, anonymous_only: bool) -> &mut Self {
+        self.anonymous_only = anonymous_only;
+        self
+    }
+
+    /// Only keep regions mapping shared memory, see [`Region::shared_memory`]
+    pub fn shared_only(&mut self, shared_only: bool) -> &mut Self {
+        self.shared_only = shared_only;
+        self
+    }
+
+    pub fn matches(&self, region: &Region) -> bool {
+        if self.anonymous_only && !region.is_anonymous() {
+            return false;
+        }
+        if self.shared_only && region.shared_memory().is_none() {
+            return false;
+        }
+        if !self.kinds.is_empty() && !self.kinds.contains(&region.kind) {
+            return false;
+        }
+        if !self.paths.is_empty() {
+            match region.path() {
+                Some(path) => self.paths.iter().any(|p| p.is_match(path)),
+                None => false,
+            }
+        } else {
+            true
+        }
+    }
+}
+
+fn parse_smaps(smaps: &str) -> Result<Vec<Region>> {
+    let mut regions: Vec<Region> = Vec::new();
+    for line in smaps.lines() {
+        // Field names end with a colon, region lines start with an address range
+        let is_field = line
+            .split_ascii_whitespace()
+            .next()
+            .is_some_and(|k| k.ends_with(':'));
+        match regions.last_mut() {
+            Some(region) if is_field => region
+                .stats
+                .get_or_insert_with(RegionStats::default)
+                .parse_line(line)?,
+            _ => regions.push(line.parse()?),
+        }
+    }
+    Ok(regions)
+}
+
+/// Regions of `/proc/PID/maps`, parsed one line at a time
+///
+/// The file is read as the iteration goes, the kernel may return lines from different states of the
+/// mappings of a running process.
+#[derive(Debug)]
+pub struct RegionIter<R> {
+    lines: Lines<R>,
+    line: usize,
+}
+
+impl<R: BufRead> RegionIter<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+            line: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for RegionIter<R> {
+    type Item = Result<Region>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let text = match self.lines.next()? {
+            Ok(text) => text,
+            Err(e) => return Some(Err(e.into())),
+        };
+        self.line += 1;
+        Some(text.parse().map_err(|e| Error::MapsLine {
+            line: self.line,
+            text,
+            source: Box::new(e),
+        }))
+    }
+}
+
+/// Collects `regions`, skipping the lines which cannot be parsed with a warning
+///
+/// Reading errors are still returned.
+fn collect_lenient<R: BufRead>(pid: u32, regions: RegionIter<R>) -> Result<Vec<Region>> {
+    let mut parsed = Vec::new();
+    for region in regions {
+        match region {
+            Ok(region) => parsed.push(region),
+            Err(e @ Error::MapsLine { .. }) => {
+                warn!("Skipping a region of process {}: {}", pid, Chain(&e))
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(parsed)
+}
+
+/// Returns the region of the sorted `regions` mapping `address`
+pub fn region_containing(regions: &[Region], address: usize) -> Option<&Region> {
+    let idx = regions.partition_point(|r| r.end <= address);
+    regions.get(idx).filter(|r| r.start <= address)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Memory {
     pid: u32,
     regions: Vec<Region>,
@@ -251,15 +654,94 @@ impl std::ops::Deref for Memory {
 }
 
 impl Memory {
+    /// Regions which do not come from `/proc/PID/maps`, sorted by address
+    pub fn from_regions(pid: u32, regions: Vec<Region>) -> Self {
+        Self { pid, regions }
+    }
+
+    /// Iterates over the regions of `/proc/PID/maps`, reading it as needed
+    pub fn iter_pid(pid: u32) -> Result<RegionIter<BufReader<File>>> {
+        let maps = File::open(format!("/proc/{}/maps", pid))?;
+        Ok(RegionIter::new(BufReader::new(maps)))
+    }
+
+    /// Reads the regions of `pid`, failing on the first line which cannot be parsed
     pub fn from_pid(pid: u32) -> Result<Self> {
-        let maps = std::fs::read_to_string(format!("/proc/{}/maps", pid))?;
-        let mut regions = Vec::new();
+        let regions = Self::iter_pid(pid)?.collect::<Result<_>>()?;
+        Ok(Self { pid, regions })
+    }
 
-        for line in maps.lines() {
-            regions.push(line.parse::<Region>()?);
-        }
+    /// Same as `from_pid`, skipping the lines which cannot be parsed with a warning
+    pub fn from_pid_lenient(pid: u32) -> Result<Self> {
+        let regions = collect_lenient(pid, Self::iter_pid(pid)?)?;
         Ok(Self { pid, regions })
     }
+
+    /// Same as `from_pid`, also filling region statistics from `/proc/PID/smaps`
+    pub fn from_pid_smaps(pid: u32) -> Result<Self> {
+        let smaps = std::fs::read_to_string(format!("/proc/{}/smaps", pid))?;
+        Self::from_smaps(pid, &smaps)
+    }
+
+    /// Parses the content of `/proc/PID/smaps`, which may come from another host
+    pub fn from_smaps(pid: u32, smaps: &str) -> Result<Self> {
+        Ok(Self {
+            pid,
+            regions: parse_smaps(smaps)?,
+        })
+    }
+
+    /// Reads `/proc/PID/maps` again, the mappings of a running process may have changed
+    ///
+    /// Statistics from `/proc/PID/smaps` are not read again.
+    pub fn refresh(&mut self) -> Result<()> {
+        self.regions = collect_lenient(self.pid, Self::iter_pid(self.pid)?)?;
+        Ok(())
+    }
+
+    /// Returns the region mapping `address`
+    pub fn region_containing(&self, address: usize) -> Option<&Region> {
+        region_containing(&self.regions, address)
+    }
+
+    /// Returns the region mapping `address` from a file, and the offset of `address` in that file
+    ///
+    /// Returns `None` if `address` is not mapped, or not from a file.
+    pub fn translate(&self, address: usize) -> Option<(&Region, usize)> {
+        let region = self
+            .region_containing(address)
+            .filter(|r| r.kind == RegionKind::File)?;
+        Some((region, region.offset + (address - region.start)))
+    }
+
+    /// Whether `region` is still mapped the same way
+    pub fn has_mapping(&self, region: &Region) -> bool {
+        self.region_containing(region.start)
+            .is_some_and(|r| r.same_mapping(region))
+    }
+
+    /// Returns the parts of `range` which are not mapped by any region
+    pub fn unmapped(&self, range: &Range<usize>) -> Vec<Range<usize>> {
+        let mut gaps: Vec<Range<usize>> = Vec::new();
+        let mut address = range.start;
+        while address < range.end {
+            match self.region_containing(address) {
+                Some(region) => address = region.end,
+                None => {
+                    let idx = self.regions.partition_point(|r| r.end <= address);
+                    let next = self.regions.get(idx).map_or(range.end, |r| r.start);
+                    gaps.push(address..next.min(range.end));
+                    address = next;
+                }
+            }
+        }
+        gaps
+    }
+
+    /// Iterates over regions selected by `filter`
+    pub fn filter<'a>(&'a self, filter: &'a RegionFilter) -> impl Iterator<Item = &'a Region> {
+        self.iter().filter(move |r| filter.matches(r))
+    }
 }
 
 #[cfg(test)]
@@ -280,6 +762,9 @@ mod tests {
             },
             inode: 1462190,
             path: Some("/usr/bin/nvim".into()),
+            deleted: false,
+            kind: RegionKind::File,
+            stats: None,
         };
         let parsed_region = match region_with_file.parse::<Region>() {
             Ok(region) => region,
@@ -308,6 +793,9 @@ mod tests {
             },
             inode: 0,
             path: None,
+            deleted: false,
+            kind: RegionKind::Anonymous,
+            stats: None,
         };
         let parsed_region = match region_with_file.parse::<Region>() {
             Ok(region) => region,
@@ -317,4 +805,249 @@ mod tests {
         };
         assert_eq!(region, parsed_region);
     }
+
+    #[test]
+    fn test_parse_region_with_special_path() {
+        let region: Region = "7f0000000000-7f0000001000 r-xp 00000000 fe:01 42     /tmp/my lib.so"
+            .parse()
+            .unwrap();
+        assert_eq!(region.path(), Some("/tmp/my lib.so"));
+        assert_eq!(region.filename(), Some("my lib.so"));
+        assert!(!region.deleted);
+
+        let region: Region =
+            "7f0000000000-7f0000001000 rw-s 00000000 00:01 1234 /memfd:ring buffer (deleted)"
+                .parse()
+                .unwrap();
+        assert_eq!(region.path(), Some("/memfd:ring buffer"));
+        assert!(region.deleted);
+        assert_eq!(region.kind, RegionKind::File);
+
+        assert!("7f0000000000-7f0000001000 r-xp 00000000 fe:01"
+            .parse::<Region>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_region_filter() {
+        let libssl: Region =
+            "7f0000000000-7f0000001000 r-xp 00000000 fe:01 42 /usr/lib/libssl.so.3"
+                .parse()
+                .unwrap();
+        let heap: Region = "559213ad7000-559213af0000 rw-p 00000000 00:00 0 [heap]"
+            .parse()
+            .unwrap();
+        let anon: Region = "7f0000002000-7f0000003000 rw-p 00000000 00:00 0"
+            .parse()
+            .unwrap();
+
+        let mut filter = RegionFilter::new();
+        filter.add_path("libssl*".parse().unwrap());
+        assert!(filter.matches(&libssl));
+        assert!(!filter.matches(&heap));
+        assert!(!filter.matches(&anon));
+
+        let mut filter = RegionFilter::new();
+        filter.anonymous_only(true);
+        assert!(!filter.matches(&libssl));
+        assert!(filter.matches(&heap));
+        assert!(filter.matches(&anon));
+
+        let mut filter = RegionFilter::new();
+        filter.add_kind(RegionKind::Heap);
+        filter.add_kind(RegionKind::Anonymous);
+        assert!(!filter.matches(&libssl));
+        assert!(filter.matches(&heap));
+        assert!(filter.matches(&anon));
+
+        let memfd: Region = "7f0000004000-7f0000005000 rw-s 00000000 00:01 7 /memfd:ring (deleted)"
+            .parse()
+            .unwrap();
+        let mut filter = RegionFilter::new();
+        filter.shared_only(true);
+        assert!(!filter.matches(&libssl));
+        assert!(!filter.matches(&heap));
+        assert!(filter.matches(&memfd));
+    }
+
+    #[test]
+    fn test_shared_memory() {
+        let shared = |line: &str| line.parse::<Region>().unwrap().shared_memory();
+        assert_eq!(
+            shared("7f0000000000-7f0000001000 rw-s 00000000 00:01 32779 /SYSV0000abcd (deleted)"),
+            Some(SharedMemory::SysV {
+                key: 0xabcd,
+                id: 32779
+            })
+        );
+        assert_eq!(
+            shared("7f0000000000-7f0000001000 rw-s 00000000 00:1a 12 /dev/shm/queue"),
+            Some(SharedMemory::Posix("queue".into()))
+        );
+        assert_eq!(
+            shared("7f0000000000-7f0000001000 rw-s 00000000 00:01 7 /memfd:ring (deleted)"),
+            Some(SharedMemory::Memfd("ring".into()))
+        );
+        assert_eq!(
+            shared("7f0000000000-7f0000001000 r-xp 00000000 fe:01 42 /usr/lib/libssl.so.3"),
+            None
+        );
+        assert_eq!(
+            shared("7f0000000000-7f0000001000 rw-p 00000000 00:00 0 [anon:memfd:x]"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_region_kind() {
+        let kind = |path: &str| {
+            format!("1000-2000 rw-p 00000000 00:00 0 {}", path)
+                .parse::<Region>()
+                .unwrap()
+                .kind
+        };
+        assert_eq!(kind(""), RegionKind::Anonymous);
+        assert_eq!(kind("/usr/lib/libc.so.6"), RegionKind::File);
+        assert_eq!(kind("[heap]"), RegionKind::Heap);
+        assert_eq!(kind("[stack]"), RegionKind::Stack);
+        assert_eq!(kind("[stack:1234]"), RegionKind::Stack);
+        assert_eq!(kind("[vdso]"), RegionKind::Vdso);
+        assert_eq!(kind("[vvar_vclock]"), RegionKind::Vvar);
+        assert_eq!(kind("[vsyscall]"), RegionKind::Vsyscall);
+        assert_eq!(kind("[anon:jit]"), RegionKind::AnonNamed("jit".into()));
+        assert_eq!(
+            kind("[anon_shmem:ring]"),
+            RegionKind::AnonNamed("ring".into())
+        );
+        assert_eq!(kind("[uprobes]"), RegionKind::Anonymous);
+
+        for kind in ["file", "heap", "stack", "anonymous", "anon:jit"] {
+            assert_eq!(kind.parse::<RegionKind>().unwrap().to_string(), kind);
+        }
+        assert!("anon:".parse::<RegionKind>().is_err());
+
+        let heap: Region = "1000-2000 rw-p 00000000 00:00 0 [heap]".parse().unwrap();
+        assert_eq!(heap.to_string(), "1000-2000_rw-p-_+0_00:00_0_heap");
+        assert_eq!(heap.filename(), None);
+        let named: Region = "1000-2000 rw-p 00000000 00:00 0 [anon:a/b]"
+            .parse()
+            .unwrap();
+        assert_eq!(named.to_string(), "1000-2000_rw-p-_+0_00:00_0_anon-a_b");
+    }
+
+    #[test]
+    fn test_region_containing() {
+        let memory = Memory {
+            pid: 1,
+            regions: [
+                "1000-2000 r--p 00000000 00:00 0",
+                "2000-3000 rw-p 00000000 00:00 0",
+                "5000-6000 rw-p 00000000 00:00 0 [stack]",
+            ]
+            .iter()
+            .map(|r| r.parse().unwrap())
+            .collect(),
+        };
+        assert_eq!(memory.region_containing(0xfff), None);
+        assert_eq!(
+            memory.region_containing(0x1000).map(|r| r.start),
+            Some(0x1000)
+        );
+        assert_eq!(
+            memory.region_containing(0x2fff).map(|r| r.start),
+            Some(0x2000)
+        );
+        assert_eq!(memory.region_containing(0x3000), None);
+        assert_eq!(
+            memory.region_containing(0x5800).map(|r| r.start),
+            Some(0x5000)
+        );
+        assert_eq!(memory.region_containing(0x6000), None);
+
+        assert_eq!(memory.unmapped(&(0x1800..0x2800)), vec![]);
+        assert_eq!(
+            memory.unmapped(&(0x800..0x5800)),
+            vec![0x800..0x1000, 0x3000..0x5000]
+        );
+        assert_eq!(memory.unmapped(&(0x5800..0x7000)), vec![0x6000..0x7000]);
+    }
+
+    #[test]
+    fn test_translate() {
+        let memory = Memory {
+            pid: 1,
+            regions: [
+                "400000-401000 r--p 00000000 fe:01 42 /usr/bin/true",
+                "401000-403000 r-xp 00001000 fe:01 42 /usr/bin/true",
+                "403000-404000 rw-p 00000000 00:00 0 [heap]",
+            ]
+            .iter()
+            .map(|r| r.parse().unwrap())
+            .collect(),
+        };
+        let translate = |address| memory.translate(address).map(|(r, o)| (r.start, o));
+        assert_eq!(translate(0x400010), Some((0x400000, 0x10)));
+        assert_eq!(translate(0x402345), Some((0x401000, 0x2345)));
+        assert_eq!(translate(0x403000), None);
+        assert_eq!(translate(0x3fffff), None);
+    }
+
+    #[test]
+    fn test_parse_smaps() {
+        let smaps = "\
+559213ad7000-559213af0000 rw-p 00000000 00:00 0                          [heap]
+Size:                100 kB
+Rss:                  64 kB
+Pss:                  32 kB
+Shared_Dirty:          4 kB
+Private_Dirty:        60 kB
+Swap:                  8 kB
+THPeligible:           0
+VmFlags: rd wr mr mw me ac
+7f0000000000-7f0000001000 r-xp 00000000 fe:01 42                         /usr/lib/libssl.so.3
+Rss:                   4 kB
+VmFlags: rd ex mr mw me dd
+";
+        let regions = parse_smaps(smaps).unwrap();
+        assert_eq!(regions.len(), 2);
+        assert_eq!(regions[0].path(), Some("[heap]"));
+        assert_eq!(
+            regions[0].stats,
+            Some(RegionStats {
+                rss: 64 * 1024,
+                pss: 32 * 1024,
+                swap: 8 * 1024,
+                shared_dirty: 4 * 1024,
+                private_dirty: 60 * 1024,
+                vm_flags: ["rd", "wr", "mr", "mw", "me", "ac"]
+                    .iter()
+                    .map(|f| f.to_string())
+                    .collect(),
+            })
+        );
+        let stats = regions[1].stats.as_ref().unwrap();
+        assert_eq!(stats.rss, 4096);
+        assert!(stats.vm_flags.iter().any(|f| f == "dd"));
+    }
+
+    #[test]
+    fn test_region_iter() {
+        let maps = "00400000-00401000 r-xp 00000000 fe:01 42 /usr/bin/prog\n\
+                    garbage\n\
+                    7ffd0000-7ffd1000 rw-p 00000000 00:00 0 [stack]\n";
+        let regions: Vec<_> = RegionIter::new(maps.as_bytes()).collect();
+        assert_eq!(regions.len(), 3);
+        assert!(regions[0].is_ok());
+        match regions[1] {
+            Err(Error::MapsLine { line, ref text, .. }) => {
+                assert_eq!(line, 2);
+                assert_eq!(text, "garbage");
+            }
+            ref other => panic!("unexpected {:?}", other),
+        }
+
+        let regions = collect_lenient(1, RegionIter::new(maps.as_bytes())).unwrap();
+        assert_eq!(regions.len(), 2);
+        assert_eq!(regions[1].start, 0x7ffd0000);
+    }
 }
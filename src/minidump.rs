@@ -0,0 +1,670 @@
+//! Minidump writer, for Breakpad and Crashpad tools such as `minidump-stackwalk`
+//!
+//! The layout is the one of `CoreDump`: a head holding the header, the stream directory and the
+//! streams, then page-aligned region contents. The head is written last, it is sized when the file
+//! is created for the memory list to hold every region.
+//!
+//! The streams are those written by Breakpad on Linux: system and process information, the threads
+//! with their registers as a Windows `CONTEXT` and the region of their stack pointer as their stack,
+//! the mapped ELF files as modules identified by their GNU build ID, the memory list locating the
+//! regions, and copies of `/proc/PID/cmdline`, `auxv` and `maps`.
+//!
+//! Streams are located with 32-bit offsets, but the regions of minidumps larger than 4 GiB are
+//! listed in a 64-bit memory list, as Windows does for full memory dumps. Only 64-bit processes are
+//! supported, on x86_64 and aarch64.
+
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::elf::{self, Class, ElfHeader, ProgramHeader, EHDR_SIZE, PHDR_SIZE, PT_NOTE};
+use crate::manifest::Manifest;
+use crate::memory::{Memory, PermissionBits, Region, RegionKind};
+use crate::output::{Output, SingleFile, Source, Stored};
+use crate::procfs;
+use crate::regs::{self, Registers};
+use crate::Result;
+
+/// `MDMP`, and the version of the format
+const SIGNATURE: u32 = 0x504d444d;
+const VERSION: u32 = 0xa793;
+
+const HEADER_SIZE: usize = 32;
+const DIRECTORY_ENTRY_SIZE: usize = 12;
+const MEMORY_DESCRIPTOR_SIZE: usize = 16;
+/// Size of the header of a `MINIDUMP_MEMORY64_LIST`, the number of regions and their base offset
+const MEMORY64_LIST_HEADER_SIZE: usize = 16;
+
+const THREAD_LIST_STREAM: u32 = 3;
+const MODULE_LIST_STREAM: u32 = 4;
+const MEMORY_LIST_STREAM: u32 = 5;
+const SYSTEM_INFO_STREAM: u32 = 7;
+const MEMORY64_LIST_STREAM: u32 = 9;
+const MISC_INFO_STREAM: u32 = 15;
+
+/// Breakpad streams holding copies of `/proc/PID` files
+const LINUX_CMD_LINE_STREAM: u32 = 0x47670006;
+const LINUX_AUXV_STREAM: u32 = 0x47670008;
+const LINUX_MAPS_STREAM: u32 = 0x47670009;
+
+const STREAM_COUNT: usize = 8;
+
+const PLATFORM_LINUX: u32 = 0x8201;
+
+const MISC1_PROCESS_ID: u32 = 1;
+
+/// Signature of the CodeView records of ELF files, followed by their build ID
+const CV_SIGNATURE_ELF: u32 = 0x4270454c;
+
+/// Bound on the size of a `PT_NOTE` segment read for the build ID
+const MAX_NOTES_SIZE: u64 = 1 << 16;
+
+#[cfg(target_arch = "x86_64")]
+const PROCESSOR_ARCHITECTURE: u16 = 9;
+#[cfg(target_arch = "aarch64")]
+const PROCESSOR_ARCHITECTURE: u16 = 12;
+/// Breakpad's value, although minidumps are not written there
+#[cfg(target_arch = "riscv64")]
+const PROCESSOR_ARCHITECTURE: u16 = 0x8007;
+
+/// Size and offset of some content of the minidump, a `MINIDUMP_LOCATION_DESCRIPTOR`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Location {
+    size: u32,
+    rva: u32,
+}
+
+impl Location {
+    fn push_to(self, data: &mut Vec<u8>) {
+        data.extend_from_slice(&self.size.to_le_bytes());
+        data.extend_from_slice(&self.rva.to_le_bytes());
+    }
+}
+
+/// Header, stream directory and streams, at the start of the minidump
+#[derive(Debug)]
+struct Head {
+    data: Vec<u8>,
+    directory: Vec<(u32, Location)>,
+}
+
+impl Head {
+    fn new() -> Self {
+        Self {
+            data: vec![0; HEADER_SIZE + STREAM_COUNT * DIRECTORY_ENTRY_SIZE],
+            directory: Vec::with_capacity(STREAM_COUNT),
+        }
+    }
+
+    /// Appends `content`, aligned on 8 bytes
+    fn push(&mut self, content: &[u8]) -> Location {
+        self.data.resize(self.data.len().next_multiple_of(8), 0);
+        let rva = self.data.len() as u32;
+        self.data.extend_from_slice(content);
+        Location {
+            size: content.len() as u32,
+            rva,
+        }
+    }
+
+    /// Appends `s` as a `MINIDUMP_STRING`, UTF-16 after its size in bytes and NUL-terminated
+    fn push_string(&mut self, s: &str) -> u32 {
+        let units: Vec<u16> = s.encode_utf16().collect();
+        let mut content = Vec::with_capacity(6 + 2 * units.len());
+        content.extend_from_slice(&(2 * units.len() as u32).to_le_bytes());
+        for unit in units.iter().chain([&0]) {
+            content.extend_from_slice(&unit.to_le_bytes());
+        }
+        self.push(&content).rva
+    }
+
+    fn stream(&mut self, stream_type: u32, content: &[u8]) {
+        let location = self.push(content);
+        self.directory.push((stream_type, location));
+    }
+
+    /// Fills the header and the directory in
+    fn finish(mut self, timestamp: u32) -> Vec<u8> {
+        assert_eq!(self.directory.len(), STREAM_COUNT);
+        let mut header = Vec::with_capacity(HEADER_SIZE + STREAM_COUNT * DIRECTORY_ENTRY_SIZE);
+        // Signature, version, number of streams, directory offset, checksum and timestamp
+        for value in [
+            SIGNATURE,
+            VERSION,
+            STREAM_COUNT as u32,
+            HEADER_SIZE as u32,
+            0,
+            timestamp,
+        ] {
+            header.extend_from_slice(&value.to_le_bytes());
+        }
+        // Flags
+        header.extend_from_slice(&0u64.to_le_bytes());
+        for (stream_type, location) in self.directory {
+            header.extend_from_slice(&stream_type.to_le_bytes());
+            location.push_to(&mut header);
+        }
+        self.data[..header.len()].copy_from_slice(&header);
+        self.data
+    }
+}
+
+/// Copies `bytes` at `offset` of `data`
+fn put(data: &mut [u8], offset: usize, bytes: &[u8]) {
+    data[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
+
+/// Size of the `CONTEXT` of the architecture, as defined by Windows
+#[cfg(target_arch = "x86_64")]
+const CONTEXT_SIZE: usize = 1232;
+#[cfg(target_arch = "aarch64")]
+const CONTEXT_SIZE: usize = 912;
+
+/// Builds the AMD64 `CONTEXT` of a thread, whose `FltSave` area has the `fxsave` layout
+#[cfg(target_arch = "x86_64")]
+fn context(regs: &Registers) -> Vec<u8> {
+    // CONTEXT_AMD64 with CONTROL, INTEGER and SEGMENTS
+    let mut flags = 0x0010_0007u32;
+    let mut context = vec![0; CONTEXT_SIZE];
+    let gp = &regs.gp;
+    for (index, selector) in [gp.cs, gp.ds, gp.es, gp.fs, gp.gs, gp.ss]
+        .iter()
+        .enumerate()
+    {
+        put(
+            &mut context,
+            56 + 2 * index,
+            &(*selector as u16).to_le_bytes(),
+        );
+    }
+    put(&mut context, 68, &(gp.eflags as u32).to_le_bytes());
+    let integers = [
+        gp.rax, gp.rcx, gp.rdx, gp.rbx, gp.rsp, gp.rbp, gp.rsi, gp.rdi, gp.r8, gp.r9, gp.r10,
+        gp.r11, gp.r12, gp.r13, gp.r14, gp.r15, gp.rip,
+    ];
+    for (index, value) in integers.iter().enumerate() {
+        put(&mut context, 120 + 8 * index, &value.to_le_bytes());
+    }
+    if let Some(ref fp) = regs.fp {
+        flags |= 8;
+        put(&mut context, 52, &fp.mxcsr.to_le_bytes());
+        put(&mut context, 256, regs::as_bytes(fp));
+    }
+    put(&mut context, 48, &flags.to_le_bytes());
+    context
+}
+
+/// Builds the ARM64 `CONTEXT` of a thread
+#[cfg(target_arch = "aarch64")]
+fn context(regs: &Registers) -> Vec<u8> {
+    // CONTEXT_ARM64 with CONTROL and INTEGER
+    let mut flags = 0x0040_0003u32;
+    let mut context = vec![0; CONTEXT_SIZE];
+    let gp = &regs.gp;
+    put(&mut context, 4, &(gp.pstate as u32).to_le_bytes());
+    for (index, value) in gp.regs.iter().chain([&gp.sp, &gp.pc]).enumerate() {
+        put(&mut context, 8 + 8 * index, &value.to_le_bytes());
+    }
+    if let Some(ref fp) = regs.fp {
+        flags |= 4;
+        for (index, value) in fp.vregs.iter().enumerate() {
+            put(&mut context, 272 + 16 * index, &value.to_le_bytes());
+        }
+        put(&mut context, 784, &fp.fpcr.to_le_bytes());
+        put(&mut context, 788, &fp.fpsr.to_le_bytes());
+    }
+    put(&mut context, 0, &flags.to_le_bytes());
+    context
+}
+
+/// Not written, see [`Minidump::create`]
+#[cfg(target_arch = "riscv64")]
+fn context(_regs: &Registers) -> Vec<u8> {
+    Vec::new()
+}
+
+/// Returns the description of the kernel, as `uname -srvm`
+fn kernel_version() -> Result<String> {
+    let mut fields = Vec::new();
+    for name in ["ostype", "osrelease", "version"] {
+        let field = std::fs::read_to_string(format!("/proc/sys/kernel/{}", name))?;
+        fields.push(field.trim().to_owned());
+    }
+    fields.push(std::env::consts::ARCH.into());
+    Ok(fields.join(" "))
+}
+
+/// Builds the `MINIDUMP_SYSTEM_INFO` of this host, whose kernel is described by `kernel`
+fn system_info(kernel: &str, csd_version: u32) -> Vec<u8> {
+    let mut info = Vec::with_capacity(56);
+    info.extend_from_slice(&PROCESSOR_ARCHITECTURE.to_le_bytes());
+    // Processor level and revision
+    info.extend_from_slice(&[0; 4]);
+    let processors = std::thread::available_parallelism().map_or(1, |n| n.get().min(255));
+    // Number of processors, and product type
+    info.extend_from_slice(&[processors as u8, 0]);
+    // Major, minor and build numbers of the kernel release
+    let release = kernel.split(' ').nth(1).unwrap_or("");
+    let mut numbers = release
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|n| n.parse::<u32>().unwrap_or(0));
+    for _ in 0..3 {
+        info.extend_from_slice(&numbers.next().unwrap_or(0).to_le_bytes());
+    }
+    info.extend_from_slice(&PLATFORM_LINUX.to_le_bytes());
+    info.extend_from_slice(&csd_version.to_le_bytes());
+    // Suite mask, padding and CPU information
+    info.resize(56, 0);
+    info
+}
+
+/// Builds the `MINIDUMP_MISC_INFO` of the process, only holding its PID
+fn misc_info(pid: u32) -> Vec<u8> {
+    let mut info = Vec::with_capacity(24);
+    for value in [24, MISC1_PROCESS_ID, pid] {
+        info.extend_from_slice(&value.to_le_bytes());
+    }
+    // Creation, user and kernel times
+    info.resize(24, 0);
+    info
+}
+
+/// Builds the memory list of `regions`, their start address, offset and size, with its type
+///
+/// The offsets and sizes of a `MINIDUMP_MEMORY_LIST` are 32-bit, past 4 GiB the regions are listed
+/// in a `MINIDUMP_MEMORY64_LIST`. Its regions follow each other from a base offset, so each one is
+/// described as ending where the next one is written.
+fn memory_list(regions: &[(u64, u64, u64)]) -> (u32, Vec<u8>) {
+    let end = regions.last().map_or(0, |&(_, offset, size)| offset + size);
+    if end <= u32::MAX as u64 {
+        let mut list = (regions.len() as u32).to_le_bytes().to_vec();
+        for &(start, offset, size) in regions {
+            list.extend_from_slice(&start.to_le_bytes());
+            Location {
+                size: size as u32,
+                rva: offset as u32,
+            }
+            .push_to(&mut list);
+        }
+        return (MEMORY_LIST_STREAM, list);
+    }
+
+    let mut list = (regions.len() as u64).to_le_bytes().to_vec();
+    list.extend_from_slice(&regions[0].1.to_le_bytes());
+    for (index, &(start, offset, size)) in regions.iter().enumerate() {
+        let size = regions.get(index + 1).map_or(size, |next| next.1 - offset);
+        list.extend_from_slice(&start.to_le_bytes());
+        list.extend_from_slice(&size.to_le_bytes());
+    }
+    (MEMORY64_LIST_STREAM, list)
+}
+
+/// A mapped ELF file
+#[derive(Debug)]
+struct Module {
+    start: u64,
+    end: u64,
+    path: String,
+    build_id: Option<Vec<u8>>,
+}
+
+/// Returns the mappings of files starting at offset 0 and partly executable, with the end of the
+/// last mapping of the same file which follows
+///
+/// Anonymous mappings between them, like the `.bss` of libraries, are part of the module.
+fn find_modules<'a>(regions: impl Iterator<Item = &'a Region>) -> Vec<(&'a Region, usize)> {
+    let mut modules: Vec<(&Region, usize, bool)> = Vec::new();
+    for region in regions.filter(|r| r.kind == RegionKind::File) {
+        let exec = region.perms.has_perm(PermissionBits::Exec);
+        match modules.last_mut() {
+            Some((first, end, executable)) if first.same_file(region) && region.offset != 0 => {
+                *end = region.end;
+                *executable |= exec;
+            }
+            _ if region.offset == 0 => modules.push((region, region.end, exec)),
+            _ => (),
+        }
+    }
+    modules
+        .into_iter()
+        .filter(|&(_, _, exec)| exec)
+        .map(|(first, end, _)| (first, end))
+        .collect()
+}
+
+/// Reads the GNU build ID of the ELF file mapped by `region` in `pid`, if it has one
+fn read_build_id(pid: u32, region: &Region) -> Result<Option<Vec<u8>>> {
+    let file = procfs::open_mapped_file(pid, region)?;
+    let mut data = [0; EHDR_SIZE];
+    file.read_exact_at(&mut data, 0)?;
+    let header = ElfHeader::parse(&data)?;
+    let mut phdrs = vec![0; header.e_phnum as usize * PHDR_SIZE];
+    file.read_exact_at(&mut phdrs, header.e_phoff)?;
+    for phdr in phdrs.chunks_exact(PHDR_SIZE) {
+        let phdr = ProgramHeader::parse(phdr)?;
+        if phdr.p_type != PT_NOTE || phdr.p_filesz > MAX_NOTES_SIZE {
+            continue;
+        }
+        let mut notes = vec![0; phdr.p_filesz as usize];
+        file.read_exact_at(&mut notes, phdr.p_offset)?;
+        if let Some(build_id) = elf::build_id(&notes) {
+            return Ok(Some(build_id.to_vec()));
+        }
+    }
+    Ok(None)
+}
+
+/// What the head of a minidump describes besides the memory
+#[derive(Debug)]
+struct Process {
+    pid: u32,
+    timestamp: u32,
+    kernel: String,
+    threads: Vec<Registers>,
+    modules: Vec<Module>,
+
+    /// Breakpad streams, with their content
+    linux_streams: Vec<(u32, Vec<u8>)>,
+}
+
+/// Writes regions into a single minidump
+#[derive(Debug)]
+pub struct Minidump {
+    path: PathBuf,
+    file: SingleFile,
+    process: Process,
+
+    /// Start address, offset and size of each region written
+    regions: Vec<(u64, u64, u64)>,
+}
+
+impl Minidump {
+    /// Creates a minidump able to hold up to `max_regions` regions of `memory`
+    ///
+    /// `threads` must start with the main thread, which debuggers consider as the crashing one.
+    pub fn create(
+        path: &Path,
+        pid: u32,
+        memory: &Memory,
+        threads: &[Registers],
+        max_regions: usize,
+    ) -> Result<Self> {
+        if cfg!(target_arch = "riscv64") {
+            return Err(
+                io::Error::other("minidumps are only written on x86_64 and aarch64").into(),
+            );
+        }
+        if procfs::elf_class(pid).unwrap_or(Class::Elf64) == Class::Elf32 {
+            return Err(io::Error::other("minidumps of 32-bit processes are not supported").into());
+        }
+
+        let modules = find_modules(memory.iter())
+            .into_iter()
+            .map(|(region, end)| {
+                let path = region.path().unwrap_or("").to_owned();
+                let build_id = read_build_id(pid, region).unwrap_or_else(|e| {
+                    debug!("Cannot read the build ID of {}: {}", path, e);
+                    None
+                });
+                Module {
+                    start: region.start as u64,
+                    end: end as u64,
+                    path,
+                    build_id,
+                }
+            })
+            .collect();
+        let mut linux_streams = Vec::new();
+        for (stream_type, name) in [
+            (LINUX_CMD_LINE_STREAM, "cmdline"),
+            (LINUX_AUXV_STREAM, "auxv"),
+            (LINUX_MAPS_STREAM, "maps"),
+        ] {
+            linux_streams.push((
+                stream_type,
+                std::fs::read(format!("/proc/{}/{}", pid, name))?,
+            ));
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as u32);
+
+        let process = Process {
+            pid,
+            timestamp,
+            kernel: kernel_version()?,
+            threads: threads.to_vec(),
+            modules,
+            linux_streams,
+        };
+        // The memory list comes last, its descriptors are the only part of the head which grows,
+        // and its header is the one of a 64-bit list at most
+        let head_size = process.head(&[]).len() - 4
+            + MEMORY64_LIST_HEADER_SIZE
+            + max_regions * MEMORY_DESCRIPTOR_SIZE;
+        Ok(Self {
+            path: path.into(),
+            file: SingleFile::create(path, head_size as u64, max_regions)?,
+            process,
+            regions: Vec::with_capacity(max_regions),
+        })
+    }
+}
+
+impl Process {
+    /// Returns the header, the stream directory and the streams, `regions` having been written
+    fn head(&self, regions: &[(u64, u64, u64)]) -> Vec<u8> {
+        let mut head = Head::new();
+
+        let csd_version = head.push_string(&self.kernel);
+        head.stream(SYSTEM_INFO_STREAM, &system_info(&self.kernel, csd_version));
+        head.stream(MISC_INFO_STREAM, &misc_info(self.pid));
+
+        let contexts: Vec<_> = self
+            .threads
+            .iter()
+            .map(|regs| head.push(&context(regs)))
+            .collect();
+        let mut threads = (self.threads.len() as u32).to_le_bytes().to_vec();
+        for (regs, context) in self.threads.iter().zip(contexts) {
+            // Thread ID, suspend count, priority class, priority and TEB
+            threads.extend_from_slice(&regs.tid.to_le_bytes());
+            threads.extend_from_slice(&[0; 20]);
+            let sp = regs.gp.sp();
+            let (start, stack) = regions
+                .iter()
+                .find(|&&(start, _, size)| (start..start + size).contains(&sp))
+                .map_or((0, Location::default()), |&(start, offset, size)| {
+                    // Past 4 GiB, readers look the stack up in the memory list instead
+                    let location = u32::try_from(offset)
+                        .ok()
+                        .zip(u32::try_from(size).ok())
+                        .map(|(rva, size)| Location { size, rva });
+                    (start, location.unwrap_or_default())
+                });
+            threads.extend_from_slice(&start.to_le_bytes());
+            stack.push_to(&mut threads);
+            context.push_to(&mut threads);
+        }
+        head.stream(THREAD_LIST_STREAM, &threads);
+
+        let mut modules = (self.modules.len() as u32).to_le_bytes().to_vec();
+        for module in &self.modules {
+            let name = head.push_string(&module.path);
+            let cv_record = match module.build_id {
+                Some(ref build_id) => {
+                    let mut record = CV_SIGNATURE_ELF.to_le_bytes().to_vec();
+                    record.extend_from_slice(build_id);
+                    head.push(&record)
+                }
+                None => Location::default(),
+            };
+            modules.extend_from_slice(&module.start.to_le_bytes());
+            // Size, checksum and timestamp
+            modules.extend_from_slice(&((module.end - module.start) as u32).to_le_bytes());
+            modules.extend_from_slice(&[0; 8]);
+            modules.extend_from_slice(&name.to_le_bytes());
+            // Version information
+            modules.extend_from_slice(&[0; 52]);
+            cv_record.push_to(&mut modules);
+            // Miscellaneous record and reserved fields
+            modules.extend_from_slice(&[0; 24]);
+        }
+        head.stream(MODULE_LIST_STREAM, &modules);
+
+        for (stream_type, content) in &self.linux_streams {
+            head.stream(*stream_type, content);
+        }
+
+        let (stream_type, memory) = memory_list(regions);
+        head.stream(stream_type, &memory);
+
+        head.finish(self.timestamp)
+    }
+}
+
+impl Output for Minidump {
+    fn write_region(
+        &mut self,
+        region: &Region,
+        _name: &str,
+        source: &mut Source,
+    ) -> Result<Stored> {
+        let (offset, size) = self.file.append(source)?;
+        self.regions.push((region.start as u64, offset, size));
+        Ok(Stored { file: None, size })
+    }
+
+    /// The manifest is written next to the minidump, with a `.manifest.json` suffix
+    fn finish(self: Box<Self>, manifest: &Manifest) -> Result<()> {
+        let head = self.process.head(&self.regions);
+        self.file.finish(&head)?;
+        let mut manifest_path = self.path.into_os_string();
+        manifest_path.push(".manifest.json");
+        manifest.write(Path::new(&manifest_path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_head() {
+        let mut head = Head::new();
+        let name = head.push_string("lib");
+        head.stream(
+            SYSTEM_INFO_STREAM,
+            &system_info("Linux 6.1.12-arch1 #1 SMP", name),
+        );
+        head.stream(MISC_INFO_STREAM, &misc_info(42));
+        for stream_type in 0..STREAM_COUNT as u32 - 2 {
+            head.stream(0x1000 + stream_type, &[0xff; 3]);
+        }
+        let data = head.finish(0x12345678);
+
+        assert_eq!(&data[..4], b"MDMP");
+        assert_eq!(elf::u32_at(&data, 8), STREAM_COUNT as u32);
+        assert_eq!(elf::u32_at(&data, 12), HEADER_SIZE as u32);
+        assert_eq!(elf::u32_at(&data, 20), 0x12345678);
+
+        // The string follows the directory
+        let name = name as usize;
+        assert_eq!(name, HEADER_SIZE + STREAM_COUNT * DIRECTORY_ENTRY_SIZE);
+        assert_eq!(elf::u32_at(&data, name), 6);
+        assert_eq!(&data[name + 4..name + 12], b"l\0i\0b\0\0\0");
+
+        let directory: Vec<_> = data[HEADER_SIZE..name]
+            .chunks_exact(DIRECTORY_ENTRY_SIZE)
+            .map(|entry| {
+                let (size, rva) = (elf::u32_at(entry, 4), elf::u32_at(entry, 8));
+                assert_eq!(rva % 8, 0);
+                (elf::u32_at(entry, 0), size, rva)
+            })
+            .collect();
+        assert_eq!(directory[0], (SYSTEM_INFO_STREAM, 56, name as u32 + 16));
+        assert_eq!(directory[1], (MISC_INFO_STREAM, 24, name as u32 + 72));
+        assert_eq!(directory[7], (0x1005, 3, name as u32 + 136));
+        assert_eq!(data.len(), name + 139);
+
+        let system_info = &data[name + 16..name + 72];
+        assert_eq!(
+            u16::from_le_bytes([system_info[0], system_info[1]]),
+            PROCESSOR_ARCHITECTURE
+        );
+        assert_eq!(elf::u32_at(system_info, 8), 6);
+        assert_eq!(elf::u32_at(system_info, 12), 1);
+        assert_eq!(elf::u32_at(system_info, 16), 12);
+        assert_eq!(elf::u32_at(system_info, 20), PLATFORM_LINUX);
+        assert_eq!(elf::u32_at(system_info, 24), name as u32);
+        assert_eq!(elf::u32_at(&data, name + 72 + 8), 42);
+    }
+
+    #[test]
+    fn test_memory_list() {
+        let (stream_type, list) = memory_list(&[(0x1000, 0x2000, 0x1000), (0x8000, 0x3000, 0x800)]);
+        assert_eq!(stream_type, MEMORY_LIST_STREAM);
+        assert_eq!(list.len(), 4 + 2 * MEMORY_DESCRIPTOR_SIZE);
+        assert_eq!(elf::u32_at(&list, 0), 2);
+        assert_eq!(elf::u64_at(&list, 20), 0x8000);
+        assert_eq!(elf::u32_at(&list, 28), 0x800);
+        assert_eq!(elf::u32_at(&list, 32), 0x3000);
+
+        let regions = [
+            (0x1000, 0x2000, 0x1000),
+            (0x8000, 0x3000, 0x1_0000_0800),
+            (0x2_0000_0000, 0x1_0000_4000, 0x1000),
+        ];
+        let (stream_type, list) = memory_list(&regions);
+        assert_eq!(stream_type, MEMORY64_LIST_STREAM);
+        assert_eq!(
+            list.len(),
+            MEMORY64_LIST_HEADER_SIZE + 3 * MEMORY_DESCRIPTOR_SIZE
+        );
+        let values: Vec<_> = list.chunks_exact(8).map(|v| elf::u64_at(v, 0)).collect();
+        assert_eq!(
+            values,
+            [
+                3,
+                0x2000,
+                0x1000,
+                0x1000,
+                0x8000,
+                0x1_0000_1000,
+                0x2_0000_0000,
+                0x1000
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_modules() {
+        let regions: Vec<Region> = [
+            "55d000000000-55d000001000 r--p 00000000 fe:01 42 /usr/bin/prog",
+            "55d000001000-55d000002000 r-xp 00001000 fe:01 42 /usr/bin/prog",
+            "55d000002000-55d000003000 rw-p 00002000 fe:01 42 /usr/bin/prog",
+            "55d001000000-55d001100000 rw-p 00000000 00:00 0 [heap]",
+            "7f0000000000-7f0000001000 r--p 00000000 fe:01 43 /usr/lib/locale/C.utf8/LC_CTYPE",
+            "7f0000010000-7f0000020000 r--p 00000000 fe:01 44 /usr/lib/libc.so.6",
+            "7f0000020000-7f0000030000 r-xp 00010000 fe:01 44 /usr/lib/libc.so.6",
+            "7f0000030000-7f0000031000 rw-p 00000000 00:00 0",
+            "7f0000031000-7f0000032000 rw-p 00020000 fe:01 44 /usr/lib/libc.so.6",
+            "7f0000040000-7f0000041000 r-xp 00003000 fe:01 45 /usr/lib/libpartial.so",
+        ]
+        .iter()
+        .map(|line| line.parse().unwrap())
+        .collect();
+        let modules: Vec<_> = find_modules(regions.iter())
+            .into_iter()
+            .map(|(first, end)| (first.path().unwrap(), first.start, end))
+            .collect();
+        assert_eq!(
+            modules,
+            [
+                ("/usr/bin/prog", 0x55d000000000, 0x55d000003000),
+                ("/usr/lib/libc.so.6", 0x7f0000010000, 0x7f0000032000),
+            ]
+        );
+    }
+}
@@ -0,0 +1,337 @@
+//! Loaded shared objects, as known by the dynamic linker
+//!
+//! The program headers of the main program are found with the auxiliary vector. Its dynamic
+//! section holds the address of `r_debug` (`DT_DEBUG`), whose `r_map` is the list of `link_map`
+//! of every loaded object, with their load base. Unlike guessing from the mappings, this also finds
+//! objects loaded from deleted or renamed files, or mapped without a backing file.
+//!
+//! These structures are made of words, of 4 bytes for 32-bit programs.
+
+use crate::cli::ModulesOptions;
+use crate::elf::{self, Class, ProgramHeader};
+use crate::json::Value;
+use crate::maps::{self, ListFormat};
+use crate::ptrace::Ptrace;
+use crate::target::{read_word, Read};
+use crate::{Error, Result};
+
+/// Bound on the length of the list, in case it is corrupted into a cycle
+const MAX_MODULES: usize = 4096;
+
+/// Bound on the length of a module name
+const MAX_NAME: usize = 4096;
+
+/// Offset of `r_map` in `struct r_debug`, in words as `r_version` is padded
+const R_MAP: usize = 1;
+
+/// Offsets of `l_addr`, `l_name`, `l_ld` and `l_next` in `struct link_map`, in words
+const L_ADDR: usize = 0;
+const L_NAME: usize = 1;
+const L_LD: usize = 2;
+const L_NEXT: usize = 3;
+
+/// A loaded object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Module {
+    /// Difference between addresses in memory and in the ELF file
+    pub base: usize,
+
+    /// Address of the dynamic section, 0 if there is none
+    pub dynamic: usize,
+
+    /// Path given to the dynamic linker, empty for the main program
+    pub name: String,
+}
+
+/// Reads the word of index `index` of the structure at `address`
+fn read_field(read: &mut Read, class: Class, address: usize, index: usize) -> Result<u64> {
+    read_word(read, class, address + index * class.word_size())
+}
+
+/// Reads the NUL-terminated string at `address`, without reading past its page
+fn read_string(read: &mut Read, address: usize) -> Result<String> {
+    const CHUNK: usize = 256;
+
+    let mut string = Vec::new();
+    let mut address = address;
+    while string.len() < MAX_NAME {
+        // Aligned chunks never cross a page boundary, the next page may not be mapped
+        let len = CHUNK - address % CHUNK;
+        let mut chunk = vec![0; len];
+        read(address, &mut chunk)?;
+        if let Some(end) = chunk.iter().position(|&b| b == 0) {
+            string.extend_from_slice(&chunk[..end]);
+            break;
+        }
+        string.extend_from_slice(&chunk);
+        address += len;
+    }
+    Ok(String::from_utf8_lossy(&string).into_owned())
+}
+
+/// Finds the main program from the auxiliary vector `auxv`, then walks the `link_map` list
+///
+/// Only the main program is returned if it has no dynamic section (statically linked), or if the
+/// dynamic linker did not fill `r_debug` yet.
+fn walk(class: Class, auxv: &[(u64, u64)], read: &mut Read) -> Result<Vec<Module>> {
+    let missing = |what| Error::Elf(format!("no {} in the auxiliary vector", what));
+    let phdr = elf::auxv_value(auxv, elf::AT_PHDR).ok_or_else(|| missing("AT_PHDR"))?;
+    let phnum = elf::auxv_value(auxv, elf::AT_PHNUM).ok_or_else(|| missing("AT_PHNUM"))?;
+
+    let mut data = vec![0; phnum as usize * class.phdr_size()];
+    read(phdr as usize, &mut data)?;
+    let headers = data
+        .chunks_exact(class.phdr_size())
+        .map(|data| ProgramHeader::parse_class(class, data))
+        .collect::<Result<Vec<_>>>()?;
+
+    let bias = match headers.iter().find(|h| h.p_type == elf::PT_PHDR) {
+        Some(h) => phdr.wrapping_sub(h.p_vaddr),
+        // Without PT_PHDR, assume the headers follow the ELF header in the first segment
+        None => headers
+            .iter()
+            .find(|h| h.p_type == elf::PT_LOAD && h.p_offset == 0)
+            .map(|h| phdr.wrapping_sub(h.p_vaddr + class.ehdr_size() as u64))
+            .ok_or_else(|| Error::Elf("cannot find the load base of the program".into()))?,
+    } as usize;
+    let main = |dynamic| Module {
+        base: bias,
+        dynamic,
+        name: String::new(),
+    };
+
+    let Some(dynamic) = headers.iter().find(|h| h.p_type == elf::PT_DYNAMIC) else {
+        return Ok(vec![main(0)]);
+    };
+    let dynamic = bias.wrapping_add(dynamic.p_vaddr as usize);
+    let mut r_debug = 0;
+    for entry in (dynamic..).step_by(2 * class.word_size()) {
+        // Tags are signed
+        let tag = match class {
+            Class::Elf32 => read_field(read, class, entry, 0)? as i32 as i64,
+            Class::Elf64 => read_field(read, class, entry, 0)? as i64,
+        };
+        if tag == elf::DT_NULL {
+            break;
+        }
+        if tag == elf::DT_DEBUG {
+            r_debug = read_field(read, class, entry, 1)? as usize;
+            break;
+        }
+    }
+    if r_debug == 0 {
+        return Ok(vec![main(dynamic)]);
+    }
+
+    let mut modules = Vec::new();
+    let mut link_map = read_field(read, class, r_debug, R_MAP)? as usize;
+    while link_map != 0 {
+        if modules.len() == MAX_MODULES {
+            return Err(Error::Elf(
+                "too many entries in link_map, it may be corrupted".into(),
+            ));
+        }
+        let name = match read_field(read, class, link_map, L_NAME)? as usize {
+            0 => String::new(),
+            address => read_string(read, address)?,
+        };
+        modules.push(Module {
+            base: read_field(read, class, link_map, L_ADDR)? as usize,
+            dynamic: read_field(read, class, link_map, L_LD)? as usize,
+            name,
+        });
+        link_map = read_field(read, class, link_map, L_NEXT)? as usize;
+    }
+    Ok(modules)
+}
+
+fn to_json(module: &Module) -> Value {
+    Value::object([
+        ("base", format!("0x{:x}", module.base).into()),
+        ("dynamic", format!("0x{:x}", module.dynamic).into()),
+        ("name", module.name.as_str().into()),
+    ])
+}
+
+pub fn run(options: &ModulesOptions) -> Result<()> {
+    let mut process = Ptrace::new(options.pid)?;
+    let class = process.class();
+    let auxv = elf::parse_auxv(
+        &std::fs::read(format!("/proc/{}/auxv", options.pid))?,
+        class,
+    );
+    let mut modules = walk(class, &auxv, &mut |address, buffer| {
+        process.read(address, buffer)
+    })?;
+    drop(process);
+
+    if modules.len() == 1 {
+        warn!(
+            "The dynamic linker did not list loaded objects, the program may be statically linked"
+        );
+    }
+    // The main program is the first object, and the only one without a name
+    if let Some(main) = modules.first_mut().filter(|m| m.name.is_empty()) {
+        if let Ok(exe) = std::fs::read_link(format!("/proc/{}/exe", options.pid)) {
+            main.name = exe.to_string_lossy().into_owned();
+        }
+    }
+
+    match options.format {
+        ListFormat::Table => {
+            println!("{:>16} {:>16} NAME", "BASE", "DYNAMIC");
+            for module in &modules {
+                println!(
+                    "{:>16x} {:>16x} {}",
+                    module.base, module.dynamic, module.name
+                );
+            }
+        }
+        ListFormat::Json => {
+            let modules = Value::Array(modules.iter().map(to_json).collect());
+            println!("{}", modules.to_pretty_string());
+        }
+        ListFormat::Csv => {
+            println!("base,dynamic,name");
+            for module in &modules {
+                println!(
+                    "0x{:x},0x{:x},{}",
+                    module.base,
+                    module.dynamic,
+                    maps::csv_field(&module.name)
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Memory of a fake process, as `(address, bytes)` blocks
+    struct Memory(Vec<(usize, Vec<u8>)>);
+
+    impl Memory {
+        fn put(&mut self, address: usize, words: &[u64]) {
+            let bytes = words.iter().flat_map(|w| w.to_ne_bytes()).collect();
+            self.0.push((address, bytes));
+        }
+
+        fn read(&self, address: usize, buffer: &mut [u8]) -> Result<()> {
+            for (start, bytes) in &self.0 {
+                if address >= *start && address + buffer.len() <= start + bytes.len() {
+                    let offset = address - start;
+                    buffer.copy_from_slice(&bytes[offset..offset + buffer.len()]);
+                    return Ok(());
+                }
+            }
+            Err(Error::RegionNotFound {
+                start: address,
+                end: address + buffer.len(),
+            })
+        }
+    }
+
+    #[test]
+    fn test_walk() {
+        let bias = 0x5555_0000_0000;
+        let mut memory = Memory(Vec::new());
+        // PT_PHDR at 0x40 and PT_DYNAMIC at 0x3000, as (type | flags << 32, offset, vaddr, ...)
+        memory.put(
+            bias + 0x40,
+            &[
+                6, 0x40, 0x40, 0x40, 0x70, 0x70, 8, 2, 0x2000, 0x3000, 0x3000, 0x100, 0x100, 8,
+            ],
+        );
+        // DT_NEEDED, then DT_DEBUG
+        memory.put(bias + 0x3000, &[1, 1, 21, 0x7000_0000, 0, 0]);
+        memory.put(0x7000_0000, &[1, 0x7000_0100]);
+        // link_map entries of the program, and of a library named at the end of a page
+        memory.put(
+            0x7000_0100,
+            &[bias as u64, 0x7000_0400, bias as u64 + 0x3000, 0x7000_0200],
+        );
+        memory.put(
+            0x7000_0200,
+            &[0x7f00_0000_0000, 0x7000_0ffa, 0x7f00_0000_4000, 0],
+        );
+        memory.put(0x7000_0400, &[0; 32]);
+        let name = b"/lib/libc.so.6\0";
+        let mut end = name[6..].to_vec();
+        end.resize(256, 0);
+        memory.0.push((0x7000_0ffa, name[..6].to_vec()));
+        memory.0.push((0x7000_1000, end));
+
+        let auxv = [(elf::AT_PHDR, bias as u64 + 0x40), (elf::AT_PHNUM, 2)];
+        let modules = walk(Class::Elf64, &auxv, &mut |a, b| memory.read(a, b)).unwrap();
+        assert_eq!(
+            modules,
+            [
+                Module {
+                    base: bias,
+                    dynamic: bias + 0x3000,
+                    name: String::new(),
+                },
+                Module {
+                    base: 0x7f00_0000_0000,
+                    dynamic: 0x7f00_0000_4000,
+                    name: "/lib/libc.so.6".into(),
+                },
+            ]
+        );
+
+        // Before the dynamic linker fills DT_DEBUG
+        memory.0.retain(|&(address, _)| address != bias + 0x3000);
+        memory.put(bias + 0x3000, &[21, 0, 0, 0]);
+        let modules = walk(Class::Elf64, &auxv, &mut |a, b| memory.read(a, b)).unwrap();
+        assert_eq!(modules.len(), 1);
+        assert_eq!(modules[0].dynamic, bias + 0x3000);
+    }
+
+    #[test]
+    fn test_walk32() {
+        let mut memory = Memory(Vec::new());
+        let words = |words: &[u32]| words.iter().flat_map(|w| w.to_ne_bytes()).collect();
+        // PT_PHDR at 0x34 and PT_DYNAMIC at 0x3000, without load bias
+        memory.0.push((
+            0x0804_8034,
+            words(&[
+                6,
+                0x34,
+                0x0804_8034,
+                0x0804_8034,
+                0x40,
+                0x40,
+                4,
+                4,
+                2,
+                0x2000,
+                0x0804_b000,
+                0x0804_b000,
+                0x80,
+                0x80,
+                6,
+                4,
+            ]),
+        ));
+        // DT_DEBUG, r_debug, then the link_map of the program, without name
+        memory
+            .0
+            .push((0x0804_b000, words(&[21, 0xf7f0_0000, 0, 0])));
+        memory.0.push((0xf7f0_0000, words(&[1, 0xf7f0_0100])));
+        memory.0.push((0xf7f0_0100, words(&[0, 0, 0x0804_b000, 0])));
+
+        let auxv = [(elf::AT_PHDR, 0x0804_8034), (elf::AT_PHNUM, 2)];
+        let modules = walk(Class::Elf32, &auxv, &mut |a, b| memory.read(a, b)).unwrap();
+        assert_eq!(
+            modules,
+            [Module {
+                base: 0,
+                dynamic: 0x0804_b000,
+                name: String::new(),
+            }]
+        );
+    }
+}
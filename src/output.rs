@@ -0,0 +1,345 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use crate::compress::Compression;
+use crate::json::Value;
+use crate::manifest::Manifest;
+use crate::memory::Region;
+use crate::pagemap;
+use crate::regs::Registers;
+use crate::{Error, Result};
+
+/// Layout of the dump on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// One file per region in a directory
+    #[default]
+    Directory,
+
+    /// A single ELF core file
+    Core,
+
+    /// A single tar archive, holding the files of the `dir` format
+    Tar,
+
+    /// Page indexes in a directory, each distinct page being stored once
+    Dedup,
+
+    /// A single minidump, as written by Breakpad
+    Minidump,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "dir" | "directory" => Ok(Self::Directory),
+            "core" => Ok(Self::Core),
+            "tar" => Ok(Self::Tar),
+            "dedup" => Ok(Self::Dedup),
+            "minidump" => Ok(Self::Minidump),
+            _ => Err(Error::Usage(format!("Unknown format {:?}", s))),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Directory => f.write_str("dir"),
+            Self::Core => f.write_str("core"),
+            Self::Tar => f.write_str("tar"),
+            Self::Dedup => f.write_str("dedup"),
+            Self::Minidump => f.write_str("minidump"),
+        }
+    }
+}
+
+/// Where and how a region was stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stored {
+    /// Name of the file holding the region, if any
+    pub file: Option<String>,
+
+    /// Number of bytes written, after compression
+    pub size: u64,
+}
+
+/// Writes `data` at the current position of `file`, seeking over zero pages to leave holes
+pub fn write_sparse(file: &mut File, data: &[u8]) -> io::Result<()> {
+    let start = file.stream_position()?;
+    let page_size = pagemap::page_size();
+    let mut offset = 0;
+    while offset < data.len() {
+        let is_hole = |o: usize| {
+            data[o..data.len().min(o + page_size)]
+                .iter()
+                .all(|b| *b == 0)
+        };
+        let hole = is_hole(offset);
+        let mut end = offset;
+        while end < data.len() && is_hole(end) == hole {
+            end = data.len().min(end + page_size);
+        }
+        if hole {
+            file.seek(SeekFrom::Current((end - offset) as i64))?;
+        } else {
+            file.write_all(&data[offset..end])?;
+        }
+        offset = end;
+    }
+    // Trailing holes do not extend the file by themselves
+    let end = start + data.len() as u64;
+    if file.metadata()?.len() < end {
+        file.set_len(end)?;
+    }
+    Ok(())
+}
+
+/// Writer leaving holes in a file for zero pages, see [`write_sparse`]
+///
+/// Writes must start on page boundaries for holes to be found.
+#[derive(Debug)]
+pub struct Sparse<'a>(pub &'a mut File);
+
+impl Write for Sparse<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        write_sparse(self.0, buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Alignment of the regions in a [`SingleFile`]
+const REGION_ALIGN: u64 = 4096;
+
+/// File holding every region one after the other, on page boundaries after headers written last
+///
+/// Core files and minidumps are made this way.
+#[derive(Debug)]
+pub struct SingleFile {
+    file: File,
+
+    /// Offset of the first region, the headers being written before
+    start: u64,
+
+    /// Offset of the next region
+    next_offset: u64,
+
+    regions: usize,
+    max_regions: usize,
+}
+
+impl SingleFile {
+    /// Creates `path`, able to hold up to `max_regions` regions after `headers` bytes of headers
+    pub fn create(path: &Path, headers: u64, max_regions: usize) -> Result<Self> {
+        let start = headers.next_multiple_of(REGION_ALIGN);
+        Ok(Self {
+            file: File::create(path)?,
+            start,
+            next_offset: start,
+            regions: 0,
+            max_regions,
+        })
+    }
+
+    /// Offset of the end of the last region
+    pub fn end(&self) -> u64 {
+        self.next_offset
+    }
+
+    /// Writes a region with `source`, returning its offset and size
+    ///
+    /// Nothing is kept if `source` fails: the next region is written at the same offset.
+    pub fn append(&mut self, source: &mut Source) -> Result<(u64, u64)> {
+        assert!(
+            self.regions < self.max_regions,
+            "File was created for {} regions only",
+            self.max_regions
+        );
+        let offset = self.next_offset;
+        self.file.seek(SeekFrom::Start(offset))?;
+        if let Err(e) = source(&mut Sparse(&mut self.file)) {
+            self.file.set_len(offset)?;
+            return Err(e);
+        }
+        let size = self.file.stream_position()? - offset;
+        self.regions += 1;
+        self.next_offset = (offset + size).next_multiple_of(REGION_ALIGN);
+        Ok((offset, size))
+    }
+
+    /// Writes `headers` at the start of the file, and flushes it
+    pub fn finish(mut self, headers: &[u8]) -> Result<()> {
+        assert!(
+            headers.len() as u64 <= self.start,
+            "Headers do not fit before the regions"
+        );
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(headers)?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// Name of the copy of the mapped file `path` in a dump, `files/` followed by its absolute path
+///
+/// Returns `None` if `path` is not absolute, or goes up with `..`: it could be written outside
+/// of `files/`.
+pub fn mapped_file_name(path: &str) -> Option<String> {
+    let relative = path.strip_prefix('/')?;
+    let mut components = Path::new(relative).components();
+    components
+        .all(|c| matches!(c, Component::Normal(_)))
+        .then(|| format!("files/{}", relative))
+}
+
+/// Streams the content of a region to the given sink
+pub type Source<'a> = dyn FnMut(&mut dyn Write) -> Result<()> + 'a;
+
+/// Destination of dumped regions
+pub trait Output {
+    /// Stores the content of `region` written by `source`, nothing is kept if it fails
+    ///
+    /// `name` is used by outputs with one file per region.
+    fn write_region(&mut self, region: &Region, name: &str, source: &mut Source) -> Result<Stored>;
+
+    /// Removes the file `name` of a region stored earlier
+    ///
+    /// Only outputs with one file per region support it.
+    fn discard(&mut self, _name: &str) -> Result<()> {
+        Err(io::Error::other("regions cannot be removed from this output").into())
+    }
+
+    /// Writes the manifest and flushes everything to disk
+    fn finish(self: Box<Self>, manifest: &Manifest) -> Result<()>;
+}
+
+/// Writes each region in its own file
+#[derive(Debug, Clone)]
+pub struct Directory {
+    path: PathBuf,
+    compression: Compression,
+}
+
+impl Directory {
+    pub fn create(path: &Path, compression: Compression) -> Result<Self> {
+        std::fs::create_dir_all(path)?;
+        Ok(Self {
+            path: path.into(),
+            compression,
+        })
+    }
+
+    /// Writes the registers of each thread to `registers/<tid>.json`
+    pub fn write_registers(&self, threads: &[Registers]) -> Result<()> {
+        let dir = self.path.join("registers");
+        std::fs::create_dir_all(&dir)?;
+        for regs in threads {
+            let mut json = regs.to_json().to_pretty_string();
+            json.push('\n');
+            std::fs::write(dir.join(format!("{}.json", regs.tid)), json)?;
+        }
+        Ok(())
+    }
+
+    /// Writes files of `/proc/PID` to `proc/<name>`
+    pub fn write_proc(&self, files: &[(&str, Vec<u8>)]) -> Result<()> {
+        let dir = self.path.join("proc");
+        std::fs::create_dir_all(&dir)?;
+        for (name, data) in files {
+            std::fs::write(dir.join(name), data)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the call stacks of the threads to `backtraces.txt`
+    pub fn write_backtraces(&self, text: &str) -> Result<()> {
+        std::fs::write(self.path.join("backtraces.txt"), text)?;
+        Ok(())
+    }
+
+    /// Writes the file descriptors of the process to `fds.json`
+    pub fn write_fds(&self, fds: &Value) -> Result<()> {
+        let mut json = fds.to_pretty_string();
+        json.push('\n');
+        std::fs::write(self.path.join("fds.json"), json)?;
+        Ok(())
+    }
+
+    /// Copies `file` to `name`, as returned by [`mapped_file_name`], returning its size
+    pub fn write_mapped_file(&self, name: &str, file: &mut File) -> Result<u64> {
+        let path = self.path.join(name);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        Ok(io::copy(file, &mut File::create(path)?)?)
+    }
+}
+
+impl Output for Directory {
+    fn write_region(
+        &mut self,
+        _region: &Region,
+        name: &str,
+        source: &mut Source,
+    ) -> Result<Stored> {
+        let filename = format!("{}{}", name, self.compression.extension());
+        let path = self.path.join(&filename);
+        let mut file = File::create(&path)?;
+        let result = if self.compression == Compression::None {
+            source(&mut Sparse(&mut file))
+        } else {
+            self.compression
+                .encoder(BufWriter::new(file))
+                .map_err(Error::from)
+                .and_then(|mut encoder| {
+                    source(&mut encoder)?;
+                    Ok(encoder.finish()?)
+                })
+        };
+        if let Err(e) = result {
+            // Best effort, the region failure matters more
+            let _ = std::fs::remove_file(&path);
+            return Err(e);
+        }
+        Ok(Stored {
+            file: Some(filename),
+            size: std::fs::metadata(&path)?.len(),
+        })
+    }
+
+    fn discard(&mut self, name: &str) -> Result<()> {
+        Ok(std::fs::remove_file(self.path.join(name))?)
+    }
+
+    fn finish(self: Box<Self>, manifest: &Manifest) -> Result<()> {
+        manifest.write(&self.path.join("manifest.json"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mapped_file_name() {
+        assert_eq!(
+            mapped_file_name("/usr/lib/libc.so.6").as_deref(),
+            Some("files/usr/lib/libc.so.6")
+        );
+        assert_eq!(
+            mapped_file_name("/memfd:jit").as_deref(),
+            Some("files/memfd:jit")
+        );
+        assert_eq!(mapped_file_name("/memfd:../../etc/passwd"), None);
+        assert_eq!(mapped_file_name("lib.so"), None);
+    }
+}
@@ -0,0 +1,117 @@
+//! Page presence, from `/proc/PID/pagemap`
+//!
+//! Pages of private anonymous mappings which were never touched are neither present nor swapped,
+//! and read as zeros: there is no need to read them, nor to store them.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+
+use crate::memory::{PermissionBits, Region, RegionKind};
+use crate::Result;
+
+const PM_PRESENT: u64 = 1 << 63;
+const PM_SWAP: u64 = 1 << 62;
+
+const _SC_PAGESIZE: i32 = 30;
+
+/// Number of entries read at once
+const BATCH: usize = 64 * 1024;
+
+extern "C" {
+    fn sysconf(name: i32) -> i64;
+}
+
+pub fn page_size() -> usize {
+    match unsafe { sysconf(_SC_PAGESIZE) } {
+        size if size > 0 => size as usize,
+        _ => 4096,
+    }
+}
+
+/// Returns true if missing pages of `region` are known to be zeros
+pub fn is_sparse_candidate(region: &Region) -> bool {
+    region.is_anonymous()
+        && region.perms.has_perm(PermissionBits::Private)
+        && !matches!(
+            region.kind,
+            RegionKind::Vvar | RegionKind::Vdso | RegionKind::Vsyscall
+        )
+}
+
+/// Merges the pages holding data into address ranges
+fn ranges_from_entries(
+    start: usize,
+    page_size: usize,
+    entries: &[u64],
+    ranges: &mut Vec<Range<usize>>,
+) {
+    for (i, entry) in entries.iter().enumerate() {
+        if entry & (PM_PRESENT | PM_SWAP) == 0 {
+            continue;
+        }
+        let page = start + i * page_size;
+        match ranges.last_mut() {
+            Some(last) if last.end == page => last.end += page_size,
+            _ => ranges.push(page..page + page_size),
+        }
+    }
+}
+
+/// Returns the ranges of `region` backed by present or swapped pages
+pub fn resident_ranges(pid: u32, region: &Region) -> Result<Vec<Range<usize>>> {
+    let page_size = page_size();
+    let mut pagemap = File::open(format!("/proc/{}/pagemap", pid))?;
+    pagemap.seek(SeekFrom::Start((region.start / page_size * 8) as u64))?;
+
+    let mut ranges = Vec::new();
+    let mut raw = vec![0u8; BATCH * 8];
+    let mut entries = Vec::with_capacity(BATCH);
+    let mut address = region.start;
+    while address < region.end {
+        let count = ((region.end - address) / page_size).min(BATCH);
+        pagemap.read_exact(&mut raw[..count * 8])?;
+        entries.clear();
+        entries.extend(
+            raw[..count * 8]
+                .chunks_exact(8)
+                .map(|e| u64::from_ne_bytes(e.try_into().unwrap())),
+        );
+        ranges_from_entries(address, page_size, &entries[..], &mut ranges);
+        address += count * page_size;
+    }
+    Ok(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ranges_from_entries() {
+        let mut ranges = Vec::new();
+        ranges_from_entries(
+            0x1000,
+            0x1000,
+            &[PM_PRESENT | 42, PM_SWAP, 0, 0, PM_PRESENT],
+            &mut ranges,
+        );
+        assert_eq!(ranges, vec![0x1000..0x3000, 0x5000..0x6000]);
+
+        // A second batch continues the last range
+        ranges_from_entries(0x6000, 0x1000, &[PM_PRESENT, 0], &mut ranges);
+        assert_eq!(ranges, vec![0x1000..0x3000, 0x5000..0x7000]);
+    }
+
+    #[test]
+    fn test_sparse_candidate() {
+        let heap: Region = "1000-2000 rw-p 00000000 00:00 0 [heap]".parse().unwrap();
+        let shared: Region = "1000-2000 rw-s 00000000 00:01 7 /dev/zero".parse().unwrap();
+        let file: Region = "1000-2000 rw-p 00000000 fe:01 42 /usr/lib/libc.so.6"
+            .parse()
+            .unwrap();
+        assert!(is_sparse_candidate(&heap));
+        assert!(!is_sparse_candidate(&shared));
+        assert!(!is_sparse_candidate(&file));
+    }
+}
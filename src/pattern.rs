@@ -0,0 +1,158 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::regex::Regex;
+use crate::{Error, Result};
+
+/// Pattern matched against a region backing path
+///
+/// Patterns prefixed with `re:` are regular expressions searched in the whole path. Other
+/// patterns are shell globs (`*`, `?`, `[...]`) matched against the whole path if they contain a
+/// `/`, against the file name otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pattern {
+    Glob(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    pub fn is_match(&self, path: &str) -> bool {
+        match self {
+            Self::Glob(glob) => {
+                let subject = if glob.contains('/') {
+                    path
+                } else {
+                    Path::new(path)
+                        .file_name()
+                        .and_then(|f| f.to_str())
+                        .unwrap_or(path)
+                };
+                glob_match(glob.as_bytes(), subject.as_bytes())
+            }
+            Self::Regex(re) => re.is_match(path.as_bytes()),
+        }
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(re) = s.strip_prefix("re:") {
+            Ok(Self::Regex(re.parse()?))
+        } else {
+            Ok(Self::Glob(s.into()))
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Glob(glob) => f.write_str(glob),
+            Self::Regex(re) => write!(f, "re:{}", re.as_str()),
+        }
+    }
+}
+
+/// Returns the length of the `[...]` class at the start of `glob` and whether `c` is in it
+fn glob_class(glob: &[u8], c: u8) -> Option<(usize, bool)> {
+    let mut i = 1;
+    let negate = matches!(glob.get(i), Some(b'!') | Some(b'^'));
+    if negate {
+        i += 1;
+    }
+    let mut found = false;
+    let mut first = true;
+    loop {
+        let low = *glob.get(i)?;
+        if low == b']' && !first {
+            return Some((i + 1, found != negate));
+        }
+        first = false;
+        if glob.get(i + 1) == Some(&b'-') && glob.get(i + 2).is_some_and(|h| *h != b']') {
+            let high = glob[i + 2];
+            found |= low <= c && c <= high;
+            i += 3;
+        } else {
+            found |= low == c;
+            i += 1;
+        }
+    }
+}
+
+/// Shell-like glob matching
+pub fn glob_match(glob: &[u8], s: &[u8]) -> bool {
+    let (mut g, mut i) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while i < s.len() {
+        match glob.get(g) {
+            Some(b'*') => {
+                backtrack = Some((g, i));
+                g += 1;
+                continue;
+            }
+            Some(b'?') => {
+                g += 1;
+                i += 1;
+                continue;
+            }
+            Some(b'[') => {
+                if let Some((len, true)) = glob_class(&glob[g..], s[i]) {
+                    g += len;
+                    i += 1;
+                    continue;
+                }
+            }
+            Some(b'\\') if glob.get(g + 1) == Some(&s[i]) => {
+                g += 2;
+                i += 1;
+                continue;
+            }
+            Some(c) if *c == s[i] => {
+                g += 1;
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+        match backtrack {
+            Some((star, pos)) => {
+                g = star + 1;
+                i = pos + 1;
+                backtrack = Some((star, pos + 1));
+            }
+            None => return false,
+        }
+    }
+
+    glob[g..].iter().all(|c| *c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob() {
+        assert!(glob_match(b"libssl*", b"libssl.so.3"));
+        assert!(!glob_match(b"libssl*", b"libcrypto.so.3"));
+        assert!(glob_match(b"lib?.so.[0-9]", b"libc.so.6"));
+        assert!(glob_match(b"*[!0-9]", b"abc"));
+        assert!(!glob_match(b"*[!0-9]", b"ab1"));
+        assert!(glob_match(b"*", b""));
+    }
+
+    #[test]
+    fn test_pattern() {
+        let p: Pattern = "libssl*".parse().unwrap();
+        assert!(p.is_match("/usr/lib/libssl.so.3"));
+        let p: Pattern = "/usr/lib/*".parse().unwrap();
+        assert!(p.is_match("/usr/lib/libssl.so.3"));
+        assert!(!p.is_match("/opt/lib/libssl.so.3"));
+        let p: Pattern = r"re:ssl|crypto".parse().unwrap();
+        assert!(p.is_match("/usr/lib/libcrypto.so.3"));
+    }
+}
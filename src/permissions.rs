@@ -0,0 +1,137 @@
+//! Pre-flight checks of the permission to trace a process
+//!
+//! The kernel only answers EPERM. The checks below find the usual reasons, so that the error
+//! tells how to get the permission.
+
+use crate::procfs::{self, Status};
+use crate::{Error, Result};
+
+const CAP_SYS_PTRACE: u32 = 19;
+const EPERM: i32 = 1;
+
+const HOW_TO_CAP: &str = "run as root, or `setcap cap_sys_ptrace+ep` the dump-memory binary";
+
+/// Yama `ptrace_scope`, if the module is enabled
+fn ptrace_scope() -> Option<u32> {
+    std::fs::read_to_string("/proc/sys/kernel/yama/ptrace_scope")
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+fn has_cap_sys_ptrace(status: &Status) -> bool {
+    status.cap_eff & (1 << CAP_SYS_PTRACE) != 0
+}
+
+/// Returns why `me` certainly cannot trace `target`, if so
+fn denial(scope: Option<u32>, me: &Status, my_pid: u32, target: &Status) -> Option<String> {
+    let capable = has_cap_sys_ptrace(me);
+    match scope {
+        Some(3) => {
+            return Some(
+                "kernel.yama.ptrace_scope is 3, which forbids ptrace until the next reboot".into(),
+            )
+        }
+        Some(2) if !capable => {
+            return Some(format!(
+                "kernel.yama.ptrace_scope is 2, only processes with CAP_SYS_PTRACE may trace: {}",
+                HOW_TO_CAP
+            ))
+        }
+        _ => {}
+    }
+    if target.tracer_pid != 0 && target.tracer_pid != my_pid {
+        return Some(format!(
+            "the process is already traced by process {} (a debugger?), detach it first",
+            target.tracer_pid
+        ));
+    }
+    // Without the capability, the real ids must match every id of the target
+    let (uid, gid) = (me.uid[0], me.gid[0]);
+    if !capable && (target.uid[..3] != [uid; 3] || target.gid[..3] != [gid; 3]) {
+        return Some(format!(
+            "the process runs as uid {} and gid {}, dump-memory as uid {} and gid {} without \
+             CAP_SYS_PTRACE: run as its user, {}",
+            target.uid[1], target.gid[1], uid, gid, HOW_TO_CAP
+        ));
+    }
+    None
+}
+
+fn permission_denied(pid: u32, reason: String) -> Error {
+    Error::PermissionDenied { pid, reason }
+}
+
+/// Fails with an explanation if tracing `pid` is certainly not allowed
+pub fn check(pid: u32) -> Result<()> {
+    let target = procfs::status(pid)?;
+    let me = procfs::status("self")?;
+    match denial(ptrace_scope(), &me, std::process::id(), &target) {
+        Some(reason) => Err(permission_denied(pid, reason)),
+        None => Ok(()),
+    }
+}
+
+/// Explains an EPERM returned when attaching to `pid`, other errors are returned as is
+pub fn explain(pid: u32, error: Error) -> Error {
+    let Error::Attach { ref source, .. } = error else {
+        return error;
+    };
+    if source.raw_os_error() != Some(EPERM) {
+        return error;
+    }
+    let capable = procfs::status("self").is_ok_and(|me| has_cap_sys_ptrace(&me));
+    let reason = if ptrace_scope() == Some(1) && !capable {
+        format!(
+            "kernel.yama.ptrace_scope is 1, only ancestors of the process may trace it: {}, or \
+             lower the scope with `sysctl kernel.yama.ptrace_scope=0`",
+            HOW_TO_CAP
+        )
+    } else {
+        "the process may not be dumpable (setuid programs, or PR_SET_DUMPABLE): run as root".into()
+    };
+    permission_denied(pid, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_denial() {
+        let user = |id| Status {
+            uid: [id; 4],
+            gid: [id; 4],
+            cap_eff: 0,
+            tracer_pid: 0,
+        };
+        let root = Status {
+            cap_eff: 1 << CAP_SYS_PTRACE,
+            ..user(0)
+        };
+        let alice = user(1000);
+        let bob = user(1001);
+
+        assert_eq!(denial(Some(1), &alice, 1, &alice), None);
+        assert_eq!(denial(None, &root, 1, &bob), None);
+        assert_eq!(denial(Some(2), &root, 1, &bob), None);
+        assert!(denial(Some(2), &alice, 1, &alice).is_some());
+        assert!(denial(Some(3), &root, 1, &bob).is_some());
+        assert!(denial(Some(0), &alice, 1, &bob).is_some());
+
+        // A setuid program keeps the real id of its caller
+        let setuid = Status {
+            uid: [1000, 0, 0, 0],
+            ..alice.clone()
+        };
+        assert!(denial(Some(0), &alice, 1, &setuid).is_some());
+
+        let traced = Status {
+            tracer_pid: 42,
+            ..alice.clone()
+        };
+        assert!(denial(Some(0), &root, 1, &traced).is_some());
+        assert_eq!(denial(Some(0), &root, 42, &traced), None);
+    }
+}
@@ -0,0 +1,374 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::fs::MetadataExt;
+
+use crate::elf::Class;
+use crate::memory::Region;
+use crate::{Error, Result};
+
+/// Subset of `/proc/PID/stat`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stat {
+    pub pid: u32,
+    pub comm: String,
+    pub state: char,
+    pub ppid: u32,
+    pub pgrp: u32,
+    pub session: u32,
+    /// Saved stack pointer, only filled by recent kernels while dumping core
+    pub kstkesp: u64,
+}
+
+impl std::str::FromStr for Stat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        // `comm` may contain spaces and parenthesis, so split around the last ')'
+        let (pid, rest) = s.split_once(" (").ok_or(Error::MalformedProcField {
+            field: "stat",
+            value: s.into(),
+        })?;
+        let (comm, rest) = rest.rsplit_once(") ").ok_or(Error::MalformedProcField {
+            field: "stat",
+            value: s.into(),
+        })?;
+        let mut fields = rest.split_ascii_whitespace();
+        let mut next = |field: &'static str| {
+            fields.next().ok_or(Error::MalformedProcField {
+                field,
+                value: s.into(),
+            })
+        };
+
+        let state = next("state")?.chars().next().unwrap_or('?');
+        let ppid = next("ppid")?.parse()?;
+        let pgrp = next("pgrp")?.parse()?;
+        let session = next("session")?.parse()?;
+        let kstkesp = fields
+            .nth(22)
+            .ok_or(Error::MalformedProcField {
+                field: "kstkesp",
+                value: s.into(),
+            })?
+            .parse()?;
+
+        Ok(Self {
+            pid: pid.parse()?,
+            comm: comm.into(),
+            state,
+            ppid,
+            pgrp,
+            session,
+            kstkesp,
+        })
+    }
+}
+
+/// Subset of `/proc/PID/status`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Status {
+    /// Real, effective, saved and filesystem user ids
+    pub uid: [u32; 4],
+
+    /// Real, effective, saved and filesystem group ids
+    pub gid: [u32; 4],
+
+    /// Effective capabilities, as a bit mask
+    pub cap_eff: u64,
+
+    /// Process tracing this one, 0 if none
+    pub tracer_pid: u32,
+}
+
+impl std::str::FromStr for Status {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let malformed = |field: &'static str, value: &str| Error::MalformedProcField {
+            field,
+            value: value.into(),
+        };
+        let ids = |field: &'static str, value: &str| -> Result<[u32; 4]> {
+            let ids = value
+                .split_ascii_whitespace()
+                .map(str::parse)
+                .collect::<std::result::Result<Vec<u32>, _>>()?;
+            ids.try_into().map_err(|_| malformed(field, value))
+        };
+
+        let mut status = Self::default();
+        for line in s.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match key {
+                "Uid" => status.uid = ids("Uid", value)?,
+                "Gid" => status.gid = ids("Gid", value)?,
+                "CapEff" => {
+                    status.cap_eff =
+                        u64::from_str_radix(value, 16).map_err(|_| malformed("CapEff", value))?
+                }
+                "TracerPid" => status.tracer_pid = value.parse()?,
+                _ => {}
+            }
+        }
+        Ok(status)
+    }
+}
+
+/// Returns the `status` of `pid`, or of the current process with `"self"`
+pub fn status(pid: impl std::fmt::Display) -> Result<Status> {
+    std::fs::read_to_string(format!("/proc/{}/status", pid))?.parse()
+}
+
+pub fn stat(pid: u32) -> Result<Stat> {
+    std::fs::read_to_string(format!("/proc/{}/stat", pid))?.parse()
+}
+
+/// Returns the `stat` of thread `tid` of `pid`
+pub fn task_stat(pid: u32, tid: u32) -> Result<Stat> {
+    std::fs::read_to_string(format!("/proc/{}/task/{}/stat", pid, tid))?.parse()
+}
+
+/// Returns the command line with arguments separated by spaces
+pub fn cmdline(pid: u32) -> Result<String> {
+    let raw = std::fs::read(format!("/proc/{}/cmdline", pid))?;
+    let args: Vec<_> = raw
+        .split(|b| *b == 0)
+        .filter(|a| !a.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect();
+    Ok(args.join(" "))
+}
+
+/// Returns the class of the program of `pid`, from the ELF header of its executable
+///
+/// A 32-bit program running on a 64-bit kernel has 4-byte pointers and registers.
+pub fn elf_class(pid: u32) -> Result<Class> {
+    let mut ident = [0; 5];
+    File::open(format!("/proc/{}/exe", pid))?.read_exact(&mut ident)?;
+    Class::parse(&ident)
+}
+
+/// Types of the namespaces of `/proc/PID/ns`
+const NAMESPACES: [&str; 8] = ["cgroup", "ipc", "mnt", "net", "pid", "time", "user", "uts"];
+
+/// Parses the target of a `/proc/PID/ns` link, `mnt:[4026531841]`
+fn parse_namespace(link: &str) -> Option<u64> {
+    link.split_once(":[")?.1.strip_suffix(']')?.parse().ok()
+}
+
+/// Returns the namespaces of `pid`, by type, as their inode number
+///
+/// Namespaces which the kernel does not have, or which cannot be read, are skipped.
+pub fn namespaces(pid: u32) -> Vec<(String, u64)> {
+    NAMESPACES
+        .iter()
+        .filter_map(|&ns| {
+            let link = std::fs::read_link(format!("/proc/{}/ns/{}", pid, ns)).ok()?;
+            Some((ns.to_owned(), parse_namespace(link.to_str()?)?))
+        })
+        .collect()
+}
+
+/// Opens the file mapped by `region` of the running process `pid`
+///
+/// Paths in the maps are those of the mount namespace of the process, which is not ours for
+/// containers. `/proc/PID/map_files/START-END` is the mapped file itself, even if it was deleted
+/// or replaced since, but needs `CAP_SYS_ADMIN`. Otherwise the path is opened through
+/// `/proc/PID/root`, and must still be the mapped inode.
+pub fn open_mapped_file(pid: u32, region: &Region) -> Result<File> {
+    let map_file = format!(
+        "/proc/{}/map_files/{:x}-{:x}",
+        pid, region.start, region.end
+    );
+    if let Ok(file) = File::open(map_file) {
+        return Ok(file);
+    }
+    let path = region
+        .path()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "region maps no file"))?;
+    if region.deleted {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "file was deleted").into());
+    }
+    let file = File::open(format!("/proc/{}/root{}", pid, path))?;
+    if file.metadata()?.ino() != region.inode {
+        return Err(io::Error::other("file was replaced since it was mapped").into());
+    }
+    Ok(file)
+}
+
+/// Files of `/proc/PID` saved with the dumps by `--with-proc`
+const METADATA_FILES: [&str; 6] = ["cmdline", "environ", "status", "stat", "auxv", "limits"];
+
+/// Lists the file descriptors of `pid` and their targets, one `FD -> TARGET` per line
+fn fd_listing(pid: impl std::fmt::Display) -> Result<String> {
+    let mut fds = Vec::new();
+    for entry in std::fs::read_dir(format!("/proc/{}/fd", pid))? {
+        let entry = entry?;
+        let Some(fd) = entry
+            .file_name()
+            .to_str()
+            .and_then(|n| n.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        // The descriptor may be closed in the meantime
+        if let Ok(target) = std::fs::read_link(entry.path()) {
+            fds.push((fd, target));
+        }
+    }
+    fds.sort_unstable();
+    Ok(fds
+        .iter()
+        .map(|(fd, target)| format!("{} -> {}\n", fd, target.display()))
+        .collect())
+}
+
+/// Returns the content of the metadata files of `pid`, and the listing of its descriptors as `fd`
+///
+/// Files which cannot be read, like `environ` of a process owned by someone else, are skipped
+/// with a warning.
+pub fn metadata(pid: impl std::fmt::Display) -> Vec<(&'static str, Vec<u8>)> {
+    let mut files = Vec::new();
+    for name in METADATA_FILES {
+        match std::fs::read(format!("/proc/{}/{}", pid, name)) {
+            Ok(data) => files.push((name, data)),
+            Err(e) => warn!("Could not read /proc/{}/{}: {}", pid, name, e),
+        }
+    }
+    match fd_listing(&pid) {
+        Ok(listing) => files.push(("fd", listing.into_bytes())),
+        Err(e) => warn!("Could not list /proc/{}/fd: {}", pid, e),
+    }
+    files
+}
+
+/// Returns the thread ids of `pid`, sorted
+pub fn tasks(pid: u32) -> Result<Vec<u32>> {
+    let mut tids = Vec::new();
+    for entry in std::fs::read_dir(format!("/proc/{}/task", pid))? {
+        let name = entry?.file_name();
+        if let Some(tid) = name.to_str().and_then(|n| n.parse().ok()) {
+            tids.push(tid);
+        }
+    }
+    tids.sort_unstable();
+    Ok(tids)
+}
+
+/// Returns the direct children of `pid`, sorted
+pub fn children(pid: u32) -> Result<Vec<u32>> {
+    let mut children = Vec::new();
+    for tid in tasks(pid)? {
+        match std::fs::read_to_string(format!("/proc/{}/task/{}/children", pid, tid)) {
+            Ok(list) => children.extend(
+                list.split_ascii_whitespace()
+                    .filter_map(|child| child.parse::<u32>().ok()),
+            ),
+            // Without CONFIG_PROC_CHILDREN, look for processes whose parent is `pid`
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return children_from_stat(pid),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    children.sort_unstable();
+    Ok(children)
+}
+
+fn children_from_stat(pid: u32) -> Result<Vec<u32>> {
+    let mut children = Vec::new();
+    for entry in std::fs::read_dir("/proc")? {
+        let name = entry?.file_name();
+        let Some(child) = name.to_str().and_then(|n| n.parse().ok()) else {
+            continue;
+        };
+        // Processes may exit while iterating
+        if stat(child).is_ok_and(|s| s.ppid == pid) {
+            children.push(child);
+        }
+    }
+    children.sort_unstable();
+    Ok(children)
+}
+
+/// Returns every descendant of `pid`, parents before their children
+pub fn descendants(pid: u32) -> Result<Vec<u32>> {
+    let mut descendants = children(pid)?;
+    let mut i = 0;
+    while i < descendants.len() {
+        // A process may exit while walking the tree
+        if let Ok(children) = children(descendants[i]) {
+            descendants.extend(children);
+        }
+        i += 1;
+    }
+    Ok(descendants)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stat() {
+        let stat: Stat = "1234 (my (weird) prog) S 1 1234 1234 34816 1234 4194304 110 0 0 0 \
+                          0 0 0 0 20 0 1 0 108476 2703360 273 18446744073709551615 \
+                          94343059267584 94343059287465 140720608823504 140720608822000 0 0 0"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            stat,
+            Stat {
+                pid: 1234,
+                comm: "my (weird) prog".into(),
+                state: 'S',
+                ppid: 1,
+                pgrp: 1234,
+                session: 1234,
+                kstkesp: 140720608822000,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_status() {
+        let status: Status = "Name:\tcat\nUid:\t1000\t1000\t1000\t1000\n\
+                              Gid:\t100\t100\t100\t100\nTracerPid:\t42\n\
+                              CapEff:\t000001ffffffffff\n"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            status,
+            Status {
+                uid: [1000; 4],
+                gid: [100; 4],
+                cap_eff: 0x1ffffffffff,
+                tracer_pid: 42,
+            }
+        );
+        assert!("Uid:\t1000\t1000\n".parse::<Status>().is_err());
+    }
+
+    #[test]
+    fn test_metadata() {
+        let files = metadata("self");
+        let names: Vec<_> = files.iter().map(|(name, _)| *name).collect();
+        assert_eq!(
+            names,
+            ["cmdline", "environ", "status", "stat", "auxv", "limits", "fd"]
+        );
+        let (_, fd) = files.last().unwrap();
+        assert!(String::from_utf8_lossy(fd)
+            .lines()
+            .any(|l| l.starts_with("0 -> ")));
+    }
+
+    #[test]
+    fn test_namespaces() {
+        assert_eq!(parse_namespace("mnt:[4026531841]"), Some(4026531841));
+        assert_eq!(parse_namespace("/tmp/mnt"), None);
+        let namespaces = namespaces(std::process::id());
+        assert!(namespaces.iter().any(|(ns, _)| ns == "mnt"));
+    }
+}
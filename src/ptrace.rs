@@ -1,18 +1,126 @@
-use std::fs::File;
-use std::io::{self, Read, Seek, SeekFrom};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+#[cfg(feature = "uring")]
+use std::os::fd::AsRawFd;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
-use crate::memory::Region;
+use crate::cgroup::Freezer;
+use crate::elf::Class;
+use crate::error::Chain;
+use crate::permissions;
+use crate::procfs;
+#[cfg(target_arch = "x86_64")]
+use crate::regs::UserRegs32;
+use crate::regs::{Registers, UserFpRegs, UserRegs};
+#[cfg(feature = "uring")]
+use crate::uring::Uring;
+use crate::worker::ReadWorker;
 use crate::{Error, Result};
 
+/// How the process is stopped while it is read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Attach {
+    /// `PTRACE_ATTACH` every thread
+    #[default]
+    Stop,
+
+    /// `PTRACE_SEIZE` and `PTRACE_INTERRUPT` every thread, no `SIGSTOP` is sent
+    Seize,
+
+    /// Do not attach, memory is read while the process runs
+    NoStop,
+
+    /// Do not attach, freeze the cgroup v2 of the process while it is read
+    Freeze,
+}
+
+/// How memory is read from `/proc/PID/mem`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// One `read` at a time
+    #[default]
+    Read,
+
+    /// Batches of reads submitted to io_uring, which the kernel does in parallel
+    #[cfg(feature = "uring")]
+    Uring,
+}
+
+impl FromStr for Backend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "read" => Ok(Self::Read),
+            #[cfg(feature = "uring")]
+            "uring" | "io_uring" => Ok(Self::Uring),
+            #[cfg(not(feature = "uring"))]
+            "uring" | "io_uring" => Err(Error::Usage(
+                "the uring backend needs dump-memory to be built with the `uring` feature".into(),
+            )),
+            _ => Err(Error::Usage(format!("Unknown backend {:?}", s))),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read => f.write_str("read"),
+            #[cfg(feature = "uring")]
+            Self::Uring => f.write_str("uring"),
+        }
+    }
+}
+
+/// Reads submitted at once with io_uring
+#[cfg(feature = "uring")]
+const URING_ENTRIES: u32 = 16;
+
 #[derive(Debug)]
 pub struct Ptrace {
     pid: u32,
+    /// Attached threads, the main thread first, empty if not attached
+    threads: Vec<u32>,
     mem: Option<File>,
+    mem_rw: Option<File>,
+    class: Class,
+    /// Whether pages which cannot be read through `/proc/PID/mem` are read with `PTRACE_PEEKDATA`
+    peek_fallback: bool,
+    /// Reads are given up after this deadline, and done by `worker` until then
+    deadline: Option<Instant>,
+    worker: Option<ReadWorker>,
+    /// When the process was stopped, `None` if it was not
+    stopped_at: Option<Instant>,
+    /// Set up by `Backend::Uring`
+    #[cfg(feature = "uring")]
+    uring: Option<Uring>,
+    /// Frozen cgroup with [`Attach::Freeze`], thawed when dropped
+    _freezer: Option<Freezer>,
+    mode: Attach,
+    /// Whether the process was resumed while still attached, see [`Ptrace::resume`]
+    running: bool,
+    /// Time the process was stopped for before it was last resumed
+    paused: Duration,
+    /// Children forked while followed, attached by the kernel and not adopted yet
+    forked: Vec<u32>,
 }
 
 extern "C" {
     fn ptrace(req: i32, pid: u32, addr: usize, data: usize) -> usize;
+    fn waitpid(pid: u32, status: *mut i32, options: i32) -> i32;
+    fn process_vm_readv(
+        pid: u32,
+        local_iov: *const IoVec,
+        liovcnt: usize,
+        remote_iov: *const IoVec,
+        riovcnt: usize,
+        flags: usize,
+    ) -> isize;
     fn __errno_location() -> *mut i32;
+    fn tgkill(tgid: u32, tid: u32, sig: i32) -> i32;
 }
 
 fn ptrace_errno() -> Result<()> {
@@ -25,6 +133,8 @@ fn ptrace_errno() -> Result<()> {
 }
 
 fn ptrace_wrapper(req: i32, pid: u32, addr: usize, data: usize) -> Result<usize> {
+    // PTRACE_PEEK* requests can legitimately return -1, errno is the only way to tell
+    unsafe { *__errno_location() = 0 };
     let ret = unsafe { ptrace(req, pid, addr, data) };
 
     if ret == usize::MAX {
@@ -33,13 +143,478 @@ fn ptrace_wrapper(req: i32, pid: u32, addr: usize, data: usize) -> Result<usize>
     Ok(ret)
 }
 
+fn waitpid_wrapper(pid: u32) -> Result<i32> {
+    let mut status = 0;
+    if unsafe { waitpid(pid, &mut status, __WALL) } == -1 {
+        return Err(Error::Wait {
+            pid,
+            source: io::Error::last_os_error(),
+        });
+    }
+    Ok(status)
+}
+
+/// Waits for a change of state of the traced thread `tid`, `None` if there is none yet with
+/// `nohang`
+fn try_waitpid(tid: u32, nohang: bool) -> Result<Option<i32>> {
+    let mut status = 0;
+    let options = if nohang { __WALL | WNOHANG } else { __WALL };
+    match unsafe { waitpid(tid, &mut status, options) } {
+        -1 => Err(Error::Wait {
+            pid: tid,
+            source: io::Error::last_os_error(),
+        }),
+        0 => Ok(None),
+        _ => Ok(Some(status)),
+    }
+}
+
+/// State of a traced thread, as reported by `waitpid`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ThreadState {
+    /// Exited, or killed by a signal
+    Exited,
+
+    /// In a ptrace-stop, on `signal` or on a `PTRACE_EVENT_*` event
+    Stopped { signal: i32, event: i32 },
+}
+
+impl ThreadState {
+    fn from_status(status: i32) -> Self {
+        if status & 0xff == 0x7f {
+            Self::Stopped {
+                signal: (status >> 8) & 0xff,
+                event: (status >> 16) & 0xffff,
+            }
+        } else {
+            Self::Exited
+        }
+    }
+}
+
+const PTRACE_PEEKDATA: i32 = 2;
+const PTRACE_POKEDATA: i32 = 5;
+const PTRACE_CONT: i32 = 7;
 const PTRACE_ATTACH: i32 = 16;
 const PTRACE_DETACH: i32 = 17;
+const PTRACE_SETOPTIONS: i32 = 0x4200;
+const PTRACE_GETEVENTMSG: i32 = 0x4201;
+const PTRACE_GETREGSET: i32 = 0x4204;
+const PTRACE_SEIZE: i32 = 0x4206;
+const PTRACE_INTERRUPT: i32 = 0x4207;
+
+const PTRACE_O_TRACEFORK: usize = 0x2;
+const PTRACE_O_TRACEVFORK: usize = 0x4;
+const PTRACE_O_TRACECLONE: usize = 0x8;
+const PTRACE_O_TRACEEXEC: usize = 0x10;
+
+const PTRACE_EVENT_FORK: i32 = 1;
+const PTRACE_EVENT_VFORK: i32 = 2;
+const PTRACE_EVENT_CLONE: i32 = 3;
+const PTRACE_EVENT_EXEC: i32 = 4;
+
+const SIGSTOP: i32 = 19;
+
+const NT_PRSTATUS: usize = 1;
+const NT_PRFPREG: usize = 2;
+#[cfg(target_arch = "x86_64")]
+const NT_PRXFPREG: usize = 0x46e62b7f;
+
+const ESRCH: i32 = 3;
+const ECHILD: i32 = 10;
+
+#[repr(C)]
+struct IoVec {
+    base: usize,
+    len: usize,
+}
+
+const WNOHANG: i32 = 1;
+const __WALL: i32 = 0x40000000;
+
+/// Reads `buffer.len()` bytes at `addr` in process `pid` without attaching to it
+pub fn process_vm_read(pid: u32, addr: usize, buffer: &mut [u8]) -> Result<()> {
+    let local = IoVec {
+        base: buffer.as_mut_ptr() as usize,
+        len: buffer.len(),
+    };
+    let remote = IoVec {
+        base: addr,
+        len: buffer.len(),
+    };
+    let read = unsafe { process_vm_readv(pid, &local, 1, &remote, 1, 0) };
+    let source = if read < 0 {
+        io::Error::last_os_error()
+    } else if read as usize != buffer.len() {
+        io::Error::from(io::ErrorKind::UnexpectedEof)
+    } else {
+        return Ok(());
+    };
+    Err(Error::Read {
+        pid,
+        address: addr,
+        source,
+    })
+}
 
 impl Ptrace {
     pub fn new(pid: u32) -> Result<Self> {
-        ptrace_wrapper(PTRACE_ATTACH, pid, 0, 0)?;
-        Ok(Self { pid, mem: None })
+        Self::attach(pid, Attach::Stop)
+    }
+
+    /// Opens process `pid`, stopping it as requested by `mode`
+    pub fn attach(pid: u32, mode: Attach) -> Result<Self> {
+        permissions::check(pid)?;
+        // Kernel threads have no executable
+        let class = procfs::elf_class(pid).unwrap_or(Class::Elf64);
+        if matches!(mode, Attach::NoStop | Attach::Freeze) {
+            let (stopped_at, freezer) = match mode {
+                Attach::Freeze => (Some(Instant::now()), Some(Freezer::freeze(pid)?)),
+                _ => (None, None),
+            };
+            return Ok(Self {
+                pid,
+                threads: Vec::new(),
+                mem: None,
+                mem_rw: None,
+                class,
+                peek_fallback: true,
+                deadline: None,
+                worker: None,
+                stopped_at,
+                #[cfg(feature = "uring")]
+                uring: None,
+                _freezer: freezer,
+                mode,
+                running: false,
+                paused: Duration::ZERO,
+                forked: Vec::new(),
+            });
+        }
+
+        let stopped_at = Instant::now();
+        Self::stop(pid, mode).map_err(|e| permissions::explain(pid, e))?;
+        let mut process = Self {
+            pid,
+            threads: vec![pid],
+            mem: None,
+            mem_rw: None,
+            class,
+            peek_fallback: true,
+            deadline: None,
+            worker: None,
+            stopped_at: Some(stopped_at),
+            #[cfg(feature = "uring")]
+            uring: None,
+            _freezer: None,
+            mode,
+            running: false,
+            paused: Duration::ZERO,
+            forked: Vec::new(),
+        };
+        waitpid_wrapper(pid)?;
+        process.attach_threads(mode)?;
+        debug!(
+            event = "attach", pid, threads = process.threads.len();
+            "Attached to process {} ({} threads)", pid, process.threads.len()
+        );
+        Ok(process)
+    }
+
+    /// Attaches to `tid` and requests it to stop, the caller must wait for it
+    fn stop(tid: u32, mode: Attach) -> Result<()> {
+        let result = match mode {
+            Attach::Stop => ptrace_wrapper(PTRACE_ATTACH, tid, 0, 0),
+            // A seized thread keeps running until interrupted, which does not queue a signal
+            Attach::Seize => ptrace_wrapper(PTRACE_SEIZE, tid, 0, 0)
+                .and_then(|_| ptrace_wrapper(PTRACE_INTERRUPT, tid, 0, 0)),
+            Attach::NoStop | Attach::Freeze => {
+                unreachable!("threads are not stopped without attaching")
+            }
+        };
+        match result {
+            Ok(_) => Ok(()),
+            Err(Error::Ptrace(source)) => Err(Error::Attach { pid: tid, source }),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Attaches to the other threads, until no new one shows up
+    fn attach_threads(&mut self, mode: Attach) -> Result<()> {
+        loop {
+            let mut attached = false;
+            for tid in procfs::tasks(self.pid)? {
+                if self.threads.contains(&tid) {
+                    continue;
+                }
+                match Self::stop(tid, mode) {
+                    Ok(()) => {}
+                    // The thread exited in the meantime
+                    Err(Error::Attach { ref source, .. })
+                        if source.raw_os_error() == Some(ESRCH) =>
+                    {
+                        continue
+                    }
+                    Err(e) => return Err(e),
+                }
+                self.threads.push(tid);
+                waitpid_wrapper(tid)?;
+                debug!("Attached to thread {}", tid);
+                attached = true;
+            }
+            if !attached {
+                return Ok(());
+            }
+        }
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// Class of the program, from its executable
+    pub fn class(&self) -> Class {
+        self.class
+    }
+
+    /// Attached threads, the main thread first, empty if not attached
+    pub fn threads(&self) -> &[u32] {
+        &self.threads[..]
+    }
+
+    /// Time the process has been stopped for, over the times it was resumed, zero if it was not
+    pub fn stopped_for(&self) -> Duration {
+        self.paused + self.stopped_at.map_or(Duration::ZERO, |t| t.elapsed())
+    }
+
+    /// Takes over process `pid`, forked by a process followed with [`Ptrace::follow_forks`]
+    ///
+    /// The kernel attached it already, with the same options, it is waited for until it stops.
+    /// Returns `None` if it exited first.
+    pub fn adopt(pid: u32, mode: Attach) -> Result<Option<Self>> {
+        let mut process = Self {
+            pid,
+            threads: vec![pid],
+            mem: None,
+            mem_rw: None,
+            class: procfs::elf_class(pid).unwrap_or(Class::Elf64),
+            peek_fallback: true,
+            deadline: None,
+            worker: None,
+            stopped_at: None,
+            #[cfg(feature = "uring")]
+            uring: None,
+            _freezer: None,
+            mode,
+            running: true,
+            paused: Duration::ZERO,
+            forked: Vec::new(),
+        };
+        // New processes start with a stop, no other one is requested
+        process.wait_stopped()?;
+        if process.threads.is_empty() {
+            return Ok(None);
+        }
+        debug!("Adopted process {}", pid);
+        Ok(Some(process))
+    }
+
+    /// Traces the threads and the children the process creates, and its `execve`s
+    ///
+    /// They are reported while it runs after [`Ptrace::resume`]: threads are followed, and
+    /// children are attached by the kernel, to be taken with [`Ptrace::take_forked`].
+    pub fn follow_forks(&mut self) -> Result<()> {
+        let options =
+            PTRACE_O_TRACEFORK | PTRACE_O_TRACEVFORK | PTRACE_O_TRACECLONE | PTRACE_O_TRACEEXEC;
+        for &tid in &self.threads {
+            ptrace_wrapper(PTRACE_SETOPTIONS, tid, 0, options)?;
+        }
+        Ok(())
+    }
+
+    /// Lets the stopped process run, while still attached
+    pub fn resume(&mut self) -> Result<()> {
+        if self.running || self.threads.is_empty() {
+            return Ok(());
+        }
+        for &tid in &self.threads {
+            match ptrace_wrapper(PTRACE_CONT, tid, 0, 0) {
+                Ok(_) => {}
+                // Killed in the meantime, which `waitpid` reports
+                Err(Error::Ptrace(ref source)) if source.raw_os_error() == Some(ESRCH) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.paused = self.stopped_for();
+        self.stopped_at = None;
+        self.running = true;
+        Ok(())
+    }
+
+    /// Stops the process resumed with [`Ptrace::resume`] again
+    pub fn interrupt(&mut self) -> Result<()> {
+        if !self.running {
+            return Ok(());
+        }
+        for &tid in &self.threads {
+            // A thread which exited in the meantime is reported by `waitpid`
+            if self.mode == Attach::Seize {
+                let _ = ptrace_wrapper(PTRACE_INTERRUPT, tid, 0, 0);
+            } else {
+                // SAFETY: only sends a signal
+                unsafe { tgkill(self.pid, tid, SIGSTOP) };
+            }
+        }
+        self.wait_stopped()
+    }
+
+    /// Handles the events of the running process which were reported already, without waiting
+    pub fn poll(&mut self) -> Result<()> {
+        if !self.running {
+            return Ok(());
+        }
+        let mut idx = 0;
+        while let Some(&tid) = self.threads.get(idx) {
+            while let Some(status) = self.wait_thread(tid, true)? {
+                self.handle_stop(tid, status, false)?;
+            }
+            // Unless it was removed, having exited
+            if self.threads.get(idx) == Some(&tid) {
+                idx += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Children forked since the last call, which are attached and must be adopted
+    pub fn take_forked(&mut self) -> Vec<u32> {
+        std::mem::take(&mut self.forked)
+    }
+
+    /// Waits for each thread of the running process to stop, handling their events until then
+    fn wait_stopped(&mut self) -> Result<()> {
+        let mut idx = 0;
+        while let Some(&tid) = self.threads.get(idx) {
+            while let Some(status) = self.wait_thread(tid, false)? {
+                if self.handle_stop(tid, status, true)? {
+                    break;
+                }
+            }
+            if self.threads.get(idx) == Some(&tid) {
+                idx += 1;
+            }
+        }
+        self.running = false;
+        self.stopped_at = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Waits for `tid` as [`try_waitpid`] does, forgetting it if it is gone
+    fn wait_thread(&mut self, tid: u32, nohang: bool) -> Result<Option<i32>> {
+        match try_waitpid(tid, nohang) {
+            Err(Error::Wait { ref source, .. }) if source.raw_os_error() == Some(ECHILD) => {
+                self.threads.retain(|&t| t != tid);
+                Ok(None)
+            }
+            result => result,
+        }
+    }
+
+    /// Handles the change of state of `tid` reported by `waitpid`, returns whether it is stopped
+    /// or gone
+    ///
+    /// New threads are followed, and new children recorded. Event stops, and `SIGSTOP`s which
+    /// also start new threads and children, leave `tid` stopped when `stopping`. It is resumed
+    /// otherwise, with the signal it was stopped on if any.
+    fn handle_stop(&mut self, tid: u32, status: i32, stopping: bool) -> Result<bool> {
+        let (signal, event) = match ThreadState::from_status(status) {
+            ThreadState::Exited => {
+                self.threads.retain(|&t| t != tid);
+                return Ok(true);
+            }
+            ThreadState::Stopped { signal, event } => (signal, event),
+        };
+        match event {
+            PTRACE_EVENT_FORK | PTRACE_EVENT_VFORK | PTRACE_EVENT_CLONE => {
+                let mut new = 0usize;
+                ptrace_wrapper(PTRACE_GETEVENTMSG, tid, 0, &mut new as *mut usize as usize)?;
+                let new = new as u32;
+                if event == PTRACE_EVENT_CLONE {
+                    debug!("Following new thread {} of process {}", new, self.pid);
+                    self.threads.push(new);
+                } else {
+                    debug!("Process {} forked process {}", self.pid, new);
+                    self.forked.push(new);
+                }
+            }
+            PTRACE_EVENT_EXEC => info!(
+                "Process {} executed a new program, its memory changed entirely",
+                self.pid
+            ),
+            _ => {}
+        }
+        let stopped = event != 0 || signal == SIGSTOP;
+        if stopping && stopped {
+            return Ok(true);
+        }
+        // Other signals are delivered
+        let resume_signal = if stopped { 0 } else { signal };
+        match ptrace_wrapper(PTRACE_CONT, tid, 0, resume_signal as usize) {
+            Ok(_) => Ok(false),
+            Err(Error::Ptrace(ref source)) if source.raw_os_error() == Some(ESRCH) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Reads the register set `n_type` of `tid` into `regs`
+    fn get_regset<T>(tid: u32, n_type: usize, regs: &mut T) -> Result<()> {
+        let mut iov = IoVec {
+            base: regs as *mut T as usize,
+            len: std::mem::size_of::<T>(),
+        };
+        ptrace_wrapper(
+            PTRACE_GETREGSET,
+            tid,
+            n_type,
+            &mut iov as *mut IoVec as usize,
+        )?;
+        Ok(())
+    }
+
+    /// Reads the registers of the stopped thread `tid`
+    ///
+    /// The kernel gives the register sets of the thread's own architecture, so 32-bit threads
+    /// have the i386 layout, and their `fxsave` area in `NT_PRXFPREG`. They are only supported on
+    /// x86_64.
+    pub fn registers(&self, tid: u32) -> Result<Registers> {
+        let (gp, fp_type) = match self.class {
+            Class::Elf64 => {
+                let mut gp = UserRegs::default();
+                Self::get_regset(tid, NT_PRSTATUS, &mut gp)?;
+                (gp, NT_PRFPREG)
+            }
+            #[cfg(target_arch = "x86_64")]
+            Class::Elf32 => {
+                let mut gp = UserRegs32::default();
+                Self::get_regset(tid, NT_PRSTATUS, &mut gp)?;
+                (gp.into(), NT_PRXFPREG)
+            }
+            #[cfg(not(target_arch = "x86_64"))]
+            Class::Elf32 => {
+                return Err(io::Error::other(
+                    "registers of 32-bit processes are only read on x86_64",
+                )
+                .into())
+            }
+        };
+        let mut fp = UserFpRegs::default();
+        let fp = Self::get_regset(tid, fp_type, &mut fp).ok().map(|_| fp);
+        Ok(Registers {
+            tid,
+            class: self.class,
+            gp,
+            fp,
+        })
     }
 
     fn open_mem(&mut self) -> Result<&mut File> {
@@ -50,35 +625,322 @@ impl Ptrace {
         Ok(self.mem.as_mut().unwrap())
     }
 
-    pub fn dump(&mut self, region: &Region, buffer: &mut Vec<u8>) -> Result<()> {
-        let mem = self.open_mem()?;
+    fn open_mem_rw(&mut self) -> Result<&mut File> {
+        if self.mem_rw.is_none() {
+            let mem = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(format!("/proc/{}/mem", self.pid))?;
+            self.mem_rw = Some(mem);
+        }
+        Ok(self.mem_rw.as_mut().unwrap())
+    }
+
+    fn write_mem(&mut self, addr: usize, data: &[u8]) -> Result<()> {
+        let mem = self.open_mem_rw()?;
         mem.seek(SeekFrom::Start(
-            region
-                .start
-                .try_into()
-                .expect("Cannot fit a usize into a u64"),
+            addr.try_into().expect("Cannot fit a usize into a u64"),
         ))?;
-        let size = region.size();
-        buffer.reserve(size);
-        // SAFETY: if the content of the buffer is not properly initialized the `read_exact`, then
-        // an error is returned and the buffer is discarded.
-        let old_len = buffer.len();
-        unsafe { buffer.set_len(old_len + size) };
-        match mem.read_exact(&mut buffer[old_len..]) {
-            Ok(_) => Ok(()),
-            Err(e) => {
-                // SAFETY: old_len was fine
-                unsafe { buffer.set_len(old_len) };
-                Err(e.into())
+        mem.write_all(data)?;
+        Ok(())
+    }
+
+    /// Writes `data` one word at a time, preserving the surrounding bytes of partial words
+    fn poke(&mut self, addr: usize, data: &[u8]) -> Result<()> {
+        const WORD: usize = std::mem::size_of::<usize>();
+
+        let mut word_addr = addr - addr % WORD;
+        let mut written = 0;
+        while written < data.len() {
+            let skip = (addr + written) - word_addr;
+            let len = (WORD - skip).min(data.len() - written);
+            let mut word = if len == WORD {
+                [0; WORD]
+            } else {
+                ptrace_wrapper(PTRACE_PEEKDATA, self.pid, word_addr, 0)?.to_ne_bytes()
+            };
+            word[skip..skip + len].copy_from_slice(&data[written..written + len]);
+            ptrace_wrapper(
+                PTRACE_POKEDATA,
+                self.pid,
+                word_addr,
+                usize::from_ne_bytes(word),
+            )?;
+            written += len;
+            word_addr += WORD;
+        }
+        Ok(())
+    }
+
+    /// Writes `data` at `addr` in the tracee
+    ///
+    /// Goes through `/proc/PID/mem` and falls back to `PTRACE_POKEDATA` when attached, if it cannot
+    /// be written. The first error is returned if both fail.
+    pub fn write(&mut self, addr: usize, data: &[u8]) -> Result<()> {
+        let error = match self.write_mem(addr, data) {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        if self.threads.is_empty() || self.poke(addr, data).is_err() {
+            return Err(error);
+        }
+        debug!(
+            "Wrote {} bytes at {:#x} with PTRACE_POKEDATA: {}",
+            data.len(),
+            addr,
+            Chain(&error)
+        );
+        Ok(())
+    }
+
+    /// Enables or disables the `PTRACE_PEEKDATA` fallback of [`Ptrace::read_page`]
+    pub fn set_peek_fallback(&mut self, enabled: bool) {
+        self.peek_fallback = enabled;
+    }
+
+    /// Reads `buffer.len()` bytes at `addr` one word at a time with `PTRACE_PEEKDATA`
+    ///
+    /// Only the thread which attached to the process can do so.
+    fn peek(&self, addr: usize, buffer: &mut [u8]) -> Result<()> {
+        const WORD: usize = std::mem::size_of::<usize>();
+
+        let mut word_addr = addr - addr % WORD;
+        let mut done = 0;
+        while done < buffer.len() {
+            let skip = (addr + done) - word_addr;
+            let len = (WORD - skip).min(buffer.len() - done);
+            let word = match ptrace_wrapper(PTRACE_PEEKDATA, self.pid, word_addr, 0) {
+                Ok(word) => word.to_ne_bytes(),
+                Err(Error::Ptrace(source)) => {
+                    return Err(Error::Read {
+                        pid: self.pid,
+                        address: word_addr,
+                        source,
+                    })
+                }
+                Err(e) => return Err(e),
+            };
+            buffer[done..done + len].copy_from_slice(&word[skip..skip + len]);
+            done += len;
+            word_addr += WORD;
+        }
+        Ok(())
+    }
+
+    /// Reads a page, or part of it, at `addr`
+    ///
+    /// Some mappings, such as those of devices, cannot be read through `/proc/PID/mem` but can be
+    /// with `PTRACE_PEEKDATA`, which is tried next when attached. The first error is returned if
+    /// both fail.
+    pub fn read_page(&mut self, addr: usize, buffer: &mut [u8]) -> Result<()> {
+        let error = match self.read(addr, buffer) {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        if !self.peek_fallback || self.threads.is_empty() || self.peek(addr, buffer).is_err() {
+            return Err(error);
+        }
+        debug!(
+            "Read {} bytes at {:#x} with PTRACE_PEEKDATA: {}",
+            buffer.len(),
+            addr,
+            Chain(&error)
+        );
+        Ok(())
+    }
+
+    /// Sets how memory is read, failing if io_uring cannot be set up
+    #[cfg(feature = "uring")]
+    pub fn set_backend(&mut self, backend: Backend) -> Result<()> {
+        self.uring =
+            match backend {
+                Backend::Read => None,
+                Backend::Uring => Some(Uring::new(URING_ENTRIES).map_err(|e| {
+                    io::Error::new(e.kind(), format!("cannot set up io_uring: {}", e))
+                })?),
+            };
+        Ok(())
+    }
+
+    /// Sets how memory is read, `read` being the only backend without the `uring` feature
+    #[cfg(not(feature = "uring"))]
+    pub fn set_backend(&mut self, backend: Backend) -> Result<()> {
+        match backend {
+            Backend::Read => Ok(()),
+        }
+    }
+
+    /// Number of reads [`Ptrace::read_batch`] does at once
+    pub fn batch_size(&self) -> usize {
+        #[cfg(feature = "uring")]
+        if let Some(uring) = &self.uring {
+            return uring.capacity();
+        }
+        1
+    }
+
+    /// Gives up the reads which do not end before `deadline`, until it is reset to `None`
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Reads through a worker thread, failing with [`Error::ReadTimeout`] at `deadline`
+    fn read_by_worker(&mut self, addr: usize, buffer: &mut [u8], deadline: Instant) -> Result<()> {
+        let pid = self.pid;
+        if Instant::now() >= deadline {
+            return Err(Error::ReadTimeout { pid, address: addr });
+        }
+        let worker = match self.worker {
+            Some(ref worker) => worker,
+            None => {
+                let mut mem = File::open(format!("/proc/{}/mem", pid))?;
+                let worker = ReadWorker::spawn(format!("read-{}", pid), move |addr, buffer| {
+                    mem.seek(SeekFrom::Start(
+                        addr.try_into().expect("Cannot fit a usize into a u64"),
+                    ))?;
+                    mem.read_exact(buffer)
+                })?;
+                self.worker.insert(worker)
             }
+        };
+        match worker.read(addr, buffer, deadline) {
+            Ok(()) => Ok(()),
+            Err(source) if source.kind() == io::ErrorKind::TimedOut => {
+                // It may stay blocked until exit, the next read starts another one
+                self.worker = None;
+                Err(Error::ReadTimeout { pid, address: addr })
+            }
+            Err(source) => Err(Error::Read {
+                pid,
+                address: addr,
+                source,
+            }),
         }
     }
+
+    /// Reads `buffer.len()` bytes at `addr`
+    pub fn read(&mut self, addr: usize, buffer: &mut [u8]) -> Result<()> {
+        if let Some(deadline) = self.deadline {
+            return self.read_by_worker(addr, buffer, deadline);
+        }
+        let pid = self.pid;
+        let mem = self.open_mem()?;
+        mem.seek(SeekFrom::Start(
+            addr.try_into().expect("Cannot fit a usize into a u64"),
+        ))
+        .and_then(|_| mem.read_exact(buffer))
+        .map_err(|source| Error::Read {
+            pid,
+            address: addr,
+            source,
+        })
+    }
+
+    /// Reads each buffer of `reads` at its address
+    ///
+    /// With `Backend::Uring`, up to [`Ptrace::batch_size`] reads are submitted at once. They are
+    /// done one at a time otherwise, or when a deadline is set.
+    #[cfg(not(feature = "uring"))]
+    pub fn read_batch(&mut self, reads: &mut [(usize, &mut [u8])]) -> Vec<Result<()>> {
+        reads
+            .iter_mut()
+            .map(|(addr, buffer)| self.read(*addr, buffer))
+            .collect()
+    }
+
+    /// Reads each buffer of `reads` at its address
+    ///
+    /// With [`Backend::Uring`], up to [`Ptrace::batch_size`] reads are submitted at once. They are
+    /// done one at a time otherwise, or when a deadline is set.
+    #[cfg(feature = "uring")]
+    pub fn read_batch(&mut self, reads: &mut [(usize, &mut [u8])]) -> Vec<Result<()>> {
+        let pid = self.pid;
+        let fd = self.open_mem().map(|mem| mem.as_raw_fd()).ok();
+        let (Some(uring), Some(fd), None) = (self.uring.as_mut(), fd, self.deadline) else {
+            return reads
+                .iter_mut()
+                .map(|(addr, buffer)| self.read(*addr, buffer))
+                .collect();
+        };
+        let mut results = Vec::with_capacity(reads.len());
+        for batch in reads.chunks_mut(uring.capacity()) {
+            let done = uring.read(fd, batch);
+            for ((addr, buffer), result) in batch.iter().zip(done) {
+                results.push(match result {
+                    Ok(len) if len == buffer.len() => Ok(()),
+                    Ok(_) => Err(Error::Read {
+                        pid,
+                        address: *addr,
+                        source: io::ErrorKind::UnexpectedEof.into(),
+                    }),
+                    Err(source) => Err(Error::Read {
+                        pid,
+                        address: *addr,
+                        source,
+                    }),
+                });
+            }
+        }
+        results
+    }
 }
 
 impl Drop for Ptrace {
     fn drop(&mut self) {
-        if let Err(e) = ptrace_wrapper(PTRACE_DETACH, self.pid, 0, 0) {
-            eprintln!("Could not detach from process {}: {}", self.pid, e);
+        // Threads must be stopped to be detached, and children forked in the meantime too
+        if let Err(e) = self.interrupt() {
+            warn!("Could not stop process {}: {}", self.pid, Chain(&e));
+        }
+        for child in self.take_forked() {
+            if let Err(e) = Self::adopt(child, self.mode) {
+                warn!("Could not detach from process {}: {}", child, Chain(&e));
+            }
+        }
+        for &tid in &self.threads {
+            if let Err(e) = ptrace_wrapper(PTRACE_DETACH, tid, 0, 0) {
+                warn!("Could not detach from thread {}: {}", tid, Chain(&e));
+            }
         }
+        if !self.threads.is_empty() {
+            let stopped = self.stopped_for();
+            debug!(
+                event = "detach", pid = self.pid, pause_time = stopped.as_secs_f64();
+                "Detached from process {}, stopped for {:.3} s", self.pid, stopped.as_secs_f64()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thread_state() {
+        // As built by the kernel: exit code, signal, and stops with their event
+        assert_eq!(ThreadState::from_status(0x100), ThreadState::Exited);
+        assert_eq!(ThreadState::from_status(9), ThreadState::Exited);
+        assert_eq!(
+            ThreadState::from_status(0x137f),
+            ThreadState::Stopped {
+                signal: SIGSTOP,
+                event: 0
+            }
+        );
+        assert_eq!(
+            ThreadState::from_status((PTRACE_EVENT_FORK << 16) | 0x57f),
+            ThreadState::Stopped {
+                signal: 5,
+                event: PTRACE_EVENT_FORK
+            }
+        );
+        // PTRACE_EVENT_STOP
+        assert_eq!(
+            ThreadState::from_status((128 << 16) | 0x57f),
+            ThreadState::Stopped {
+                signal: 5,
+                event: 128
+            }
+        );
     }
 }
@@ -0,0 +1,103 @@
+//! Raw read of an address range, for scripting
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+
+use crate::cli::ReadOptions;
+use crate::error::Chain;
+use crate::signal;
+use crate::{Error, Result};
+
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Splits `range` in chunks of at most `CHUNK_SIZE` bytes, each either mapped or within `gaps`
+fn chunks(range: &Range<usize>, gaps: &[Range<usize>]) -> Vec<(Range<usize>, bool)> {
+    let mut chunks = Vec::new();
+    let mut address = range.start;
+    let mut gaps = gaps.iter().peekable();
+    while address < range.end {
+        let (end, mapped) = match gaps.peek() {
+            Some(gap) if gap.start <= address => {
+                let end = gap.end;
+                gaps.next();
+                (end, false)
+            }
+            Some(gap) => (gap.start, true),
+            None => (range.end, true),
+        };
+        let end = end.min(address.saturating_add(CHUNK_SIZE));
+        chunks.push((address..end, mapped));
+        address = end;
+    }
+    chunks
+}
+
+pub fn run(options: &ReadOptions) -> Result<()> {
+    let range = &options.range;
+    let (mut reader, memory) = options.target.open()?;
+    let gaps = memory.unmapped(range);
+    if let Some(gap) = gaps.first() {
+        if !options.force {
+            return Err(Error::RegionNotFound {
+                start: gap.start,
+                end: gap.end,
+            });
+        }
+    }
+    for gap in &gaps {
+        warn!(
+            "0x{:x}..0x{:x} is not mapped, written as zeros",
+            gap.start, gap.end
+        );
+    }
+
+    let mut output: Box<dyn Write> = match options.output {
+        Some(ref path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+    let mut buffer = vec![0; CHUNK_SIZE.min(range.len())];
+    for (chunk, mapped) in chunks(range, &gaps[..]) {
+        signal::check()?;
+        let data = &mut buffer[..chunk.len()];
+        data.fill(0);
+        if mapped {
+            if let Err(e) = reader.read(chunk.start, data) {
+                if !options.force {
+                    return Err(e);
+                }
+                warn!(
+                    "Could not read 0x{:x}..0x{:x} ({}), written as zeros",
+                    chunk.start,
+                    chunk.end,
+                    Chain(&e)
+                );
+                data.fill(0);
+            }
+        }
+        output.write_all(data)?;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunks() {
+        let range = 0x1000..0x1000 + 2 * CHUNK_SIZE + 0x3000;
+        let gap = 0x1000 + CHUNK_SIZE..0x1000 + CHUNK_SIZE + 0x2000;
+        assert_eq!(
+            chunks(&range, std::slice::from_ref(&gap)),
+            vec![
+                (0x1000..gap.start, true),
+                (gap.clone(), false),
+                (gap.end..gap.end + CHUNK_SIZE, true),
+                (gap.end + CHUNK_SIZE..range.end, true),
+            ]
+        );
+        assert_eq!(chunks(&(0x1000..0x1800), &[]), vec![(0x1000..0x1800, true)]);
+    }
+}
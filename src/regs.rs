@@ -0,0 +1,675 @@
+//! CPU registers of a traced thread
+//!
+//! Each supported architecture (x86_64, aarch64 and riscv64) has its own layouts, the ones of the
+//! kernel's `NT_PRSTATUS` and `NT_PRFPREG` register sets.
+//!
+//! On x86_64, registers of 32-bit threads are widened into the 64-bit layout, as the kernel does
+//! when a 64-bit debugger reads them with `PTRACE_GETREGS`, and narrowed back to be saved.
+
+use crate::elf::Class;
+use crate::json::Value;
+
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64"
+)))]
+compile_error!("only x86_64, aarch64 and riscv64 are supported");
+
+/// General purpose registers, laid out as the kernel's `struct user_regs_struct`
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UserRegs {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub rbp: u64,
+    pub rbx: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rax: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub orig_rax: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub eflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+    pub fs_base: u64,
+    pub gs_base: u64,
+    pub ds: u64,
+    pub es: u64,
+    pub fs: u64,
+    pub gs: u64,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl UserRegs {
+    /// Names and values, in kernel order
+    pub fn fields(&self) -> [(&'static str, u64); 27] {
+        [
+            ("r15", self.r15),
+            ("r14", self.r14),
+            ("r13", self.r13),
+            ("r12", self.r12),
+            ("rbp", self.rbp),
+            ("rbx", self.rbx),
+            ("r11", self.r11),
+            ("r10", self.r10),
+            ("r9", self.r9),
+            ("r8", self.r8),
+            ("rax", self.rax),
+            ("rcx", self.rcx),
+            ("rdx", self.rdx),
+            ("rsi", self.rsi),
+            ("rdi", self.rdi),
+            ("orig_rax", self.orig_rax),
+            ("rip", self.rip),
+            ("cs", self.cs),
+            ("eflags", self.eflags),
+            ("rsp", self.rsp),
+            ("ss", self.ss),
+            ("fs_base", self.fs_base),
+            ("gs_base", self.gs_base),
+            ("ds", self.ds),
+            ("es", self.es),
+            ("fs", self.fs),
+            ("gs", self.gs),
+        ]
+    }
+
+    pub fn to_json(self) -> Value {
+        Value::object(
+            self.fields()
+                .iter()
+                .map(|&(name, value)| (name, format!("0x{:x}", value).into())),
+        )
+    }
+
+    /// Instruction pointer
+    pub fn pc(&self) -> u64 {
+        self.rip
+    }
+
+    /// Stack pointer
+    pub fn sp(&self) -> u64 {
+        self.rsp
+    }
+
+    /// Frame pointer
+    pub fn fp(&self) -> u64 {
+        self.rbp
+    }
+
+    #[cfg(test)]
+    pub fn with_frame(pc: u64, sp: u64, fp: u64) -> Self {
+        Self {
+            rip: pc,
+            rsp: sp,
+            rbp: fp,
+            ..Self::default()
+        }
+    }
+}
+
+/// General purpose registers of 32-bit threads, laid out as the i386 `struct user_regs_struct`
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UserRegs32 {
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+    pub esi: u32,
+    pub edi: u32,
+    pub ebp: u32,
+    pub eax: u32,
+    pub ds: u32,
+    pub es: u32,
+    pub fs: u32,
+    pub gs: u32,
+    pub orig_eax: u32,
+    pub eip: u32,
+    pub cs: u32,
+    pub eflags: u32,
+    pub esp: u32,
+    pub ss: u32,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl UserRegs32 {
+    /// Names and values, in kernel order
+    pub fn fields(&self) -> [(&'static str, u32); 17] {
+        [
+            ("ebx", self.ebx),
+            ("ecx", self.ecx),
+            ("edx", self.edx),
+            ("esi", self.esi),
+            ("edi", self.edi),
+            ("ebp", self.ebp),
+            ("eax", self.eax),
+            ("ds", self.ds),
+            ("es", self.es),
+            ("fs", self.fs),
+            ("gs", self.gs),
+            ("orig_eax", self.orig_eax),
+            ("eip", self.eip),
+            ("cs", self.cs),
+            ("eflags", self.eflags),
+            ("esp", self.esp),
+            ("ss", self.ss),
+        ]
+    }
+
+    pub fn to_json(self) -> Value {
+        Value::object(
+            self.fields()
+                .iter()
+                .map(|&(name, value)| (name, format!("0x{:x}", value).into())),
+        )
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl From<UserRegs32> for UserRegs {
+    fn from(regs: UserRegs32) -> Self {
+        Self {
+            rbx: regs.ebx as u64,
+            rcx: regs.ecx as u64,
+            rdx: regs.edx as u64,
+            rsi: regs.esi as u64,
+            rdi: regs.edi as u64,
+            rbp: regs.ebp as u64,
+            rax: regs.eax as u64,
+            ds: regs.ds as u64,
+            es: regs.es as u64,
+            fs: regs.fs as u64,
+            gs: regs.gs as u64,
+            // Sign-extended, -1 outside of system calls
+            orig_rax: regs.orig_eax as i32 as u64,
+            rip: regs.eip as u64,
+            cs: regs.cs as u64,
+            eflags: regs.eflags as u64,
+            rsp: regs.esp as u64,
+            ss: regs.ss as u64,
+            ..Self::default()
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl From<UserRegs> for UserRegs32 {
+    /// Keeps the low halves, the high ones are zero for 32-bit threads
+    fn from(regs: UserRegs) -> Self {
+        Self {
+            ebx: regs.rbx as u32,
+            ecx: regs.rcx as u32,
+            edx: regs.rdx as u32,
+            esi: regs.rsi as u32,
+            edi: regs.rdi as u32,
+            ebp: regs.rbp as u32,
+            eax: regs.rax as u32,
+            ds: regs.ds as u32,
+            es: regs.es as u32,
+            fs: regs.fs as u32,
+            gs: regs.gs as u32,
+            orig_eax: regs.orig_rax as u32,
+            eip: regs.rip as u32,
+            cs: regs.cs as u32,
+            eflags: regs.eflags as u32,
+            esp: regs.rsp as u32,
+            ss: regs.ss as u32,
+        }
+    }
+}
+
+/// x87/SSE registers, laid out as the kernel's `struct user_fpregs_struct`
+///
+/// This is the `fxsave` layout, also used for 32-bit threads with `NT_PRXFPREG`.
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UserFpRegs {
+    pub cwd: u16,
+    pub swd: u16,
+    pub ftw: u16,
+    pub fop: u16,
+    pub rip: u64,
+    pub rdp: u64,
+    pub mxcsr: u32,
+    pub mxcr_mask: u32,
+    /// 8 registers of 16 bytes, only the first 10 bytes are used
+    pub st_space: [u32; 32],
+    /// 16 registers of 16 bytes
+    pub xmm_space: [u32; 64],
+    pub padding: [u32; 24],
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Default for UserFpRegs {
+    fn default() -> Self {
+        Self {
+            cwd: 0,
+            swd: 0,
+            ftw: 0,
+            fop: 0,
+            rip: 0,
+            rdp: 0,
+            mxcsr: 0,
+            mxcr_mask: 0,
+            st_space: [0; 32],
+            xmm_space: [0; 64],
+            padding: [0; 24],
+        }
+    }
+}
+
+/// Formats 16-byte registers stored as little endian `u32`s
+#[cfg(target_arch = "x86_64")]
+fn wide_registers(space: &[u32]) -> Value {
+    Value::Array(
+        space
+            .chunks(4)
+            .map(|words| {
+                let value = words
+                    .iter()
+                    .rev()
+                    .fold(0u128, |acc, w| (acc << 32) | *w as u128);
+                format!("0x{:x}", value).into()
+            })
+            .collect(),
+    )
+}
+
+#[cfg(target_arch = "x86_64")]
+impl UserFpRegs {
+    pub fn to_json(self) -> Value {
+        Value::object([
+            ("cwd", format!("0x{:x}", self.cwd).into()),
+            ("swd", format!("0x{:x}", self.swd).into()),
+            ("ftw", format!("0x{:x}", self.ftw).into()),
+            ("fop", format!("0x{:x}", self.fop).into()),
+            ("rip", format!("0x{:x}", self.rip).into()),
+            ("rdp", format!("0x{:x}", self.rdp).into()),
+            ("mxcsr", format!("0x{:x}", self.mxcsr).into()),
+            ("mxcr_mask", format!("0x{:x}", self.mxcr_mask).into()),
+            ("st", wide_registers(&self.st_space)),
+            ("xmm", wide_registers(&self.xmm_space)),
+        ])
+    }
+}
+
+/// General purpose registers, laid out as the kernel's `struct user_pt_regs`
+#[cfg(target_arch = "aarch64")]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UserRegs {
+    /// `x0` to `x30`, `x29` being the frame pointer and `x30` the link register
+    pub regs: [u64; 31],
+    pub sp: u64,
+    pub pc: u64,
+    pub pstate: u64,
+}
+
+#[cfg(target_arch = "aarch64")]
+impl UserRegs {
+    const NAMES: [&'static str; 34] = [
+        "x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11", "x12", "x13",
+        "x14", "x15", "x16", "x17", "x18", "x19", "x20", "x21", "x22", "x23", "x24", "x25", "x26",
+        "x27", "x28", "x29", "x30", "sp", "pc", "pstate",
+    ];
+
+    /// Names and values, in kernel order
+    pub fn fields(&self) -> [(&'static str, u64); 34] {
+        std::array::from_fn(|idx| {
+            let value = match idx {
+                0..=30 => self.regs[idx],
+                31 => self.sp,
+                32 => self.pc,
+                _ => self.pstate,
+            };
+            (Self::NAMES[idx], value)
+        })
+    }
+
+    pub fn to_json(self) -> Value {
+        Value::object(
+            self.fields()
+                .iter()
+                .map(|&(name, value)| (name, format!("0x{:x}", value).into())),
+        )
+    }
+
+    /// Instruction pointer
+    pub fn pc(&self) -> u64 {
+        self.pc
+    }
+
+    /// Stack pointer
+    pub fn sp(&self) -> u64 {
+        self.sp
+    }
+
+    /// Frame pointer
+    pub fn fp(&self) -> u64 {
+        self.regs[29]
+    }
+
+    #[cfg(test)]
+    pub fn with_frame(pc: u64, sp: u64, fp: u64) -> Self {
+        let mut regs = Self {
+            pc,
+            sp,
+            ..Self::default()
+        };
+        regs.regs[29] = fp;
+        regs
+    }
+}
+
+/// Floating point and SIMD registers, laid out as the kernel's `struct user_fpsimd_state`
+#[cfg(target_arch = "aarch64")]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UserFpRegs {
+    pub vregs: [u128; 32],
+    pub fpsr: u32,
+    pub fpcr: u32,
+    pub reserved: [u32; 2],
+}
+
+#[cfg(target_arch = "aarch64")]
+impl UserFpRegs {
+    pub fn to_json(self) -> Value {
+        Value::object([
+            (
+                "v",
+                Value::Array(
+                    self.vregs
+                        .iter()
+                        .map(|v| format!("0x{:x}", v).into())
+                        .collect(),
+                ),
+            ),
+            ("fpsr", format!("0x{:x}", self.fpsr).into()),
+            ("fpcr", format!("0x{:x}", self.fpcr).into()),
+        ])
+    }
+}
+
+/// General purpose registers, laid out as the kernel's `struct user_regs_struct`
+#[cfg(target_arch = "riscv64")]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UserRegs {
+    pub pc: u64,
+    pub ra: u64,
+    pub sp: u64,
+    pub gp: u64,
+    pub tp: u64,
+    pub t0: u64,
+    pub t1: u64,
+    pub t2: u64,
+    /// Also the frame pointer
+    pub s0: u64,
+    pub s1: u64,
+    pub a0: u64,
+    pub a1: u64,
+    pub a2: u64,
+    pub a3: u64,
+    pub a4: u64,
+    pub a5: u64,
+    pub a6: u64,
+    pub a7: u64,
+    pub s2: u64,
+    pub s3: u64,
+    pub s4: u64,
+    pub s5: u64,
+    pub s6: u64,
+    pub s7: u64,
+    pub s8: u64,
+    pub s9: u64,
+    pub s10: u64,
+    pub s11: u64,
+    pub t3: u64,
+    pub t4: u64,
+    pub t5: u64,
+    pub t6: u64,
+}
+
+#[cfg(target_arch = "riscv64")]
+impl UserRegs {
+    /// Names and values, in kernel order
+    pub fn fields(&self) -> [(&'static str, u64); 32] {
+        [
+            ("pc", self.pc),
+            ("ra", self.ra),
+            ("sp", self.sp),
+            ("gp", self.gp),
+            ("tp", self.tp),
+            ("t0", self.t0),
+            ("t1", self.t1),
+            ("t2", self.t2),
+            ("s0", self.s0),
+            ("s1", self.s1),
+            ("a0", self.a0),
+            ("a1", self.a1),
+            ("a2", self.a2),
+            ("a3", self.a3),
+            ("a4", self.a4),
+            ("a5", self.a5),
+            ("a6", self.a6),
+            ("a7", self.a7),
+            ("s2", self.s2),
+            ("s3", self.s3),
+            ("s4", self.s4),
+            ("s5", self.s5),
+            ("s6", self.s6),
+            ("s7", self.s7),
+            ("s8", self.s8),
+            ("s9", self.s9),
+            ("s10", self.s10),
+            ("s11", self.s11),
+            ("t3", self.t3),
+            ("t4", self.t4),
+            ("t5", self.t5),
+            ("t6", self.t6),
+        ]
+    }
+
+    pub fn to_json(self) -> Value {
+        Value::object(
+            self.fields()
+                .iter()
+                .map(|&(name, value)| (name, format!("0x{:x}", value).into())),
+        )
+    }
+
+    /// Instruction pointer
+    pub fn pc(&self) -> u64 {
+        self.pc
+    }
+
+    /// Stack pointer
+    pub fn sp(&self) -> u64 {
+        self.sp
+    }
+
+    /// Frame pointer
+    pub fn fp(&self) -> u64 {
+        self.s0
+    }
+
+    #[cfg(test)]
+    pub fn with_frame(pc: u64, sp: u64, fp: u64) -> Self {
+        Self {
+            pc,
+            sp,
+            s0: fp,
+            ..Self::default()
+        }
+    }
+}
+
+/// Floating point registers, laid out as the kernel's `struct __riscv_d_ext_state`
+#[cfg(target_arch = "riscv64")]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UserFpRegs {
+    pub f: [u64; 32],
+    pub fcsr: u32,
+    /// Padding of the kernel structure, part of the register set
+    pub padding: u32,
+}
+
+#[cfg(target_arch = "riscv64")]
+impl UserFpRegs {
+    pub fn to_json(self) -> Value {
+        Value::object([
+            (
+                "f",
+                Value::Array(self.f.iter().map(|f| format!("0x{:x}", f).into()).collect()),
+            ),
+            ("fcsr", format!("0x{:x}", self.fcsr).into()),
+        ])
+    }
+}
+
+/// Views a plain `repr(C)` register struct as bytes
+pub fn as_bytes<T: Copy>(regs: &T) -> &[u8] {
+    // SAFETY: register structs are `repr(C)` and only made of integers, without padding
+    unsafe { std::slice::from_raw_parts((regs as *const T).cast(), std::mem::size_of::<T>()) }
+}
+
+/// Reads a plain `repr(C)` register struct from the start of `data`, as saved in core files
+pub fn from_bytes<T: Copy>(data: &[u8]) -> Option<T> {
+    if data.len() < std::mem::size_of::<T>() {
+        return None;
+    }
+    // SAFETY: `data` holds enough bytes, and any bit pattern is valid for register structs
+    Some(unsafe { std::ptr::read_unaligned(data.as_ptr().cast()) })
+}
+
+/// Registers of one thread
+#[derive(Debug, Clone)]
+pub struct Registers {
+    pub tid: u32,
+
+    /// Class of the program, the registers of 32-bit threads are widened in `gp` (x86_64 only)
+    pub class: Class,
+    pub gp: UserRegs,
+
+    /// Floating point registers, if the kernel provided them
+    pub fp: Option<UserFpRegs>,
+}
+
+impl Registers {
+    pub fn to_json(&self) -> Value {
+        Value::object([
+            ("tid", self.tid.into()),
+            (
+                "gp",
+                match self.class {
+                    #[cfg(target_arch = "x86_64")]
+                    Class::Elf32 => UserRegs32::from(self.gp).to_json(),
+                    _ => self.gp.to_json(),
+                },
+            ),
+            ("fp", self.fp.map_or(Value::Null, UserFpRegs::to_json)),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_layout() {
+        assert_eq!(std::mem::size_of::<UserRegs>(), 27 * 8);
+        assert_eq!(std::mem::size_of::<UserRegs32>(), 17 * 4);
+        assert_eq!(std::mem::size_of::<UserFpRegs>(), 512);
+    }
+
+    #[test]
+    #[cfg(target_arch = "aarch64")]
+    fn test_layout() {
+        assert_eq!(std::mem::size_of::<UserRegs>(), 34 * 8);
+        assert_eq!(std::mem::size_of::<UserFpRegs>(), 528);
+    }
+
+    #[test]
+    #[cfg(target_arch = "riscv64")]
+    fn test_layout() {
+        assert_eq!(std::mem::size_of::<UserRegs>(), 32 * 8);
+        assert_eq!(std::mem::size_of::<UserFpRegs>(), 33 * 8);
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_to_json() {
+        let mut fp = UserFpRegs::default();
+        fp.xmm_space[4] = 0x11223344;
+        fp.xmm_space[7] = 0xaa000000;
+        let regs = Registers {
+            tid: 42,
+            class: Class::Elf64,
+            gp: UserRegs {
+                rip: 0x401000,
+                ..Default::default()
+            },
+            fp: Some(fp),
+        };
+        let json = regs.to_json();
+        assert_eq!(json.get("tid").and_then(Value::as_u64), Some(42));
+        assert_eq!(
+            json.get("gp")
+                .and_then(|gp| gp.get("rip"))
+                .and_then(Value::as_str),
+            Some("0x401000")
+        );
+        let xmm = json
+            .get("fp")
+            .and_then(|fp| fp.get("xmm"))
+            .and_then(Value::as_array)
+            .unwrap();
+        assert_eq!(xmm.len(), 16);
+        assert_eq!(xmm[1].as_str(), Some("0xaa000000000000000000000011223344"));
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_i386() {
+        let regs32 = UserRegs32 {
+            eax: 0xffff_fff2,
+            orig_eax: u32::MAX,
+            eip: 0x0804_9000,
+            esp: 0xffff_d000,
+            ..Default::default()
+        };
+        let gp = UserRegs::from(regs32);
+        assert_eq!(gp.rax, 0xffff_fff2);
+        assert_eq!(gp.orig_rax, u64::MAX);
+        assert_eq!(gp.rip, 0x0804_9000);
+        assert_eq!(UserRegs32::from(gp), regs32);
+
+        let regs = Registers {
+            tid: 7,
+            class: Class::Elf32,
+            gp,
+            fp: None,
+        };
+        let json = regs.to_json();
+        let gp = json.get("gp").unwrap();
+        assert_eq!(gp.get("eip").and_then(Value::as_str), Some("0x8049000"));
+        assert!(gp.get("rip").is_none());
+    }
+}
@@ -0,0 +1,615 @@
+//! Memory of the processes of another host, read through an agent serving it over TCP
+//!
+//! `dump-memory serve ADDRESS` runs on the host of the processes, and dumps are written on the
+//! host connecting to it with `--remote`: nothing is written to the disk of the first one. Each
+//! connection opens one process, which stays attached until the connection is closed. Connections
+//! are served one at a time.
+//!
+//! Messages are frames made of a type byte and of a 32-bit little-endian length, followed by as
+//! many bytes:
+//! * `J`: a JSON object, for requests and their answers;
+//! * `D`: a chunk of memory, compressed as requested when the process was opened;
+//! * `E`: the error message of a request which failed.
+//!
+//! The client first sends `{"op": "open", "version": 1, "pid": PID, "compression": ALGO}`, and gets
+//! `{"pid": PID, "name": NAME, "smaps": SMAPS}` back, `SMAPS` being the content of
+//! `/proc/PID/smaps`. Then:
+//! * `{"op": "read", "address": ADDRESS, "length": LENGTH}` is answered with a single `D` frame,
+//!   `LENGTH` being at most [`MAX_READ_SIZE`];
+//! * `{"op": "dump", "start": START, "end": END, "ranges": [[START, END], ...]}` is answered with
+//!   the region as `D` frames of at most [`CHUNK_SIZE`] bytes once decompressed, the pages outside
+//!   of the ranges or which cannot be read being zeros, followed by
+//!   `{"unreadable": [[START, END], ...]}`.
+//!
+//! Both can be answered with an `E` frame instead, after some `D` frames for a dump.
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::ops::Range;
+use std::time::Duration;
+
+use crate::cli::ServeOptions;
+use crate::compress::Compression;
+use crate::dump;
+use crate::error::Chain;
+use crate::json::Value;
+use crate::memory::{Memory, Region};
+use crate::pagemap;
+use crate::ptrace::Ptrace;
+use crate::signal;
+use crate::target::Reader;
+use crate::{Error, Result};
+
+/// Version of the protocol, sent when opening a process
+const PROTOCOL_VERSION: u64 = 1;
+
+/// Largest chunk of memory sent in a `D` frame, before compression
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Largest frame accepted, which is more than a compressed chunk can take
+const MAX_FRAME_SIZE: usize = 4 * CHUNK_SIZE;
+
+/// Largest read served at once, a chunk whose `D` frame fits in [`MAX_FRAME_SIZE`] even when it
+/// does not compress
+const MAX_READ_SIZE: usize = CHUNK_SIZE;
+
+/// How often the agent checks for an interruption while waiting
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+const JSON: u8 = b'J';
+const DATA: u8 = b'D';
+const ERROR: u8 = b'E';
+
+/// A message of the protocol
+#[derive(Debug, PartialEq)]
+enum Frame {
+    Json(Value),
+    Data(Vec<u8>),
+    Error(String),
+}
+
+fn write_frame(w: &mut impl Write, kind: u8, payload: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(payload.len()).map_err(|_| io::ErrorKind::InvalidInput)?;
+    let mut frame = Vec::with_capacity(5 + payload.len());
+    frame.push(kind);
+    frame.extend_from_slice(&len.to_le_bytes());
+    frame.extend_from_slice(payload);
+    w.write_all(&frame)
+}
+
+/// Reads a frame, `None` if the peer closed the connection before it
+fn read_frame(r: &mut impl Read) -> Result<Option<Frame>> {
+    let mut header = [0u8; 5];
+    match r.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(protocol_error(format!("frame of {} bytes", len)));
+    }
+    let mut payload = vec![0; len];
+    r.read_exact(&mut payload)?;
+    let kind = header[0];
+    if kind == DATA {
+        return Ok(Some(Frame::Data(payload)));
+    }
+    let text = String::from_utf8(payload).map_err(|_| protocol_error("text is not UTF-8"))?;
+    match kind {
+        JSON => Ok(Some(Frame::Json(text.parse()?))),
+        ERROR => Ok(Some(Frame::Error(text))),
+        _ => Err(protocol_error(format!("unknown frame type 0x{:02x}", kind))),
+    }
+}
+
+fn protocol_error(reason: impl Into<String>) -> Error {
+    Error::Protocol(reason.into())
+}
+
+/// Compresses `data` as a whole, for a `D` frame
+fn compress(compression: Compression, data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut compressed = Vec::new();
+    let mut encoder = compression.encoder(&mut compressed)?;
+    encoder.write_all(data)?;
+    encoder.finish()?;
+    Ok(compressed)
+}
+
+fn ranges_to_json(ranges: &[Range<usize>]) -> Value {
+    Value::Array(
+        ranges
+            .iter()
+            .map(|r| Value::Array(vec![r.start.into(), r.end.into()]))
+            .collect(),
+    )
+}
+
+fn ranges_from_json(json: Option<&Value>) -> Result<Vec<Range<usize>>> {
+    let malformed = || protocol_error("malformed ranges");
+    json.and_then(Value::as_array)
+        .ok_or_else(malformed)?
+        .iter()
+        .map(|range| match range.as_array() {
+            Some([start, end]) => {
+                let bound = |v: &Value| v.as_u64().map(|v| v as usize).ok_or_else(malformed);
+                Ok(bound(start)?..bound(end)?)
+            }
+            _ => Err(malformed()),
+        })
+        .collect()
+}
+
+/// Checks that `ranges` are sorted, disjoint, not empty and inside `region`, as the reader expects
+fn check_ranges(ranges: &[Range<usize>], region: &Range<usize>) -> Result<()> {
+    let mut previous_end = region.start;
+    for range in ranges {
+        if range.start < previous_end || range.end <= range.start || range.end > region.end {
+            return Err(protocol_error(format!(
+                "range {:x}-{:x} is empty, overlaps or is not sorted, or is outside of {:x}-{:x}",
+                range.start, range.end, region.start, region.end
+            )));
+        }
+        previous_end = range.end;
+    }
+    Ok(())
+}
+
+/// Returns the unsigned integer `field` of a message
+fn u64_field(json: &Value, field: &str) -> Result<u64> {
+    json.get(field)
+        .and_then(Value::as_u64)
+        .ok_or_else(|| protocol_error(format!("missing or malformed {}", field)))
+}
+
+/// Connection to an agent, on which a process is open
+#[derive(Debug)]
+pub struct Remote {
+    stream: TcpStream,
+
+    /// Address of the agent, as given on the command line
+    address: String,
+    pid: u32,
+
+    /// Name of the program, from its command line
+    name: String,
+    compression: Compression,
+}
+
+impl Remote {
+    /// Connects to the agent at `address` and opens `pid`, returning its regions
+    ///
+    /// Memory is sent compressed with `compression`.
+    pub fn connect(address: &str, pid: u32, compression: Compression) -> Result<(Self, Memory)> {
+        let stream = TcpStream::connect(address).map_err(|e| Error::Remote {
+            address: address.into(),
+            reason: e.to_string(),
+        })?;
+        stream.set_nodelay(true)?;
+        let mut remote = Self {
+            stream,
+            address: address.into(),
+            pid,
+            name: String::new(),
+            compression,
+        };
+        let reply = remote.request(Value::object([
+            ("op", "open".into()),
+            ("version", PROTOCOL_VERSION.into()),
+            ("pid", pid.into()),
+            ("compression", compression.to_string().into()),
+        ]))?;
+        let Frame::Json(reply) = reply else {
+            return Err(remote.unexpected());
+        };
+        let smaps = reply.get("smaps").and_then(Value::as_str).unwrap_or("");
+        let memory = Memory::from_smaps(pid, smaps)?;
+        remote.name = reply
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .into();
+        Ok((remote, memory))
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Sends `request` and returns the first frame of the answer, failing on an error message
+    fn request(&mut self, request: Value) -> Result<Frame> {
+        write_frame(&mut self.stream, JSON, request.to_string().as_bytes())?;
+        self.next_frame()
+    }
+
+    fn next_frame(&mut self) -> Result<Frame> {
+        match read_frame(&mut self.stream)? {
+            Some(Frame::Error(reason)) => Err(Error::Remote {
+                address: self.address.clone(),
+                reason,
+            }),
+            Some(frame) => Ok(frame),
+            None => Err(Error::Remote {
+                address: self.address.clone(),
+                reason: "connection closed".into(),
+            }),
+        }
+    }
+
+    fn unexpected(&self) -> Error {
+        Error::Remote {
+            address: self.address.clone(),
+            reason: "unexpected message".into(),
+        }
+    }
+
+    /// Reads `buffer.len()` bytes at `addr`
+    pub fn read(&mut self, addr: usize, buffer: &mut [u8]) -> Result<()> {
+        for (offset, chunk) in (0..)
+            .step_by(MAX_READ_SIZE)
+            .zip(buffer.chunks_mut(MAX_READ_SIZE))
+        {
+            let reply = self.request(Value::object([
+                ("op", "read".into()),
+                ("address", (addr + offset).into()),
+                ("length", chunk.len().into()),
+            ]))?;
+            let Frame::Data(compressed) = reply else {
+                return Err(self.unexpected());
+            };
+            let data = self.compression.decode(&compressed)?;
+            if data.len() != chunk.len() {
+                return Err(self.unexpected());
+            }
+            chunk.copy_from_slice(&data);
+        }
+        Ok(())
+    }
+
+    /// Streams `region` to `sink`, only reading the sorted `ranges`, see [`Reader::dump_ranges`]
+    pub fn dump_ranges<W: Write + ?Sized>(
+        &mut self,
+        region: &Region,
+        ranges: &[Range<usize>],
+        sink: &mut W,
+    ) -> Result<Vec<Range<usize>>> {
+        let mut frame = self.request(Value::object([
+            ("op", "dump".into()),
+            ("start", region.start.into()),
+            ("end", region.end.into()),
+            ("ranges", ranges_to_json(ranges)),
+        ]))?;
+        let mut received = 0;
+        loop {
+            match frame {
+                Frame::Data(compressed) => {
+                    let data = self.compression.decode(&compressed)?;
+                    received += data.len();
+                    if received > region.size() {
+                        return Err(self.unexpected());
+                    }
+                    sink.write_all(&data)?;
+                }
+                Frame::Json(end) if received == region.size() => {
+                    return ranges_from_json(end.get("unreadable"));
+                }
+                _ => return Err(self.unexpected()),
+            }
+            frame = self.next_frame()?;
+        }
+    }
+}
+
+/// Sends the chunks written to it as `D` frames
+struct Chunks<'a, W: Write> {
+    inner: &'a mut W,
+    compression: Compression,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> Chunks<'_, W> {
+    fn send(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            let compressed = compress(self.compression, &self.buffer)?;
+            write_frame(self.inner, DATA, &compressed)?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Chunks<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        if self.buffer.len() == CHUNK_SIZE {
+            self.send()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()?;
+        self.inner.flush()
+    }
+}
+
+/// A process opened by a client
+struct Opened {
+    reader: Reader,
+    memory: Memory,
+    compression: Compression,
+}
+
+/// Waits for the next request, `false` if the client closed the connection
+///
+/// Interruptions are checked while waiting.
+fn wait_request(stream: &mut TcpStream) -> Result<bool> {
+    stream.set_read_timeout(Some(POLL_INTERVAL))?;
+    let ready = loop {
+        signal::check()?;
+        match stream.peek(&mut [0]) {
+            Ok(n) => break n > 0,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(e) => return Err(e.into()),
+        }
+    };
+    stream.set_read_timeout(None)?;
+    Ok(ready)
+}
+
+/// Attaches to the process requested by `open`
+fn open(request: &Value) -> Result<(Opened, Value)> {
+    let version = u64_field(request, "version")?;
+    if version != PROTOCOL_VERSION {
+        return Err(protocol_error(format!(
+            "protocol version {} is not supported, only version {} is",
+            version, PROTOCOL_VERSION
+        )));
+    }
+    let pid = u64_field(request, "pid")?
+        .try_into()
+        .map_err(|_| protocol_error("malformed pid"))?;
+    let compression = request
+        .get("compression")
+        .and_then(Value::as_str)
+        .unwrap_or("none")
+        .parse()?;
+    let reader = Reader::Process(Ptrace::new(pid)?);
+    let smaps = std::fs::read_to_string(format!("/proc/{}/smaps", pid))?;
+    let memory = Memory::from_smaps(pid, &smaps)?;
+    let name = dump::get_program_name(pid)?;
+    let reply = Value::object([
+        ("pid", pid.into()),
+        ("name", name.into()),
+        ("smaps", smaps.into()),
+    ]);
+    let opened = Opened {
+        reader,
+        memory,
+        compression,
+    };
+    Ok((opened, reply))
+}
+
+/// Answers a `read` or `dump` request, the errors of which are sent by the caller
+fn answer(stream: &mut TcpStream, opened: &mut Opened, request: &Value) -> Result<()> {
+    match request.get("op").and_then(Value::as_str) {
+        Some("read") => {
+            let address = u64_field(request, "address")? as usize;
+            let length = u64_field(request, "length")? as usize;
+            if length > MAX_READ_SIZE {
+                return Err(protocol_error(format!("read of {} bytes", length)));
+            }
+            let mut buffer = vec![0; length];
+            opened.reader.read(address, &mut buffer)?;
+            let compressed = compress(opened.compression, &buffer)?;
+            write_frame(stream, DATA, &compressed)?;
+        }
+        Some("dump") => {
+            let start = u64_field(request, "start")? as usize;
+            let end = u64_field(request, "end")? as usize;
+            let mut ranges = ranges_from_json(request.get("ranges"))?;
+            let Some(region) = opened
+                .memory
+                .iter()
+                .find(|r| r.start == start && r.end == end)
+            else {
+                return Err(Error::RegionNotFound { start, end });
+            };
+            check_ranges(&ranges, &(start..end))?;
+            // Only the resident pages of a whole sparse region are read, as for local dumps
+            let whole = start..end;
+            if ranges == std::slice::from_ref(&whole) && pagemap::is_sparse_candidate(region) {
+                if let Ok(resident) = pagemap::resident_ranges(opened.reader.pid(), region) {
+                    ranges = resident;
+                }
+            }
+            let mut chunks = Chunks {
+                inner: stream,
+                compression: opened.compression,
+                buffer: Vec::with_capacity(CHUNK_SIZE),
+            };
+            let unreadable = opened.reader.dump_ranges(region, &ranges, &mut chunks);
+            chunks.flush()?;
+            let reply = Value::object([("unreadable", ranges_to_json(&unreadable?))]);
+            write_frame(stream, JSON, reply.to_string().as_bytes())?;
+        }
+        _ => return Err(protocol_error("unknown request")),
+    }
+    Ok(())
+}
+
+/// Serves the requests of a client until it closes the connection
+fn serve_client(stream: &mut TcpStream, peer: SocketAddr) -> Result<()> {
+    stream.set_nodelay(true)?;
+    let mut opened = None;
+    while wait_request(stream)? {
+        let Some(Frame::Json(request)) = read_frame(stream)? else {
+            return Err(protocol_error("expected a request"));
+        };
+        let result = match opened {
+            None if request.get("op").and_then(Value::as_str) == Some("open") => open(&request)
+                .and_then(|(process, reply)| {
+                    info!("Client {} opened process {}", peer, process.reader.pid());
+                    opened = Some(process);
+                    Ok(write_frame(stream, JSON, reply.to_string().as_bytes())?)
+                }),
+            None => Err(protocol_error("no process is open")),
+            Some(ref mut process) => answer(stream, process, &request),
+        };
+        match result {
+            Ok(()) => {}
+            Err(Error::Interrupted) => return Err(Error::Interrupted),
+            // Sending the error fails too if the connection is broken
+            Err(e) => write_frame(stream, ERROR, Chain(&e).to_string().as_bytes())?,
+        }
+    }
+    Ok(())
+}
+
+/// Resolves the address to listen on, refusing the non-loopback ones unless `allow_non_loopback`
+///
+/// Clients are not authenticated, anyone reaching the agent can read the memory it can.
+fn listen_addresses(address: &str, allow_non_loopback: bool) -> Result<Vec<SocketAddr>> {
+    let addresses: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+    if let Some(exposed) = addresses.iter().find(|a| !a.ip().is_loopback()) {
+        if !allow_non_loopback {
+            return Err(Error::Usage(format!(
+                "{} is not a loopback address, and clients are not authenticated: forward a \
+                 local port through SSH, or give --allow-non-loopback",
+                exposed
+            )));
+        }
+        warn!(
+            "Listening on {}, anyone connecting can read the memory of the processes",
+            exposed
+        );
+    }
+    Ok(addresses)
+}
+
+pub fn run(options: &ServeOptions) -> Result<()> {
+    let addresses = listen_addresses(&options.address, options.allow_non_loopback)?;
+    let listener = TcpListener::bind(&addresses[..])?;
+    listener.set_nonblocking(true)?;
+    info!("Serving memory on {}", listener.local_addr()?);
+    loop {
+        let (mut stream, peer) = match listener.accept() {
+            Ok(accepted) => accepted,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                signal::sleep(POLL_INTERVAL)?;
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        stream.set_nonblocking(false)?;
+        info!("Connection from {}", peer);
+        match serve_client(&mut stream, peer) {
+            Ok(()) => info!("Client {} disconnected", peer),
+            Err(Error::Interrupted) => return Err(Error::Interrupted),
+            Err(e) => warn!("Connection from {} failed: {}", peer, Chain(&e)),
+        }
+        if options.once {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames() {
+        let mut stream = Vec::new();
+        let request = Value::object([("op", "read".into()), ("address", 0x1000u64.into())]);
+        write_frame(&mut stream, JSON, request.to_string().as_bytes()).unwrap();
+        write_frame(&mut stream, ERROR, b"Input/output error").unwrap();
+        let mut chunks = Chunks {
+            inner: &mut stream,
+            compression: Compression::Gzip,
+            buffer: Vec::new(),
+        };
+        let data: Vec<u8> = (0..CHUNK_SIZE + 10).map(|i| (i / 4096) as u8).collect();
+        chunks.write_all(&data).unwrap();
+        chunks.flush().unwrap();
+        // Truncated frame
+        stream.extend_from_slice(&[DATA, 8, 0, 0, 0, 1]);
+
+        let mut stream = &stream[..];
+        assert_eq!(read_frame(&mut stream).unwrap(), Some(Frame::Json(request)));
+        assert_eq!(
+            read_frame(&mut stream).unwrap(),
+            Some(Frame::Error("Input/output error".into()))
+        );
+        let mut received = Vec::new();
+        for _ in 0..2 {
+            let Some(Frame::Data(compressed)) = read_frame(&mut stream).unwrap() else {
+                panic!("expected data");
+            };
+            received.extend(Compression::Gzip.decode(&compressed).unwrap());
+        }
+        assert_eq!(received, data);
+        assert!(read_frame(&mut stream).is_err());
+        assert_eq!(read_frame(&mut &b""[..]).unwrap(), None);
+        assert!(read_frame(&mut &[b'X', 0, 0, 0, 0][..]).is_err());
+    }
+
+    #[test]
+    fn test_largest_read() {
+        let data = vec![0xa5; MAX_READ_SIZE];
+        let mut stream = Vec::new();
+        write_frame(
+            &mut stream,
+            DATA,
+            &compress(Compression::None, &data).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            read_frame(&mut &stream[..]).unwrap(),
+            Some(Frame::Data(data))
+        );
+    }
+
+    #[test]
+    fn test_ranges() {
+        let ranges = [0x1000..0x3000, 0x5000..0x6000];
+        let json = ranges_to_json(&ranges);
+        assert_eq!(ranges_from_json(Some(&json)).unwrap(), ranges);
+        let json: Value = "[[4096]]".parse().unwrap();
+        assert!(ranges_from_json(Some(&json)).is_err());
+        assert!(ranges_from_json(None).is_err());
+
+        let region = 0x1000..0x8000;
+        assert!(check_ranges(&ranges, &region).is_ok());
+        assert!(check_ranges(&[], &region).is_ok());
+        assert!(check_ranges(&[0x1000..0x3000, 0x1000..0x3000], &region).is_err());
+        assert!(check_ranges(&[0x5000..0x6000, 0x1000..0x3000], &region).is_err());
+        for range in [0x2000..0x2000, 0x7000..0x9000, 0..0x2000] {
+            assert!(check_ranges(std::slice::from_ref(&range), &region).is_err());
+        }
+    }
+
+    #[test]
+    fn test_listen_addresses() {
+        assert_eq!(
+            listen_addresses("127.0.0.1:7070", false).unwrap(),
+            ["127.0.0.1:7070".parse().unwrap()]
+        );
+        assert!(listen_addresses("[::1]:7070", false).is_ok());
+        assert!(matches!(
+            listen_addresses("0.0.0.0:7070", false),
+            Err(Error::Usage(_))
+        ));
+        assert!(listen_addresses("0.0.0.0:7070", true).is_ok());
+    }
+}
@@ -0,0 +1,416 @@
+//! Interactive prompt, exploring a process without attaching to it again for each command
+//!
+//! Commands are read from the standard input, one per line, until `quit` or the end of the input.
+//! The process stays attached, and stopped, for the whole session. Words are separated by spaces,
+//! and can be quoted with double quotes, inside of which a backslash escapes the next character.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufWriter, IsTerminal, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::cli::{self, Args, ReplOptions};
+use crate::error::Chain;
+use crate::hexdump;
+use crate::maps;
+use crate::memory::{Memory, RegionFilter};
+use crate::search::{self, BytePattern};
+use crate::signal;
+use crate::target::Reader;
+use crate::write;
+use crate::{Error, Result};
+
+const HELP: &str = "\
+Commands:
+  maps [SELECTION]        list the regions, with statistics for a running process
+  hexdump START..END      print a range as `hexdump -C` would (alias: x)
+  read START..END FILE    write the raw bytes of a range to FILE
+  search [-i] [-x] [-C N] [SELECTION] PATTERN
+                          print the addresses of PATTERN in the readable regions
+  write [-x] [--force] ADDRESS DATA
+                          write DATA at ADDRESS, in a running process only
+  dump-region ADDRESS FILE
+                          write the region containing ADDRESS to FILE
+  history                 list the previous commands
+  !N, !!                  run again the command N of the history, or the last one
+  help                    print this help
+  quit                    detach and exit (alias: exit)
+
+SELECTION is made of the region selection options of the command line (--path,
+--kind and --anonymous-only), -i, -x, -C and --force are those of the search
+and write commands. Ctrl-C interrupts a search or a dump, twice at the prompt exits
+right away.";
+
+/// A command of the prompt
+#[derive(Debug, PartialEq)]
+enum Request {
+    Maps(RegionFilter),
+    Hexdump(Range<usize>),
+    Read(Range<usize>, PathBuf),
+    Search {
+        pattern: BytePattern,
+        ignore_case: bool,
+        context: usize,
+        filter: RegionFilter,
+    },
+    Write {
+        address: usize,
+        data: Vec<u8>,
+        force: bool,
+    },
+    DumpRegion(usize, PathBuf),
+    History,
+    Help,
+    Quit,
+}
+
+/// Splits a line in words, separated by whitespace outside of double quotes
+fn split_words(line: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quoted = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                word.get_or_insert_default();
+            }
+            '\\' if quoted => match chars.next() {
+                Some(c) => word.get_or_insert_default().push(c),
+                None => break,
+            },
+            c if c.is_whitespace() && !quoted => words.extend(word.take()),
+            c => word.get_or_insert_default().push(c),
+        }
+    }
+    if quoted {
+        return Err(Error::Usage("Unterminated quote".into()));
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Returns the command of the history which `line` refers to with `!N` or `!!`, or `line`
+fn expand_history<'a>(line: &'a str, history: &'a [String]) -> Result<&'a str> {
+    let Some(reference) = line.strip_prefix('!') else {
+        return Ok(line);
+    };
+    let index = match reference {
+        "!" => history.len().checked_sub(1),
+        n => n.parse::<usize>().ok().and_then(|n| n.checked_sub(1)),
+    };
+    index
+        .and_then(|index| history.get(index))
+        .map(String::as_str)
+        .ok_or_else(|| Error::Usage(format!("No command {} in the history", line)))
+}
+
+fn parse_request(words: Vec<String>) -> Result<Request> {
+    let mut words = words.into_iter();
+    let name = words.next().unwrap_or_default();
+    let mut args = Args::new(words);
+    let mut positional = Vec::new();
+    let mut filter = RegionFilter::new();
+    let mut hex = false;
+    let mut ignore_case = false;
+    let mut force = false;
+    let mut context = 0;
+
+    while let Some(arg) = args.next_arg()? {
+        match arg.as_str() {
+            "-x" | "--hex" if matches!(name.as_str(), "search" | "write") => hex = true,
+            "-i" | "--ignore-case" if name == "search" => ignore_case = true,
+            "-C" | "--context" if name == "search" => context = args.parse_value(&arg)?,
+            "--force" if name == "write" => force = true,
+            _ if matches!(name.as_str(), "maps" | "search")
+                && cli::parse_filter_option(&arg, &mut args, &mut filter)? => {}
+            _ if cli::is_option(&arg) => {
+                return Err(Error::Usage(format!("Unknown option {} for {}", arg, name)))
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    let request = match (name.as_str(), &positional[..]) {
+        ("maps", []) => Request::Maps(filter),
+        ("hexdump" | "x", [range]) => Request::Hexdump(cli::parse_range(range)?),
+        ("read", [range, path]) => Request::Read(cli::parse_range(range)?, path.into()),
+        ("search", [pattern]) => Request::Search {
+            pattern: BytePattern::parse(pattern, hex)?,
+            ignore_case,
+            context,
+            filter,
+        },
+        ("write", [address, data]) => Request::Write {
+            address: cli::parse_address(address)?,
+            data: match hex {
+                true => cli::parse_hex_bytes(data)?,
+                false => data.as_bytes().to_vec(),
+            },
+            force,
+        },
+        ("dump-region", [address, path]) => {
+            Request::DumpRegion(cli::parse_address(address)?, path.into())
+        }
+        ("history", []) => Request::History,
+        ("help", []) => Request::Help,
+        ("quit" | "exit", []) => Request::Quit,
+        (
+            "maps" | "hexdump" | "x" | "read" | "search" | "write" | "dump-region" | "history"
+            | "help" | "quit" | "exit",
+            _,
+        ) => {
+            return Err(Error::Usage(format!(
+                "Wrong number of arguments for {}, see `help`",
+                name
+            )))
+        }
+        _ => {
+            return Err(Error::Usage(format!(
+                "Unknown command {:?}, see `help`",
+                name
+            )))
+        }
+    };
+    match request {
+        Request::Write { ref data, .. } if data.is_empty() => {
+            Err(Error::Usage("Empty DATA".into()))
+        }
+        request => Ok(request),
+    }
+}
+
+/// Runs the commands which read or write memory
+fn execute(reader: &mut Reader, memory: &Memory, request: Request) -> Result<()> {
+    match request {
+        Request::Maps(filter) => {
+            // The regions of the session come from /proc/PID/maps, without statistics
+            let fresh;
+            let memory = match reader.live_pid() {
+                Some(pid) => {
+                    fresh = Memory::from_pid_smaps(pid)?;
+                    &fresh
+                }
+                None => memory,
+            };
+            maps::print_table(memory.filter(&filter));
+        }
+        Request::Hexdump(range) => {
+            let buffer = hexdump::read_range(reader, memory, &range)?;
+            for line in hexdump::lines(range.start, &buffer) {
+                println!("{}", line);
+            }
+        }
+        Request::Read(range, path) => {
+            let buffer = hexdump::read_range(reader, memory, &range)?;
+            std::fs::write(&path, &buffer)?;
+            println!("Wrote {} bytes to {}", buffer.len(), path.display());
+        }
+        Request::Search {
+            pattern,
+            ignore_case,
+            context,
+            filter,
+        } => search::search(
+            reader,
+            memory,
+            &filter,
+            std::slice::from_ref(&pattern),
+            ignore_case,
+            context,
+        )?,
+        Request::Write {
+            address,
+            data,
+            force,
+        } => {
+            let Reader::Process(process) = reader else {
+                return Err(Error::Usage(
+                    "Only the memory of a running process can be written".into(),
+                ));
+            };
+            if !force {
+                write::check_writable(memory, address, data.len())?;
+            }
+            process.write(address, &data)?;
+            println!("Wrote {} bytes at 0x{:x}", data.len(), address);
+        }
+        Request::DumpRegion(address, path) => {
+            let region = memory
+                .region_containing(address)
+                .ok_or(Error::RegionNotFound {
+                    start: address,
+                    end: address.saturating_add(1),
+                })?;
+            let mut file = BufWriter::new(File::create(&path)?);
+            let unreadable = reader.dump(region, &mut file)?;
+            file.flush()?;
+            for range in unreadable {
+                warn!(
+                    "Could not read 0x{:x}..0x{:x}, written as zeros",
+                    range.start, range.end
+                );
+            }
+            println!(
+                "Wrote region {:x}-{:x} ({} bytes) to {}",
+                region.start,
+                region.end,
+                region.size(),
+                path.display()
+            );
+        }
+        Request::History | Request::Help | Request::Quit => unreachable!(),
+    }
+    Ok(())
+}
+
+fn load_history(path: &Path) -> Result<Vec<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(content.lines().map(String::from).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn run(options: &ReplOptions) -> Result<()> {
+    let (mut reader, memory) = options.target.open()?;
+    let mut history = match options.history {
+        Some(ref path) => load_history(path)?,
+        None => Vec::new(),
+    };
+    let mut history_file = match options.history {
+        Some(ref path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+        None => None,
+    };
+
+    // Commands may be piped, the prompt is only shown to humans
+    let interactive = io::stdin().is_terminal();
+    if interactive {
+        info!(
+            "Process {} is open, `help` lists the commands",
+            reader.pid()
+        );
+    }
+    let mut stdin = io::stdin().lock();
+    let mut line = String::new();
+    loop {
+        if interactive {
+            eprint!("{}> ", reader.pid());
+            io::stderr().flush()?;
+        }
+        line.clear();
+        if stdin.read_line(&mut line)? == 0 {
+            if interactive {
+                eprintln!();
+            }
+            break;
+        }
+        let command = match expand_history(line.trim(), &history) {
+            Ok(command) => command.to_owned(),
+            Err(e) => {
+                error!("{}", e);
+                continue;
+            }
+        };
+        if command.is_empty() {
+            continue;
+        }
+        if command != line.trim() {
+            println!("{}", command);
+        }
+        if let Some(ref mut file) = history_file {
+            writeln!(file, "{}", command)?;
+        }
+        history.push(command.clone());
+
+        // A Ctrl-C at the prompt must not interrupt the next command
+        signal::reset();
+        let result = match split_words(&command).and_then(parse_request) {
+            Ok(Request::Quit) => break,
+            Ok(Request::Help) => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            Ok(Request::History) => {
+                for (number, command) in history.iter().enumerate() {
+                    println!("{:>5}  {}", number + 1, command);
+                }
+                Ok(())
+            }
+            Ok(request) => execute(&mut reader, &memory, request),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => {}
+            Err(Error::Interrupted) => warn!("Interrupted"),
+            Err(e) => error!("{}", Chain(&e)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<Request> {
+        parse_request(split_words(line)?)
+    }
+
+    #[test]
+    fn test_split_words() {
+        assert_eq!(
+            split_words("  search -x \"de ad\"  x\"y\\\"z\"\t\"\"").unwrap(),
+            ["search", "-x", "de ad", "xy\"z", ""]
+        );
+        assert!(split_words("").unwrap().is_empty());
+        assert!(split_words("search \"abc").is_err());
+    }
+
+    #[test]
+    fn test_expand_history() {
+        let history = ["maps".to_owned(), "x 1000..+10".to_owned()];
+        assert_eq!(expand_history("!!", &history).unwrap(), "x 1000..+10");
+        assert_eq!(expand_history("!1", &history).unwrap(), "maps");
+        assert_eq!(expand_history("help", &history).unwrap(), "help");
+        assert!(expand_history("!0", &history).is_err());
+        assert!(expand_history("!3", &history).is_err());
+        assert!(expand_history("!!", &[]).is_err());
+    }
+
+    #[test]
+    fn test_parse_request() {
+        let mut filter = RegionFilter::new();
+        filter.add_kind("heap".parse().unwrap());
+        assert_eq!(
+            parse("search -i --kind heap -C 16 -x \"41 ?? 42\"").unwrap(),
+            Request::Search {
+                pattern: BytePattern::parse("41 ?? 42", true).unwrap(),
+                ignore_case: true,
+                context: 16,
+                filter,
+            }
+        );
+        assert_eq!(
+            parse("x 1000..+0x10").unwrap(),
+            Request::Hexdump(0x1000..0x1010)
+        );
+        assert_eq!(
+            parse("write --force 0x1000 hello").unwrap(),
+            Request::Write {
+                address: 0x1000,
+                data: b"hello".to_vec(),
+                force: true,
+            }
+        );
+        assert_eq!(
+            parse("dump-region 7f00 heap.bin").unwrap(),
+            Request::DumpRegion(0x7f00, "heap.bin".into())
+        );
+        assert!(parse("read 1000..2000").is_err());
+        assert!(parse("hexdump -i 1000..2000").is_err());
+        assert!(parse("write 0x1000 \"\"").is_err());
+        assert!(parse("frobnicate").is_err());
+    }
+}
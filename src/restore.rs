@@ -0,0 +1,148 @@
+//! Writing a dump back into a live process
+
+use std::ops::Range;
+
+use crate::cli::RestoreOptions;
+use crate::error::Chain;
+use crate::memory::{Memory, PermissionBits, Region};
+use crate::ptrace::Ptrace;
+use crate::signal;
+use crate::snapshot::Snapshot;
+use crate::Result;
+
+fn describe(region: &Region) -> String {
+    format!(
+        "{:x}-{:x} {} ({})",
+        region.start,
+        region.end,
+        region.perms,
+        region.path().unwrap_or("no file")
+    )
+}
+
+/// Whether the `unreadable` ranges of a manifest entry are sorted, disjoint and inside `region`
+fn gaps_inside(unreadable: &[Range<usize>], region: &Region) -> bool {
+    let mut previous_end = region.start;
+    unreadable.iter().all(|gap| {
+        let inside = previous_end <= gap.start && gap.start <= gap.end && gap.end <= region.end;
+        previous_end = gap.end;
+        inside
+    })
+}
+
+pub fn run(options: &RestoreOptions) -> Result<()> {
+    let snapshot = Snapshot::open(&options.dump)?;
+    if snapshot.manifest.pid != options.pid {
+        warn!(
+            "Restoring a dump of process {} into process {}",
+            snapshot.manifest.pid, options.pid
+        );
+    }
+
+    let mut process = Ptrace::new(options.pid)?;
+    let memory = Memory::from_pid(options.pid)?;
+
+    let entries: Vec<_> = snapshot
+        .manifest
+        .entries
+        .iter()
+        .filter(|e| options.filter.matches(&e.region))
+        .collect();
+    for region in memory.filter(&options.filter) {
+        if !entries.iter().any(|e| e.region.start == region.start) {
+            warn!("Region {} is not in the dump", describe(region));
+        }
+    }
+
+    // Regions are matched by address and size, other ones are left untouched
+    let mut restored = 0;
+    for entry in entries {
+        signal::check()?;
+        let Some(current) = memory.iter().find(|r| r.start == entry.region.start) else {
+            warn!("Region {} is not mapped anymore", describe(&entry.region));
+            continue;
+        };
+        if current.end != entry.region.end || current.path() != entry.region.path() {
+            warn!(
+                "Region {} is now mapped as {}",
+                describe(&entry.region),
+                describe(current)
+            );
+            continue;
+        }
+        if !current.perms.has_perm(PermissionBits::Write) && !options.force {
+            warn!(
+                "Region {} is not writable, give --force to restore it",
+                describe(current)
+            );
+            continue;
+        }
+        let Some(data) = snapshot.read(entry)? else {
+            continue;
+        };
+        if data.len() != current.size() {
+            warn!(
+                "Region file of {} holds 0x{:x} bytes instead of 0x{:x}",
+                describe(current),
+                data.len(),
+                current.size()
+            );
+            continue;
+        }
+        if !gaps_inside(&entry.unreadable, current) {
+            warn!(
+                "Unreadable ranges of region {} in the manifest are not inside it",
+                describe(current)
+            );
+            continue;
+        }
+        // Unreadable ranges were dumped as zeros and are left untouched
+        let mut result = Ok(());
+        let mut start = current.start;
+        for gap in entry.unreadable.iter().chain([&(current.end..current.end)]) {
+            if start < gap.start {
+                let data = &data[start - current.start..gap.start - current.start];
+                result = result.and_then(|_| process.write(start, data));
+            }
+            start = gap.end;
+        }
+        if let Err(e) = result {
+            warn!(
+                "Could not restore region {}: {}",
+                describe(current),
+                Chain(&e)
+            );
+            continue;
+        }
+        println!("Restored region {}", describe(current));
+        restored += 1;
+    }
+    info!("{} regions restored", restored);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gaps_inside() {
+        let region: Region = "7f0000000000-7f0000004000 rw-p 00000000 00:00 0"
+            .parse()
+            .unwrap();
+        let start = region.start;
+        assert!(gaps_inside(&[], &region));
+        assert!(gaps_inside(
+            &[start..start + 0x1000, start + 0x3000..start + 0x4000],
+            &region
+        ));
+        for gap in [start + 0x3000..start + 0x5000, 0..0x1000] {
+            assert!(!gaps_inside(std::slice::from_ref(&gap), &region));
+        }
+        assert!(!gaps_inside(
+            &[start + 0x2000..start + 0x3000, start..start + 0x1000],
+            &region
+        ));
+    }
+}
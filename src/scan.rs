@@ -0,0 +1,520 @@
+//! Scanning memory for typed values
+//!
+//! Hits can be saved to a JSON state file and narrowed down by later scans, possibly from
+//! another attach session, by comparing the current values against the saved ones.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::cli::ScanOptions;
+use crate::error::Chain;
+use crate::json::Value;
+use crate::memory::{PermissionBits, Region};
+use crate::signal;
+use crate::symbols::Symbolizer;
+use crate::target::Reader;
+use crate::{Error, Result};
+
+/// Type of the scanned value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+}
+
+/// Byte order of scanned values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+macro_rules! encode {
+    ($ty:ty, $value:expr, $endian:expr) => {{
+        let v: $ty = $value.parse().map_err(|_| Error::InvalidValue {
+            ty: stringify!($ty),
+            value: $value.into(),
+        })?;
+        match $endian {
+            Endian::Little => v.to_le_bytes().to_vec(),
+            Endian::Big => v.to_be_bytes().to_vec(),
+        }
+    }};
+}
+
+macro_rules! decode {
+    ($ty:ty, $bytes:expr, $endian:expr, $variant:ident) => {{
+        let bytes = $bytes.try_into().expect("Value has the size of its type");
+        Number::$variant(match $endian {
+            Endian::Little => <$ty>::from_le_bytes(bytes),
+            Endian::Big => <$ty>::from_be_bytes(bytes),
+        } as _)
+    }};
+}
+
+/// A decoded value
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Number {
+    Int(i128),
+    Float(f64),
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int(n) => write!(f, "{}", n),
+            Self::Float(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+impl ValueType {
+    pub fn size(&self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::I64 | Self::U64 | Self::F64 => 8,
+        }
+    }
+
+    /// Converts `value` to its in-memory representation
+    pub fn encode(&self, value: &str, endian: Endian) -> Result<Vec<u8>> {
+        Ok(match self {
+            Self::I8 => encode!(i8, value, endian),
+            Self::I16 => encode!(i16, value, endian),
+            Self::I32 => encode!(i32, value, endian),
+            Self::I64 => encode!(i64, value, endian),
+            Self::U8 => encode!(u8, value, endian),
+            Self::U16 => encode!(u16, value, endian),
+            Self::U32 => encode!(u32, value, endian),
+            Self::U64 => encode!(u64, value, endian),
+            Self::F32 => encode!(f32, value, endian),
+            Self::F64 => encode!(f64, value, endian),
+        })
+    }
+
+    /// Converts an in-memory representation to a number
+    pub fn decode(&self, bytes: &[u8], endian: Endian) -> Number {
+        match self {
+            Self::I8 => decode!(i8, bytes, endian, Int),
+            Self::I16 => decode!(i16, bytes, endian, Int),
+            Self::I32 => decode!(i32, bytes, endian, Int),
+            Self::I64 => decode!(i64, bytes, endian, Int),
+            Self::U8 => decode!(u8, bytes, endian, Int),
+            Self::U16 => decode!(u16, bytes, endian, Int),
+            Self::U32 => decode!(u32, bytes, endian, Int),
+            Self::U64 => decode!(u64, bytes, endian, Int),
+            Self::F32 => decode!(f32, bytes, endian, Float),
+            Self::F64 => decode!(f64, bytes, endian, Float),
+        }
+    }
+}
+
+impl FromStr for ValueType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "i8" => Self::I8,
+            "i16" => Self::I16,
+            "i32" => Self::I32,
+            "i64" => Self::I64,
+            "u8" => Self::U8,
+            "u16" => Self::U16,
+            "u32" => Self::U32,
+            "u64" => Self::U64,
+            "f32" => Self::F32,
+            "f64" => Self::F64,
+            _ => return Err(Error::Usage(format!("Unknown value type {:?}", s))),
+        })
+    }
+}
+
+impl FromStr for Endian {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "little" => Ok(Self::Little),
+            "big" => Ok(Self::Big),
+            _ => Err(Error::Usage(format!("Unknown endianness {:?}", s))),
+        }
+    }
+}
+
+impl fmt::Display for Endian {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Little => f.write_str("little"),
+            Self::Big => f.write_str("big"),
+        }
+    }
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::I8 => "i8",
+            Self::I16 => "i16",
+            Self::I32 => "i32",
+            Self::I64 => "i64",
+            Self::U8 => "u8",
+            Self::U16 => "u16",
+            Self::U32 => "u32",
+            Self::U64 => "u64",
+            Self::F32 => "f32",
+            Self::F64 => "f64",
+        })
+    }
+}
+
+/// Returns offsets in `data` (mapped at `base`) of `pattern`, at `align`-aligned addresses
+pub fn scan_buffer(data: &[u8], base: usize, pattern: &[u8], align: usize) -> Vec<usize> {
+    let first = (align - base % align) % align;
+    (first..data.len().saturating_sub(pattern.len() - 1))
+        .step_by(align)
+        .filter(|off| &data[*off..*off + pattern.len()] == pattern)
+        .collect()
+}
+
+/// How to narrow down previous hits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Refine {
+    /// Keep hits now holding this value
+    Value(String),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+}
+
+impl Refine {
+    fn keep(
+        &self,
+        value_type: ValueType,
+        endian: Endian,
+        old: &[u8],
+        new: &[u8],
+        exact: &[u8],
+    ) -> bool {
+        let order = || {
+            value_type
+                .decode(new, endian)
+                .partial_cmp(&value_type.decode(old, endian))
+        };
+        match self {
+            Self::Value(_) => new == exact,
+            Self::Changed => new != old,
+            Self::Unchanged => new == old,
+            Self::Increased => order() == Some(Ordering::Greater),
+            Self::Decreased => order() == Some(Ordering::Less),
+        }
+    }
+}
+
+/// Address holding the scanned value, with the value read at that time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hit {
+    pub address: usize,
+    pub value: Vec<u8>,
+}
+
+/// Result of a scan, saved between sessions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanState {
+    pub pid: u32,
+    pub value_type: ValueType,
+    pub endian: Endian,
+    pub hits: Vec<Hit>,
+}
+
+fn malformed_state(field: &'static str, value: &Value) -> Error {
+    Error::MalformedState {
+        field,
+        value: value.to_string(),
+    }
+}
+
+impl ScanState {
+    pub fn to_json(&self) -> Value {
+        let hits = self
+            .hits
+            .iter()
+            .map(|h| {
+                let value: String = h.value.iter().map(|b| format!("{:02x}", b)).collect();
+                Value::object([
+                    ("address", format!("0x{:x}", h.address).into()),
+                    ("value", value.into()),
+                ])
+            })
+            .collect();
+        Value::object([
+            ("version", 1u64.into()),
+            ("pid", self.pid.into()),
+            ("type", self.value_type.to_string().into()),
+            ("endian", self.endian.to_string().into()),
+            ("hits", Value::Array(hits)),
+        ])
+    }
+
+    pub fn from_json(json: &Value) -> Result<Self> {
+        let field = |name: &'static str| json.get(name).ok_or_else(|| malformed_state(name, json));
+        let pid = field("pid")?;
+        let pid = pid
+            .as_u64()
+            .and_then(|p| p.try_into().ok())
+            .ok_or_else(|| malformed_state("pid", pid))?;
+        let value_type = field("type")?;
+        let value_type: ValueType = value_type
+            .as_str()
+            .and_then(|t| t.parse().ok())
+            .ok_or_else(|| malformed_state("type", value_type))?;
+        let endian = field("endian")?;
+        let endian = endian
+            .as_str()
+            .and_then(|e| e.parse().ok())
+            .ok_or_else(|| malformed_state("endian", endian))?;
+        let hits = field("hits")?;
+        let hits = hits
+            .as_array()
+            .ok_or_else(|| malformed_state("hits", hits))?
+            .iter()
+            .map(|hit| {
+                let address = hit
+                    .get("address")
+                    .and_then(Value::as_str)
+                    .and_then(|a| a.strip_prefix("0x"))
+                    .and_then(|a| usize::from_str_radix(a, 16).ok())
+                    .ok_or_else(|| malformed_state("address", hit))?;
+                let value = hit
+                    .get("value")
+                    .and_then(Value::as_str)
+                    .and_then(|v| crate::cli::parse_hex_bytes(v).ok())
+                    .filter(|v| v.len() == value_type.size())
+                    .ok_or_else(|| malformed_state("value", hit))?;
+                Ok(Hit { address, value })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            pid,
+            value_type,
+            endian,
+            hits,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::from_json(&std::fs::read_to_string(path)?.parse()?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut json = self.to_json().to_string();
+        json.push('\n');
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+}
+
+fn print_value_hit(
+    symbols: &mut Symbolizer,
+    region: &Region,
+    hit: &Hit,
+    value_type: ValueType,
+    endian: Endian,
+) {
+    println!(
+        "0x{:x} {} = {} ({:x}-{:x} {} +0x{:x} {})",
+        hit.address,
+        symbols.describe(region, hit.address),
+        value_type.decode(&hit.value[..], endian),
+        region.start,
+        region.end,
+        region.perms.to_maps_string(),
+        hit.address - region.start,
+        region.path().unwrap_or("")
+    );
+}
+
+fn read_region(reader: &mut Reader, region: &Region, buffer: &mut Vec<u8>) -> bool {
+    buffer.clear();
+    if let Err(e) = reader.dump(region, buffer) {
+        warn!(
+            "Could not read region {:x}-{:x} {} ({}): {}",
+            region.start,
+            region.end,
+            region.perms,
+            region.path().unwrap_or("no file"),
+            Chain(&e)
+        );
+        return false;
+    }
+    true
+}
+
+pub fn run(options: &ScanOptions) -> Result<()> {
+    let previous = options.from.as_deref().map(ScanState::load).transpose()?;
+    let (value_type, endian) = match previous {
+        Some(ref state) => (state.value_type, state.endian),
+        None => (
+            options
+                .value_type
+                .ok_or_else(|| Error::Usage("Missing --type".into()))?,
+            options.endian,
+        ),
+    };
+    let exact = match options.refine {
+        Some(Refine::Value(ref value)) => value_type.encode(value, endian)?,
+        _ => Vec::new(),
+    };
+    let size = value_type.size();
+
+    let (mut reader, memory) = options.target.open()?;
+    let pid = reader.pid();
+    if let Some(ref state) = previous {
+        if state.pid != pid {
+            warn!("Scan state was saved for PID {}, not {}", state.pid, pid);
+        }
+    }
+    let mut symbols = Symbolizer::new(&memory, reader.live_pid());
+
+    let mut buffer = Vec::new();
+    let mut hits = Vec::new();
+    let regions = memory
+        .filter(&options.filter)
+        .filter(|r| r.perms.has_perm(PermissionBits::Read));
+
+    match previous {
+        None => {
+            let align = options.align.unwrap_or(size);
+            for region in regions {
+                signal::check()?;
+                if !read_region(&mut reader, region, &mut buffer) {
+                    continue;
+                }
+                for offset in scan_buffer(&buffer[..], region.start, &exact[..], align) {
+                    let hit = Hit {
+                        address: region.start + offset,
+                        value: exact.clone(),
+                    };
+                    print_value_hit(&mut symbols, region, &hit, value_type, endian);
+                    hits.push(hit);
+                }
+            }
+        }
+        Some(state) => {
+            let refine = options
+                .refine
+                .as_ref()
+                .expect("Refining requires a condition");
+            let mut previous = state.hits;
+            previous.sort_by_key(|h| h.address);
+            for region in regions {
+                signal::check()?;
+                let start = previous.partition_point(|h| h.address < region.start);
+                let end = previous.partition_point(|h| h.address + size <= region.end);
+                if start >= end || !read_region(&mut reader, region, &mut buffer) {
+                    continue;
+                }
+                for old in &previous[start..end] {
+                    let offset = old.address - region.start;
+                    let new = &buffer[offset..offset + size];
+                    if refine.keep(value_type, endian, &old.value[..], new, &exact[..]) {
+                        let hit = Hit {
+                            address: old.address,
+                            value: new.to_vec(),
+                        };
+                        print_value_hit(&mut symbols, region, &hit, value_type, endian);
+                        hits.push(hit);
+                    }
+                }
+            }
+        }
+    }
+    info!("{} hits for {}", hits.len(), value_type);
+
+    if let Some(ref path) = options.save {
+        ScanState {
+            pid,
+            value_type,
+            endian,
+            hits,
+        }
+        .save(path)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(
+            ValueType::I32.encode("1337", Endian::Little).unwrap(),
+            vec![0x39, 0x05, 0, 0]
+        );
+        assert_eq!(
+            ValueType::I16.encode("-2", Endian::Big).unwrap(),
+            vec![0xff, 0xfe]
+        );
+        assert_eq!(
+            ValueType::F32.encode("1.5", Endian::Little).unwrap(),
+            1.5f32.to_le_bytes().to_vec()
+        );
+        assert!(ValueType::U8.encode("256", Endian::Little).is_err());
+    }
+
+    #[test]
+    fn test_refine() {
+        let (t, e) = (ValueType::I16, Endian::Little);
+        let old = (-5i16).to_le_bytes();
+        let new = 3i16.to_le_bytes();
+        assert!(Refine::Increased.keep(t, e, &old, &new, &[]));
+        assert!(!Refine::Decreased.keep(t, e, &old, &new, &[]));
+        assert!(Refine::Changed.keep(t, e, &old, &new, &[]));
+        assert!(!Refine::Unchanged.keep(t, e, &old, &new, &[]));
+        assert!(Refine::Value("3".into()).keep(t, e, &old, &new, &new));
+    }
+
+    #[test]
+    fn test_state_roundtrip() {
+        let state = ScanState {
+            pid: 42,
+            value_type: ValueType::U16,
+            endian: Endian::Big,
+            hits: vec![Hit {
+                address: 0x7fff0010,
+                value: vec![0x12, 0x34],
+            }],
+        };
+        let parsed = ScanState::from_json(&state.to_json().to_string().parse().unwrap());
+        assert_eq!(parsed.unwrap(), state);
+    }
+
+    #[test]
+    fn test_scan_buffer() {
+        let data = [0x39, 0x05, 0, 0, 0, 0x39, 0x05, 0, 0, 0, 0, 0];
+        assert_eq!(scan_buffer(&data, 0x1000, &[0x39, 0x05, 0, 0], 4), vec![0]);
+        assert_eq!(
+            scan_buffer(&data, 0x1000, &[0x39, 0x05, 0, 0], 1),
+            vec![0, 5]
+        );
+        // base is misaligned by 3 bytes, so the first aligned offset is 1
+        assert_eq!(scan_buffer(&data, 0x1003, &[0x39, 0x05, 0, 0], 4), vec![5]);
+    }
+}
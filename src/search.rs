@@ -0,0 +1,230 @@
+use std::fmt;
+use std::ops::Range;
+
+use crate::cli::{self, SearchOptions};
+use crate::error::Chain;
+use crate::hexdump;
+use crate::memmem::{self, Finder};
+use crate::memory::{Memory, PermissionBits, Region, RegionFilter};
+use crate::signal;
+use crate::symbols::Symbolizer;
+use crate::target::Reader;
+use crate::{Error, Result};
+
+/// Prints an address with its location in its module and its owning region
+pub fn print_hit(symbols: &mut Symbolizer, region: &Region, address: usize) {
+    println!(
+        "0x{:x} {} {:x}-{:x} {} +0x{:x} {}",
+        address,
+        symbols.describe(region, address),
+        region.start,
+        region.end,
+        region.perms.to_maps_string(),
+        address - region.start,
+        region.path().unwrap_or("")
+    );
+}
+
+/// Bytes to look for, some of which may be wildcards (`??` in hex patterns)
+#[derive(Debug, Clone, PartialEq)]
+pub struct BytePattern {
+    /// The pattern as given, to tell hits of several patterns apart
+    text: String,
+    bytes: Vec<Option<u8>>,
+
+    /// Longest run of literal bytes, searched for before the whole pattern is checked
+    anchor: Range<usize>,
+}
+
+impl BytePattern {
+    /// Parses a string, or hex-encoded bytes if `hex` is set
+    pub fn parse(text: &str, hex: bool) -> Result<Self> {
+        let bytes = match hex {
+            true => cli::parse_hex_pattern(text)?,
+            false => text.bytes().map(Some).collect(),
+        };
+        let mut anchor = 0..0;
+        let mut start = 0;
+        for (i, byte) in bytes.iter().enumerate() {
+            if byte.is_none() {
+                start = i + 1;
+            } else if i + 1 - start > anchor.len() {
+                anchor = start..i + 1;
+            }
+        }
+        if bytes.is_empty() {
+            return Err(Error::Usage("Empty PATTERN".into()));
+        }
+        if anchor.is_empty() {
+            return Err(Error::Usage(format!(
+                "Pattern {:?} only has wildcards",
+                text
+            )));
+        }
+        Ok(Self {
+            text: text.into(),
+            bytes,
+            anchor,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Returns the offsets of the occurrences, which may overlap, of the pattern in `data`
+    pub fn find_all(&self, data: &[u8], ignore_case: bool) -> Vec<usize> {
+        let anchor: Vec<u8> = self.bytes[self.anchor.clone()]
+            .iter()
+            .flatten()
+            .copied()
+            .collect();
+        // Without wildcards, the anchor is the whole pattern
+        if anchor.len() == self.len() {
+            return match ignore_case {
+                true => memmem::find_iter_no_case(data, &anchor).collect(),
+                false => memmem::find_iter(data, &anchor).collect(),
+            };
+        }
+        let finder = match ignore_case {
+            true => Finder::new_no_case(&anchor),
+            false => Finder::new(&anchor),
+        };
+        let eq = |a: u8, b: u8| match ignore_case {
+            true => a.eq_ignore_ascii_case(&b),
+            false => a == b,
+        };
+        let mut hits = Vec::new();
+        // Position of the anchor from which to look for the next one
+        let mut from = self.anchor.start;
+        while let Some(found) = data.get(from..).and_then(|rest| finder.find(rest)) {
+            let start = from + found - self.anchor.start;
+            let candidate = data.get(start..start + self.len());
+            if candidate.is_some_and(|candidate| {
+                candidate
+                    .iter()
+                    .zip(&self.bytes)
+                    .all(|(&b, p)| p.is_none_or(|p| eq(b, p)))
+            }) {
+                hits.push(start);
+            }
+            from += found + 1;
+        }
+        hits
+    }
+}
+
+impl fmt::Display for BytePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Prints the occurrences of `patterns` in the readable regions selected by `filter`
+///
+/// Hits are prefixed with their pattern when there are several, and followed by a hexdump of
+/// `context` bytes around them.
+pub fn search(
+    reader: &mut Reader,
+    memory: &Memory,
+    filter: &RegionFilter,
+    patterns: &[BytePattern],
+    ignore_case: bool,
+    context: usize,
+) -> Result<()> {
+    let mut symbols = Symbolizer::new(memory, reader.live_pid());
+
+    let mut buffer = Vec::new();
+    for region in memory
+        .filter(filter)
+        .filter(|r| r.perms.has_perm(PermissionBits::Read))
+    {
+        signal::check()?;
+        buffer.clear();
+        if let Err(e) = reader.dump(region, &mut buffer) {
+            warn!(
+                "Could not read region {:x}-{:x} {} ({}): {}",
+                region.start,
+                region.end,
+                region.perms,
+                region.path().unwrap_or("no file"),
+                Chain(&e)
+            );
+            continue;
+        }
+        let mut hits: Vec<(usize, usize)> = patterns
+            .iter()
+            .enumerate()
+            .flat_map(|(index, pattern)| {
+                pattern
+                    .find_all(&buffer, ignore_case)
+                    .into_iter()
+                    .map(move |offset| (offset, index))
+            })
+            .collect();
+        hits.sort_unstable();
+        for (offset, index) in hits {
+            if patterns.len() > 1 {
+                print!("{} ", patterns[index]);
+            }
+            print_hit(&mut symbols, region, region.start + offset);
+            if context > 0 {
+                let start = offset.saturating_sub(context);
+                let end = (offset + patterns[index].len())
+                    .saturating_add(context)
+                    .min(buffer.len());
+                for line in hexdump::lines(region.start + start, &buffer[start..end]) {
+                    println!("{}", line);
+                }
+                println!();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn run(options: &SearchOptions) -> Result<()> {
+    let (mut reader, memory) = options.target.open()?;
+    search(
+        &mut reader,
+        &memory,
+        &options.filter,
+        &options.patterns,
+        options.ignore_case,
+        options.context,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let pattern = BytePattern::parse("de ad ?? ?? be ef 00", true).unwrap();
+        assert_eq!(pattern.len(), 7);
+        assert_eq!(pattern.anchor, 4..7);
+        assert_eq!(pattern.to_string(), "de ad ?? ?? be ef 00");
+        assert_eq!(BytePattern::parse("a??", false).unwrap().anchor, 0..3);
+        assert!(BytePattern::parse("?? ??", true).is_err());
+        assert!(BytePattern::parse("de a?", true).is_err());
+        assert!(BytePattern::parse("", false).is_err());
+    }
+
+    #[test]
+    fn test_find_all() {
+        let pattern = BytePattern::parse("41 ?? 41 42", true).unwrap();
+        assert_eq!(pattern.find_all(b"AAABzzAxAB", false), [0, 6]);
+        // The anchor `41 42` is first found where the pattern does not match
+        assert_eq!(pattern.find_all(b"zzABAxAB", false), [4]);
+        assert_eq!(pattern.find_all(b"axab", true), [0]);
+        assert!(pattern.find_all(b"axab", false).is_empty());
+        assert!(pattern.find_all(b"AB", false).is_empty());
+
+        let pattern = BytePattern::parse("aa", false).unwrap();
+        assert_eq!(pattern.find_all(b"aaaaa", false), [0, 1, 2, 3]);
+        let pattern = BytePattern::parse("?? 61", true).unwrap();
+        assert_eq!(pattern.find_all(b"aaaaa", false), [0, 1, 2, 3]);
+    }
+}
@@ -0,0 +1,404 @@
+//! Dumps of a child process whose memory is known, checked against it
+//!
+//! The child is this program again, run as `selftest --child`. It fills a dense mapping with words
+//! derived from their own address, and a sparse one of which only some pages are touched, prints
+//! where they are, then waits for its standard input to be closed. Each configuration dumps it with
+//! `dump`, and reads the dump back as `--from-dump` or `--from-core` do, comparing the test memory
+//! word by word.
+//!
+//! With `--bench`, the child is only dumped again and again with each backend measured, in turn so
+//! that they share the changes of load of the machine, and the median time of each is reported.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::process::{self, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::cli::{DumpOptions, SelftestOptions};
+use crate::dump;
+use crate::error::Chain;
+use crate::output::Format;
+use crate::pagemap;
+use crate::procfs;
+use crate::ptrace::Backend;
+use crate::target::Target;
+#[cfg(feature = "uring")]
+use crate::uring::Uring;
+use crate::{Error, Result};
+
+/// Mixed with the address of each word, so that zeros never match
+const SEED: u64 = 0x5e1f_7e57_5e1f_7e57;
+
+/// One page of the sparse mapping out of `SPARSE_STRIDE` is touched
+const SPARSE_STRIDE: usize = 16;
+
+/// Bytes read back from a dump at once
+const CHUNK: usize = 1 << 20;
+
+/// Configurations tested, with the options given to `dump`
+const CONFIGS: &[(&str, &[&str])] = &[
+    ("read", &[]),
+    #[cfg(feature = "uring")]
+    ("uring", &["--backend", "uring"]),
+    ("seize", &["--seize"]),
+    ("minimize-pause", &["--minimize-pause"]),
+    ("no-stop", &["--no-stop"]),
+    ("jobs", &["--jobs", "4"]),
+    ("dedup", &["--format", "dedup"]),
+    ("core", &["--format", "core"]),
+];
+
+/// Dumps of the child made with each backend by `--bench`
+const BENCH_RUNS: usize = 5;
+
+fn pattern(address: usize) -> u64 {
+    address as u64 ^ SEED
+}
+
+/// Where the test memory of the child is, as it prints it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Layout {
+    dense: usize,
+    sparse: usize,
+    size: usize,
+}
+
+impl Layout {
+    /// Parses `DENSE SPARSE SIZE`, in hexadecimal
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line
+            .split_ascii_whitespace()
+            .map(|f| usize::from_str_radix(f, 16).ok());
+        let layout = Self {
+            dense: fields.next()??,
+            sparse: fields.next()??,
+            size: fields.next()??,
+        };
+        fields.next().is_none().then_some(layout)
+    }
+
+    /// Expected word at `address`, in one of the test mappings
+    fn expected(&self, address: usize, page_size: usize) -> u64 {
+        let dense = (self.dense..self.dense + self.size).contains(&address);
+        if dense || ((address - self.sparse) / page_size).is_multiple_of(SPARSE_STRIDE) {
+            pattern(address)
+        } else {
+            0
+        }
+    }
+}
+
+/// Fills the test memory and waits, as the child
+fn run_child(size: usize) -> Result<()> {
+    let page_size = pagemap::page_size();
+    // Large zeroed allocations are fresh mappings, whose pages are not present until touched
+    let mut dense = vec![0u64; size / 8];
+    for word in dense.iter_mut() {
+        *word = pattern(word as *mut u64 as usize);
+    }
+    let mut sparse = vec![0u64; size / 8];
+    for page in sparse.chunks_mut(page_size / 8).step_by(SPARSE_STRIDE) {
+        for word in page {
+            *word = pattern(word as *mut u64 as usize);
+        }
+    }
+
+    let mut stdout = io::stdout();
+    writeln!(
+        stdout,
+        "{:x} {:x} {:x}",
+        dense.as_ptr() as usize,
+        sparse.as_ptr() as usize,
+        size
+    )?;
+    stdout.flush()?;
+    io::stdin().read_to_end(&mut Vec::new())?;
+    // The memory is never read here, it must still be written
+    std::hint::black_box((dense, sparse));
+    Ok(())
+}
+
+/// The child under test, killed when dropped
+#[derive(Debug)]
+struct Child {
+    process: process::Child,
+    layout: Layout,
+}
+
+impl Child {
+    fn spawn(size: usize) -> Result<Self> {
+        let mut process = process::Command::new(std::env::current_exe()?)
+            .args(["selftest", "--child", "--size", &size.to_string()])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdout = process.stdout.take();
+        let mut child = Self {
+            process,
+            layout: Layout {
+                dense: 0,
+                sparse: 0,
+                size: 0,
+            },
+        };
+        let mut line = String::new();
+        if let Some(stdout) = stdout {
+            BufReader::new(stdout).read_line(&mut line)?;
+        }
+        child.layout = Layout::parse(&line).ok_or_else(|| {
+            io::Error::other(format!("unexpected output of the child: {:?}", line))
+        })?;
+        Ok(child)
+    }
+
+    fn pid(&self) -> u32 {
+        self.process.id()
+    }
+}
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+/// Compares the test memory with the dump `target`, returning the bytes of memory it holds, or
+/// the first difference
+fn check(child: &Child, target: &Target) -> std::result::Result<u64, String> {
+    let (mut reader, memory) = target.open().map_err(|e| Chain(&e).to_string())?;
+    let layout = child.layout;
+    let page_size = pagemap::page_size();
+    let mut buffer = vec![0u8; CHUNK];
+    for start in [layout.dense, layout.sparse] {
+        for offset in (0..layout.size).step_by(CHUNK) {
+            let address = start + offset;
+            let buffer = &mut buffer[..CHUNK.min(layout.size - offset)];
+            if let Err(e) = reader.read(address, buffer) {
+                return Err(format!("cannot read 0x{:x}: {}", address, Chain(&e)));
+            }
+            for (index, word) in buffer.chunks_exact(8).enumerate() {
+                let address = address + index * 8;
+                let found = u64::from_ne_bytes(word.try_into().unwrap());
+                let expected = layout.expected(address, page_size);
+                if found != expected {
+                    return Err(format!(
+                        "0x{:016x} at 0x{:x}, expected 0x{:016x}",
+                        found, address, expected
+                    ));
+                }
+            }
+        }
+    }
+    Ok(memory.iter().map(|r| r.size() as u64).sum())
+}
+
+/// Dumps the child to `path` with `args`, and checks the dump
+///
+/// Returns the time the dump took and the bytes of memory it holds, or why the test failed.
+fn test(
+    child: &Child,
+    args: &[&str],
+    path: &Path,
+) -> Result<std::result::Result<(Duration, u64), String>> {
+    // The test memory is anonymous, the mappings of the program itself are not worth the time
+    let options = DumpOptions::parse(
+        args.iter()
+            .chain(&["--kind", "anonymous"])
+            .map(|a| a.to_string())
+            .chain([child.pid().to_string(), path.display().to_string()]),
+    )?;
+    let started = Instant::now();
+    match dump::run(&options) {
+        Ok(()) => {}
+        Err(Error::Interrupted) => return Err(Error::Interrupted),
+        Err(e) => return Ok(Err(Chain(&e).to_string())),
+    }
+    let elapsed = started.elapsed();
+
+    let status = procfs::status(child.pid())?;
+    if status.tracer_pid != 0 {
+        return Ok(Err(format!("still traced by {}", status.tracer_pid)));
+    }
+    if matches!(procfs::stat(child.pid())?.state, 't' | 'T') {
+        return Ok(Err("left stopped".into()));
+    }
+
+    let target = match options.format {
+        Format::Core => Target::Core(path.into()),
+        _ => Target::Dump(path.into()),
+    };
+    Ok(check(child, &target).map(|bytes| (elapsed, bytes)))
+}
+
+/// Dumps the child `BENCH_RUNS` times with each of `backends`, printing the time of each dump and
+/// the median throughput of each backend
+fn bench(child: &Child, backends: &[Backend], output: &Path) -> Result<()> {
+    let mut backends: Vec<_> = backends.iter().map(|&b| (b, Vec::new())).collect();
+    backends.retain(|&(backend, _)| match backend {
+        #[cfg(feature = "uring")]
+        Backend::Uring => match Uring::new(1) {
+            Ok(_) => true,
+            Err(e) => {
+                println!("{:8} skipped: {}", backend.to_string(), e);
+                false
+            }
+        },
+        Backend::Read => true,
+    });
+    let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+    println!("{} CPUs", cpus);
+
+    println!(
+        "{:8} {:>4} {:>8} {:>12}",
+        "BACKEND", "RUN", "TIME", "THROUGHPUT"
+    );
+    let mut bytes = 0;
+    for run in 1..=BENCH_RUNS {
+        for (backend, times) in backends.iter_mut() {
+            let path = output.join(backend.to_string());
+            if path.exists() {
+                std::fs::remove_dir_all(&path)?;
+            }
+            let options = DumpOptions::parse(
+                ["--backend", &backend.to_string(), "--kind", "anonymous"]
+                    .map(String::from)
+                    .into_iter()
+                    .chain([child.pid().to_string(), path.display().to_string()]),
+            )?;
+            let started = Instant::now();
+            dump::run(&options)?;
+            let elapsed = started.elapsed();
+            let (_, memory) = Target::Dump(path).open()?;
+            bytes = memory.iter().map(|r| r.size() as u64).sum();
+            println!(
+                "{:8} {:>4} {:>7.2}s {:>8.0} MiB/s",
+                backend.to_string(),
+                run,
+                elapsed.as_secs_f64(),
+                bytes as f64 / (1 << 20) as f64 / elapsed.as_secs_f64()
+            );
+            times.push(elapsed);
+        }
+    }
+
+    for (backend, mut times) in backends {
+        times.sort();
+        let median = times[times.len() / 2];
+        println!(
+            "{:8} {:>4} {:>7.2}s {:>8.0} MiB/s",
+            backend.to_string(),
+            "med",
+            median.as_secs_f64(),
+            bytes as f64 / (1 << 20) as f64 / median.as_secs_f64()
+        );
+    }
+    Ok(())
+}
+
+pub fn run(options: &SelftestOptions) -> Result<()> {
+    let page_size = pagemap::page_size();
+    let size = options.size.div_ceil(page_size) * page_size;
+    if options.child {
+        return run_child(size);
+    }
+
+    let (output, temporary) = match options.output {
+        Some(ref path) => (path.clone(), false),
+        None => (
+            std::env::temp_dir().join(format!("dump-memory-selftest-{}", process::id())),
+            true,
+        ),
+    };
+    std::fs::create_dir_all(&output)?;
+    let child = Child::spawn(size)?;
+    info!(
+        "Testing process {}, with {} bytes of dense and sparse memory",
+        child.pid(),
+        size
+    );
+    if !options.bench.is_empty() {
+        let result = bench(&child, &options.bench, &output);
+        drop(child);
+        if temporary {
+            // Best effort, the results matter more
+            let _ = std::fs::remove_dir_all(&output);
+        }
+        return result;
+    }
+
+    println!("{:16} {:>8} {:>12}  RESULT", "CONFIG", "TIME", "THROUGHPUT");
+    let mut failed = 0;
+    for &(name, args) in CONFIGS {
+        #[cfg(feature = "uring")]
+        if args.contains(&"uring") {
+            if let Err(e) = Uring::new(1) {
+                println!("{:16} {:>8} {:>12}  skipped: {}", name, "-", "-", e);
+                continue;
+            }
+        }
+        let path = match args {
+            [.., "--format", "core"] => output.join(format!("{}.core", name)),
+            _ => output.join(name),
+        };
+        match test(&child, args, &path)? {
+            Ok((elapsed, bytes)) => println!(
+                "{:16} {:>7.2}s {:>8.0} MiB/s  ok",
+                name,
+                elapsed.as_secs_f64(),
+                bytes as f64 / (1 << 20) as f64 / elapsed.as_secs_f64()
+            ),
+            Err(reason) => {
+                failed += 1;
+                println!("{:16} {:>8} {:>12}  FAILED: {}", name, "-", "-", reason);
+            }
+        }
+    }
+    drop(child);
+    if temporary {
+        // Best effort, the results matter more
+        let _ = std::fs::remove_dir_all(&output);
+    }
+
+    if failed > 0 {
+        return Err(Error::SelftestFailed {
+            failed,
+            total: CONFIGS.len(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout() {
+        let layout = Layout::parse("7f0000000000 7f0000100000 40000\n").unwrap();
+        assert_eq!(
+            layout,
+            Layout {
+                dense: 0x7f0000000000,
+                sparse: 0x7f0000100000,
+                size: 0x40000,
+            }
+        );
+        assert_eq!(Layout::parse("7f0000000000 7f0000100000"), None);
+        assert_eq!(Layout::parse("7f0000000000 7f0000100000 40000 1"), None);
+
+        assert_eq!(
+            layout.expected(0x7f0000000008, 0x1000),
+            pattern(0x7f0000000008)
+        );
+        assert_eq!(
+            layout.expected(0x7f0000100ff8, 0x1000),
+            pattern(0x7f0000100ff8)
+        );
+        assert_eq!(layout.expected(0x7f0000101000, 0x1000), 0);
+        assert_eq!(
+            layout.expected(0x7f0000110000, 0x1000),
+            pattern(0x7f0000110000)
+        );
+    }
+}
@@ -0,0 +1,73 @@
+//! Interruption by SIGINT or SIGTERM
+//!
+//! The first signal only sets a flag, which long running commands check between steps: they stop,
+//! detach from the process and write what they have. A second signal exits right away, possibly
+//! leaving the process stopped.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::{Error, Result};
+
+const SIGINT: i32 = 2;
+const SIGTERM: i32 = 15;
+const SIG_ERR: usize = usize::MAX;
+
+/// Exit status after an interruption, as reported by shells for SIGINT
+pub const EXIT_STATUS: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" {
+    fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    fn _exit(status: i32) -> !;
+}
+
+extern "C" fn handler(_signum: i32) {
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        // SAFETY: `_exit` is async-signal-safe
+        unsafe { _exit(EXIT_STATUS) };
+    }
+}
+
+pub fn install() -> Result<()> {
+    for signum in [SIGINT, SIGTERM] {
+        // SAFETY: the handler only touches an atomic, or exits
+        if unsafe { signal(signum, handler) } == SIG_ERR {
+            return Err(io::Error::last_os_error().into());
+        }
+    }
+    Ok(())
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Forgets the signal received, for commands which go on after an interruption
+pub fn reset() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+}
+
+/// Fails with [`Error::Interrupted`] once a signal was received
+pub fn check() -> Result<()> {
+    if interrupted() {
+        Err(Error::Interrupted)
+    } else {
+        Ok(())
+    }
+}
+
+/// Sleeps for `duration`, unless interrupted
+pub fn sleep(duration: Duration) -> Result<()> {
+    let deadline = Instant::now() + duration;
+    loop {
+        check()?;
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(());
+        }
+        std::thread::sleep((deadline - now).min(Duration::from_millis(100)));
+    }
+}
@@ -0,0 +1,179 @@
+//! Reading back dumps written in the `dir` and `dedup` formats, and ELF core files
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::corefile::CoreFile;
+use crate::dedup;
+use crate::manifest::{Entry, Manifest, Status};
+use crate::memory::Memory;
+use crate::{Error, Result};
+
+/// Finds the entry holding `address`, and the number of bytes which can be read from there
+///
+/// Fails with the reason why the byte at `address` cannot be read.
+fn locate(entries: &[Entry], address: usize) -> std::result::Result<(usize, usize), &'static str> {
+    let idx = entries
+        .iter()
+        .position(|e| e.region.start <= address && address < e.region.end)
+        .ok_or("not mapped")?;
+    let entry = &entries[idx];
+    if entry.status != Status::Dumped {
+        return Err("not dumped");
+    }
+    if entry.unreadable.iter().any(|r| r.contains(&address)) {
+        return Err("could not be read when dumped");
+    }
+    let end = entry
+        .unreadable
+        .iter()
+        .map(|r| r.start)
+        .filter(|&start| start > address)
+        .fold(entry.region.end, usize::min);
+    Ok((idx, end - address))
+}
+
+/// A dump directory and its manifest
+#[derive(Debug)]
+pub struct Snapshot {
+    dir: PathBuf,
+    /// Where the data is for core files, described by a manifest built from their segments
+    core: Option<CoreFile>,
+    pub manifest: Manifest,
+
+    /// Index and content of the last entry read by [`Snapshot::read_at`]
+    cache: Option<(usize, Vec<u8>)>,
+}
+
+impl Snapshot {
+    /// Opens a dump directory, the manifest file itself, or a core file
+    pub fn open(path: &Path) -> Result<Self> {
+        if CoreFile::is_elf(path) {
+            let core = CoreFile::open(path)?;
+            return Ok(Self {
+                dir: PathBuf::new(),
+                manifest: core.manifest(),
+                core: Some(core),
+                cache: None,
+            });
+        }
+        let (dir, manifest_path) = if path.is_dir() {
+            (path.to_path_buf(), path.join("manifest.json"))
+        } else {
+            let dir = path.parent().unwrap_or_else(|| Path::new("."));
+            (dir.to_path_buf(), path.to_path_buf())
+        };
+        Ok(Self {
+            dir,
+            core: None,
+            manifest: Manifest::load(&manifest_path)?,
+            cache: None,
+        })
+    }
+
+    /// Path of `name` in the dump directory, `None` for core files
+    pub fn path(&self, name: &str) -> Option<PathBuf> {
+        self.core.is_none().then(|| self.dir.join(name))
+    }
+
+    /// Regions of the process when it was dumped, sorted by address
+    pub fn memory(&self) -> Memory {
+        let mut regions: Vec<_> = self
+            .manifest
+            .entries
+            .iter()
+            .map(|e| e.region.clone())
+            .collect();
+        regions.sort_by_key(|r| r.start);
+        Memory::from_regions(self.manifest.pid, regions)
+    }
+
+    /// Reads and decompresses the content of a region, if it was stored in its own file
+    ///
+    /// Regions of core files are read from their segment, with zeros for the pages not saved.
+    pub fn read(&self, entry: &Entry) -> Result<Option<Vec<u8>>> {
+        if let Some(ref core) = self.core {
+            return core.read_segment(entry.region.start).map(Some);
+        }
+        match (&entry.status, &entry.file) {
+            (Status::Dumped, Some(file)) => {
+                let data = std::fs::read(self.dir.join(file))?;
+                if let Some(ref store) = self.manifest.store {
+                    let store_dir = self.dir.join(&store.path);
+                    return Ok(Some(dedup::read(&data[..], &store_dir, store.page_size)?));
+                }
+                Ok(Some(self.manifest.compression.decode(&data[..])?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Reads `buffer.len()` bytes at `addr`, failing if any of them was not dumped
+    ///
+    /// The content of the last region read is kept, for reads close to each other.
+    pub fn read_at(&mut self, addr: usize, buffer: &mut [u8]) -> Result<()> {
+        let mut done = 0;
+        while done < buffer.len() {
+            let address = addr + done;
+            let (idx, len) =
+                locate(&self.manifest.entries, address).map_err(|reason| Error::Read {
+                    pid: self.manifest.pid,
+                    address,
+                    source: io::Error::other(reason),
+                })?;
+            if self.cache.as_ref().is_none_or(|(cached, _)| *cached != idx) {
+                let entry = &self.manifest.entries[idx];
+                let data = self.read(entry)?.ok_or_else(|| Error::Read {
+                    pid: self.manifest.pid,
+                    address,
+                    source: io::Error::other("not stored in its own file"),
+                })?;
+                self.cache = Some((idx, data));
+            }
+            let (_, data) = self.cache.as_ref().expect("Region was just cached");
+            let offset = address - self.manifest.entries[idx].region.start;
+            let len = len.min(buffer.len() - done);
+            let data = data.get(offset..offset + len).ok_or_else(|| Error::Read {
+                pid: self.manifest.pid,
+                address,
+                source: io::Error::other("region file is truncated"),
+            })?;
+            buffer[done..done + len].copy_from_slice(data);
+            done += len;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locate() {
+        let region = |line: &str| line.parse().unwrap();
+        let mut heap = Entry::failed(
+            &region("55d000000000-55d000004000 rw-p 00000000 00:00 0 [heap]"),
+            None,
+            String::new(),
+        );
+        heap.status = Status::Dumped;
+        let unreadable = 0x55d0_0000_1000..0x55d0_0000_2000;
+        heap.unreadable = std::slice::from_ref(&unreadable).to_vec();
+        let vvar = Entry::failed(
+            &region("7ffc00000000-7ffc00004000 r--p 00000000 00:00 0 [vvar]"),
+            None,
+            "Input/output error".into(),
+        );
+        let entries = [heap, vvar];
+
+        assert_eq!(locate(&entries, 0x55d0_0000_0010), Ok((0, 0xff0)));
+        assert_eq!(
+            locate(&entries, 0x55d0_0000_1800),
+            Err("could not be read when dumped")
+        );
+        assert_eq!(locate(&entries, 0x55d0_0000_2000), Ok((0, 0x2000)));
+        assert_eq!(locate(&entries, 0x7ffc_0000_0000), Err("not dumped"));
+        assert_eq!(locate(&entries, 0x1000), Err("not mapped"));
+    }
+}
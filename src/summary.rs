@@ -0,0 +1,301 @@
+//! Memory usage of a process, like `pmap -x`, from `/proc/PID/smaps`
+
+use std::collections::HashMap;
+
+use crate::cli::SummaryOptions;
+use crate::dump::human_size;
+use crate::json::Value;
+use crate::manifest;
+use crate::maps::{kib, ListFormat};
+use crate::memory::{Memory, Region, RegionKind};
+use crate::Result;
+
+/// Sizes added up over some regions, in bytes
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Usage {
+    regions: usize,
+    size: u64,
+    rss: u64,
+    pss: u64,
+    swap: u64,
+    shared_dirty: u64,
+    private_dirty: u64,
+}
+
+impl Usage {
+    fn add(&mut self, region: &Region) {
+        let stats = region.stats.clone().unwrap_or_default();
+        self.regions += 1;
+        self.size += region.size() as u64;
+        self.rss += stats.rss;
+        self.pss += stats.pss;
+        self.swap += stats.swap;
+        self.shared_dirty += stats.shared_dirty;
+        self.private_dirty += stats.private_dirty;
+    }
+
+    fn to_json(&self) -> Value {
+        Value::object([
+            ("regions", self.regions.into()),
+            ("size", self.size.into()),
+            ("rss", self.rss.into()),
+            ("pss", self.pss.into()),
+            ("swap", self.swap.into()),
+            ("shared_dirty", self.shared_dirty.into()),
+            ("private_dirty", self.private_dirty.into()),
+        ])
+    }
+}
+
+/// Usage of the regions of a process, as a whole and by group
+#[derive(Debug)]
+struct Summary<'a> {
+    total: Usage,
+
+    /// By kind of region, the largest resident first
+    kinds: Vec<(String, Usage)>,
+
+    /// By mapped file, the largest resident first
+    files: Vec<(String, Usage)>,
+
+    /// Largest regions, by virtual size
+    largest: Vec<&'a Region>,
+}
+
+/// Groups the usage by key, the largest resident first
+fn sorted(groups: HashMap<String, Usage>) -> Vec<(String, Usage)> {
+    let mut groups: Vec<_> = groups.into_iter().collect();
+    groups.sort_by(|(a_key, a), (b_key, b)| {
+        (b.rss, b.size)
+            .cmp(&(a.rss, a.size))
+            .then_with(|| a_key.cmp(b_key))
+    });
+    groups
+}
+
+impl<'a> Summary<'a> {
+    /// Adds up the usage of `regions`, keeping the `top` largest ones
+    fn new(regions: impl Iterator<Item = &'a Region>, top: usize) -> Self {
+        let mut total = Usage::default();
+        let mut kinds: HashMap<String, Usage> = HashMap::new();
+        let mut files: HashMap<String, Usage> = HashMap::new();
+        let mut largest = Vec::new();
+        for region in regions {
+            total.add(region);
+            kinds
+                .entry(region.kind.to_string())
+                .or_default()
+                .add(region);
+            if let (RegionKind::File, Some(path)) = (&region.kind, region.path()) {
+                files.entry(path.into()).or_default().add(region);
+            }
+            largest.push(region);
+        }
+        largest.sort_by_key(|r| (std::cmp::Reverse(r.size()), r.start));
+        largest.truncate(top);
+        Self {
+            total,
+            kinds: sorted(kinds),
+            files: sorted(files),
+            largest,
+        }
+    }
+
+    fn to_json(&self, pid: u32) -> Value {
+        let groups = |groups: &[(String, Usage)], key: &str| {
+            Value::Array(
+                groups
+                    .iter()
+                    .map(|(name, usage)| {
+                        let mut value = usage.to_json();
+                        value.push(key, name.as_str());
+                        value
+                    })
+                    .collect(),
+            )
+        };
+        Value::object([
+            ("pid", pid.into()),
+            ("total", self.total.to_json()),
+            ("kinds", groups(&self.kinds, "kind")),
+            ("files", groups(&self.files, "path")),
+            (
+                "largest",
+                Value::Array(
+                    self.largest
+                        .iter()
+                        .map(|r| manifest::region_to_json(r))
+                        .collect(),
+                ),
+            ),
+        ])
+    }
+
+    fn print(&self, pid: u32) {
+        let total = &self.total;
+        let percent = match total.size {
+            0 => 0.0,
+            size => total.rss as f64 * 100.0 / size as f64,
+        };
+        println!(
+            "Process {}: {} regions, {} virtual, {} resident ({:.1}%), {} swapped",
+            pid,
+            total.regions,
+            human_size(total.size),
+            human_size(total.rss),
+            percent,
+            human_size(total.swap)
+        );
+        println!(
+            "PSS {}, private dirty {}, shared dirty {}",
+            human_size(total.pss),
+            human_size(total.private_dirty),
+            human_size(total.shared_dirty)
+        );
+
+        for (title, groups, column) in [
+            ("By kind:", &self.kinds, "KIND"),
+            ("By file:", &self.files, "PATH"),
+        ] {
+            if groups.is_empty() {
+                continue;
+            }
+            println!();
+            println!("{}", title);
+            println!(
+                "{:>7} {:>9} {:>9} {:>9} {:>9} {:>9} {}",
+                "REGIONS", "SIZE", "RSS", "PSS", "SWAP", "DIRTY", column
+            );
+            for (name, usage) in groups.iter() {
+                println!(
+                    "{:>7} {:>9} {:>9} {:>9} {:>9} {:>9} {}",
+                    usage.regions,
+                    kib(usage.size),
+                    kib(usage.rss),
+                    kib(usage.pss),
+                    kib(usage.swap),
+                    kib(usage.private_dirty + usage.shared_dirty),
+                    name
+                );
+            }
+        }
+
+        if !self.largest.is_empty() {
+            println!();
+            println!("Largest regions:");
+            println!(
+                "{:>16} {:>16} {:5} {:>9} {:>9} {:>9} PATH",
+                "START", "END", "PERMS", "SIZE", "RSS", "SWAP"
+            );
+            for region in &self.largest {
+                let stats = region.stats.clone().unwrap_or_default();
+                println!(
+                    "{:>16x} {:>16x} {:5} {:>9} {:>9} {:>9} {}",
+                    region.start,
+                    region.end,
+                    region.perms.to_maps_string(),
+                    kib(region.size() as u64),
+                    kib(stats.rss),
+                    kib(stats.swap),
+                    region.path().unwrap_or("")
+                );
+            }
+        }
+    }
+}
+
+pub fn run(options: &SummaryOptions) -> Result<()> {
+    let memory = Memory::from_pid_smaps(options.pid)?;
+    let summary = Summary::new(memory.filter(&options.filter), options.top);
+    match options.format {
+        ListFormat::Json => println!("{}", summary.to_json(options.pid).to_pretty_string()),
+        _ => summary.print(options.pid),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::RegionStats;
+
+    #[test]
+    fn test_summary() {
+        let region = |line: &str, rss: u64| {
+            let mut region: Region = line.parse().unwrap();
+            region.stats = Some(RegionStats {
+                rss,
+                private_dirty: rss / 2,
+                ..Default::default()
+            });
+            region
+        };
+        let regions = [
+            region(
+                "55d000000000-55d000002000 r-xp 00000000 fe:01 42 /usr/bin/prog",
+                0x2000,
+            ),
+            region(
+                "55d000002000-55d000003000 rw-p 00002000 fe:01 42 /usr/bin/prog",
+                0x1000,
+            ),
+            region(
+                "55d001000000-55d001100000 rw-p 00000000 00:00 0 [heap]",
+                0x10000,
+            ),
+            region(
+                "7f0000000000-7f0000010000 r-xp 00000000 fe:01 43 /usr/lib/libc.so.6",
+                0x8000,
+            ),
+            region(
+                "7ffc00000000-7ffc00021000 rw-p 00000000 00:00 0 [stack]",
+                0x3000,
+            ),
+        ];
+        let summary = Summary::new(regions.iter(), 2);
+        assert_eq!(
+            summary.total,
+            Usage {
+                regions: 5,
+                size: 0x134000,
+                rss: 0x1e000,
+                private_dirty: 0xf000,
+                ..Default::default()
+            }
+        );
+        let kinds: Vec<_> = summary
+            .kinds
+            .iter()
+            .map(|(kind, usage)| (kind.as_str(), usage.regions, usage.rss))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("heap", 1, 0x10000),
+                ("file", 3, 0xb000),
+                ("stack", 1, 0x3000)
+            ]
+        );
+        let files: Vec<_> = summary
+            .files
+            .iter()
+            .map(|(path, usage)| (path.as_str(), usage.size))
+            .collect();
+        assert_eq!(
+            files,
+            [("/usr/lib/libc.so.6", 0x10000), ("/usr/bin/prog", 0x3000)]
+        );
+        let largest: Vec<_> = summary.largest.iter().map(|r| r.start).collect();
+        assert_eq!(largest, [0x55d001000000, 0x7ffc00000000]);
+
+        let json = summary.to_json(42);
+        assert_eq!(json.get("pid").and_then(Value::as_u64), Some(42));
+        assert_eq!(
+            json.get("files")
+                .and_then(Value::as_array)
+                .and_then(|files| files[0].get("path"))
+                .and_then(Value::as_str),
+            Some("/usr/lib/libc.so.6")
+        );
+    }
+}
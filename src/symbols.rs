@@ -0,0 +1,359 @@
+//! Resolution of addresses to `module+offset`, and to the symbol holding them
+//!
+//! The base of a module is the start of the mapping of offset 0 of its file, as listed in the
+//! maps. Symbols come from the `.symtab` and `.dynsym` sections of the file on disk, which are not
+//! loaded in memory. Files of a running process are opened through `/proc`, which finds the files
+//! of containers, and the mapped file itself even if it was deleted. For core files and dumps,
+//! symbols are not used if the file at the mapped path was deleted or replaced since it was
+//! mapped. Core files do not record inodes, the file at the mapped path is then assumed to be the
+//! same.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read};
+use std::os::unix::fs::MetadataExt;
+
+use crate::elf::{self, ElfHeader, ProgramHeader, SectionHeader, SymbolEntry};
+use crate::memory::{Region, RegionKind};
+use crate::pagemap;
+use crate::procfs;
+use crate::{Error, Result};
+
+/// A function or object of an ELF file
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Symbol {
+    /// Address in the file, before relocation
+    address: u64,
+    size: u64,
+    name: String,
+}
+
+/// Symbols of an ELF file
+#[derive(Debug)]
+struct Symbols {
+    /// Address of the start of the file, where its base is mapped
+    start: u64,
+
+    /// Sorted by address
+    symbols: Vec<Symbol>,
+}
+
+fn truncated() -> Error {
+    Error::Elf("truncated file".into())
+}
+
+/// Returns `size` bytes at `offset` of `data`
+fn slice(data: &[u8], offset: u64, size: u64) -> Result<&[u8]> {
+    let start = usize::try_from(offset).map_err(|_| truncated())?;
+    let end = start
+        .checked_add(usize::try_from(size).map_err(|_| truncated())?)
+        .ok_or_else(truncated)?;
+    data.get(start..end).ok_or_else(truncated)
+}
+
+/// Returns the NUL-terminated string at `offset` of the string table `strtab`
+fn string_at(strtab: &[u8], offset: u32) -> Option<String> {
+    let string = strtab.get(offset as usize..)?;
+    let end = string.iter().position(|&b| b == 0)?;
+    Some(String::from_utf8_lossy(&string[..end]).into_owned())
+}
+
+impl Symbols {
+    /// Parses the functions and objects of the ELF file `data`
+    fn parse(data: &[u8]) -> Result<Self> {
+        let header = ElfHeader::parse(data)?;
+        let start = slice(
+            data,
+            header.e_phoff,
+            header.e_phnum as u64 * elf::PHDR_SIZE as u64,
+        )?
+        .chunks_exact(elf::PHDR_SIZE)
+        .map(ProgramHeader::parse)
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .find(|h| h.p_type == elf::PT_LOAD && h.p_offset == 0)
+        .map(|h| h.p_vaddr - h.p_vaddr % pagemap::page_size() as u64)
+        .ok_or_else(|| Error::Elf("no segment holds the ELF header".into()))?;
+
+        let sections = slice(
+            data,
+            header.e_shoff,
+            header.e_shnum as u64 * elf::SHDR_SIZE as u64,
+        )?
+        .chunks_exact(elf::SHDR_SIZE)
+        .map(SectionHeader::parse)
+        .collect::<Result<Vec<_>>>()?;
+
+        let mut symbols = Vec::new();
+        for table in sections
+            .iter()
+            .filter(|s| s.sh_type == elf::SHT_SYMTAB || s.sh_type == elf::SHT_DYNSYM)
+        {
+            let strtab = sections
+                .get(table.sh_link as usize)
+                .ok_or_else(|| Error::Elf("no string table for the symbols".into()))?;
+            let strtab = slice(data, strtab.sh_offset, strtab.sh_size)?;
+            for entry in slice(data, table.sh_offset, table.sh_size)?.chunks_exact(elf::SYM_SIZE) {
+                let entry = SymbolEntry::parse(entry)?;
+                if entry.st_shndx == 0
+                    || entry.st_value == 0
+                    || (entry.kind() != elf::STT_FUNC && entry.kind() != elf::STT_OBJECT)
+                {
+                    continue;
+                }
+                if let Some(name) = string_at(strtab, entry.st_name).filter(|n| !n.is_empty()) {
+                    symbols.push(Symbol {
+                        address: entry.st_value,
+                        size: entry.st_size,
+                        name,
+                    });
+                }
+            }
+        }
+        // Exported symbols are in both tables
+        symbols.sort_by(|a, b| (a.address, &a.name).cmp(&(b.address, &b.name)));
+        symbols.dedup();
+        Ok(Self { start, symbols })
+    }
+
+    /// Returns the symbol holding `offset` from the start of the file, and the offset within it
+    fn lookup(&self, offset: usize) -> Option<(&Symbol, u64)> {
+        let address = self.start.checked_add(offset as u64)?;
+        let below = &self.symbols[..self.symbols.partition_point(|s| s.address <= address)];
+        let last = below.last()?;
+        // Aliases share an address, the largest one is kept
+        let symbol = below
+            .iter()
+            .rev()
+            .take_while(|s| s.address == last.address)
+            .max_by_key(|s| s.size)?;
+        // Symbols of unknown size only hold their first byte
+        let offset = address - symbol.address;
+        (offset < symbol.size.max(1)).then_some((symbol, offset))
+    }
+}
+
+/// Where an address is, relative to its module
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    /// File name of the module
+    pub module: String,
+
+    /// Offset from the base of the module
+    pub offset: usize,
+
+    /// Symbol holding the address and the offset within it, if known
+    pub symbol: Option<(String, u64)>,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}+0x{:x}", self.module, self.offset)?;
+        if let Some((ref name, offset)) = self.symbol {
+            write!(f, "<{}+0x{:x}>", name, offset)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the base of the module mapped by `region`, among `regions`
+pub fn module_base(regions: &[Region], region: &Region) -> Option<usize> {
+    // The closest mapping of offset 0 below, if the same file is loaded more than once
+    regions
+        .iter()
+        .filter(|r| r.offset == 0 && r.start <= region.start && r.same_file(region))
+        .map(|r| r.start)
+        .max()
+}
+
+/// Reads the file mapped by `region` of a core file or dump, at the same path on this host
+fn read_host_file(region: &Region, path: &str) -> Result<Vec<u8>> {
+    let replaced =
+        !std::fs::metadata(path).is_ok_and(|m| region.inode == 0 || m.ino() == region.inode);
+    if region.deleted || replaced {
+        return Err(io::Error::other("file was deleted or replaced since it was mapped").into());
+    }
+    Ok(std::fs::read(path)?)
+}
+
+/// Resolves addresses of a process, caching the symbols of each file
+pub struct Symbolizer<'a> {
+    regions: &'a [Region],
+    /// Running process whose files are opened through `/proc`, `None` for core files and dumps
+    pid: Option<u32>,
+    files: HashMap<String, Option<Symbols>>,
+}
+
+impl<'a> Symbolizer<'a> {
+    pub fn new(regions: &'a [Region], pid: Option<u32>) -> Self {
+        Self {
+            regions,
+            pid,
+            files: HashMap::new(),
+        }
+    }
+
+    fn symbols(&mut self, region: &Region) -> Option<&Symbols> {
+        let path = region.path()?;
+        let pid = self.pid;
+        self.files
+            .entry(path.to_owned())
+            .or_insert_with(|| {
+                let data = match pid {
+                    Some(pid) => procfs::open_mapped_file(pid, region).and_then(|mut file| {
+                        let mut data = Vec::new();
+                        file.read_to_end(&mut data)?;
+                        Ok(data)
+                    }),
+                    None => read_host_file(region, path),
+                };
+                data.and_then(|data| Symbols::parse(&data))
+                    .map_err(|e| debug!("No symbols for {}: {}", path, e))
+                    .ok()
+            })
+            .as_ref()
+    }
+
+    /// Locates `address`, mapped by `region`, in its module
+    ///
+    /// Returns `None` if `region` does not map a file.
+    pub fn locate(&mut self, region: &Region, address: usize) -> Option<Location> {
+        if region.kind != RegionKind::File {
+            return None;
+        }
+        let module = region.filename()?.to_owned();
+        let offset = address - module_base(self.regions, region)?;
+        let symbol = self
+            .symbols(region)
+            .and_then(|s| s.lookup(offset))
+            .map(|(symbol, offset)| (symbol.name.clone(), offset));
+        Some(Location {
+            module,
+            offset,
+            symbol,
+        })
+    }
+
+    /// Formats the location of `address` as a single word, `-` if it is not in a module
+    pub fn describe(&mut self, region: &Region, address: usize) -> String {
+        self.locate(region, address)
+            .map_or_else(|| "-".into(), |l| l.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds an ELF file whose first segment is at 0x400000, with a symbol table
+    fn elf_file(symbols: &[(&str, u8, u64, u64)]) -> Vec<u8> {
+        let mut strtab = vec![0];
+        let mut symtab = vec![0; elf::SYM_SIZE];
+        for &(name, kind, value, size) in symbols {
+            let mut entry = vec![0; elf::SYM_SIZE];
+            entry[..4].copy_from_slice(&(strtab.len() as u32).to_ne_bytes());
+            entry[4] = kind;
+            entry[6] = 1;
+            entry[8..16].copy_from_slice(&value.to_ne_bytes());
+            entry[16..24].copy_from_slice(&size.to_ne_bytes());
+            symtab.extend_from_slice(&entry);
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+        }
+
+        let phoff = elf::EHDR_SIZE;
+        let symtab_offset = phoff + elf::PHDR_SIZE;
+        let strtab_offset = symtab_offset + symtab.len();
+        let shoff = strtab_offset + strtab.len();
+
+        let mut data = vec![0; elf::EHDR_SIZE];
+        data[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        data[32] = phoff as u8;
+        data[40..48].copy_from_slice(&(shoff as u64).to_ne_bytes());
+        data[54] = elf::PHDR_SIZE as u8;
+        data[56] = 1;
+        data[58] = elf::SHDR_SIZE as u8;
+        data[60] = 3;
+
+        let mut phdr = vec![0; elf::PHDR_SIZE];
+        phdr[..4].copy_from_slice(&elf::PT_LOAD.to_ne_bytes());
+        phdr[16..24].copy_from_slice(&0x40_0000u64.to_ne_bytes());
+        data.extend_from_slice(&phdr);
+        data.extend_from_slice(&symtab);
+        data.extend_from_slice(&strtab);
+
+        // Null section, then the symbol table linked to the string table
+        data.extend_from_slice(&[0; elf::SHDR_SIZE]);
+        for (kind, offset, size, link) in [
+            (elf::SHT_SYMTAB, symtab_offset, symtab.len(), 2u32),
+            (3, strtab_offset, strtab.len(), 0),
+        ] {
+            let mut shdr = vec![0; elf::SHDR_SIZE];
+            shdr[4..8].copy_from_slice(&kind.to_ne_bytes());
+            shdr[24..32].copy_from_slice(&(offset as u64).to_ne_bytes());
+            shdr[32..40].copy_from_slice(&(size as u64).to_ne_bytes());
+            shdr[40..44].copy_from_slice(&link.to_ne_bytes());
+            data.extend_from_slice(&shdr);
+        }
+        data
+    }
+
+    #[test]
+    fn test_lookup() {
+        let data = elf_file(&[
+            ("main", elf::STT_FUNC, 0x40_1000, 0x40),
+            ("helper", elf::STT_FUNC, 0x40_1040, 0x10),
+            ("table", elf::STT_OBJECT, 0x40_3000, 0x100),
+            ("file.c", 4, 0x40_1000, 0),
+            ("marker", elf::STT_OBJECT, 0x40_4000, 0),
+        ]);
+        let symbols = Symbols::parse(&data).unwrap();
+        assert_eq!(symbols.start, 0x40_0000);
+        assert_eq!(symbols.symbols.len(), 4);
+
+        let name = |offset| symbols.lookup(offset).map(|(s, o)| (s.name.as_str(), o));
+        assert_eq!(name(0x1000), Some(("main", 0)));
+        assert_eq!(name(0x103f), Some(("main", 0x3f)));
+        assert_eq!(name(0x1048), Some(("helper", 8)));
+        assert_eq!(name(0x1050), None);
+        assert_eq!(name(0x30ff), Some(("table", 0xff)));
+        assert_eq!(name(0x4000), Some(("marker", 0)));
+        assert_eq!(name(0x4001), None);
+        assert_eq!(name(0x10), None);
+
+        assert!(Symbols::parse(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_locate() {
+        let regions = [
+            "555555554000-555555556000 r--p 00000000 fe:01 42 /usr/bin/true",
+            "555555556000-555555558000 r-xp 00002000 fe:01 42 /usr/bin/true",
+            "555555558000-555555559000 rw-p 00000000 00:00 0 [heap]",
+            "7ffff7dd0000-7ffff7dd2000 r--p 00000000 fe:01 43 /tmp/does not exist/libfoo.so",
+            "7ffff7dd2000-7ffff7dd4000 r-xp 00002000 fe:01 43 /tmp/does not exist/libfoo.so",
+        ]
+        .iter()
+        .map(|line| line.parse::<Region>().unwrap())
+        .collect::<Vec<_>>();
+        let mut symbolizer = Symbolizer::new(&regions, None);
+
+        let location = symbolizer.locate(&regions[4], 0x7fff_f7dd_2010).unwrap();
+        assert_eq!(
+            location,
+            Location {
+                module: "libfoo.so".into(),
+                offset: 0x2010,
+                symbol: None,
+            }
+        );
+        assert_eq!(location.to_string(), "libfoo.so+0x2010");
+        assert_eq!(symbolizer.describe(&regions[2], 0x5555_5555_8010), "-");
+
+        let location = Location {
+            symbol: Some(("foo".into(), 0x10)),
+            ..location
+        };
+        assert_eq!(location.to_string(), "libfoo.so+0x2010<foo+0x10>");
+    }
+}
@@ -0,0 +1,252 @@
+//! Tar archive writer
+//!
+//! Regions are stored as ustar members, named like the files of the `dir` format, followed by
+//! `registers/<tid>.json`, the other files of the `dir` format, and `manifest.json`. Names which
+//! do not fit in a ustar header get a pax `path` record, and sizes of 8 GiB or more use the
+//! base-256 encoding understood by GNU and BSD tar.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::compress::Compression;
+use crate::json::Value;
+use crate::manifest::Manifest;
+use crate::memory::Region;
+use crate::output::{self, Output, Source, Stored};
+use crate::regs::Registers;
+use crate::{Error, Result};
+
+const BLOCK_SIZE: u64 = 512;
+
+const REGTYPE: u8 = b'0';
+const XHDTYPE: u8 = b'x';
+
+/// Largest size stored in octal in the 12 bytes size field
+const MAX_OCTAL_SIZE: u64 = 0o77777777777;
+
+fn align_up(n: u64) -> u64 {
+    n.div_ceil(BLOCK_SIZE) * BLOCK_SIZE
+}
+
+/// Writes `value` in octal, on `field.len() - 1` digits followed by a NUL byte
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    field[..digits].copy_from_slice(format!("{:0width$o}", value, width = digits).as_bytes());
+    field[digits] = 0;
+}
+
+/// Builds a ustar header, `name` must fit in 100 bytes
+fn header(name: &str, size: u64, mtime: u64, typeflag: u8) -> [u8; BLOCK_SIZE as usize] {
+    let mut header = [0u8; BLOCK_SIZE as usize];
+    let name = &name.as_bytes()[..name.len().min(100)];
+    header[..name.len()].copy_from_slice(name);
+    write_octal(&mut header[100..108], 0o644);
+    write_octal(&mut header[108..116], 0);
+    write_octal(&mut header[116..124], 0);
+    if size <= MAX_OCTAL_SIZE {
+        write_octal(&mut header[124..136], size);
+    } else {
+        header[124] = 0x80;
+        header[128..136].copy_from_slice(&size.to_be_bytes());
+    }
+    write_octal(&mut header[136..148], mtime);
+    header[156] = typeflag;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+
+    // The checksum is computed with its own field filled with spaces
+    header[148..156].fill(b' ');
+    let checksum: u64 = header.iter().map(|&b| b as u64).sum();
+    write_octal(&mut header[148..155], checksum);
+    header
+}
+
+/// Builds a pax record, whose length prefix counts itself
+fn pax_record(key: &str, value: &str) -> String {
+    let payload = format!(" {}={}\n", key, value);
+    // Counting the digits of the length can add one more digit
+    let len = payload.len() + payload.len().to_string().len();
+    let len = payload.len() + len.to_string().len();
+    format!("{}{}", len, payload)
+}
+
+/// Writes every region, the registers and the manifest in a single tar archive
+#[derive(Debug)]
+pub struct Tar {
+    file: File,
+    compression: Compression,
+    mtime: u64,
+}
+
+impl Tar {
+    pub fn create(path: &Path, compression: Compression) -> Result<Self> {
+        let mtime = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Ok(Self {
+            file: File::create(path)?,
+            compression,
+            mtime,
+        })
+    }
+
+    /// Writes the headers of member `name` at the current position, with a pax header if needed
+    fn write_header(&mut self, name: &str, size: u64) -> Result<()> {
+        if name.len() > 100 {
+            let record = pax_record("path", name);
+            let pax_name = format!("PaxHeader/{}", name);
+            self.file
+                .write_all(&header(&pax_name, record.len() as u64, self.mtime, XHDTYPE))?;
+            self.file.write_all(record.as_bytes())?;
+            self.pad(record.len() as u64)?;
+        }
+        self.file
+            .write_all(&header(name, size, self.mtime, REGTYPE))?;
+        Ok(())
+    }
+
+    /// Pads member data of `size` bytes to a whole block
+    fn pad(&mut self, size: u64) -> Result<()> {
+        let padding = align_up(size) - size;
+        self.file
+            .write_all(&[0u8; BLOCK_SIZE as usize][..padding as usize])?;
+        Ok(())
+    }
+
+    fn write_file(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        self.write_header(name, data.len() as u64)?;
+        self.file.write_all(data)?;
+        self.pad(data.len() as u64)
+    }
+
+    /// Writes the registers of each thread to `registers/<tid>.json` members
+    pub fn write_registers(&mut self, threads: &[Registers]) -> Result<()> {
+        for regs in threads {
+            let mut json = regs.to_json().to_pretty_string();
+            json.push('\n');
+            self.write_file(&format!("registers/{}.json", regs.tid), json.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Writes files of `/proc/PID` to `proc/<name>` members
+    pub fn write_proc(&mut self, files: &[(&str, Vec<u8>)]) -> Result<()> {
+        for (name, data) in files {
+            self.write_file(&format!("proc/{}", name), data)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the call stacks of the threads to a `backtraces.txt` member
+    pub fn write_backtraces(&mut self, text: &str) -> Result<()> {
+        self.write_file("backtraces.txt", text.as_bytes())
+    }
+
+    /// Writes the file descriptors of the process to a `fds.json` member
+    pub fn write_fds(&mut self, fds: &Value) -> Result<()> {
+        let mut json = fds.to_pretty_string();
+        json.push('\n');
+        self.write_file("fds.json", json.as_bytes())
+    }
+
+    /// Copies `file` to the `name` member, as returned by [`output::mapped_file_name`]
+    ///
+    /// The member has the size of the file when it is opened, a file truncated meanwhile is
+    /// padded with zeros. Returns the size of the member.
+    pub fn write_mapped_file(&mut self, name: &str, file: &mut File) -> Result<u64> {
+        let size = file.metadata()?.len();
+        self.write_header(name, size)?;
+        let copied = io::copy(&mut file.take(size), &mut self.file)?;
+        io::copy(&mut io::repeat(0).take(size - copied), &mut self.file)?;
+        self.pad(size)?;
+        Ok(size)
+    }
+}
+
+impl Output for Tar {
+    /// The member size is only known once the region is written, its header is then rewritten
+    fn write_region(
+        &mut self,
+        _region: &Region,
+        name: &str,
+        source: &mut Source,
+    ) -> Result<Stored> {
+        let member = format!("{}{}", name, self.compression.extension());
+        let start = self.file.stream_position()?;
+        self.write_header(&member, 0)?;
+        let data_start = self.file.stream_position()?;
+
+        let result = if self.compression == Compression::None {
+            source(&mut output::Sparse(&mut self.file))
+        } else {
+            self.compression
+                .encoder(BufWriter::new(&mut self.file))
+                .map_err(Error::from)
+                .and_then(|mut encoder| {
+                    source(&mut encoder)?;
+                    Ok(encoder.finish()?)
+                })
+        };
+        if let Err(e) = result {
+            // The next member will be written at the same offset
+            self.file.set_len(start)?;
+            self.file.seek(SeekFrom::Start(start))?;
+            return Err(e);
+        }
+
+        let size = self.file.stream_position()? - data_start;
+        self.pad(size)?;
+        let end = self.file.stream_position()?;
+        self.file.seek(SeekFrom::Start(data_start - BLOCK_SIZE))?;
+        self.file
+            .write_all(&header(&member, size, self.mtime, REGTYPE))?;
+        self.file.seek(SeekFrom::Start(end))?;
+        Ok(Stored {
+            file: Some(member),
+            size,
+        })
+    }
+
+    /// The manifest is the last member, followed by the two empty blocks ending the archive
+    fn finish(mut self: Box<Self>, manifest: &Manifest) -> Result<()> {
+        let mut json = manifest.to_json().to_pretty_string();
+        json.push('\n');
+        self.write_file("manifest.json", json.as_bytes())?;
+        self.file.write_all(&[0u8; 2 * BLOCK_SIZE as usize])?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header() {
+        let header = header("manifest.json", 1234, 0o12345, REGTYPE);
+        assert_eq!(&header[..14], b"manifest.json\0");
+        assert_eq!(&header[124..136], b"00000002322\0");
+        assert_eq!(&header[257..265], b"ustar\x0000");
+        let checksum: u64 = header
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| if (148..156).contains(&i) { b' ' } else { b } as u64)
+            .sum();
+        assert_eq!(&header[148..156], format!("{:06o}\0 ", checksum).as_bytes());
+
+        let huge = super::header("huge", MAX_OCTAL_SIZE + 1, 0, REGTYPE);
+        assert_eq!(huge[124], 0x80);
+        assert_eq!(&huge[128..136], &(MAX_OCTAL_SIZE + 1).to_be_bytes());
+    }
+
+    #[test]
+    fn test_pax_record() {
+        assert_eq!(pax_record("path", "a"), "9 path=a\n");
+        let record = pax_record("path", &"x".repeat(91));
+        assert_eq!(record.len(), 101);
+        assert!(record.starts_with("101 path="));
+    }
+}
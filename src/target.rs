@@ -0,0 +1,298 @@
+//! Where memory is read from: a running process, a core file, a dump written earlier, the kernel,
+//! or a process of another host through its agent. All the processes of a cgroup can be dumped
+//! too.
+//!
+//! Commands reading memory take a [`Target`], and read through the [`Reader`] it opens, with the
+//! same [`Memory`] model of regions in both cases.
+
+use std::io::Write;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::cgroup::Cgroup;
+use crate::compress::Compression;
+use crate::corefile::CoreFile;
+use crate::elf::Class;
+use crate::memory::{Memory, Region};
+use crate::pagemap;
+use crate::ptrace::Ptrace;
+use crate::remote::Remote;
+use crate::snapshot::Snapshot;
+use crate::{Error, Result};
+
+/// Bytes of a region read at once when dumping it, a chunk which cannot be read being read again
+/// page by page
+///
+/// Regions are streamed through a window of one chunk per read in flight.
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Reads memory at an address, filling the whole buffer or failing
+///
+/// Code walking the structures of a process, like its stack or the list of its modules, reads
+/// through this, whether the memory is that of a running process or of a dump.
+pub type Read<'a> = dyn FnMut(usize, &mut [u8]) -> Result<()> + 'a;
+
+/// Reads the word of `class` at `address`
+pub fn read_word(read: &mut Read, class: Class, address: usize) -> Result<u64> {
+    let mut data = [0; 8];
+    let word = class.word_size();
+    read(address, &mut data[..word])?;
+    Ok(class.word_at(&data, 0))
+}
+
+/// The memory given on the command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// A running process
+    Process(u32),
+
+    /// An ELF core file, with `--from-core`
+    Core(PathBuf),
+
+    /// A `dir` or `dedup` dump, with `--from-dump`
+    Dump(PathBuf),
+
+    /// Kernel memory from `/proc/kcore`, with `--kernel`
+    Kernel,
+
+    /// The processes of a cgroup, with `--cgroup` or `--container`, only dumped
+    Cgroup(Cgroup),
+
+    /// A process of another host, with `--remote`, as the address of its agent and its PID
+    Remote(String, u32),
+}
+
+impl Target {
+    /// Opens the target, returning a reader and its regions
+    pub fn open(&self) -> Result<(Reader, Memory)> {
+        match *self {
+            // A region the kernel describes in an unexpected way is not a reason to read nothing
+            Self::Process(pid) => Ok((
+                Reader::Process(Ptrace::new(pid)?),
+                Memory::from_pid_lenient(pid)?,
+            )),
+            Self::Core(ref path) => {
+                let core = CoreFile::open(path)?;
+                let memory = core.memory();
+                Ok((Reader::Core(core), memory))
+            }
+            Self::Kernel => {
+                let core = CoreFile::kernel()?;
+                let memory = core.memory();
+                Ok((Reader::Core(core), memory))
+            }
+            Self::Dump(ref path) => {
+                let snapshot = Snapshot::open(path)?;
+                let memory = snapshot.memory();
+                Ok((Reader::Dump(snapshot), memory))
+            }
+            Self::Remote(ref address, pid) => {
+                let (remote, memory) = Remote::connect(address, pid, Compression::None)?;
+                Ok((Reader::Remote(remote), memory))
+            }
+            Self::Cgroup(_) => Err(Error::Usage("Cgroups can only be dumped".into())),
+        }
+    }
+}
+
+/// Reads the memory of a [`Target`]
+#[derive(Debug)]
+pub enum Reader {
+    Process(Ptrace),
+    Core(CoreFile),
+    Dump(Snapshot),
+    Remote(Remote),
+}
+
+impl Reader {
+    /// PID of the process, as recorded in the core file or in the manifest
+    pub fn pid(&self) -> u32 {
+        match self {
+            Self::Process(process) => process.pid(),
+            Self::Core(core) => core.pid(),
+            Self::Dump(snapshot) => snapshot.manifest.pid,
+            Self::Remote(remote) => remote.pid(),
+        }
+    }
+
+    /// Whether the process is running on this host, so that `/proc` describes it
+    pub fn is_live(&self) -> bool {
+        matches!(self, Self::Process(_))
+    }
+
+    /// PID of the running process, `None` for core files, dumps and remote processes
+    pub fn live_pid(&self) -> Option<u32> {
+        self.is_live().then(|| self.pid())
+    }
+
+    /// Reads `buffer.len()` bytes at `addr`
+    pub fn read(&mut self, addr: usize, buffer: &mut [u8]) -> Result<()> {
+        match self {
+            Self::Process(process) => process.read(addr, buffer),
+            Self::Core(core) => core.read(addr, buffer),
+            Self::Dump(snapshot) => snapshot.read_at(addr, buffer),
+            Self::Remote(remote) => remote.read(addr, buffer),
+        }
+    }
+
+    /// Gives up the reads of a running process which do not end before `deadline`
+    ///
+    /// See [`Ptrace::set_deadline`], core files and dumps are always read.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        if let Self::Process(process) = self {
+            process.set_deadline(deadline);
+        }
+    }
+
+    /// Time the running process has been stopped for, zero for core files and dumps
+    pub fn stopped_for(&self) -> Duration {
+        match self {
+            Self::Process(process) => process.stopped_for(),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Reads a page, or part of it, at `addr`, see [`Ptrace::read_page`]
+    fn read_page(&mut self, addr: usize, buffer: &mut [u8]) -> Result<()> {
+        match self {
+            Self::Process(process) => process.read_page(addr, buffer),
+            _ => self.read(addr, buffer),
+        }
+    }
+
+    /// Number of chunks [`Reader::dump_ranges`] reads at once, see [`Ptrace::read_batch`]
+    fn batch_size(&self) -> usize {
+        match self {
+            Self::Process(process) => process.batch_size(),
+            _ => 1,
+        }
+    }
+
+    /// Reads each buffer of `reads` at its address
+    fn read_batch(&mut self, reads: &mut [(usize, &mut [u8])]) -> Vec<Result<()>> {
+        match self {
+            Self::Process(process) => process.read_batch(reads),
+            _ => reads
+                .iter_mut()
+                .map(|(addr, buffer)| self.read(*addr, buffer))
+                .collect(),
+        }
+    }
+
+    /// Reads `buffer.len()` bytes at `addr` page by page after a failed read, filling the pages
+    /// which cannot be read with zeros
+    ///
+    /// Unreadable ranges are appended to `unreadable`, and the last read error is returned. A read
+    /// which timed out is not retried, its error is returned right away.
+    fn read_pages(
+        &mut self,
+        addr: usize,
+        buffer: &mut [u8],
+        unreadable: &mut Vec<Range<usize>>,
+    ) -> Result<()> {
+        // Retry page by page to only lose the bad ones
+        let page_size = pagemap::page_size();
+        let mut result = Ok(());
+        let mut offset = 0;
+        while offset < buffer.len() {
+            let page_addr = addr + offset;
+            let len = (page_size - page_addr % page_size).min(buffer.len() - offset);
+            let page = &mut buffer[offset..offset + len];
+            if let Err(e) = self.read_page(page_addr, page) {
+                if let Error::ReadTimeout { .. } = e {
+                    return Err(e);
+                }
+                page.fill(0);
+                match unreadable.last_mut() {
+                    Some(last) if last.end == page_addr => last.end += len,
+                    _ => unreadable.push(page_addr..page_addr + len),
+                }
+                result = Err(e);
+            }
+            offset += len;
+        }
+        result
+    }
+
+    /// Streams `region` to `sink`, returning the ranges which could not be read
+    ///
+    /// See [`Reader::dump_ranges`].
+    pub fn dump<W: Write + ?Sized>(
+        &mut self,
+        region: &Region,
+        sink: &mut W,
+    ) -> Result<Vec<Range<usize>>> {
+        let whole = region.start..region.end;
+        self.dump_ranges(region, std::slice::from_ref(&whole), sink)
+    }
+
+    /// Streams `region` to `sink`, only reading the sorted `ranges`, the rest is written as zeros
+    ///
+    /// Memory is read in chunks of a fixed size, several at once with io_uring. Pages which cannot
+    /// be read are written as zeros, and returned. An error is returned if nothing could be read, if
+    /// a read timed out, or if `sink` fails.
+    pub fn dump_ranges<W: Write + ?Sized>(
+        &mut self,
+        region: &Region,
+        ranges: &[Range<usize>],
+        sink: &mut W,
+    ) -> Result<Vec<Range<usize>>> {
+        // The agent reads the region the same way, and streams it
+        if let Self::Remote(remote) = self {
+            return remote.dump_ranges(region, ranges, sink);
+        }
+        let window = CHUNK_SIZE * self.batch_size();
+        let mut buffer = vec![0u8; window.min(region.size())];
+        let mut unreadable = Vec::new();
+        let mut error = None;
+        let mut next = 0;
+        let mut addr = region.start;
+        while addr < region.end {
+            let end = region.end.min(addr.saturating_add(window));
+            let data = &mut buffer[..end - addr];
+            data.fill(0);
+            while next < ranges.len() && ranges[next].end <= addr {
+                next += 1;
+            }
+            // The part of the ranges in each chunk of the window
+            let mut reads = Vec::new();
+            let mut rest = &mut data[..];
+            let mut rest_addr = addr;
+            for range in ranges[next..].iter().take_while(|r| r.start < end) {
+                let mut start = range.start.max(addr);
+                let range_end = range.end.min(end);
+                while start < range_end {
+                    let chunk_end = range_end.min(start - (start - addr) % CHUNK_SIZE + CHUNK_SIZE);
+                    let (_, tail) = std::mem::take(&mut rest).split_at_mut(start - rest_addr);
+                    let (piece, tail) = tail.split_at_mut(chunk_end - start);
+                    reads.push((start, piece));
+                    rest = tail;
+                    rest_addr = chunk_end;
+                    start = chunk_end;
+                }
+            }
+            let results = self.read_batch(&mut reads);
+            for ((start, piece), result) in reads.into_iter().zip(results) {
+                let result = match result {
+                    Err(Error::ReadTimeout { .. }) | Ok(()) => result,
+                    Err(_) => self.read_pages(start, piece, &mut unreadable),
+                };
+                match result {
+                    Ok(()) => {}
+                    Err(e @ Error::ReadTimeout { .. }) => return Err(e),
+                    Err(e) => error = Some(e),
+                }
+            }
+            sink.write_all(data)?;
+            addr = end;
+        }
+
+        let requested: usize = ranges.iter().map(|r| r.len()).sum();
+        let failed: usize = unreadable.iter().map(|r| r.len()).sum();
+        match error {
+            Some(e) if failed == requested => Err(e),
+            _ => Ok(unreadable),
+        }
+    }
+}
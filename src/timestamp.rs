@@ -0,0 +1,51 @@
+//! UTC timestamps, used to name snapshots and log events
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Formats `time` as `YYYYmmddTHHMMSS.mmmZ`
+pub fn utc(time: SystemTime) -> String {
+    let elapsed = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = elapsed.as_secs();
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+
+    // Civil date from days since 1970-01-01, in the proleptic Gregorian calendar
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u64;
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        elapsed.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_utc() {
+        assert_eq!(utc(UNIX_EPOCH), "19700101T000000.000Z");
+        assert_eq!(
+            utc(UNIX_EPOCH + Duration::from_millis(951_827_696_789)),
+            "20000229T123456.789Z"
+        );
+        assert_eq!(
+            utc(UNIX_EPOCH + Duration::from_secs(1_792_108_799)),
+            "20261015T235959.000Z"
+        );
+    }
+}
@@ -0,0 +1,346 @@
+//! Minimal io_uring, submitting batches of reads of `/proc/PID/mem`
+//!
+//! `/proc/PID/mem` cannot be read without blocking, the kernel hands each read to one of its worker
+//! threads: the reads of a batch run in parallel, on as many CPUs as there are. Only `IORING_OP_READ`
+//! is used, available since Linux 5.6.
+
+use std::ffi::c_void;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Same numbers on every architecture
+const SYS_IO_URING_SETUP: i64 = 425;
+const SYS_IO_URING_ENTER: i64 = 426;
+
+const IORING_OFF_SQ_RING: i64 = 0;
+const IORING_OFF_CQ_RING: i64 = 0x8000000;
+const IORING_OFF_SQES: i64 = 0x10000000;
+
+const IORING_ENTER_GETEVENTS: u32 = 1;
+
+const IORING_OP_READ: u8 = 22;
+
+const PROT_READ: i32 = 1;
+const PROT_WRITE: i32 = 2;
+const MAP_SHARED: i32 = 1;
+const MAP_POPULATE: i32 = 0x8000;
+
+extern "C" {
+    fn syscall(number: i64, ...) -> i64;
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: i32,
+        flags: i32,
+        fd: i32,
+        offset: i64,
+    ) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> i32;
+}
+
+/// `struct io_sqring_offsets`
+#[repr(C)]
+#[derive(Debug, Default)]
+struct SqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+/// `struct io_cqring_offsets`
+#[repr(C)]
+#[derive(Debug, Default)]
+struct CqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+/// `struct io_uring_params`
+#[repr(C)]
+#[derive(Debug, Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqRingOffsets,
+    cq_off: CqRingOffsets,
+}
+
+/// `struct io_uring_sqe`, as used by `IORING_OP_READ`
+#[repr(C)]
+#[derive(Debug, Default)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    rw_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+/// `struct io_uring_cqe`
+#[repr(C)]
+#[derive(Debug)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+/// A ring, or the submission entries, shared with the kernel
+#[derive(Debug)]
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: RawFd, len: usize, offset: i64) -> io::Result<Self> {
+        // SAFETY: a new mapping is created, nothing else is touched
+        let ptr = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED | MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if ptr as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: ptr.cast(),
+            len,
+        })
+    }
+
+    /// Returns a pointer to the `T` at `offset`, which the kernel gave
+    fn at<T>(&self, offset: u32) -> *mut T {
+        assert!(offset as usize + std::mem::size_of::<T>() <= self.len);
+        // SAFETY: the offset is within the mapping
+        unsafe { self.ptr.add(offset as usize).cast() }
+    }
+
+    fn atomic(&self, offset: u32) -> &AtomicU32 {
+        // SAFETY: ring heads and tails are aligned `u32`, shared with the kernel
+        unsafe { AtomicU32::from_ptr(self.at(offset)) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: the mapping is not used anymore
+        unsafe { munmap(self.ptr.cast(), self.len) };
+    }
+}
+
+/// An io_uring instance, reading up to [`Uring::capacity`] buffers at once
+#[derive(Debug)]
+pub struct Uring {
+    params: Params,
+    sq: Mapping,
+    cq: Mapping,
+    sqes: Mapping,
+    // Closed after the mappings are
+    fd: OwnedFd,
+}
+
+impl Uring {
+    /// Sets up an instance with `entries` submission entries, rounded up to a power of 2
+    pub fn new(entries: u32) -> io::Result<Self> {
+        let mut params = Params::default();
+        // SAFETY: `params` is a `struct io_uring_params`, which the kernel fills
+        let fd = unsafe { syscall(SYS_IO_URING_SETUP, entries, &mut params as *mut Params) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the file descriptor was just created
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+        let raw = fd.as_raw_fd();
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+        let cq_len =
+            params.cq_off.cqes as usize + params.cq_entries as usize * std::mem::size_of::<Cqe>();
+        let sqes_len = params.sq_entries as usize * std::mem::size_of::<Sqe>();
+        Ok(Self {
+            sq: Mapping::new(raw, sq_len, IORING_OFF_SQ_RING)?,
+            cq: Mapping::new(raw, cq_len, IORING_OFF_CQ_RING)?,
+            sqes: Mapping::new(raw, sqes_len, IORING_OFF_SQES)?,
+            params,
+            fd,
+        })
+    }
+
+    /// Number of reads submitted at once
+    pub fn capacity(&self) -> usize {
+        self.params.sq_entries as usize
+    }
+
+    /// Reads each buffer of `reads` at its offset in the file `fd`, returning the bytes read
+    ///
+    /// Everything is submitted at once, and waited for.
+    pub fn read(&mut self, fd: RawFd, reads: &mut [(usize, &mut [u8])]) -> Vec<io::Result<usize>> {
+        assert!(reads.len() <= self.capacity());
+        let sq_off = &self.params.sq_off;
+        // SAFETY: the offsets are those given by the kernel, the submission queue is only written
+        // here and is empty, as every read submitted before was waited for
+        let sq_mask = unsafe { *self.sq.at::<u32>(sq_off.ring_mask) };
+        let sq_tail = self.sq.atomic(sq_off.tail);
+        let mut tail = sq_tail.load(Ordering::Relaxed);
+        for (index, (offset, buffer)) in reads.iter_mut().enumerate() {
+            let slot = tail & sq_mask;
+            let sqe = Sqe {
+                opcode: IORING_OP_READ,
+                fd,
+                off: *offset as u64,
+                addr: buffer.as_mut_ptr() as u64,
+                len: buffer.len() as u32,
+                user_data: index as u64,
+                ..Default::default()
+            };
+            // SAFETY: `slot` is below the number of entries
+            unsafe {
+                self.sqes.at::<Sqe>(0).add(slot as usize).write(sqe);
+                self.sq
+                    .at::<u32>(sq_off.array)
+                    .add(slot as usize)
+                    .write(slot);
+            }
+            tail = tail.wrapping_add(1);
+        }
+        sq_tail.store(tail, Ordering::Release);
+
+        let mut results: Vec<Option<io::Result<usize>>> = reads.iter().map(|_| None).collect();
+        let mut to_submit = reads.len();
+        let mut pending = reads.len();
+        let mut failed = false;
+        while pending > 0 {
+            if failed {
+                // Completions are posted to the ring without `io_uring_enter`
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            } else {
+                // SAFETY: the buffers outlive the reads, which are all waited for
+                let submitted = unsafe {
+                    syscall(
+                        SYS_IO_URING_ENTER,
+                        self.fd.as_raw_fd(),
+                        to_submit as u32,
+                        1u32,
+                        IORING_ENTER_GETEVENTS,
+                        std::ptr::null::<c_void>(),
+                        0usize,
+                    )
+                };
+                if submitted < 0 {
+                    let error = io::Error::last_os_error();
+                    if error.kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    // Take back the last entries, which the kernel did not consume, and fail their
+                    // reads. Those submitted already write to their buffers until they complete,
+                    // they are waited for without calling `io_uring_enter` again.
+                    tail = tail.wrapping_sub(to_submit as u32);
+                    sq_tail.store(tail, Ordering::Release);
+                    for result in &mut results[reads.len() - to_submit..] {
+                        *result = Some(Err(io::Error::new(error.kind(), error.to_string())));
+                    }
+                    pending -= to_submit;
+                    to_submit = 0;
+                    failed = true;
+                } else {
+                    to_submit -= submitted as usize;
+                }
+            }
+
+            let cq_off = &self.params.cq_off;
+            // SAFETY: the offsets are those given by the kernel
+            let cq_mask = unsafe { *self.cq.at::<u32>(cq_off.ring_mask) };
+            let cq_head = self.cq.atomic(cq_off.head);
+            let cq_tail = self.cq.atomic(cq_off.tail).load(Ordering::Acquire);
+            let mut head = cq_head.load(Ordering::Relaxed);
+            while head != cq_tail {
+                // SAFETY: entries between the head and the tail were written by the kernel
+                let cqe = unsafe {
+                    &*self
+                        .cq
+                        .at::<Cqe>(cq_off.cqes)
+                        .add((head & cq_mask) as usize)
+                };
+                results[cqe.user_data as usize] = Some(match cqe.res {
+                    res if res < 0 => Err(io::Error::from_raw_os_error(-res)),
+                    res => Ok(res as usize),
+                });
+                pending -= 1;
+                head = head.wrapping_add(1);
+            }
+            cq_head.store(head, Ordering::Release);
+        }
+        results.into_iter().map(Option::unwrap).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_read() {
+        // io_uring may be disabled, by `kernel.io_uring_disabled` or a seccomp filter
+        let Ok(mut uring) = Uring::new(4) else {
+            return;
+        };
+        assert_eq!(uring.capacity(), 4);
+        let path = std::env::temp_dir().join(format!("dump-memory-uring-{}", std::process::id()));
+        let data: Vec<u8> = (0..0x3000).map(|i| (i % 251) as u8).collect();
+        std::fs::File::create(&path)
+            .unwrap()
+            .write_all(&data)
+            .unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let (mut first, mut second, mut past) = (vec![0; 0x1000], vec![0; 0x1800], vec![0; 16]);
+        let mut reads = [
+            (0x2000, &mut second[..]),
+            (0x10, &mut first[..]),
+            (0x4000, &mut past[..]),
+        ];
+        let results: Vec<_> = uring
+            .read(file.as_raw_fd(), &mut reads)
+            .into_iter()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(results, [0x1000, 0x1000, 0]);
+        assert_eq!(first, data[0x10..0x1010]);
+        assert_eq!(second[..0x1000], data[0x2000..]);
+    }
+}
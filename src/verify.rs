@@ -0,0 +1,321 @@
+//! Comparison of the code of a process with the files it maps
+//!
+//! Each executable mapping of a file is read from memory and compared page by page with the bytes
+//! of the file at the same offset, which are those the kernel mapped. Pages holding words written
+//! by the dynamic linker, only found in code with text relocations, legitimately differ and are
+//! reported as relocated. Other differences are patches of the code, like inline hooks or software
+//! breakpoints.
+
+use std::collections::BTreeSet;
+use std::io::Read as _;
+
+use crate::cli::VerifyOptions;
+use crate::elf;
+use crate::json::Value;
+use crate::maps::ListFormat;
+use crate::memory::{Memory, PermissionBits, Region, RegionKind};
+use crate::pagemap;
+use crate::procfs;
+use crate::ptrace::Ptrace;
+use crate::signal;
+use crate::symbols::Symbolizer;
+use crate::target::Read;
+use crate::{Error, Result};
+
+/// Bytes of code read from memory at once, before being compared page by page with the file
+const CHUNK_SIZE: usize = 1 << 16;
+
+/// A page of code which differs from the mapped file
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Difference {
+    /// Address of the first differing byte
+    address: usize,
+
+    /// Offset of that byte in the file
+    offset: u64,
+
+    /// Number of differing bytes in the page
+    bytes: usize,
+
+    /// Whether the page holds words written by the dynamic linker
+    relocated: bool,
+}
+
+/// Outcome of the comparison of a region with its file
+#[derive(Debug, Default)]
+struct Comparison {
+    /// Pages compared
+    pages: usize,
+
+    /// Pages which could not be read
+    unreadable: usize,
+
+    differences: Vec<Difference>,
+}
+
+/// Returns the pages of `file` written by the dynamic linker, as file offsets divided by the page
+/// size
+fn relocated_pages(file: &[u8], path: &str) -> BTreeSet<u64> {
+    let page_size = pagemap::page_size() as u64;
+    match elf::relocated_offsets(file) {
+        // A relocated word may cross a page
+        Ok(offsets) => offsets
+            .iter()
+            .flat_map(|&offset| [offset / page_size, (offset + 7) / page_size])
+            .collect(),
+        Err(e) => {
+            warn!(
+                "Relocations of {} are unknown, relocated pages are reported as modified: {}",
+                path, e
+            );
+            BTreeSet::new()
+        }
+    }
+}
+
+/// Compares `region`, read with `read`, with `file`, the content of its file
+///
+/// `relocated` holds the pages of the file written by the dynamic linker.
+fn compare(read: &mut Read, region: &Region, file: &[u8], relocated: &BTreeSet<u64>) -> Comparison {
+    let page_size = pagemap::page_size();
+    let mut comparison = Comparison::default();
+    let mut memory = vec![0; CHUNK_SIZE];
+    let mut expected = vec![0; page_size];
+    let mut start = region.start;
+    while start < region.end {
+        let chunk = &mut memory[..CHUNK_SIZE.min(region.end - start)];
+        let whole = read(start, chunk).is_ok();
+        for (index, page) in chunk.chunks_mut(page_size).enumerate() {
+            let address = start + index * page_size;
+            if !whole && read(address, page).is_err() {
+                comparison.unreadable += 1;
+                continue;
+            }
+            comparison.pages += 1;
+
+            // The end of the last page of the file is mapped as zeros
+            let offset = (region.offset + address - region.start) as u64;
+            let content = file.get(offset as usize..).unwrap_or_default();
+            let len = content.len().min(page_size);
+            expected[..len].copy_from_slice(&content[..len]);
+            expected[len..].fill(0);
+
+            let mut differing = page
+                .iter()
+                .zip(&expected)
+                .enumerate()
+                .filter(|(_, (actual, expected))| actual != expected)
+                .map(|(index, _)| index);
+            if let Some(first) = differing.next() {
+                comparison.differences.push(Difference {
+                    address: address + first,
+                    offset: offset + first as u64,
+                    bytes: 1 + differing.count(),
+                    relocated: relocated.contains(&(offset / page_size as u64)),
+                });
+            }
+        }
+        start += chunk.len();
+    }
+    comparison
+}
+
+/// Reads the file mapped by `region`, and finds its relocated pages
+fn read_file(pid: u32, region: &Region) -> Result<(Vec<u8>, BTreeSet<u64>)> {
+    let mut data = Vec::new();
+    procfs::open_mapped_file(pid, region)?.read_to_end(&mut data)?;
+    let relocated = relocated_pages(&data, region.path().unwrap_or(""));
+    Ok((data, relocated))
+}
+
+fn status(difference: &Difference) -> &'static str {
+    if difference.relocated {
+        "relocated"
+    } else {
+        "modified"
+    }
+}
+
+pub fn run(options: &VerifyOptions) -> Result<()> {
+    let mut process = Ptrace::new(options.pid)?;
+    let memory = Memory::from_pid(options.pid)?;
+
+    let mut results = Vec::new();
+    let mut file: Option<(&Region, Vec<u8>, BTreeSet<u64>)> = None;
+    for region in memory
+        .filter(&options.filter)
+        .filter(|r| r.kind == RegionKind::File && r.perms.has_perm(PermissionBits::Exec))
+    {
+        signal::check()?;
+        if !file.as_ref().is_some_and(|(r, ..)| r.same_file(region)) {
+            file = match read_file(options.pid, region) {
+                Ok((data, relocated)) => Some((region, data, relocated)),
+                Err(e) => {
+                    warn!(
+                        "Cannot read {} to verify {:x}-{:x}: {}",
+                        region.path().unwrap_or("no file"),
+                        region.start,
+                        region.end,
+                        e
+                    );
+                    None
+                }
+            };
+        }
+        let Some((_, ref data, ref relocated)) = file else {
+            continue;
+        };
+        let comparison = compare(
+            &mut |address, buffer| process.read(address, buffer),
+            region,
+            data,
+            relocated,
+        );
+        if comparison.unreadable > 0 {
+            warn!(
+                "Could not read {} pages of {:x}-{:x} {}",
+                comparison.unreadable,
+                region.start,
+                region.end,
+                region.path().unwrap_or("")
+            );
+        }
+        results.push((region, comparison));
+    }
+    // The symbols are read from the files, the process can go on
+    drop(process);
+
+    let mut symbols = Symbolizer::new(&memory, Some(options.pid));
+    let differences = results
+        .iter()
+        .flat_map(|(region, c)| c.differences.iter().map(move |d| (*region, d)));
+    let pages: usize = results.iter().map(|(_, c)| c.pages).sum();
+    let unreadable: usize = results.iter().map(|(_, c)| c.unreadable).sum();
+    let relocated = differences.clone().filter(|(_, d)| d.relocated).count();
+    let modified = differences.clone().count() - relocated;
+    match options.format {
+        ListFormat::Json => {
+            let differences = differences
+                .map(|(region, difference)| {
+                    Value::object([
+                        ("address", difference.address.into()),
+                        ("offset", difference.offset.into()),
+                        ("bytes", difference.bytes.into()),
+                        ("status", status(difference).into()),
+                        (
+                            "location",
+                            symbols
+                                .locate(region, difference.address)
+                                .map_or(Value::Null, |l| l.to_string().into()),
+                        ),
+                        ("path", region.path().unwrap_or("").into()),
+                    ])
+                })
+                .collect();
+            let report = Value::object([
+                ("pid", options.pid.into()),
+                ("pages", pages.into()),
+                ("unreadable", unreadable.into()),
+                ("modified", modified.into()),
+                ("relocated", relocated.into()),
+                ("differences", Value::Array(differences)),
+            ]);
+            println!("{}", report.to_pretty_string());
+        }
+        _ => {
+            if modified + relocated > 0 {
+                println!(
+                    "{:>16} {:>10} {:>5} {:9} LOCATION PATH",
+                    "ADDRESS", "OFFSET", "BYTES", "STATUS"
+                );
+            }
+            for (region, difference) in differences {
+                println!(
+                    "{:>16x} {:>10x} {:>5} {:9} {} {}",
+                    difference.address,
+                    difference.offset,
+                    difference.bytes,
+                    status(difference),
+                    symbols.describe(region, difference.address),
+                    region.path().unwrap_or("")
+                );
+            }
+            println!(
+                "{} pages compared, {} modified, {} relocated, {} unreadable",
+                pages, modified, relocated, unreadable
+            );
+        }
+    }
+    if modified > 0 {
+        return Err(Error::CodeModified {
+            pid: options.pid,
+            pages: modified,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare() {
+        let page = pagemap::page_size();
+        // Four pages mapped from the second page of a file which ends in the middle of the last
+        let region = Region::new(
+            0x7f00_0000_0000,
+            0x7f00_0000_0000 + 4 * page,
+            "r-xp".parse().unwrap(),
+            page,
+            "/usr/lib/libfoo.so",
+        );
+        let file: Vec<u8> = (0..4 * page + page / 2).map(|i| i as u8 | 1).collect();
+        let mut memory = file[page..].to_vec();
+        memory.resize(4 * page, 0);
+        // A hook in the first page, a relocation in the second one, and the third is unreadable
+        memory[0x10..0x15].copy_from_slice(&[0xe9, 0, 0, 0, 0]);
+        memory[page + 8] = 0;
+        // Past the end of the file
+        memory[3 * page + page / 2] = 0xcc;
+
+        let mut read = |address: usize, buffer: &mut [u8]| {
+            let offset = address - region.start;
+            if (2 * page..3 * page).contains(&offset) || buffer.len() > page {
+                return Err(Error::RegionNotFound {
+                    start: address,
+                    end: address + buffer.len(),
+                });
+            }
+            buffer.copy_from_slice(&memory[offset..offset + buffer.len()]);
+            Ok(())
+        };
+        let relocated = BTreeSet::from([2]);
+        let comparison = compare(&mut read, &region, &file, &relocated);
+        assert_eq!(comparison.pages, 3);
+        assert_eq!(comparison.unreadable, 1);
+        assert_eq!(
+            comparison.differences,
+            [
+                Difference {
+                    address: region.start + 0x10,
+                    offset: page as u64 + 0x10,
+                    bytes: 5,
+                    relocated: false,
+                },
+                Difference {
+                    address: region.start + page + 8,
+                    offset: 2 * page as u64 + 8,
+                    bytes: 1,
+                    relocated: true,
+                },
+                Difference {
+                    address: region.start + 3 * page + page / 2,
+                    offset: 4 * page as u64 + page as u64 / 2,
+                    bytes: 1,
+                    relocated: false,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,74 @@
+//! Periodic reads of an address range, reporting changes
+
+use std::ops::Range;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::cli::WatchOptions;
+use crate::hexdump;
+use crate::ptrace::{self, Ptrace};
+use crate::signal;
+use crate::timestamp;
+use crate::Result;
+
+/// Reads `range` without stopping the process if possible, or with a brief attach otherwise
+fn read_range(pid: u32, range: &Range<usize>) -> Result<Vec<u8>> {
+    let mut buffer = vec![0; range.len()];
+    if ptrace::process_vm_read(pid, range.start, &mut buffer[..]).is_err() {
+        Ptrace::new(pid)?.read(range.start, &mut buffer[..])?;
+    }
+    Ok(buffer)
+}
+
+/// Prints the hexdump lines which differ between `old` and `new`
+fn print_changes(base: usize, old: &[u8], new: &[u8]) {
+    for ((offset, old_line), new_line) in old
+        .chunks(hexdump::WIDTH)
+        .enumerate()
+        .zip(new.chunks(hexdump::WIDTH))
+    {
+        if old_line != new_line {
+            let address = base + offset * hexdump::WIDTH;
+            println!("  -{}", hexdump::line(address, old_line));
+            println!("  +{}", hexdump::line(address, new_line));
+        }
+    }
+}
+
+pub fn run(options: &WatchOptions) -> Result<()> {
+    let range = &options.range;
+    let mut previous = read_range(options.pid, range)?;
+    println!(
+        "{} 0x{:x}..0x{:x}",
+        timestamp::utc(SystemTime::now()),
+        range.start,
+        range.end
+    );
+    for (i, line) in previous.chunks(hexdump::WIDTH).enumerate() {
+        println!(
+            "   {}",
+            hexdump::line(range.start + i * hexdump::WIDTH, line)
+        );
+    }
+
+    let mut reads = 1;
+    while options.count.is_none_or(|count| reads < count) {
+        signal::sleep(options.interval)?;
+        let current = match read_range(options.pid, range) {
+            Ok(current) => current,
+            Err(_) if !Path::new(&format!("/proc/{}", options.pid)).exists() => {
+                println!("Process {} exited", options.pid);
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        reads += 1;
+        if current != previous {
+            println!("{} changed", timestamp::utc(SystemTime::now()));
+            print_changes(range.start, &previous[..], &current[..]);
+            previous = current;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,96 @@
+//! Reads which are given up after a deadline
+//!
+//! Reading `/proc/PID/mem` may block forever on some mappings, such as those backed by a hung FUSE
+//! daemon or by some devices, and signals do not interrupt the page faults it waits for. With a
+//! read timeout, reads are done by a worker thread, which is abandoned when it does not answer in
+//! time: it stays blocked until this process exits, while the thread which attached to the process
+//! goes on with the other regions and detaches from it.
+
+use std::io;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Instant;
+
+/// A thread reading memory on behalf of another one
+#[derive(Debug)]
+pub struct ReadWorker {
+    requests: Sender<(usize, Vec<u8>)>,
+    replies: Receiver<(Vec<u8>, io::Result<()>)>,
+}
+
+impl ReadWorker {
+    /// Starts a thread named `name`, reading with `read`
+    pub fn spawn<F>(name: String, mut read: F) -> io::Result<Self>
+    where
+        F: FnMut(usize, &mut [u8]) -> io::Result<()> + Send + 'static,
+    {
+        let (requests, pending) = mpsc::channel::<(usize, Vec<u8>)>();
+        let (done, replies) = mpsc::channel();
+        thread::Builder::new().name(name).spawn(move || {
+            for (addr, mut buffer) in pending {
+                let result = read(addr, &mut buffer[..]);
+                // The reader gave up on this worker
+                if done.send((buffer, result)).is_err() {
+                    break;
+                }
+            }
+        })?;
+        Ok(Self { requests, replies })
+    }
+
+    /// Reads `buffer.len()` bytes at `addr`, failing with [`io::ErrorKind::TimedOut`] if the worker
+    /// does not answer by `deadline`
+    ///
+    /// The worker may never answer after a timeout, and must not be used anymore.
+    pub fn read(&self, addr: usize, buffer: &mut [u8], deadline: Instant) -> io::Result<()> {
+        let exited = || io::Error::other("read worker exited");
+        self.requests
+            .send((addr, vec![0; buffer.len()]))
+            .map_err(|_| exited())?;
+        match self
+            .replies
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        {
+            Ok((data, result)) => {
+                result?;
+                buffer.copy_from_slice(&data[..]);
+                Ok(())
+            }
+            Err(RecvTimeoutError::Timeout) => Err(io::ErrorKind::TimedOut.into()),
+            Err(RecvTimeoutError::Disconnected) => Err(exited()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_read_worker() {
+        let worker = ReadWorker::spawn("test-read".into(), |addr, buffer| match addr {
+            0 => loop {
+                thread::park();
+            },
+            1 => Err(io::ErrorKind::InvalidInput.into()),
+            _ => {
+                buffer.fill(addr as u8);
+                Ok(())
+            }
+        })
+        .unwrap();
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let mut buffer = [0; 4];
+        worker.read(0x42, &mut buffer, deadline).unwrap();
+        assert_eq!(buffer, [0x42; 4]);
+        let error = worker.read(1, &mut buffer, deadline).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(50);
+        let error = worker.read(0, &mut buffer, deadline).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,75 @@
+use crate::cli::WriteOptions;
+use crate::memory::{Memory, PermissionBits, Region};
+use crate::ptrace::Ptrace;
+use crate::{Error, Result};
+
+/// Checks that every byte of `address..address + len` lies in a writable region
+///
+/// `regions` must be sorted, as they are in `/proc/PID/maps`.
+pub fn check_writable(regions: &[Region], address: usize, len: usize) -> Result<()> {
+    let end = address.saturating_add(len);
+    let mut cursor = address;
+    for region in regions {
+        if region.end <= cursor {
+            continue;
+        }
+        if region.start > cursor || !region.perms.has_perm(PermissionBits::Write) {
+            break;
+        }
+        cursor = region.end;
+        if cursor >= end {
+            return Ok(());
+        }
+    }
+    Err(Error::NotWritable { address: cursor })
+}
+
+pub fn run(options: &WriteOptions) -> Result<()> {
+    let mut process = Ptrace::new(options.pid)?;
+    let memory = Memory::from_pid(options.pid)?;
+
+    if !options.force {
+        check_writable(&memory, options.address, options.data.len())?;
+    }
+    process.write(options.address, &options.data[..])?;
+    println!(
+        "Wrote {} bytes at 0x{:x}",
+        options.data.len(),
+        options.address
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_writable() {
+        let regions: Vec<Region> = [
+            "1000-2000 rw-p 00000000 00:00 0",
+            "2000-3000 rw-p 00000000 00:00 0",
+            "3000-4000 r--p 00000000 00:00 0",
+            "5000-6000 rw-p 00000000 00:00 0",
+        ]
+        .iter()
+        .map(|r| r.parse().unwrap())
+        .collect();
+
+        assert!(check_writable(&regions, 0x1ff8, 0x10).is_ok());
+        assert!(check_writable(&regions, 0x5000, 0x1000).is_ok());
+        assert!(matches!(
+            check_writable(&regions, 0x2ff8, 0x10),
+            Err(Error::NotWritable { address: 0x3000 })
+        ));
+        assert!(matches!(
+            check_writable(&regions, 0x4800, 1),
+            Err(Error::NotWritable { address: 0x4800 })
+        ));
+        assert!(matches!(
+            check_writable(&regions, 0x5ff8, 0x10),
+            Err(Error::NotWritable { address: 0x6000 })
+        ));
+    }
+}
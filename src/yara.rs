@@ -0,0 +1,318 @@
+//! YARA rules matching, with bindings to libyara 4.3 or later
+//!
+//! Regions are streamed through windows of `WINDOW_SIZE` bytes, overlapping by `OVERLAP` bytes so
+//! that strings crossing a window boundary are still found. Rules are evaluated on each window:
+//! conditions involving data further apart than a window, or `filesize`, do not behave as when
+//! scanning a file.
+
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::io::{self, Write};
+use std::ptr;
+
+use crate::cli::ScanYaraOptions;
+use crate::error::Chain;
+use crate::memory::{PermissionBits, Region};
+use crate::search::print_hit;
+use crate::signal;
+use crate::symbols::Symbolizer;
+use crate::target::Reader;
+use crate::{Error, Result};
+
+const WINDOW_SIZE: usize = 1 << 20;
+const OVERLAP: usize = 4096;
+
+const ERROR_SUCCESS: c_int = 0;
+const CALLBACK_MSG_RULE_MATCHING: c_int = 1;
+const CALLBACK_CONTINUE: c_int = 0;
+
+#[repr(C)]
+struct YrCompiler {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct YrRules {
+    _private: [u8; 0],
+}
+
+/// Beginning of `YR_RULE`, as laid out since YARA 4.3
+#[repr(C)]
+struct YrRule {
+    _flags: i32,
+    _num_atoms: i32,
+    _required_strings: u32,
+    _unused: u32,
+    identifier: *const c_char,
+}
+
+type CompilerCallback = extern "C" fn(
+    error_level: c_int,
+    file_name: *const c_char,
+    line_number: c_int,
+    rule: *const YrRule,
+    message: *const c_char,
+    user_data: *mut c_void,
+);
+
+type ScanCallback = extern "C" fn(
+    context: *mut c_void,
+    message: c_int,
+    message_data: *mut c_void,
+    user_data: *mut c_void,
+) -> c_int;
+
+#[link(name = "yara")]
+extern "C" {
+    fn yr_initialize() -> c_int;
+    fn yr_finalize() -> c_int;
+    fn yr_compiler_create(compiler: *mut *mut YrCompiler) -> c_int;
+    fn yr_compiler_destroy(compiler: *mut YrCompiler);
+    fn yr_compiler_set_callback(
+        compiler: *mut YrCompiler,
+        callback: CompilerCallback,
+        user_data: *mut c_void,
+    );
+    fn yr_compiler_add_string(
+        compiler: *mut YrCompiler,
+        rules_string: *const c_char,
+        namespace: *const c_char,
+    ) -> c_int;
+    fn yr_compiler_get_rules(compiler: *mut YrCompiler, rules: *mut *mut YrRules) -> c_int;
+    fn yr_rules_destroy(rules: *mut YrRules) -> c_int;
+    fn yr_rules_scan_mem(
+        rules: *mut YrRules,
+        buffer: *const u8,
+        buffer_size: usize,
+        flags: c_int,
+        callback: ScanCallback,
+        user_data: *mut c_void,
+        timeout: c_int,
+    ) -> c_int;
+}
+
+fn check(function: &'static str, code: c_int) -> Result<()> {
+    if code == ERROR_SUCCESS {
+        Ok(())
+    } else {
+        Err(Error::Yara(format!(
+            "{} failed with error {}",
+            function, code
+        )))
+    }
+}
+
+/// Collects compilation errors and warnings, as `file:line: message`
+extern "C" fn compiler_callback(
+    _error_level: c_int,
+    file_name: *const c_char,
+    line_number: c_int,
+    _rule: *const YrRule,
+    message: *const c_char,
+    user_data: *mut c_void,
+) {
+    // SAFETY: `user_data` is the `Vec` given to `yr_compiler_set_callback`, and libyara passes
+    // NUL-terminated strings
+    let messages = unsafe { &mut *(user_data as *mut Vec<String>) };
+    let file_name = if file_name.is_null() {
+        "rules".into()
+    } else {
+        unsafe { CStr::from_ptr(file_name) }.to_string_lossy()
+    };
+    let message = unsafe { CStr::from_ptr(message) }.to_string_lossy();
+    messages.push(format!("{}:{}: {}", file_name, line_number, message));
+}
+
+/// Collects the identifiers of the matching rules
+extern "C" fn scan_callback(
+    _context: *mut c_void,
+    message: c_int,
+    message_data: *mut c_void,
+    user_data: *mut c_void,
+) -> c_int {
+    if message == CALLBACK_MSG_RULE_MATCHING {
+        // SAFETY: `user_data` is the `Vec` given to `yr_rules_scan_mem`, and `message_data` points
+        // to the matching `YR_RULE`
+        let matches = unsafe { &mut *(user_data as *mut Vec<String>) };
+        let rule = unsafe { &*(message_data as *const YrRule) };
+        let identifier = unsafe { CStr::from_ptr(rule.identifier) };
+        matches.push(identifier.to_string_lossy().into_owned());
+    }
+    CALLBACK_CONTINUE
+}
+
+/// Compiled rules, libyara is initialized for as long as they live
+struct Rules(*mut YrRules);
+
+impl Rules {
+    fn compile(source: &str) -> Result<Self> {
+        let source =
+            CString::new(source).map_err(|_| Error::Yara("rules contain a NUL byte".into()))?;
+        check("yr_initialize", unsafe { yr_initialize() })?;
+
+        let mut compiler = ptr::null_mut();
+        if let Err(e) = check("yr_compiler_create", unsafe {
+            yr_compiler_create(&mut compiler)
+        }) {
+            unsafe { yr_finalize() };
+            return Err(e);
+        }
+        let mut messages: Vec<String> = Vec::new();
+        let mut rules = ptr::null_mut();
+        // SAFETY: `messages` outlives the compiler, which is destroyed below
+        let result = unsafe {
+            yr_compiler_set_callback(
+                compiler,
+                compiler_callback,
+                &mut messages as *mut Vec<String> as *mut c_void,
+            );
+            if yr_compiler_add_string(compiler, source.as_ptr(), ptr::null()) != 0 {
+                Err(Error::Yara(messages.join("\n")))
+            } else {
+                check("yr_compiler_get_rules", {
+                    yr_compiler_get_rules(compiler, &mut rules)
+                })
+            }
+        };
+        unsafe { yr_compiler_destroy(compiler) };
+        if let Err(e) = result {
+            unsafe { yr_finalize() };
+            return Err(e);
+        }
+        for warning in messages {
+            warn!("{}", warning);
+        }
+        Ok(Self(rules))
+    }
+
+    /// Returns the identifiers of the rules matching `data`
+    fn scan(&self, data: &[u8]) -> Result<Vec<String>> {
+        let mut matches: Vec<String> = Vec::new();
+        // SAFETY: `matches` outlives the scan, during which the callback is called
+        let code = unsafe {
+            yr_rules_scan_mem(
+                self.0,
+                data.as_ptr(),
+                data.len(),
+                0,
+                scan_callback,
+                &mut matches as *mut Vec<String> as *mut c_void,
+                0,
+            )
+        };
+        check("yr_rules_scan_mem", code)?;
+        Ok(matches)
+    }
+}
+
+impl Drop for Rules {
+    fn drop(&mut self) {
+        unsafe {
+            yr_rules_destroy(self.0);
+            yr_finalize();
+        }
+    }
+}
+
+/// Sink scanning a region window by window
+struct Windows<'a> {
+    rules: &'a Rules,
+    window: Vec<u8>,
+
+    /// Offset in the region of the first byte of `window`
+    offset: usize,
+
+    /// Bytes at the end of `window` which were not scanned yet
+    pending: usize,
+
+    /// Matching rules, with the offset of the first window they matched in
+    matches: Vec<(String, usize)>,
+}
+
+impl Windows<'_> {
+    fn scan(&mut self) -> io::Result<()> {
+        let rules = self
+            .rules
+            .scan(&self.window[..])
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        for rule in rules {
+            if !self.matches.iter().any(|(r, _)| *r == rule) {
+                self.matches.push((rule, self.offset));
+            }
+        }
+        self.pending = 0;
+        Ok(())
+    }
+
+    /// Scans the last bytes, returns the matching rules
+    fn finish(mut self) -> io::Result<Vec<(String, usize)>> {
+        if self.pending > 0 {
+            self.scan()?;
+        }
+        Ok(self.matches)
+    }
+}
+
+impl Write for Windows<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = (WINDOW_SIZE - self.window.len()).min(buf.len());
+        self.window.extend_from_slice(&buf[..n]);
+        self.pending += n;
+        if self.window.len() == WINDOW_SIZE {
+            self.scan()?;
+            let kept = WINDOW_SIZE - OVERLAP;
+            self.window.drain(..kept);
+            self.offset += kept;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn scan_region(
+    reader: &mut Reader,
+    symbols: &mut Symbolizer,
+    rules: &Rules,
+    region: &Region,
+) -> Result<()> {
+    let mut windows = Windows {
+        rules,
+        window: Vec::with_capacity(WINDOW_SIZE),
+        offset: 0,
+        pending: 0,
+        matches: Vec::new(),
+    };
+    reader.dump(region, &mut windows)?;
+    for (rule, offset) in windows.finish()? {
+        print!("{} ", rule);
+        print_hit(symbols, region, region.start + offset);
+    }
+    Ok(())
+}
+
+pub fn run(options: &ScanYaraOptions) -> Result<()> {
+    let rules = Rules::compile(&std::fs::read_to_string(&options.rules)?)?;
+    let (mut reader, memory) = options.target.open()?;
+    let mut symbols = Symbolizer::new(&memory, reader.live_pid());
+
+    for region in memory
+        .filter(&options.filter)
+        .filter(|r| r.perms.has_perm(PermissionBits::Read))
+    {
+        signal::check()?;
+        if let Err(e) = scan_region(&mut reader, &mut symbols, &rules, region) {
+            warn!(
+                "Could not scan region {:x}-{:x} {} ({}): {}",
+                region.start,
+                region.end,
+                region.perms,
+                region.path().unwrap_or("no file"),
+                Chain(&e)
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,35 @@
+//! `--events ndjson` without `--events-output` keeps the standard output for the events
+
+use std::process::Command;
+
+#[test]
+fn test_events_only_on_stdout() {
+    let output = std::env::temp_dir().join(format!("dump-memory-events-{}", std::process::id()));
+    // The shell starts the target then becomes the dumper, which may trace its child even when
+    // kernel.yama.ptrace_scope is 1. With `--entropy`, a table is printed too.
+    let dump = Command::new("sh")
+        .arg("-c")
+        .arg(r#"sleep 60 </dev/null >/dev/null 2>&1 & echo $! >&2; exec "$0" -q --events ndjson --entropy $! "$1""#)
+        .arg(env!("CARGO_BIN_EXE_dump-memory"))
+        .arg(&output)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&dump.stderr);
+    if let Some(pid) = stderr.lines().next() {
+        let _ = Command::new("kill").arg(pid).status();
+    }
+    let _ = std::fs::remove_dir_all(&output);
+    assert!(dump.status.success(), "dump failed: {}", stderr);
+
+    let stdout = String::from_utf8(dump.stdout).unwrap();
+    for line in stdout.lines() {
+        assert!(
+            line.starts_with("{\"level\":") && line.ends_with('}'),
+            "not an event: {:?}",
+            line
+        );
+    }
+    assert!(stdout.contains("\"event\":\"attach\""));
+    assert!(stdout.contains("\"event\":\"summary\""));
+    assert!(stderr.contains("ENTROPY"));
+}
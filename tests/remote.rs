@@ -0,0 +1,108 @@
+//! `serve` answers malformed `dump` requests with an error, and keeps serving
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+fn write_frame(stream: &mut TcpStream, json: &str) {
+    let mut frame = vec![b'J'];
+    frame.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    frame.extend_from_slice(json.as_bytes());
+    stream.write_all(&frame).unwrap();
+}
+
+/// Reads a frame, returning its type and payload
+fn read_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut header = [0u8; 5];
+    stream.read_exact(&mut header).unwrap();
+    let mut payload = vec![0; u32::from_le_bytes(header[1..].try_into().unwrap()) as usize];
+    stream.read_exact(&mut payload).unwrap();
+    (header[0], payload)
+}
+
+fn dump_request(start: usize, end: usize, ranges: &[(usize, usize)]) -> String {
+    let ranges: Vec<_> = ranges
+        .iter()
+        .map(|(start, end)| format!("[{},{}]", start, end))
+        .collect();
+    format!(
+        r#"{{"op":"dump","start":{},"end":{},"ranges":[{}]}}"#,
+        start,
+        end,
+        ranges.join(",")
+    )
+}
+
+#[test]
+fn test_malformed_ranges() {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    // The agent is the parent of the target, which it may trace even when
+    // kernel.yama.ptrace_scope is 1
+    let mut agent = Command::new("sh")
+        .arg("-c")
+        .arg(r#"sleep 60 </dev/null >/dev/null 2>&1 & echo $!; exec "$0" -q serve --once "$1""#)
+        .arg(env!("CARGO_BIN_EXE_dump-memory"))
+        .arg(format!("127.0.0.1:{}", port))
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut pid = String::new();
+    BufReader::new(agent.stdout.take().unwrap())
+        .read_line(&mut pid)
+        .unwrap();
+    let pid: u32 = pid.trim().parse().unwrap();
+
+    let mut stream = (0..50)
+        .find_map(|_| {
+            TcpStream::connect(("127.0.0.1", port))
+                .inspect_err(|_| thread::sleep(Duration::from_millis(100)))
+                .ok()
+        })
+        .expect("the agent does not listen");
+    write_frame(
+        &mut stream,
+        &format!(
+            r#"{{"op":"open","version":1,"pid":{},"compression":"none"}}"#,
+            pid
+        ),
+    );
+    assert_eq!(read_frame(&mut stream).0, b'J');
+
+    let maps = std::fs::read_to_string(format!("/proc/{}/maps", pid)).unwrap();
+    let range = maps.split(' ').next().unwrap();
+    let (start, end) = range.split_once('-').unwrap();
+    let start = usize::from_str_radix(start, 16).unwrap();
+    let end = usize::from_str_radix(end, 16).unwrap();
+
+    for ranges in [
+        [(start, start + 0x1000), (start, start + 0x1000)],
+        [(start, start + 0x1000), (end, end + 0x1000)],
+    ] {
+        write_frame(&mut stream, &dump_request(start, end, &ranges));
+        let (kind, message) = read_frame(&mut stream);
+        assert_eq!(kind, b'E', "{}", String::from_utf8_lossy(&message));
+    }
+
+    // The agent is still there
+    write_frame(&mut stream, &dump_request(start, end, &[(start, end)]));
+    let mut size = 0;
+    loop {
+        match read_frame(&mut stream) {
+            (b'D', data) => size += data.len(),
+            (b'J', _) => break,
+            (kind, message) => panic!("{}: {}", kind, String::from_utf8_lossy(&message)),
+        }
+    }
+    assert_eq!(size, end - start);
+    drop(stream);
+
+    let status = agent.wait().unwrap();
+    let _ = Command::new("kill").arg(pid.to_string()).status();
+    assert!(status.success());
+}